@@ -9,7 +9,7 @@ use plumber_asset_core::{Asset, AssetConfig, Cached, Context, Handler, NoError};
 use plumber_asset_vmt::VmtConfig;
 use plumber_vmf::{
     builder::{BuiltBrushEntity, GeometrySettings},
-    vmf::{Entity, World},
+    vmf::{CordonBox, Entity, World},
 };
 
 #[derive(Clone, Copy)]
@@ -17,6 +17,8 @@ pub struct BrushConfig<'a, M> {
     pub vmt_config: M,
     pub side_faces_map: &'a Mutex<BTreeMap<i32, Vec<Vec<Vec3>>>>,
     pub geometry_settings: GeometrySettings,
+    /// Boxes the brushes are clipped to, or empty to import the whole map.
+    pub cordons: &'a [CordonBox],
     pub scale: f32,
 }
 
@@ -50,12 +52,14 @@ where
                 |path| context.depend_on(self.vmt_config, path.clone()).ok(),
                 self.side_faces_map,
                 &self.geometry_settings,
+                self.cordons,
                 self.scale,
             ),
             BrushInput::Entity(entity) => entity.build_brush(
                 |path| context.depend_on(self.vmt_config, path.clone()).ok(),
                 self.side_faces_map,
                 &self.geometry_settings,
+                self.cordons,
                 self.scale,
             ),
         };
@@ -0,0 +1,78 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+};
+
+use plumber_asset_core::{Asset, AssetConfig, Cached, Context, Handler};
+use plumber_asset_vmt::VmtConfig;
+use plumber_fs::GamePathBuf;
+use plumber_vmf::{
+    builder::{BuiltDecal, DecalError as VmfDecalError, GeometrySettings},
+    entities::{BaseEntity, Decal},
+};
+
+use glam::Vec3;
+use thiserror::Error;
+
+#[derive(Clone, Copy)]
+pub struct DecalConfig<'a, M> {
+    pub vmt_config: M,
+    pub side_faces_map: &'a BTreeMap<i32, Vec<Vec<Vec3>>>,
+    pub geometry_settings: GeometrySettings,
+    pub scale: f32,
+}
+
+impl<'a, M> Debug for DecalConfig<'a, M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("DecalConfig")
+    }
+}
+
+impl<'a, M, H> AssetConfig<H> for DecalConfig<'a, M>
+where
+    H: Handler<Asset<Self>> + Handler<Cached<M>>,
+    M: VmtConfig<H>,
+{
+    type Input<'b> = Decal<'b>;
+    type Output<'b> = BuiltDecal<'b>;
+    type Error<'b> = DecalError;
+
+    fn process<'b>(
+        self,
+        input: Self::Input<'b>,
+        context: &mut Context<H>,
+    ) -> Result<Self::Output<'b>, Self::Error<'b>> {
+        let mut material_path = GamePathBuf::from("materials");
+        let material_path_part = input
+            .material()
+            .map_err(|e| DecalError::new(input.entity().id, e.into()))?;
+
+        material_path.push(&material_path_part);
+
+        let material_info = context
+            .depend_on(self.vmt_config, material_path.into())
+            .unwrap_or_default();
+
+        input
+            .build_mesh(
+                &material_info,
+                self.side_faces_map,
+                &self.geometry_settings,
+                self.scale,
+            )
+            .map_err(|e| DecalError::new(input.entity().id, e))
+    }
+}
+
+#[derive(Debug, Error, Clone, Hash, PartialEq, Eq)]
+#[error("decal `{id}`: {error}")]
+pub struct DecalError {
+    id: i32,
+    error: VmfDecalError,
+}
+
+impl DecalError {
+    pub fn new(id: i32, error: VmfDecalError) -> Self {
+        Self { id, error }
+    }
+}
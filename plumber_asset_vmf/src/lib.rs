@@ -5,18 +5,27 @@ use rayon::prelude::*;
 use plumber_asset_core::{Asset, AssetConfig, Cached, Context, Handler, NoError};
 use plumber_asset_mdl::MdlConfig;
 use plumber_asset_vmt::{skybox::SkyBoxConfig, VmtConfig};
-use plumber_fs::GamePathBuf;
-use plumber_vmf::{builder::GeometrySettings, entities::TypedEntity, vmf::Vmf};
+use plumber_fs::{GamePathBuf, PathBuf};
+use plumber_vmf::{
+    builder::GeometrySettings,
+    entities::TypedEntity,
+    instance::InstanceError,
+    vmf::{EditorFilter, Vmf},
+};
 
 pub mod brush;
+pub mod decal;
 pub mod other_entity;
 pub mod overlay;
 pub mod prop;
+pub mod vis_groups;
 
 use brush::{BrushConfig, BrushInput};
+use decal::DecalConfig;
 use other_entity::OtherEntityConfig;
 use overlay::OverlayConfig;
 use prop::PropConfig;
+use vis_groups::VisGroupsConfig;
 
 #[derive(Debug, Clone, Copy)]
 pub enum BrushSetting {
@@ -31,54 +40,99 @@ impl Default for BrushSetting {
 }
 
 #[derive(Debug, Clone, Copy)]
-pub struct VmfConfig<M> {
+pub struct VmfConfig<'a, M> {
     pub vmt_config: M,
     pub scale: f32,
     pub brushes: BrushSetting,
     pub import_overlays: bool,
+    pub import_decals: bool,
     pub import_props: bool,
     pub import_animations: bool,
     pub import_other_entities: bool,
     pub import_skybox: bool,
+    /// Collapse `func_instance` entities into the map before anything is filtered or imported.
+    pub collapse_instances: bool,
+    /// Selects the solids and entities to import based on visgroups and hidden state.
+    pub editor_filter: EditorFilter<'a>,
+    /// Import only what is inside the active cordons, clipping brushes to the cordon boxes.
+    pub import_cordons_only: bool,
 }
 
-impl<M> VmfConfig<M> {
+impl<'a, M> VmfConfig<'a, M> {
     pub fn new(vmt_config: M) -> Self {
         Self {
             vmt_config,
             scale: 1.0,
             brushes: BrushSetting::Import(GeometrySettings::default()),
             import_overlays: true,
+            import_decals: true,
             import_props: true,
             import_animations: true,
             import_other_entities: true,
             import_skybox: true,
+            collapse_instances: true,
+            editor_filter: EditorFilter::default(),
+            import_cordons_only: false,
         }
     }
 }
 
-impl<H, M> AssetConfig<H> for VmfConfig<M>
+#[derive(Debug)]
+pub struct VmfInput {
+    pub vmf: Vmf,
+    /// Path of the map, instance files are read relative to it.
+    pub path: PathBuf,
+}
+
+impl<'a, H, M> AssetConfig<H> for VmfConfig<'a, M>
 where
     H: Handler<Asset<OtherEntityConfig>>
-        + for<'a> Handler<Asset<BrushConfig<'a, M>>>
-        + for<'a> Handler<Asset<OverlayConfig<'a, M>>>
+        + for<'b> Handler<Asset<BrushConfig<'b, M>>>
+        + for<'b> Handler<Asset<OverlayConfig<'b, M>>>
+        + for<'b> Handler<Asset<DecalConfig<'b, M>>>
         + Handler<Asset<PropConfig<M>>>
         + Handler<Asset<SkyBoxConfig>>
+        + Handler<Asset<VisGroupsConfig>>
         + Handler<Cached<MdlConfig<M>>>
         + Handler<Cached<M>>
         + 'static,
     M: VmtConfig<H>,
 {
-    type Input<'a> = Vmf;
-    type Output<'a> = ();
-    type Error<'a> = NoError;
+    type Input<'b> = VmfInput;
+    /// Errors of the instances that failed to load.
+    type Output<'b> = Vec<InstanceError>;
+    type Error<'b> = NoError;
 
-    fn process<'a>(
+    fn process<'b>(
         self,
-        input: Self::Input<'a>,
+        input: Self::Input<'b>,
         context: &mut Context<H>,
-    ) -> Result<Self::Output<'a>, Self::Error<'a>> {
+    ) -> Result<Self::Output<'b>, Self::Error<'b>> {
+        let VmfInput {
+            vmf: mut input,
+            path,
+        } = input;
+
+        let instance_errors = if self.collapse_instances {
+            input.collapse_instances(&path, context.fs())
+        } else {
+            Vec::new()
+        };
+
+        input.apply_editor_filter(&self.editor_filter);
+
+        let cordons = if self.import_cordons_only {
+            input.cordons.active_boxes()
+        } else {
+            Vec::new()
+        };
+        input.remove_outside_cordons(&cordons);
+
         context.scope(|context| {
+            if !input.vis_groups.vis_groups.is_empty() {
+                context.queue(VisGroupsConfig, &input.vis_groups);
+            }
+
             if self.import_props {
                 let prop_config = PropConfig {
                     mdl_config: MdlConfig {
@@ -113,8 +167,11 @@ where
 
                     let typed = e.typed();
 
-                    // overlays and props are loaded separately
-                    if let TypedEntity::Overlay(..) | TypedEntity::Prop(..) = &typed {
+                    // overlays, decals and props are loaded separately
+                    if let TypedEntity::Overlay(..)
+                    | TypedEntity::Decal(..)
+                    | TypedEntity::Prop(..) = &typed
+                    {
                         return None;
                     }
 
@@ -133,6 +190,7 @@ where
                         vmt_config: self.vmt_config,
                         side_faces_map: &side_faces_map,
                         geometry_settings,
+                        cordons: &cordons,
                         scale: self.scale,
                     };
 
@@ -171,6 +229,25 @@ where
 
                         context.process_each(overlay_config, overlays);
                     }
+
+                    if self.import_decals {
+                        let decal_config = DecalConfig {
+                            vmt_config: self.vmt_config,
+                            side_faces_map: &side_faces_map,
+                            geometry_settings,
+                            scale: self.scale,
+                        };
+
+                        let decals = input.entities.par_iter().filter_map(|e| {
+                            if let TypedEntity::Decal(d) = e.typed() {
+                                Some(d)
+                            } else {
+                                None
+                            }
+                        });
+
+                        context.process_each(decal_config, decals);
+                    }
                 });
             }
 
@@ -178,10 +255,10 @@ where
                 let mut sky_path = GamePathBuf::from("materials/skybox");
                 sky_path.push(&input.world.sky_name);
 
-                context.queue(SkyBoxConfig, sky_path);
+                context.queue(SkyBoxConfig::default(), sky_path);
             }
         });
 
-        Ok(())
+        Ok(instance_errors)
     }
 }
@@ -0,0 +1,21 @@
+use plumber_asset_core::{Asset, AssetConfig, Context, Handler, NoError};
+use plumber_vmf::vmf::VisGroups;
+
+/// Passes the visgroup tree of the map to the handler,
+/// so the visgroup ids of brushes and entities can be resolved.
+#[derive(Debug, Clone, Copy)]
+pub struct VisGroupsConfig;
+
+impl<H: Handler<Asset<Self>>> AssetConfig<H> for VisGroupsConfig {
+    type Input<'a> = &'a VisGroups;
+    type Output<'a> = &'a VisGroups;
+    type Error<'a> = NoError;
+
+    fn process<'a>(
+        self,
+        input: Self::Input<'a>,
+        _context: &mut Context<H>,
+    ) -> Result<Self::Output<'a>, Self::Error<'a>> {
+        Ok(input)
+    }
+}
@@ -5,12 +5,14 @@ use plumber_asset_vtf::VtfErrorInner;
 use plumber_fs::{GamePathBuf, OpenFileSystem, Path, PathBuf};
 use plumber_uncased::AsUncased;
 use plumber_vmt::{
-    MaterialInfo, ParameterError, ParameterType, Shader, ShaderResolveError, TexturePath, Vmt,
+    MaterialInfo, ParameterError, ParameterType, Shader, ShaderResolveError, TargetProfile,
+    TexturePath, Vmt,
 };
 
 use thiserror::Error;
-use vtflib2::VtfFile;
 
+pub mod lint;
+pub mod pbr;
 pub mod skybox;
 
 pub trait VmtConfig<H>:
@@ -148,10 +150,10 @@ impl<'a> VmtHelper<'a> {
                     .read(&texture_path.ensure_extension("vtf"))
                     .map_err(|err| VtfErrorInner::from_io(&err, &texture_path))?;
 
-                let mut vtf = VtfFile::new();
-                vtf.load_header(&vtf_bytes).map_err(VtfErrorInner::from)?;
+                let header =
+                    plumber_vtf::Header::from_bytes(&vtf_bytes).map_err(VtfErrorInner::from)?;
 
-                (vtf.width(), vtf.height())
+                (header.width, header.height)
             }
             None => (512, 512),
         };
@@ -213,5 +215,5 @@ pub fn get_shader(material_path: Path, fs: &OpenFileSystem) -> Result<Shader, Vm
 
     let material = Vmt::from_bytes(&material_contents)?;
 
-    Ok(material.resolve_shader(fs)?)
+    Ok(material.resolve_shader(fs, &TargetProfile::default())?)
 }
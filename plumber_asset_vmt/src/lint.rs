@@ -0,0 +1,533 @@
+//! Material validation.
+//!
+//! Checks materials for problems that the engine silently ignores or works around,
+//! such as missing textures or conflicting flags.
+//! With the `serde` feature, the reports can be serialized into a machine-readable format.
+
+use plumber_fs::{DirEntryType, GamePath, GamePathBuf, OpenFileSystem, ReadDir};
+use plumber_uncased::AsUncased;
+use plumber_vmt::{
+    shaders::{is_known_shader, SchemaWarning, TypedShader},
+    ParameterType, Shader, ShaderResolveError, TargetProfile, TexturePath, Transform, Vmt,
+};
+use plumber_vtf::Header;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use thiserror::Error;
+
+/// Parameters that refer to textures.
+const TEXTURE_PARAMETERS: &[&str] = &[
+    "$basetexture",
+    "$basetexture2",
+    "$bumpmap",
+    "$bumpmap2",
+    "$normalmap",
+    "$normalmap2",
+    "$detail",
+    "$detail2",
+    "$envmap",
+    "$envmapmask",
+    "$envmapmask2",
+    "$phongexponenttexture",
+    "$phongwarptexture",
+    "$selfillummask",
+    "$selfillumtexture",
+    "$lightwarptexture",
+    "$blendmodulatetexture",
+    "$dudvmap",
+    "$iris",
+    "$corneatexture",
+    "$ambientoccltexture",
+    "$refracttinttexture",
+    "$flowmap",
+    "$texture2",
+    "$hdrbasetexture",
+    "$hdrcompressedtexture",
+];
+
+/// Flags that enable alpha testing and the flags that conflict with it.
+const ALPHA_TEST_FLAG: &str = "$alphatest";
+const BLENDING_FLAGS: &[&str] = &["$translucent", "$additive"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Severity {
+    /// The material can't be loaded correctly.
+    Error,
+    /// The material loads, but may not look as intended.
+    Warning,
+}
+
+#[derive(Debug, Clone, Error, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum LintIssue {
+    #[error("can't read material: {message}")]
+    Unreadable { message: String },
+    #[error("can't parse material: {message}")]
+    Unparseable { message: String },
+    #[error("patch include cycle: {}", chain.join(" -> "))]
+    PatchCycle { chain: Vec<String> },
+    #[error("can't resolve patch material: {message}")]
+    Unresolvable { message: String },
+    #[error("unknown shader `{shader}`")]
+    UnknownShader { shader: String },
+    #[error("unknown parameter `{parameter}` for shader `{shader}`")]
+    UnknownParameter { shader: String, parameter: String },
+    #[error("parameter `{parameter}` is not a valid {expected}: `{value}`")]
+    InvalidParameter {
+        parameter: String,
+        expected: String,
+        value: String,
+    },
+    #[error("texture `{path}` in `{parameter}` not found")]
+    MissingTexture { parameter: String, path: String },
+    #[error("texture `{path}` in `{parameter}` can't be loaded: {message}")]
+    InvalidTexture {
+        parameter: String,
+        path: String,
+        message: String,
+    },
+    #[error("texture `{path}` in `{parameter}` is not a power of two: {width}x{height}")]
+    NonPowerOfTwoTexture {
+        parameter: String,
+        path: String,
+        width: u32,
+        height: u32,
+    },
+    #[error("conflicting translucency flags: {}", flags.join(", "))]
+    ConflictingTranslucency { flags: Vec<String> },
+}
+
+impl LintIssue {
+    #[must_use]
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::Unreadable { .. }
+            | Self::Unparseable { .. }
+            | Self::PatchCycle { .. }
+            | Self::Unresolvable { .. }
+            | Self::MissingTexture { .. }
+            | Self::InvalidTexture { .. } => Severity::Error,
+            Self::UnknownShader { .. }
+            | Self::UnknownParameter { .. }
+            | Self::InvalidParameter { .. }
+            | Self::NonPowerOfTwoTexture { .. }
+            | Self::ConflictingTranslucency { .. } => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct MaterialReport {
+    pub material: GamePathBuf,
+    pub issues: Vec<LintIssue>,
+}
+
+impl MaterialReport {
+    fn push(&mut self, issue: LintIssue) {
+        if !self.issues.contains(&issue) {
+            self.issues.push(issue);
+        }
+    }
+
+    #[must_use]
+    pub fn has_errors(&self) -> bool {
+        self.issues
+            .iter()
+            .any(|issue| issue.severity() == Severity::Error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LintReport {
+    /// Reports of the materials that have issues.
+    pub materials: Vec<MaterialReport>,
+    /// Number of checked materials, including the ones without issues.
+    pub checked: usize,
+}
+
+/// Checks a single material.
+/// `material_path` is relative to the game root, for example `materials/concrete/concrete01.vmt`.
+#[must_use]
+pub fn lint_material(material_path: &GamePath, fs: &OpenFileSystem) -> MaterialReport {
+    let material_path = material_path.with_extension("vmt");
+    let mut report = MaterialReport {
+        material: material_path.clone(),
+        issues: Vec::new(),
+    };
+
+    let vmt = match read_vmt(&material_path, fs) {
+        Ok(vmt) => vmt,
+        Err(issue) => {
+            report.push(issue);
+            return report;
+        }
+    };
+
+    let shader =
+        match vmt.resolve_shader_with_provenance(&material_path, fs, &TargetProfile::default()) {
+            Ok(resolved) => resolved.shader,
+            Err(ShaderResolveError::PatchCycle { chain }) => {
+                report.push(LintIssue::PatchCycle { chain });
+                return report;
+            }
+            Err(err) => {
+                report.push(LintIssue::Unresolvable {
+                    message: err.to_string(),
+                });
+                return report;
+            }
+        };
+
+    check_shader(&shader, &mut report);
+    check_parameters(&shader, &mut report);
+    check_textures(&shader, fs, &mut report);
+    check_translucency(&shader, &mut report);
+
+    report
+}
+
+/// Checks every material under `materials/`.
+#[must_use]
+pub fn lint_all(fs: &OpenFileSystem) -> LintReport {
+    let mut report = LintReport::default();
+    let root = GamePath::try_from_str("materials").expect("cannot fail");
+
+    lint_dir(fs.read_dir(root), fs, &mut report);
+
+    report
+}
+
+fn lint_dir(read_dir: ReadDir, fs: &OpenFileSystem, report: &mut LintReport) {
+    for entry in read_dir.flatten() {
+        match entry.entry_type() {
+            DirEntryType::File => {
+                let is_vmt = entry
+                    .name()
+                    .as_str()
+                    .rsplit('.')
+                    .next()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("vmt"));
+
+                if is_vmt {
+                    let material_report = lint_material(entry.path(), fs);
+                    report.checked += 1;
+
+                    if !material_report.issues.is_empty() {
+                        report.materials.push(material_report);
+                    }
+                }
+            }
+            DirEntryType::Directory => lint_dir(entry.read_dir(), fs, report),
+        }
+    }
+}
+
+fn read_vmt(path: &GamePath, fs: &OpenFileSystem) -> Result<Vmt, LintIssue> {
+    let contents = fs.read(path).map_err(|err| LintIssue::Unreadable {
+        message: format!("`{}`: {}", path, err),
+    })?;
+
+    Vmt::from_bytes(&contents).map_err(|err| LintIssue::Unparseable {
+        message: format!("`{}`: {}", path, err),
+    })
+}
+
+fn check_shader(shader: &Shader, report: &mut MaterialReport) {
+    if !is_known_shader(shader.shader.as_str()) {
+        report.push(LintIssue::UnknownShader {
+            shader: shader.shader.to_string(),
+        });
+    }
+}
+
+fn check_parameters(shader: &Shader, report: &mut MaterialReport) {
+    let mut warnings = Vec::new();
+    // only the warnings are needed
+    let _ = TypedShader::from_shader_with_warnings(shader, &mut warnings);
+
+    for warning in warnings {
+        report.push(match warning {
+            SchemaWarning::UnknownParameter(parameter) => LintIssue::UnknownParameter {
+                shader: shader.shader.to_string(),
+                parameter: parameter.to_string(),
+            },
+            SchemaWarning::InvalidParameter(err) => LintIssue::InvalidParameter {
+                parameter: err.parameter().to_owned(),
+                expected: err.kind().to_owned(),
+                value: err.value().to_owned(),
+            },
+        });
+    }
+
+    // texture transforms are checked for every shader, also the ones without a schema
+    for (parameter, value) in &shader.parameters {
+        let is_transform = parameter
+            .as_str()
+            .to_ascii_lowercase()
+            .ends_with("transform");
+        if is_transform && Transform::parse(value).is_none() {
+            report.push(LintIssue::InvalidParameter {
+                parameter: parameter.to_string(),
+                expected: Transform::TYPE_NAME.to_owned(),
+                value: value.clone(),
+            });
+        }
+    }
+}
+
+fn check_textures(shader: &Shader, fs: &OpenFileSystem, report: &mut MaterialReport) {
+    for &parameter in TEXTURE_PARAMETERS {
+        let Some(value) = shader.parameters.get(parameter.as_uncased()) else {
+            continue;
+        };
+
+        // cubemaps and render targets are provided by the engine
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("env_cubemap") || value.starts_with("_rt_") {
+            continue;
+        }
+
+        let Some(texture) = TexturePath::parse(value) else {
+            continue;
+        };
+        let texture_path = texture.absolute_path().with_extension("vtf");
+
+        let bytes = match fs.read(&texture_path) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                report.push(LintIssue::MissingTexture {
+                    parameter: parameter.to_owned(),
+                    path: texture_path.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let header = match Header::from_bytes(&bytes) {
+            Ok(header) => header,
+            Err(err) => {
+                report.push(LintIssue::InvalidTexture {
+                    parameter: parameter.to_owned(),
+                    path: texture_path.to_string(),
+                    message: err.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let (width, height) = (header.width, header.height);
+        if !width.is_power_of_two() || !height.is_power_of_two() {
+            report.push(LintIssue::NonPowerOfTwoTexture {
+                parameter: parameter.to_owned(),
+                path: texture_path.to_string(),
+                width,
+                height,
+            });
+        }
+    }
+}
+
+fn check_translucency(shader: &Shader, report: &mut MaterialReport) {
+    let is_set = |flag: &str| {
+        shader
+            .parameters
+            .get(flag.as_uncased())
+            .and_then(|value| bool::parse(value))
+            .unwrap_or(false)
+    };
+
+    if !is_set(ALPHA_TEST_FLAG) {
+        return;
+    }
+
+    let conflicting: Vec<_> = BLENDING_FLAGS
+        .iter()
+        .filter(|flag| is_set(flag))
+        .map(|&flag| flag.to_owned())
+        .collect();
+
+    if !conflicting.is_empty() {
+        let mut flags = vec![ALPHA_TEST_FLAG.to_owned()];
+        flags.extend(conflicting);
+        report.push(LintIssue::ConflictingTranslucency { flags });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf as StdPathBuf, process};
+
+    use plumber_fs::{FileSystem, SearchPath};
+    use plumber_vtf::{encode, EncodeSettings, ImageFormat, SourceImage};
+
+    use super::*;
+
+    /// A temporary game directory, removed when dropped.
+    struct TestGame {
+        root: StdPathBuf,
+        fs: OpenFileSystem,
+    }
+
+    impl TestGame {
+        fn new(name: &str, files: &[(&str, &[u8])]) -> Self {
+            let root = std::env::temp_dir().join(format!("plumber_lint_{}_{name}", process::id()));
+
+            for (path, contents) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+
+            let fs = FileSystem {
+                name: name.to_owned(),
+                search_paths: vec![SearchPath::Directory(root.clone())],
+            }
+            .open()
+            .unwrap();
+
+            Self { root, fs }
+        }
+
+        fn lint(&self, material: &str) -> Vec<LintIssue> {
+            lint_material(GamePath::try_from_str(material).unwrap(), &self.fs).issues
+        }
+    }
+
+    impl Drop for TestGame {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn vtf(width: u32, height: u32) -> Vec<u8> {
+        let pixels = vec![255; width as usize * height as usize * 4];
+        let mut settings = EncodeSettings::new();
+        settings.format(ImageFormat::Rgba8888);
+        settings.mipmaps(false);
+        settings.low_res_image(false);
+        encode(SourceImage::Rgba8(&pixels), width, height, &settings).unwrap()
+    }
+
+    #[test]
+    fn unknown_parameters() {
+        let game = TestGame::new(
+            "unknown_parameters",
+            &[(
+                "materials/a.vmt",
+                b"\"UnlitGeneric\"\n{\n\"$unusedvar\" \"1\"\n}\n",
+            )],
+        );
+
+        assert_eq!(
+            game.lint("materials/a"),
+            vec![LintIssue::UnknownParameter {
+                shader: "UnlitGeneric".to_owned(),
+                parameter: "$unusedvar".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn patch_cycle() {
+        let patch = |include: &str| {
+            format!("\"patch\"\n{{\n\"include\" \"{include}\"\n\"insert\" {{ }}\n}}\n")
+        };
+        let a = patch("materials/b.vmt");
+        let b = patch("materials/a.vmt");
+        let game = TestGame::new(
+            "patch_cycle",
+            &[
+                ("materials/a.vmt", a.as_bytes()),
+                ("materials/b.vmt", b.as_bytes()),
+            ],
+        );
+
+        let issues = game.lint("materials/a");
+        assert_eq!(
+            issues,
+            vec![LintIssue::PatchCycle {
+                chain: vec![
+                    "materials/a.vmt".to_owned(),
+                    "materials/b.vmt".to_owned(),
+                    "materials/a.vmt".to_owned(),
+                ],
+            }]
+        );
+        assert_eq!(issues[0].severity(), Severity::Error);
+    }
+
+    #[test]
+    fn invalid_transform() {
+        let game = TestGame::new(
+            "invalid_transform",
+            &[(
+                "materials/a.vmt",
+                b"\"UnlitGeneric\"\n{\n\"$basetexturetransform\" \"center .5\"\n}\n",
+            )],
+        );
+
+        let issues = game.lint("materials/a");
+        assert!(issues.contains(&LintIssue::InvalidParameter {
+            parameter: "$basetexturetransform".to_owned(),
+            expected: Transform::TYPE_NAME.to_owned(),
+            value: "center .5".to_owned(),
+        }));
+    }
+
+    #[test]
+    fn mixed_case_transform() {
+        let game = TestGame::new(
+            "mixed_case_transform",
+            &[(
+                "materials/a.vmt",
+                b"\"LightmappedGeneric\"\n{\n\"$BaseTextureTransform\" \"center .5\"\n}\n",
+            )],
+        );
+
+        let issues = game.lint("materials/a");
+        assert!(issues.contains(&LintIssue::InvalidParameter {
+            parameter: "$BaseTextureTransform".to_owned(),
+            expected: Transform::TYPE_NAME.to_owned(),
+            value: "center .5".to_owned(),
+        }));
+    }
+
+    #[test]
+    fn textures() {
+        let non_power_of_two = vtf(3, 2);
+        let power_of_two = vtf(4, 2);
+        let game = TestGame::new(
+            "textures",
+            &[
+                (
+                    "materials/a.vmt",
+                    b"\"LightmappedGeneric\"\n{\n\"$basetexture\" \"npot\"\n\"$bumpmap\" \"pot\"\n\"$detail\" \"missing\"\n\"$envmap\" \"env_cubemap\"\n}\n",
+                ),
+                ("materials/npot.vtf", &non_power_of_two),
+                ("materials/pot.vtf", &power_of_two),
+            ],
+        );
+
+        assert_eq!(
+            game.lint("materials/a"),
+            vec![
+                LintIssue::NonPowerOfTwoTexture {
+                    parameter: "$basetexture".to_owned(),
+                    path: "materials/npot.vtf".to_owned(),
+                    width: 3,
+                    height: 2,
+                },
+                LintIssue::MissingTexture {
+                    parameter: "$detail".to_owned(),
+                    path: "materials/missing.vtf".to_owned(),
+                },
+            ]
+        );
+    }
+}
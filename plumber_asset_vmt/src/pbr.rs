@@ -0,0 +1,493 @@
+//! Conversion of Source materials into a renderer-neutral metallic/roughness description.
+//!
+//! Source shaders have no direct PBR equivalent, so the conversion is an approximation:
+//! - roughness is derived from the phong exponent (or the phong exponent texture),
+//!   masked by the phong mask,
+//! - metallic is derived from the envmap tint and the envmap mask,
+//! - emissive comes from selfillum, masked by the selfillum mask or the base texture alpha,
+//! - SSBump maps are converted into tangent-space normal maps.
+
+use std::fmt::{self, Debug, Formatter};
+
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use rgb::RGB;
+
+use plumber_asset_vtf::normal::ssbump_to_normal;
+use plumber_fs::{GamePathBuf, Path};
+use plumber_vmt::{Shader, TexturePath};
+
+/// Roughness used for surfaces with neither phong nor an envmap.
+const DIFFUSE_ROUGHNESS: f32 = 1.0;
+/// Roughness used for envmapped surfaces without phong.
+const ENVMAP_ROUGHNESS: f32 = 0.3;
+/// Phong exponent used if the material has neither `$phongexponent` nor `$phongexponenttexture`.
+const DEFAULT_PHONG_EXPONENT: f32 = 5.0;
+/// Maximum phong exponent that can be stored in a phong exponent texture.
+const MAX_TEXTURE_PHONG_EXPONENT: f32 = 150.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Alpha tested, pixels with alpha below `cutoff` are discarded.
+    Mask {
+        cutoff: f32,
+    },
+    Blend,
+    /// Additively blended.
+    Additive,
+}
+
+/// A texture of a PBR material.
+#[derive(Clone)]
+pub enum PbrTexture {
+    /// A material texture that can be used as is.
+    Path(GamePathBuf),
+    /// A texture generated from the material textures.
+    Image(RgbaImage),
+}
+
+impl Debug for PbrTexture {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            Self::Image(_) => f.debug_struct("Image").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// A single-channel PBR input, either constant or varying per pixel.
+#[derive(Clone)]
+pub enum PbrScalar {
+    Value(f32),
+    Texture(GrayImage),
+}
+
+impl Debug for PbrScalar {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Value(value) => f.debug_tuple("Value").field(value).finish(),
+            Self::Texture(_) => f.debug_struct("Texture").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Renderer-neutral metallic/roughness material.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PbrMaterial {
+    /// Linear RGBA multiplier of the base color texture.
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<PbrTexture>,
+    /// Tangent-space normal map, in the DirectX convention used by Source (green pointing down).
+    pub normal_texture: Option<PbrTexture>,
+    pub roughness: PbrScalar,
+    pub metallic: PbrScalar,
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<PbrTexture>,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+}
+
+/// A channel of a texture used as a mask.
+#[derive(Clone, Copy)]
+struct Mask<'a> {
+    image: &'a RgbaImage,
+    channel: usize,
+}
+
+impl Mask<'_> {
+    fn sample(self, u: f32, v: f32) -> f32 {
+        f32::from(sample(self.image, u, v)[self.channel]) / 255.0
+    }
+}
+
+/// Samples the image at normalized coordinates using nearest filtering.
+fn sample(image: &RgbaImage, u: f32, v: f32) -> Rgba<u8> {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    let to_pixel = |coord: f32, size: u32| ((coord * size as f32) as u32).min(size - 1);
+
+    *image.get_pixel(to_pixel(u, image.width()), to_pixel(v, image.height()))
+}
+
+/// Generates a grayscale image with the size of the largest of the given images.
+fn generate_gray<'a>(
+    sources: impl IntoIterator<Item = &'a RgbaImage>,
+    f: impl Fn(f32, f32) -> f32,
+) -> GrayImage {
+    let (width, height) = sources
+        .into_iter()
+        .map(RgbaImage::dimensions)
+        .max_by_key(|&(width, height)| width * height)
+        .unwrap_or((1, 1));
+
+    GrayImage::from_fn(width, height, |x, y| {
+        #[allow(clippy::cast_precision_loss)]
+        let (u, v) = (
+            (x as f32 + 0.5) / width as f32,
+            (y as f32 + 0.5) / height as f32,
+        );
+        Luma([to_u8(f(u, v))])
+    })
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn luminance(color: RGB<f32>) -> f32 {
+    0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b
+}
+
+/// Converts a Blinn-Phong specular exponent into GGX roughness.
+fn exponent_to_roughness(exponent: f32) -> f32 {
+    (2.0 / (exponent.max(0.0) + 2.0)).sqrt()
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Converts a resolved shader into a PBR material.
+///
+/// `textures` is called with absolute texture paths (starting with `materials/`)
+/// and should return the loaded texture, or `None` if it's not available.
+/// Without the textures, per-pixel inputs fall back to constant values.
+/// Invalid parameters are logged and replaced with their default values.
+pub fn to_pbr<'a>(
+    shader: &Shader,
+    material_name: Path,
+    textures: impl Fn(&GamePathBuf) -> Option<&'a RgbaImage>,
+) -> PbrMaterial {
+    // empty images can't be sampled, treat them as missing
+    let textures =
+        |path: &GamePathBuf| textures(path).filter(|image| image.width() > 0 && image.height() > 0);
+    let texture_path = |parameter| {
+        shader
+            .extract_param::<TexturePath>(parameter, material_name)
+            .map(|path| path.absolute_path())
+    };
+    let flag = |parameter| {
+        shader
+            .extract_param::<bool>(parameter, material_name)
+            .unwrap_or_default()
+    };
+    let float = |parameter, default| {
+        shader
+            .extract_param::<f32>(parameter, material_name)
+            .unwrap_or(default)
+    };
+    let color = |parameter| {
+        shader
+            .extract_param::<RGB<f32>>(parameter, material_name)
+            .unwrap_or(RGB::new(1.0, 1.0, 1.0))
+    };
+
+    let base_texture = texture_path("$basetexture");
+    let base_image = base_texture.as_ref().and_then(&textures);
+
+    let bump_texture = texture_path("$bumpmap").or_else(|| texture_path("$normalmap"));
+    let bump_image = bump_texture.as_ref().and_then(&textures);
+    let ssbump = flag("$ssbump");
+
+    let base_alpha = base_image.map(|image| Mask { image, channel: 3 });
+    let normal_alpha = bump_image
+        .filter(|_| !ssbump)
+        .map(|image| Mask { image, channel: 3 });
+
+    let tint = color("$color");
+    let alpha = float("$alpha", 1.0);
+
+    let alpha_mode = if flag("$alphatest") {
+        AlphaMode::Mask {
+            cutoff: float("$alphatestreference", 0.5),
+        }
+    } else if flag("$additive") {
+        AlphaMode::Additive
+    } else if flag("$translucent") || alpha < 1.0 {
+        AlphaMode::Blend
+    } else {
+        AlphaMode::Opaque
+    };
+
+    let normal_texture = match (bump_texture, ssbump) {
+        (Some(_), true) => bump_image.map(|image| PbrTexture::Image(ssbump_to_normal(image))),
+        (Some(path), false) => Some(PbrTexture::Path(path)),
+        (None, _) => None,
+    };
+
+    // metallic from the envmap tint and mask
+    let env_map = texture_path("$envmap");
+    let env_map_strength = luminance(color("$envmaptint"));
+    let env_map_mask = if flag("$basealphaenvmapmask") {
+        base_alpha
+    } else if flag("$normalmapalphaenvmapmask") {
+        normal_alpha
+    } else {
+        texture_path("$envmapmask")
+            .as_ref()
+            .and_then(&textures)
+            .map(|image| Mask { image, channel: 0 })
+    };
+
+    let metallic = match (&env_map, env_map_mask) {
+        (None, _) => PbrScalar::Value(0.0),
+        (Some(_), None) => PbrScalar::Value(env_map_strength.clamp(0.0, 1.0)),
+        (Some(_), Some(mask)) => PbrScalar::Texture(generate_gray([mask.image], |u, v| {
+            env_map_strength * mask.sample(u, v)
+        })),
+    };
+
+    // roughness from the phong exponent, or from the envmap
+    let roughness = if flag("$phong") {
+        let explicit_exponent = shader.extract_param::<f32>("$phongexponent", material_name);
+        let exponent_image = texture_path("$phongexponenttexture")
+            .as_ref()
+            .and_then(&textures)
+            .filter(|_| explicit_exponent.is_none());
+        let exponent = explicit_exponent.unwrap_or(DEFAULT_PHONG_EXPONENT);
+
+        let phong_mask = if flag("$basemapalphaphongmask") {
+            base_alpha
+        } else {
+            normal_alpha
+        };
+
+        if exponent_image.is_none() && phong_mask.is_none() {
+            PbrScalar::Value(exponent_to_roughness(exponent))
+        } else {
+            let sources = exponent_image
+                .into_iter()
+                .chain(phong_mask.map(|mask| mask.image));
+            PbrScalar::Texture(generate_gray(sources, |u, v| {
+                let exponent = exponent_image.map_or(exponent, |image| {
+                    1.0 + f32::from(sample(image, u, v)[0]) / 255.0
+                        * (MAX_TEXTURE_PHONG_EXPONENT - 1.0)
+                });
+                let mask = phong_mask.map_or(1.0, |mask| mask.sample(u, v));
+                lerp(DIFFUSE_ROUGHNESS, exponent_to_roughness(exponent), mask)
+            }))
+        }
+    } else if env_map.is_some() {
+        match env_map_mask {
+            Some(mask) => PbrScalar::Texture(generate_gray([mask.image], |u, v| {
+                lerp(DIFFUSE_ROUGHNESS, ENVMAP_ROUGHNESS, mask.sample(u, v))
+            })),
+            None => PbrScalar::Value(ENVMAP_ROUGHNESS),
+        }
+    } else {
+        PbrScalar::Value(DIFFUSE_ROUGHNESS)
+    };
+
+    // emissive from selfillum
+    let (emissive_factor, emissive_texture) = if flag("$selfillum") {
+        let self_illum_tint = color("$selfillumtint");
+        let mask = texture_path("$selfillummask")
+            .as_ref()
+            .and_then(&textures)
+            .map(|image| Mask { image, channel: 0 })
+            .or(base_alpha);
+
+        let texture = base_image.map(|base| {
+            let mut emissive = RgbaImage::new(base.width(), base.height());
+            for (x, y, pixel) in emissive.enumerate_pixels_mut() {
+                #[allow(clippy::cast_precision_loss)]
+                let (u, v) = (
+                    (x as f32 + 0.5) / base.width() as f32,
+                    (y as f32 + 0.5) / base.height() as f32,
+                );
+                let mask = mask.map_or(1.0, |mask| mask.sample(u, v));
+                let color = base.get_pixel(x, y);
+                *pixel = Rgba([
+                    to_u8(f32::from(color[0]) / 255.0 * mask),
+                    to_u8(f32::from(color[1]) / 255.0 * mask),
+                    to_u8(f32::from(color[2]) / 255.0 * mask),
+                    255,
+                ]);
+            }
+            PbrTexture::Image(emissive)
+        });
+
+        (
+            [self_illum_tint.r, self_illum_tint.g, self_illum_tint.b],
+            texture,
+        )
+    } else {
+        ([0.0; 3], None)
+    };
+
+    PbrMaterial {
+        base_color_factor: [tint.r, tint.g, tint.b, alpha],
+        base_color_texture: base_texture.map(PbrTexture::Path),
+        normal_texture,
+        roughness,
+        metallic,
+        emissive_factor,
+        emissive_texture,
+        alpha_mode,
+        double_sided: flag("$nocull"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use plumber_fs::GamePath;
+    use plumber_vmt::Vmt;
+
+    use super::*;
+
+    fn convert(
+        shader: &str,
+        parameters: &[(&str, &str)],
+        textures: &[(&str, RgbaImage)],
+    ) -> PbrMaterial {
+        let mut input = format!("\"{shader}\"\n{{\n");
+        for (parameter, value) in parameters {
+            writeln!(input, "\"{parameter}\" \"{value}\"").unwrap();
+        }
+        input.push('}');
+
+        let shader = Vmt::from_bytes(input.as_bytes())
+            .unwrap()
+            .into_shader()
+            .unwrap();
+        let name = GamePath::try_from_str("test").unwrap();
+
+        to_pbr(&shader, Path::Game(name), |path| {
+            textures
+                .iter()
+                .find(|(texture, _)| *path == **texture)
+                .map(|(_, image)| image)
+        })
+    }
+
+    /// A mask that is black on the left pixel and white on the right one.
+    fn left_right_mask() -> RgbaImage {
+        RgbaImage::from_fn(2, 1, |x, _| {
+            let value = if x == 0 { 0 } else { 255 };
+            Rgba([value, value, value, value])
+        })
+    }
+
+    fn value(scalar: &PbrScalar) -> f32 {
+        match scalar {
+            PbrScalar::Value(value) => *value,
+            PbrScalar::Texture(_) => panic!("expected a constant value"),
+        }
+    }
+
+    fn texture(scalar: &PbrScalar) -> &GrayImage {
+        match scalar {
+            PbrScalar::Texture(texture) => texture,
+            PbrScalar::Value(_) => panic!("expected a texture"),
+        }
+    }
+
+    #[test]
+    fn phong_roughness() {
+        let material = convert(
+            "VertexLitGeneric",
+            &[("$phong", "1"), ("$phongexponent", "98")],
+            &[],
+        );
+        assert!((value(&material.roughness) - 0.141_421_4).abs() < 1e-4);
+        assert!(value(&material.metallic).abs() < f32::EPSILON);
+
+        let diffuse = convert("VertexLitGeneric", &[("$basetexture", "a")], &[]);
+        assert!((value(&diffuse.roughness) - DIFFUSE_ROUGHNESS).abs() < f32::EPSILON);
+
+        // the phong mask blends towards diffuse roughness
+        let masked = convert(
+            "VertexLitGeneric",
+            &[("$bumpmap", "n"), ("$phong", "1"), ("$phongexponent", "98")],
+            &[("materials/n", left_right_mask())],
+        );
+        let roughness = texture(&masked.roughness);
+        assert_eq!(roughness.get_pixel(0, 0)[0], 255);
+        assert_eq!(roughness.get_pixel(1, 0)[0], 36);
+    }
+
+    #[test]
+    fn envmap_metallic() {
+        let material = convert(
+            "LightmappedGeneric",
+            &[("$envmap", "env_cubemap"), ("$envmaptint", "[.5 .5 .5]")],
+            &[],
+        );
+        assert!((value(&material.metallic) - 0.5).abs() < 1e-4);
+        assert!((value(&material.roughness) - ENVMAP_ROUGHNESS).abs() < f32::EPSILON);
+
+        let masked = convert(
+            "LightmappedGeneric",
+            &[
+                ("$basetexture", "a"),
+                ("$envmap", "env_cubemap"),
+                ("$basealphaenvmapmask", "1"),
+            ],
+            &[("materials/a", left_right_mask())],
+        );
+        let metallic = texture(&masked.metallic);
+        assert_eq!(metallic.get_pixel(0, 0)[0], 0);
+        assert_eq!(metallic.get_pixel(1, 0)[0], 255);
+    }
+
+    #[test]
+    fn self_illum() {
+        let mut base = left_right_mask();
+        for pixel in base.pixels_mut() {
+            pixel.0[..3].copy_from_slice(&[200, 100, 50]);
+        }
+
+        let material = convert(
+            "VertexLitGeneric",
+            &[
+                ("$basetexture", "a"),
+                ("$selfillum", "1"),
+                ("$selfillumtint", "[2 1 1]"),
+            ],
+            &[("materials/a", base)],
+        );
+
+        assert_eq!(material.emissive_factor, [2.0, 1.0, 1.0]);
+        let Some(PbrTexture::Image(emissive)) = &material.emissive_texture else {
+            panic!("expected a generated emissive texture");
+        };
+        assert_eq!(emissive.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(emissive.get_pixel(1, 0), &Rgba([200, 100, 50, 255]));
+
+        let unlit = convert("VertexLitGeneric", &[("$basetexture", "a")], &[]);
+        assert_eq!(unlit.emissive_factor, [0.0; 3]);
+        assert!(unlit.emissive_texture.is_none());
+    }
+
+    #[test]
+    fn alpha_modes() {
+        let alpha_mode = |parameters| convert("UnlitGeneric", parameters, &[]).alpha_mode;
+
+        assert_eq!(alpha_mode(&[]), AlphaMode::Opaque);
+        assert_eq!(
+            alpha_mode(&[("$alphatest", "1"), ("$alphatestreference", ".25")]),
+            AlphaMode::Mask { cutoff: 0.25 }
+        );
+        assert_eq!(alpha_mode(&[("$additive", "1")]), AlphaMode::Additive);
+        assert_eq!(alpha_mode(&[("$translucent", "1")]), AlphaMode::Blend);
+        assert_eq!(alpha_mode(&[("$alpha", ".5")]), AlphaMode::Blend);
+    }
+
+    #[test]
+    fn empty_textures() {
+        // an empty mask is ignored instead of sampled
+        let material = convert(
+            "LightmappedGeneric",
+            &[("$envmap", "env_cubemap"), ("$envmapmask", "m")],
+            &[("materials/m", RgbaImage::new(0, 0))],
+        );
+        assert!((value(&material.metallic) - 1.0).abs() < f32::EPSILON);
+    }
+}
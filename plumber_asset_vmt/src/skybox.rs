@@ -1,22 +1,62 @@
-use std::fmt::{self, Debug, Formatter};
+use std::{
+    array,
+    f32::consts::{FRAC_PI_2, PI},
+    fmt::{self, Debug, Formatter},
+};
 
 use plumber_asset_core::{Asset, AssetConfig, Context, Handler};
-use plumber_asset_vtf::VtfErrorInner;
-use plumber_fs::{GamePathBuf, OpenFileSystem, Path};
-use plumber_uncased::AsUncased;
-use plumber_vmt::TexturePath;
-
-use half::f16;
-use image::{Rgba32FImage, RgbaImage};
-use itertools::Itertools;
+use plumber_asset_vtf::{TextureData, VtfConfig, VtfError, VtfErrorInner};
+use plumber_fs::{GamePathBuf, OpenFileSystem, Path, PathBuf};
+use plumber_vmt::{Shader, TexturePath};
+
+use image::{imageops, ImageBuffer, Pixel, Rgba, Rgba32FImage, RgbaImage};
 use thiserror::Error;
-use vtflib2::VtfFile;
-use zerocopy::LayoutVerified;
+use tracing::warn;
 
 use crate::{get_shader, VmtErrorInner};
 
+/// How skybox faces that are missing or fail to load are filled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MissingFaces {
+    /// Fail loading the whole skybox.
+    Error,
+    /// Fill the face with opaque black.
+    Black,
+    /// Mirror the opposite face, or fill with black if it's also missing.
+    Mirror,
+}
+
 #[derive(Debug, Clone, Copy)]
-pub struct SkyBoxConfig;
+#[non_exhaustive]
+pub struct SkyBoxConfig {
+    /// Load the HDR textures when the materials have both HDR and SDR textures.
+    pub prefer_hdr: bool,
+    pub missing_faces: MissingFaces,
+}
+
+impl SkyBoxConfig {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn prefer_hdr(&mut self, prefer_hdr: bool) {
+        self.prefer_hdr = prefer_hdr;
+    }
+
+    pub fn missing_faces(&mut self, missing_faces: MissingFaces) {
+        self.missing_faces = missing_faces;
+    }
+}
+
+impl Default for SkyBoxConfig {
+    fn default() -> Self {
+        Self {
+            prefer_hdr: true,
+            missing_faces: MissingFaces::Mirror,
+        }
+    }
+}
 
 impl<H: Handler<Asset<Self>>> AssetConfig<H> for SkyBoxConfig {
     type Input<'a> = GamePathBuf;
@@ -30,7 +70,7 @@ impl<H: Handler<Asset<Self>>> AssetConfig<H> for SkyBoxConfig {
     ) -> Result<Self::Output<'a>, SkyBoxError> {
         input.set_extension("");
 
-        load_skybox(&input, context.fs()).map_err(|e| SkyBoxError::new(input, e))
+        load_skybox(&input, self, context.fs())
     }
 }
 
@@ -38,6 +78,8 @@ impl<H: Handler<Asset<Self>>> AssetConfig<H> for SkyBoxConfig {
 pub struct SkyBox {
     pub name: GamePathBuf,
     pub data: SkyBoxData,
+    /// Errors of the faces that were filled in, in [`CUBEMAP_SUFFIXES`] order.
+    pub face_errors: [Option<VmtErrorInner>; 6],
 }
 
 #[derive(Clone)]
@@ -46,6 +88,39 @@ pub enum SkyBoxData {
     Hdr([Rgba32FImage; 6]),
 }
 
+impl SkyBoxData {
+    /// Converts the faces into an equirectangular panorama, with bilinear filtering.
+    ///
+    /// The center of the panorama looks towards +X and the top row towards +Z,
+    /// with +Y to the left of the center.
+    /// Side faces less tall than wide are treated as the top part of a square face.
+    #[must_use]
+    pub fn to_equirectangular(&self, width: u32, height: u32) -> TextureData {
+        match self {
+            Self::Sdr(faces) => {
+                let dimensions = faces.each_ref().map(RgbaImage::dimensions);
+                let pixels = equirectangular(dimensions, width, height, |face, x, y| {
+                    faces[face].get_pixel(x, y).0.map(f32::from)
+                });
+
+                TextureData::Sdr(RgbaImage::from_fn(width, height, |x, y| {
+                    Rgba(pixels[(y * width + x) as usize].map(|c| c.round() as u8))
+                }))
+            }
+            Self::Hdr(faces) => {
+                let dimensions = faces.each_ref().map(Rgba32FImage::dimensions);
+                let pixels = equirectangular(dimensions, width, height, |face, x, y| {
+                    faces[face].get_pixel(x, y).0
+                });
+
+                TextureData::Hdr(Rgba32FImage::from_fn(width, height, |x, y| {
+                    Rgba(pixels[(y * width + x) as usize])
+                }))
+            }
+        }
+    }
+}
+
 impl Debug for SkyBoxData {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
@@ -68,154 +143,342 @@ impl SkyBoxError {
     }
 }
 
-const CUBEMAP_SUFFIXES: [&str; 6] = ["lf", "rt", "up", "dn", "ft", "bk"];
-
-fn load_skybox(sky_path: &GamePathBuf, fs: &OpenFileSystem) -> Result<SkyBox, VmtErrorInner> {
-    let file_name = sky_path
-        .file_name()
-        .ok_or(VmtErrorInner::Custom("invalid sky filename"))?;
-
-    let skybox_shaders: Vec<_> = CUBEMAP_SUFFIXES
-        .iter()
-        .map(|suffix| {
-            let mut file_name = file_name.to_owned();
-            file_name.push_str(suffix);
+/// Suffixes of the face materials, in the order of the faces in [`SkyBoxData`].
+pub const CUBEMAP_SUFFIXES: [&str; 6] = ["lf", "rt", "up", "dn", "ft", "bk"];
 
-            let mut path = sky_path.clone();
-            path.set_file_name(&file_name);
+/// Index of the opposite face in `CUBEMAP_SUFFIXES` order.
+const OPPOSITE_FACES: [usize; 6] = [1, 0, 3, 2, 5, 4];
 
-            get_shader(Path::from(&path), fs)
-        })
-        .try_collect()?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FaceTexture {
+    Sdr,
+    Hdr,
+    CompressedHdr,
+}
 
-    let parameters = &skybox_shaders[0].parameters;
-    let hdr = parameters.contains_key("$hdrbasetexture".as_uncased())
-        || parameters.contains_key("$hdrcompressedtexture".as_uncased());
+fn load_skybox(
+    sky_path: &GamePathBuf,
+    config: SkyBoxConfig,
+    fs: &OpenFileSystem,
+) -> Result<SkyBox, SkyBoxError> {
+    let file_name = sky_path.file_name().ok_or_else(|| {
+        SkyBoxError::new(
+            sky_path.clone(),
+            VmtErrorInner::Custom("invalid sky filename"),
+        )
+    })?;
+
+    let faces = CUBEMAP_SUFFIXES.map(|suffix| {
+        let (material_path, shader) = face_shader(sky_path, file_name, suffix, config, fs);
+
+        let texture = shader.and_then(|shader| {
+            let (texture_path, kind) = face_texture(&shader, &material_path, config)?;
+            Ok(load_face_texture(&texture_path, kind, fs)?)
+        });
+
+        (material_path, texture)
+    });
+
+    let all_failed = faces.iter().all(|(_, texture)| texture.is_err());
+    if config.missing_faces == MissingFaces::Error || all_failed {
+        if let Some((material_path, Err(error))) =
+            faces.iter().find(|(_, texture)| texture.is_err())
+        {
+            return Err(SkyBoxError::new(material_path.clone(), error.clone()));
+        }
+    }
 
-    let texture_paths: Vec<_> = skybox_shaders
+    let hdr = faces
         .iter()
-        .map(|shader| {
-            let current_hdr;
-            let texture_path;
-
-            if let Some(value) = shader
-                .extract_param::<TexturePath>("$hdrbasetexture", sky_path.into())
-                .or_else(|| shader.extract_param("$hdrcompressedtexture", sky_path.into()))
-            {
-                current_hdr = true;
-                texture_path = value.absolute_path();
-            } else if let Some(value) =
-                shader.extract_param::<TexturePath>("$basetexture", sky_path.into())
-            {
-                current_hdr = false;
-                texture_path = value.absolute_path();
-            } else {
-                return Err(VmtErrorInner::Custom(
-                    "skybox material has no texture specified",
-                ));
-            }
-
-            if current_hdr != hdr {
-                return Err(VmtErrorInner::Custom(
-                    "skybox sides have inconsistent hdr/sdr format",
-                ));
-            }
-
-            Ok(texture_path)
-        })
-        .try_collect()?;
+        .any(|(_, texture)| texture.as_ref().is_ok_and(TextureData::is_hdr));
+
+    let mut face_errors = [(); 6].map(|()| None);
+    let textures: [_; 6] = array::from_fn(|i| match &faces[i].1 {
+        Ok(texture) => Some(texture),
+        Err(error) => {
+            warn!(
+                "skybox `{}`: face `{}` filled in: {}",
+                sky_path, CUBEMAP_SUFFIXES[i], error
+            );
+            face_errors[i] = Some(error.clone());
+            None
+        }
+    });
 
+    let mirror = config.missing_faces == MissingFaces::Mirror;
     let data = if hdr {
-        let textures: Vec<_> = texture_paths
-            .into_iter()
-            .map(|path| {
-                let texture_path = Path::Game(&path).ensure_extension("vtf");
-
-                let vtf_bytes = fs
-                    .read(&texture_path)
-                    .map_err(|err| VtfErrorInner::from_io(&err, &texture_path))?;
-
-                let mut vtf = VtfFile::new();
-                vtf.load(&vtf_bytes)?;
-
-                let data = vtf.data(0, 0, 0, 0).ok_or(vtflib2::Error::ImageNotLoaded)?;
-                let format = vtf.format().ok_or(vtflib2::Error::InvalidFormat)?;
-                let width = vtf.width();
-                let height = vtf.height();
-
-                let f32_data = match format {
-                    vtflib2::ImageFormat::Rgba32323232F => LayoutVerified::new_slice(data)
-                        .expect("vtflib should return properly aligned images")
-                        .into_slice()
-                        .to_vec(),
-                    vtflib2::ImageFormat::Rgba16161616F => f16s_to_f32s(data),
-                    vtflib2::ImageFormat::Bgra8888 => decompress_hdr(data),
-                    _ => {
-                        let data = VtfFile::convert_image_to_rgba8888(data, width, height, format)?;
-                        data.into_iter().map(|b| f32::from(b) / 255.0).collect()
-                    }
-                };
-
-                Ok(Rgba32FImage::from_raw(width, height, f32_data)
-                    .expect("vtf should return valid images"))
-            })
-            .collect::<Result<_, VtfErrorInner>>()?;
-
-        SkyBoxData::Hdr(textures.try_into().expect("vec should have correct length"))
+        SkyBoxData::Hdr(fill_missing(
+            textures.map(|texture| texture.map(TextureData::to_rgba32f)),
+            Rgba([0.0, 0.0, 0.0, 1.0]),
+            mirror,
+        ))
     } else {
-        let textures: Vec<_> = texture_paths
-            .into_iter()
-            .map(|path| {
-                let texture_path = Path::Game(&path).ensure_extension("vtf");
-                let bytes = fs
-                    .read(&texture_path)
-                    .map_err(|err| VtfErrorInner::from_io(&err, &texture_path))?;
-
-                let mut vtf = VtfFile::new();
-                vtf.load(&bytes)?;
-
-                let data = vtf.data(0, 0, 0, 0).ok_or(vtflib2::Error::ImageNotLoaded)?;
-                let format = vtf.format().ok_or(vtflib2::Error::InvalidFormat)?;
-                let width = vtf.width();
-                let height = vtf.height();
-
-                let data = VtfFile::convert_image_to_rgba8888(data, width, height, format)?;
-
-                Ok(RgbaImage::from_raw(width, height, data)
-                    .expect("vtflib should return valid images"))
-            })
-            .collect::<Result<_, VtfErrorInner>>()?;
-
-        SkyBoxData::Sdr(textures.try_into().expect("vec should have correct length"))
+        SkyBoxData::Sdr(fill_missing(
+            textures.map(|texture| texture.map(TextureData::to_rgba8)),
+            Rgba([0, 0, 0, 255]),
+            mirror,
+        ))
     };
 
     Ok(SkyBox {
         name: sky_path.clone(),
         data,
+        face_errors,
     })
 }
 
-fn f16s_to_f32s(data: &[u8]) -> Vec<f32> {
-    let floats: &[f16] = LayoutVerified::new_slice(data)
-        .expect("vtf should return properly aligned images")
-        .into_slice();
-    floats.iter().copied().map(f32::from).collect()
+/// Loads the material of a face, trying the `_hdr` variant first if HDR is preferred.
+fn face_shader(
+    sky_path: &GamePathBuf,
+    file_name: &str,
+    suffix: &str,
+    config: SkyBoxConfig,
+    fs: &OpenFileSystem,
+) -> (GamePathBuf, Result<Shader, VmtErrorInner>) {
+    let material_path = |infix: &str| {
+        let mut path = sky_path.clone();
+        path.set_file_name(format!("{file_name}{infix}{suffix}"));
+        path
+    };
+
+    if config.prefer_hdr {
+        let path = material_path("_hdr");
+        if let Ok(shader) = get_shader(Path::from(&path), fs) {
+            return (path, Ok(shader));
+        }
+    }
+
+    let path = material_path("");
+    let shader = get_shader(Path::from(&path), fs);
+    (path, shader)
 }
 
-fn decompress_hdr(data: &[u8]) -> Vec<f32> {
-    data.iter()
-        .copied()
-        .tuples()
-        .flat_map(|(b, g, r, a)| {
-            let a = f32::from(a);
+fn face_texture(
+    shader: &Shader,
+    material_path: &GamePathBuf,
+    config: SkyBoxConfig,
+) -> Result<(GamePathBuf, FaceTexture), VmtErrorInner> {
+    let texture = |parameter, kind| {
+        shader
+            .extract_param::<TexturePath>(parameter, material_path.into())
+            .map(|value| (value.absolute_path(), kind))
+    };
+    let hdr = || {
+        texture("$hdrbasetexture", FaceTexture::Hdr)
+            .or_else(|| texture("$hdrcompressedtexture", FaceTexture::CompressedHdr))
+    };
+    let sdr = || texture("$basetexture", FaceTexture::Sdr);
 
-            let r = f32::from(r) * a * 16.0 / 262_144.0;
-            let g = f32::from(g) * a * 16.0 / 262_144.0;
-            let b = f32::from(b) * a * 16.0 / 262_144.0;
+    if config.prefer_hdr {
+        hdr().or_else(sdr)
+    } else {
+        sdr().or_else(hdr)
+    }
+    .ok_or(VmtErrorInner::Custom(
+        "skybox material has no texture specified",
+    ))
+}
 
-            let a = 1.0;
+fn load_face_texture(
+    path: &GamePathBuf,
+    kind: FaceTexture,
+    fs: &OpenFileSystem,
+) -> Result<TextureData, VtfErrorInner> {
+    let config = VtfConfig {
+        compressed_hdr: kind == FaceTexture::CompressedHdr,
+        ..VtfConfig::default()
+    };
+    let loaded = config
+        .load_vtf(PathBuf::Game(path.clone()), fs)
+        .map_err(VtfError::into_inner)?;
+
+    // the material decides if the face is hdr, regardless of the texture format
+    Ok(match kind {
+        FaceTexture::Sdr => TextureData::Sdr(loaded.data.to_rgba8()),
+        FaceTexture::Hdr | FaceTexture::CompressedHdr => TextureData::Hdr(loaded.data.to_rgba32f()),
+    })
+}
 
-            [r, g, b, a]
+/// Fills in missing faces with the mirrored opposite face if `mirror` is set, or with `black`.
+///
+/// Black faces get the size of the first loaded face.
+fn fill_missing<P: Pixel + 'static>(
+    mut faces: [Option<ImageBuffer<P, Vec<P::Subpixel>>>; 6],
+    black: P,
+    mirror: bool,
+) -> [ImageBuffer<P, Vec<P::Subpixel>>; 6] {
+    let (width, height) = faces
+        .iter()
+        .flatten()
+        .next()
+        .map_or((1, 1), ImageBuffer::dimensions);
+
+    let mut mirrored: [_; 6] = array::from_fn(|i| {
+        let opposite = faces[OPPOSITE_FACES[i]].as_ref()?;
+        (mirror && faces[i].is_none()).then(|| {
+            // up and dn share the horizontal axis, the side faces share the vertical axis
+            if matches!(CUBEMAP_SUFFIXES[i], "up" | "dn") {
+                imageops::flip_vertical(opposite)
+            } else {
+                imageops::flip_horizontal(opposite)
+            }
         })
-        .collect()
+    });
+
+    array::from_fn(|i| {
+        faces[i]
+            .take()
+            .or_else(|| mirrored[i].take())
+            .unwrap_or_else(|| ImageBuffer::from_pixel(width, height, black))
+    })
+}
+
+/// Returns the face index in `CUBEMAP_SUFFIXES` order and the texture coordinates of a direction.
+///
+/// Source skybox faces follow the Quake convention:
+/// the side faces have +Z up and the `up` and `dn` faces have -Y to the right.
+fn cube_face_coordinates(direction: [f32; 3]) -> (usize, [f32; 2]) {
+    let [x, y, z] = direction;
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+    // (face, s, t), with s and t from -1 to 1 and t pointing up in the image
+    let (face, s, t) = if ax >= ay && ax >= az {
+        if x > 0.0 {
+            (1, -y / ax, z / ax) // rt
+        } else {
+            (0, y / ax, z / ax) // lf
+        }
+    } else if ay >= az {
+        if y > 0.0 {
+            (5, x / ay, z / ay) // bk
+        } else {
+            (4, -x / ay, z / ay) // ft
+        }
+    } else if z > 0.0 {
+        (2, -y / az, -x / az) // up
+    } else {
+        (3, -y / az, x / az) // dn
+    };
+
+    (face, [(s + 1.0) * 0.5, (1.0 - t) * 0.5])
+}
+
+/// Samples an equirectangular panorama from cube faces in `CUBEMAP_SUFFIXES` order.
+fn equirectangular(
+    dimensions: [(u32, u32); 6],
+    width: u32,
+    height: u32,
+    pixel: impl Fn(usize, u32, u32) -> [f32; 4],
+) -> Vec<[f32; 4]> {
+    let mut output = Vec::with_capacity(width as usize * height as usize);
+
+    for row in 0..height {
+        let latitude = FRAC_PI_2 - (row as f32 + 0.5) / height as f32 * PI;
+
+        for column in 0..width {
+            let longitude = PI - (column as f32 + 0.5) / width as f32 * 2.0 * PI;
+            let direction = [
+                latitude.cos() * longitude.cos(),
+                latitude.cos() * longitude.sin(),
+                latitude.sin(),
+            ];
+
+            let (face, [u, v]) = cube_face_coordinates(direction);
+            let (face_width, face_height) = dimensions[face];
+            if face_width == 0 || face_height == 0 {
+                output.push([0.0; 4]);
+                continue;
+            }
+
+            // coordinates are relative to a square face, so that half-height faces cover the top half
+            let size = face_width as f32;
+            let x = (u * size - 0.5).clamp(0.0, (face_width - 1) as f32);
+            let y = (v * size - 0.5).clamp(0.0, (face_height - 1) as f32);
+
+            output.push(bilinear(x, y, face_width, face_height, |x, y| {
+                pixel(face, x, y)
+            }));
+        }
+    }
+
+    output
+}
+
+fn bilinear(
+    x: f32,
+    y: f32,
+    width: u32,
+    height: u32,
+    pixel: impl Fn(u32, u32) -> [f32; 4],
+) -> [f32; 4] {
+    let x0 = x.floor() as u32;
+    let y0 = y.floor() as u32;
+    let x1 = (x0 + 1).min(width - 1);
+    let y1 = (y0 + 1).min(height - 1);
+    let fx = x.fract();
+    let fy = y.fract();
+
+    let p00 = pixel(x0, y0);
+    let p10 = pixel(x1, y0);
+    let p01 = pixel(x0, y1);
+    let p11 = pixel(x1, y1);
+
+    let mut result = [0.0; 4];
+    for (i, channel) in result.iter_mut().enumerate() {
+        let top = p00[i] + (p10[i] - p00[i]) * fx;
+        let bottom = p01[i] + (p11[i] - p01[i]) * fx;
+        *channel = top + (bottom - top) * fy;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equirectangular_faces() {
+        // each face filled with its index
+        let faces = [0, 1, 2, 3, 4, 5].map(|face| RgbaImage::from_pixel(4, 4, Rgba([face; 4])));
+        let TextureData::Sdr(panorama) = SkyBoxData::Sdr(faces).to_equirectangular(16, 8) else {
+            panic!("sdr skybox should convert to an sdr image");
+        };
+
+        let face_at = |x, y| panorama.get_pixel(x, y)[0];
+        // center looks towards +X, +Y is to the left
+        assert_eq!(face_at(8, 4), 1); // rt
+        assert_eq!(face_at(0, 4), 0); // lf
+        assert_eq!(face_at(4, 4), 5); // bk
+        assert_eq!(face_at(12, 4), 4); // ft
+        assert_eq!(face_at(8, 0), 2); // up
+        assert_eq!(face_at(8, 7), 3); // dn
+    }
+
+    #[test]
+    fn up_face_orientation() {
+        // the bottom edge of the up face joins the top edge of the rt face
+        let (face, [u, v]) = cube_face_coordinates([1.0, 0.0, 1.01]);
+        assert_eq!(face, 2);
+        assert!((u - 0.5).abs() < 1e-3);
+        assert!(v > 0.99);
+    }
+
+    #[test]
+    fn missing_faces() {
+        let mut up = RgbaImage::from_pixel(2, 2, Rgba([1; 4]));
+        up.put_pixel(0, 0, Rgba([2; 4]));
+        let mut faces = [(); 6].map(|()| None);
+        faces[2] = Some(up);
+        faces[0] = Some(RgbaImage::from_pixel(2, 2, Rgba([3; 4])));
+
+        let black = Rgba([0, 0, 0, 255]);
+        let [lf, rt, _, dn, ft, _] = fill_missing(faces.clone(), black, true);
+        // dn mirrors up vertically, rt mirrors lf, ft has no opposite
+        assert_eq!(dn.get_pixel(0, 1), &Rgba([2; 4]));
+        assert_eq!(rt, lf);
+        assert_eq!(ft, RgbaImage::from_pixel(2, 2, black));
+
+        let [_, _, _, dn, ..] = fill_missing(faces, black, false);
+        assert_eq!(dn, RgbaImage::from_pixel(2, 2, black));
+    }
 }
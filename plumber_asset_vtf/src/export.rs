@@ -0,0 +1,638 @@
+//! Exporting textures to common image formats.
+//!
+//! PNG, TGA and EXR files contain the largest mipmap of the first frame and face.
+//! DDS and KTX2 files keep the mipmaps, cubemap faces and depth slices,
+//! with block compressed data copied without recompression.
+//! KTX2 files also keep the frames of animated textures, as array layers.
+
+use std::{
+    fs,
+    io::{self, Seek, Write},
+    path::{Path as StdPath, PathBuf as StdPathBuf},
+};
+
+use image::{DynamicImage, Rgba32FImage};
+use plumber_fs::{OpenFileSystem, PathBuf};
+use plumber_vtf::{ImageFormat, TextureFlags, Vtf};
+use thiserror::Error;
+
+use crate::{LoadedVtf, TextureData, VtfConfig, VtfError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    Png,
+    Tga,
+    Exr,
+    Dds,
+    Ktx2,
+}
+
+impl ExportFormat {
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Tga => "tga",
+            Self::Exr => "exr",
+            Self::Dds => "dds",
+            Self::Ktx2 => "ktx2",
+        }
+    }
+}
+
+#[derive(Debug, Error, Clone, Hash, PartialEq, Eq)]
+pub enum ExportError {
+    #[error("io error: {0}")]
+    Io(String),
+    #[error("error encoding image: {0}")]
+    Image(String),
+    #[error("error reading vtf: {0}")]
+    Vtf(#[from] plumber_vtf::Error),
+    #[error("{0}")]
+    Load(#[from] VtfError),
+    #[error("exporting to {} needs `VtfConfig::keep_file`", .0.extension())]
+    FileNotKept(ExportFormat),
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
+
+impl From<image::ImageError> for ExportError {
+    fn from(err: image::ImageError) -> Self {
+        Self::Image(err.to_string())
+    }
+}
+
+impl LoadedVtf {
+    /// Writes the texture in the given format.
+    ///
+    /// HDR textures are clamped when written to PNG or TGA.
+    /// DDS and KTX2 need the raw file, so the texture must be loaded with `VtfConfig::keep_file`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if writing fails or the raw file is needed but wasn't kept.
+    pub fn export<W: Write + Seek>(
+        &self,
+        format: ExportFormat,
+        writer: &mut W,
+    ) -> Result<(), ExportError> {
+        match format {
+            ExportFormat::Png => self
+                .data
+                .to_rgba8()
+                .write_to(writer, image::ImageFormat::Png)?,
+            ExportFormat::Tga => self
+                .data
+                .to_rgba8()
+                .write_to(writer, image::ImageFormat::Tga)?,
+            ExportFormat::Exr => {
+                to_rgba32f(&self.data).write_to(writer, image::ImageFormat::OpenExr)?;
+            }
+            ExportFormat::Dds | ExportFormat::Ktx2 => {
+                let vtf = self.vtf().ok_or(ExportError::FileNotKept(format))?;
+
+                if format == ExportFormat::Dds {
+                    write_dds(&vtf, self.info.hdr, writer)?;
+                } else {
+                    write_ktx2(&vtf, self.info.hdr, writer)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the texture into a directory, keeping the game path of the texture.
+    /// Returns the path of the written file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if creating the file or writing fails.
+    pub fn export_to_directory(
+        &self,
+        directory: impl AsRef<StdPath>,
+        format: ExportFormat,
+    ) -> Result<StdPathBuf, ExportError> {
+        let relative_path = match &self.name {
+            PathBuf::Game(path) => StdPathBuf::from(path.as_str()),
+            PathBuf::Os(path) => StdPathBuf::from(path.file_name().unwrap_or_default()),
+        };
+        let path = directory
+            .as_ref()
+            .join(relative_path)
+            .with_extension(format.extension());
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut writer = io::BufWriter::new(fs::File::create(&path)?);
+        self.export(format, &mut writer)?;
+        writer.flush()?;
+
+        Ok(path)
+    }
+}
+
+/// Loads and exports textures into a directory, keeping their game paths.
+/// Returns the path of each written file, or the error if the texture failed.
+/// `VtfConfig::keep_file` is set automatically if the format needs it.
+pub fn export_textures(
+    fs: &OpenFileSystem,
+    textures: impl IntoIterator<Item = PathBuf>,
+    directory: impl AsRef<StdPath>,
+    format: ExportFormat,
+    mut config: VtfConfig,
+) -> Vec<(PathBuf, Result<StdPathBuf, ExportError>)> {
+    config.keep_file |= matches!(format, ExportFormat::Dds | ExportFormat::Ktx2);
+
+    textures
+        .into_iter()
+        .map(|texture| {
+            let result = config
+                .load_vtf(texture.clone(), fs)
+                .map_err(ExportError::from)
+                .and_then(|loaded| loaded.export_to_directory(directory.as_ref(), format));
+
+            (texture, result)
+        })
+        .collect()
+}
+
+fn to_rgba32f(data: &TextureData) -> Rgba32FImage {
+    match data {
+        TextureData::Sdr(image) => DynamicImage::ImageRgba8(image.clone()).into_rgba32f(),
+        TextureData::Hdr(image) => image.clone(),
+    }
+}
+
+/// How the image data is stored in DDS and KTX2 files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Payload {
+    /// The data is copied as is.
+    Raw(ImageFormat),
+    Rgba8,
+    Rgba32F,
+    CompressedHdr,
+}
+
+impl Payload {
+    fn new(vtf: &Vtf, hdr: bool) -> Self {
+        match vtf.format() {
+            format @ (ImageFormat::Dxt1
+            | ImageFormat::Dxt1OneBitAlpha
+            | ImageFormat::Dxt3
+            | ImageFormat::Dxt5
+            | ImageFormat::Ati1N
+            | ImageFormat::Ati2N
+            | ImageFormat::Rgba16161616F
+            | ImageFormat::Rgba32323232F) => Self::Raw(format),
+            ImageFormat::Bgra8888 if hdr => Self::CompressedHdr,
+            format if format.is_high_precision() => Self::Rgba32F,
+            _ => Self::Rgba8,
+        }
+    }
+
+    fn image_data(
+        self,
+        vtf: &Vtf,
+        mipmap: u32,
+        frame: u32,
+        face: u32,
+        slice: u32,
+    ) -> Result<Vec<u8>, plumber_vtf::Error> {
+        let floats_to_bytes =
+            |data: Vec<f32>| data.into_iter().flat_map(f32::to_le_bytes).collect();
+
+        Ok(match self {
+            Self::Raw(_) => vtf
+                .data(mipmap, frame, face, slice)
+                .ok_or(plumber_vtf::Error::Corrupted("image index out of range"))?
+                .to_vec(),
+            Self::Rgba8 => vtf.decode_rgba8(mipmap, frame, face, slice)?,
+            Self::Rgba32F => floats_to_bytes(vtf.decode_rgba32f(mipmap, frame, face, slice)?),
+            Self::CompressedHdr => {
+                floats_to_bytes(vtf.decode_compressed_hdr(mipmap, frame, face, slice)?)
+            }
+        })
+    }
+
+    fn is_compressed(self) -> bool {
+        matches!(self, Self::Raw(format) if format.is_compressed())
+    }
+
+    /// Size of a pixel, or a 4x4 block for compressed formats.
+    fn block_size(self) -> usize {
+        match self {
+            Self::Raw(ImageFormat::Dxt1 | ImageFormat::Dxt1OneBitAlpha | ImageFormat::Ati1N)
+            | Self::Raw(ImageFormat::Rgba16161616F) => 8,
+            Self::Rgba8 => 4,
+            _ => 16,
+        }
+    }
+}
+
+/// Returns the number of faces, ignoring the spheremap face of older cubemaps.
+fn face_count(vtf: &Vtf) -> u32 {
+    vtf.header().face_count().min(6)
+}
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const DDS_HEADER_SIZE: u32 = 124;
+const DDS_PIXEL_FORMAT_SIZE: u32 = 32;
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x2_0000;
+const DDSD_LINEARSIZE: u32 = 0x8_0000;
+const DDSD_DEPTH: u32 = 0x80_0000;
+
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x40_0000;
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0xfe00;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+
+/// `D3DFMT_A16B16G16R16F`
+const D3DFMT_RGBA16F: u32 = 113;
+/// `D3DFMT_A32B32G32R32F`
+const D3DFMT_RGBA32F: u32 = 116;
+
+fn write_dds(vtf: &Vtf, hdr: bool, writer: &mut impl Write) -> Result<(), ExportError> {
+    let payload = Payload::new(vtf, hdr);
+    let header = vtf.header();
+    let faces = face_count(vtf);
+
+    let fourcc = match payload {
+        Payload::Raw(ImageFormat::Dxt1 | ImageFormat::Dxt1OneBitAlpha) => {
+            Some(u32::from_le_bytes(*b"DXT1"))
+        }
+        Payload::Raw(ImageFormat::Dxt3) => Some(u32::from_le_bytes(*b"DXT3")),
+        Payload::Raw(ImageFormat::Dxt5) => Some(u32::from_le_bytes(*b"DXT5")),
+        Payload::Raw(ImageFormat::Ati1N) => Some(u32::from_le_bytes(*b"ATI1")),
+        Payload::Raw(ImageFormat::Ati2N) => Some(u32::from_le_bytes(*b"ATI2")),
+        Payload::Raw(ImageFormat::Rgba16161616F) => Some(D3DFMT_RGBA16F),
+        Payload::Rgba8 => None,
+        _ => Some(D3DFMT_RGBA32F),
+    };
+
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_MIPMAPCOUNT;
+    let pitch_or_linear_size = if payload.is_compressed() {
+        flags |= DDSD_LINEARSIZE;
+        payload_image_size(payload, header.width, header.height)
+    } else {
+        flags |= DDSD_PITCH;
+        header.width as usize * payload.block_size()
+    };
+
+    let mut caps = DDSCAPS_TEXTURE;
+    let mut caps2 = 0;
+    if header.mipmap_count > 1 {
+        caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+    if faces > 1 {
+        caps |= DDSCAPS_COMPLEX;
+        caps2 |= DDSCAPS2_CUBEMAP_ALL_FACES;
+    }
+    if header.depth > 1 {
+        flags |= DDSD_DEPTH;
+        caps |= DDSCAPS_COMPLEX;
+        caps2 |= DDSCAPS2_VOLUME;
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(DDS_MAGIC);
+    for value in [
+        DDS_HEADER_SIZE,
+        flags,
+        header.height,
+        header.width,
+        pitch_or_linear_size as u32,
+        header.depth,
+        header.mipmap_count,
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[0; 11 * 4]);
+
+    let pixel_format = match fourcc {
+        Some(fourcc) => [DDS_PIXEL_FORMAT_SIZE, DDPF_FOURCC, fourcc, 0, 0, 0, 0, 0],
+        None => [
+            DDS_PIXEL_FORMAT_SIZE,
+            DDPF_RGB | DDPF_ALPHAPIXELS,
+            0,
+            32,
+            0x0000_00ff,
+            0x0000_ff00,
+            0x00ff_0000,
+            0xff00_0000,
+        ],
+    };
+    for value in pixel_format.into_iter().chain([caps, caps2, 0, 0, 0]) {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    writer.write_all(&bytes)?;
+
+    // faces are stored one after the other, each with all of its mipmaps
+    for face in 0..faces {
+        for mipmap in 0..header.mipmap_count {
+            let (_, _, depth) = header.mipmap_dimensions(mipmap);
+            for slice in 0..depth {
+                writer.write_all(&payload.image_data(vtf, mipmap, 0, face, slice)?)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn payload_image_size(payload: Payload, width: u32, height: u32) -> usize {
+    match payload {
+        Payload::Raw(format) => format.image_size(width, height),
+        _ => width as usize * height as usize * payload.block_size(),
+    }
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+const KTX2_HEADER_SIZE: usize = 80;
+const KTX2_LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+/// Data format descriptor color models.
+const KHR_DF_MODEL_RGBSDA: u8 = 1;
+const KHR_DF_MODEL_BC1A: u8 = 128;
+const KHR_DF_MODEL_BC2: u8 = 129;
+const KHR_DF_MODEL_BC3: u8 = 130;
+const KHR_DF_MODEL_BC4: u8 = 131;
+const KHR_DF_MODEL_BC5: u8 = 132;
+
+const KHR_DF_PRIMARIES_BT709: u8 = 1;
+const KHR_DF_TRANSFER_LINEAR: u8 = 1;
+const KHR_DF_TRANSFER_SRGB: u8 = 2;
+
+const KHR_DF_CHANNEL_ALPHA: u8 = 15;
+const KHR_DF_SAMPLE_DATATYPE_LINEAR: u8 = 0x10;
+const KHR_DF_SAMPLE_DATATYPE_SIGNED: u8 = 0x40;
+const KHR_DF_SAMPLE_DATATYPE_FLOAT: u8 = 0x80;
+
+/// A sample of a data format descriptor, as `(bit offset, bit length, channel, lower, upper)`.
+type Sample = (u16, u8, u8, u32, u32);
+
+fn write_ktx2(vtf: &Vtf, hdr: bool, writer: &mut impl Write) -> Result<(), ExportError> {
+    let payload = Payload::new(vtf, hdr);
+    let header = vtf.header();
+    let faces = face_count(vtf);
+    let srgb = header.flags.contains(TextureFlags::SRGB);
+
+    let (vk_format, type_size) = match payload {
+        Payload::Raw(ImageFormat::Dxt1) => (131 + u32::from(srgb), 1),
+        Payload::Raw(ImageFormat::Dxt1OneBitAlpha) => (133 + u32::from(srgb), 1),
+        Payload::Raw(ImageFormat::Dxt3) => (135 + u32::from(srgb), 1),
+        Payload::Raw(ImageFormat::Dxt5) => (137 + u32::from(srgb), 1),
+        Payload::Raw(ImageFormat::Ati1N) => (139, 1),
+        Payload::Raw(ImageFormat::Ati2N) => (141, 1),
+        Payload::Raw(ImageFormat::Rgba16161616F) => (97, 2),
+        Payload::Rgba8 if srgb => (43, 1),
+        Payload::Rgba8 => (37, 1),
+        _ => (109, 4),
+    };
+
+    // the float formats are always linear
+    let srgb = srgb && !matches!(vk_format, 97 | 109);
+    let descriptor = data_format_descriptor(payload, srgb);
+
+    // every level must be aligned to the least common multiple of the block size and 4
+    let alignment = payload.block_size();
+
+    let levels = (0..header.mipmap_count)
+        .map(|mipmap| {
+            let (_, _, depth) = header.mipmap_dimensions(mipmap);
+            let mut level = Vec::new();
+
+            for frame in 0..header.frames {
+                for face in 0..faces {
+                    for slice in 0..depth {
+                        level.extend(payload.image_data(vtf, mipmap, frame, face, slice)?);
+                    }
+                }
+            }
+
+            Ok(level)
+        })
+        .collect::<Result<Vec<_>, plumber_vtf::Error>>()?;
+
+    let level_count = levels.len();
+    let descriptor_offset = KTX2_HEADER_SIZE + level_count * KTX2_LEVEL_INDEX_ENTRY_SIZE;
+    let mut data_offset = descriptor_offset + descriptor.len();
+
+    // levels are stored from the smallest to the largest
+    let mut level_offsets = vec![0; level_count];
+    for (mipmap, level) in levels.iter().enumerate().rev() {
+        data_offset = data_offset.next_multiple_of(alignment);
+        level_offsets[mipmap] = data_offset;
+        data_offset += level.len();
+    }
+
+    let mut bytes = Vec::with_capacity(data_offset);
+    bytes.extend_from_slice(&KTX2_IDENTIFIER);
+    for value in [
+        vk_format,
+        type_size,
+        header.width,
+        header.height,
+        if header.depth > 1 { header.depth } else { 0 },
+        if header.frames > 1 { header.frames } else { 0 },
+        faces,
+        level_count as u32,
+        0, // supercompression scheme
+        descriptor_offset as u32,
+        descriptor.len() as u32,
+        0, // key value data offset
+        0, // key value data length
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[0; 16]); // supercompression global data
+
+    for (level, offset) in levels.iter().zip(&level_offsets) {
+        let length = level.len() as u64;
+        for value in [*offset as u64, length, length] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    bytes.extend_from_slice(&descriptor);
+
+    for (mipmap, level) in levels.iter().enumerate().rev() {
+        bytes.resize(level_offsets[mipmap], 0);
+        bytes.extend_from_slice(level);
+    }
+
+    writer.write_all(&bytes)?;
+
+    Ok(())
+}
+
+/// Builds the basic data format descriptor block for the payload.
+fn data_format_descriptor(payload: Payload, srgb: bool) -> Vec<u8> {
+    // alpha is always linear, even when the color channels are sRGB encoded
+    let alpha = KHR_DF_CHANNEL_ALPHA
+        | if srgb {
+            KHR_DF_SAMPLE_DATATYPE_LINEAR
+        } else {
+            0
+        };
+    let block = |channel| (0, 64, channel, 0, u32::MAX);
+    let second_block = |channel| (64, 64, channel, 0, u32::MAX);
+
+    let (model, block_dimensions, samples): (u8, u8, Vec<Sample>) = match payload {
+        Payload::Raw(ImageFormat::Dxt1) => (KHR_DF_MODEL_BC1A, 3, vec![block(0)]),
+        Payload::Raw(ImageFormat::Dxt1OneBitAlpha) => (KHR_DF_MODEL_BC1A, 3, vec![block(1)]),
+        Payload::Raw(ImageFormat::Dxt3) => {
+            (KHR_DF_MODEL_BC2, 3, vec![block(alpha), second_block(0)])
+        }
+        Payload::Raw(ImageFormat::Dxt5) => {
+            (KHR_DF_MODEL_BC3, 3, vec![block(alpha), second_block(0)])
+        }
+        Payload::Raw(ImageFormat::Ati1N) => (KHR_DF_MODEL_BC4, 3, vec![block(0)]),
+        Payload::Raw(ImageFormat::Ati2N) => (KHR_DF_MODEL_BC5, 3, vec![block(0), second_block(1)]),
+        Payload::Rgba8 => (
+            KHR_DF_MODEL_RGBSDA,
+            0,
+            [0, 1, 2, alpha]
+                .into_iter()
+                .zip(0..)
+                .map(|(channel, i)| (i * 8, 8, channel, 0, 255))
+                .collect(),
+        ),
+        _ => {
+            let bits = if payload == Payload::Raw(ImageFormat::Rgba16161616F) {
+                16
+            } else {
+                32
+            };
+            let float = KHR_DF_SAMPLE_DATATYPE_FLOAT | KHR_DF_SAMPLE_DATATYPE_SIGNED;
+
+            (
+                KHR_DF_MODEL_RGBSDA,
+                0,
+                [0, 1, 2, KHR_DF_CHANNEL_ALPHA]
+                    .into_iter()
+                    .zip(0..)
+                    .map(|(channel, i)| {
+                        (
+                            i * u16::from(bits),
+                            bits,
+                            channel | float,
+                            (-1.0_f32).to_bits(),
+                            1.0_f32.to_bits(),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+    };
+
+    let block_size = 24 + 16 * samples.len();
+    let mut bytes = Vec::with_capacity(4 + block_size);
+
+    bytes.extend_from_slice(&(4 + block_size as u32).to_le_bytes());
+    bytes.extend_from_slice(&0_u32.to_le_bytes()); // vendor id and descriptor type
+    bytes.extend_from_slice(&2_u16.to_le_bytes()); // version
+    bytes.extend_from_slice(&(block_size as u16).to_le_bytes());
+    bytes.extend_from_slice(&[
+        model,
+        KHR_DF_PRIMARIES_BT709,
+        if srgb {
+            KHR_DF_TRANSFER_SRGB
+        } else {
+            KHR_DF_TRANSFER_LINEAR
+        },
+        0, // flags
+        block_dimensions,
+        block_dimensions,
+        0,
+        0,
+    ]);
+
+    let mut bytes_planes = [0_u8; 8];
+    bytes_planes[0] = payload.block_size() as u8;
+    bytes.extend_from_slice(&bytes_planes);
+
+    for (bit_offset, bit_length, channel, lower, upper) in samples {
+        bytes.extend_from_slice(&bit_offset.to_le_bytes());
+        bytes.push(bit_length - 1);
+        bytes.push(channel);
+        bytes.extend_from_slice(&[0; 4]); // sample position
+        bytes.extend_from_slice(&lower.to_le_bytes());
+        bytes.extend_from_slice(&upper.to_le_bytes());
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use plumber_vtf::{EncodeSettings, SourceImage};
+
+    use super::*;
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let pixels: Vec<u8> = (0..8 * 8 * 4).map(|i| i as u8).collect();
+        let mut settings = EncodeSettings::new();
+        settings.format(format);
+        plumber_vtf::encode(SourceImage::Rgba8(&pixels), 8, 8, &settings).unwrap()
+    }
+
+    #[test]
+    fn dds_keeps_blocks() {
+        let bytes = encode(ImageFormat::Dxt5);
+        let vtf = Vtf::from_bytes(&bytes).unwrap();
+
+        let mut dds = Vec::new();
+        write_dds(&vtf, false, &mut dds).unwrap();
+
+        assert_eq!(&dds[..4], DDS_MAGIC);
+        assert_eq!(&dds[84..88], b"DXT5");
+        // mipmap count
+        assert_eq!(dds[28..32], 4_u32.to_le_bytes());
+        // 8x8, 4x4, 2x2 and 1x1 mipmaps of 16 byte blocks
+        assert_eq!(dds.len(), 128 + 16 * (4 + 1 + 1 + 1));
+        assert_eq!(&dds[128..128 + 64], vtf.data(0, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn ktx2_levels() {
+        let bytes = encode(ImageFormat::Bgra8888);
+        let vtf = Vtf::from_bytes(&bytes).unwrap();
+
+        let mut ktx2 = Vec::new();
+        write_ktx2(&vtf, false, &mut ktx2).unwrap();
+
+        assert_eq!(ktx2[..12], KTX2_IDENTIFIER);
+        // R8G8B8A8_UNORM, 4 levels
+        assert_eq!(ktx2[12..16], 37_u32.to_le_bytes());
+        assert_eq!(ktx2[40..44], 4_u32.to_le_bytes());
+
+        let level_offset = u64::from_le_bytes(ktx2[80..88].try_into().unwrap()) as usize;
+        let level_length = u64::from_le_bytes(ktx2[88..96].try_into().unwrap()) as usize;
+        assert_eq!(level_length, 8 * 8 * 4);
+        assert_eq!(
+            ktx2[level_offset..level_offset + level_length],
+            vtf.decode_rgba8(0, 0, 0, 0).unwrap()
+        );
+        assert_eq!(level_offset + level_length, ktx2.len());
+    }
+}
@@ -1,13 +1,39 @@
-use std::io;
+use std::{
+    fmt::{self, Debug, Formatter},
+    io,
+};
 
-use image::RgbaImage;
+use image::{Rgba32FImage, RgbaImage};
 use plumber_asset_core::{Cached, CachedAssetConfig, Context, Handler};
-use plumber_fs::PathBuf;
+use plumber_fs::{OpenFileSystem, PathBuf};
+use plumber_vdf::Document;
+use plumber_vtf::{SourceImage, Vtf};
 use thiserror::Error;
-use vtflib2::VtfFile;
+use tracing::warn;
 
-#[derive(Debug, Clone, Copy)]
-pub struct VtfConfig;
+pub use plumber_vtf::{
+    EncodeSettings, ImageFormat, LodControl, Sheet, SheetFrame, SheetSequence, TextureFlags, UvRect,
+};
+
+pub mod export;
+pub mod normal;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VtfConfig {
+    /// Also load every mipmap, frame, face and depth slice into `LoadedVtf::images`.
+    pub load_all_images: bool,
+    /// Decode BGRA8888 textures as compressed HDR, as used by `$hdrcompressedtexture`.
+    pub compressed_hdr: bool,
+    /// Keep the raw file in `LoadedVtf`, which is needed for exporting to DDS or KTX2.
+    pub keep_file: bool,
+}
+
+impl VtfConfig {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
 
 impl<H> CachedAssetConfig<H> for VtfConfig
 where
@@ -47,33 +73,211 @@ impl VtfConfig {
     where
         H: Handler<Cached<Self>>,
     {
+        self.load(input, context.fs())
+    }
+
+    /// Loads a texture directly, without going through the asset system.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the texture can't be read or loaded.
+    pub fn load_vtf(self, mut path: PathBuf, fs: &OpenFileSystem) -> Result<LoadedVtf, VtfError> {
+        path.normalize_extension();
+
+        self.load(path.clone(), fs)
+            .map(|(loaded, _)| loaded)
+            .map_err(|e| VtfError::new(path, e))
+    }
+
+    fn load(
+        self,
+        input: PathBuf,
+        fs: &OpenFileSystem,
+    ) -> Result<(LoadedVtf, VtfInfo), VtfErrorInner> {
         let vtf_path = input.ensure_extension("vtf");
 
-        let bytes = context
-            .fs()
+        let bytes = fs
             .read(&vtf_path)
             .map_err(|err| VtfErrorInner::from_io(&err, &vtf_path))?;
 
-        let mut vtf = VtfFile::new();
-        vtf.load(&bytes)?;
+        let vtf = Vtf::from_bytes(&bytes)?;
+        let header = vtf.header();
 
-        let data = vtf.data(0, 0, 0, 0).ok_or(vtflib2::Error::ImageNotLoaded)?;
-        let format = vtf.format().ok_or(vtflib2::Error::InvalidFormat)?;
-        let width = vtf.width();
-        let height = vtf.height();
+        let info = VtfInfo {
+            width: header.width,
+            height: header.height,
+            depth: header.depth,
+            format: header.format,
+            flags: header.flags,
+            frames: header.frames,
+            faces: header.face_count(),
+            mipmaps: header.mipmap_count,
+            reflectivity: header.reflectivity,
+            bumpmap_scale: header.bumpmap_scale,
+            hdr: self.is_hdr(header.format),
+            sheet: vtf.sheet().unwrap_or_else(|err| {
+                warn!("texture `{}`: invalid sheet: {}", input, err);
+                None
+            }),
+            lod_control: vtf.lod_control(),
+            crc: vtf.crc(),
+            extended_flags: vtf.extended_flags(),
+            key_values: vtf
+                .key_values()
+                .and_then(|bytes| match Document::from_bytes(bytes) {
+                    Ok(document) => Some(document),
+                    Err(err) => {
+                        warn!("texture `{}`: invalid key values: {}", input, err);
+                        None
+                    }
+                }),
+        };
 
-        let data = VtfFile::convert_image_to_rgba8888(data, width, height, format)?;
+        let data = decode_image(&vtf, info.hdr, self.compressed_hdr, 0, 0, 0, 0)?;
+
+        let images = if self.load_all_images {
+            Some(VtfImages::load(&vtf, &info, self.compressed_hdr)?)
+        } else {
+            None
+        };
 
-        let info = VtfInfo { width, height };
         let loaded = LoadedVtf {
             name: input,
             info: info.clone(),
-            data: RgbaImage::from_raw(width, height, data)
-                .expect("vtflib should return valid images"),
+            data,
+            images,
+            bytes: self.keep_file.then_some(VtfBytes(bytes)),
         };
 
         Ok((loaded, info))
     }
+
+    fn is_hdr(self, format: ImageFormat) -> bool {
+        format.is_high_precision() || (self.compressed_hdr && format == ImageFormat::Bgra8888)
+    }
+}
+
+fn decode_image(
+    vtf: &Vtf,
+    hdr: bool,
+    compressed_hdr: bool,
+    mipmap: u32,
+    frame: u32,
+    face: u32,
+    slice: u32,
+) -> Result<TextureData, plumber_vtf::Error> {
+    let (width, height, _) = vtf.header().mipmap_dimensions(mipmap);
+
+    Ok(if !hdr {
+        let data = vtf.decode_rgba8(mipmap, frame, face, slice)?;
+
+        TextureData::Sdr(
+            RgbaImage::from_raw(width, height, data).expect("vtf should return valid images"),
+        )
+    } else {
+        let data = if compressed_hdr && vtf.format() == ImageFormat::Bgra8888 {
+            vtf.decode_compressed_hdr(mipmap, frame, face, slice)?
+        } else {
+            vtf.decode_rgba32f(mipmap, frame, face, slice)?
+        };
+
+        TextureData::Hdr(
+            Rgba32FImage::from_raw(width, height, data).expect("vtf should return valid images"),
+        )
+    })
+}
+
+/// Decoded image data of a texture.
+/// HDR and high precision textures are decoded to 32-bit floats, others to 8-bit RGBA.
+#[derive(Clone)]
+pub enum TextureData {
+    Sdr(RgbaImage),
+    Hdr(Rgba32FImage),
+}
+
+impl TextureData {
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        match self {
+            Self::Sdr(image) => image.width(),
+            Self::Hdr(image) => image.width(),
+        }
+    }
+
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        match self {
+            Self::Sdr(image) => image.height(),
+            Self::Hdr(image) => image.height(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, Self::Hdr(_))
+    }
+
+    /// Converts the image into 8-bit RGBA, clamping HDR values.
+    #[must_use]
+    pub fn to_rgba8(&self) -> RgbaImage {
+        match self {
+            Self::Sdr(image) => image.clone(),
+            Self::Hdr(image) => RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+                image::Rgba(
+                    image
+                        .get_pixel(x, y)
+                        .0
+                        .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8),
+                )
+            }),
+        }
+    }
+
+    /// Converts the image into 32-bit float RGBA, mapping SDR values to the 0 to 1 range.
+    #[must_use]
+    pub fn to_rgba32f(&self) -> Rgba32FImage {
+        match self {
+            Self::Sdr(image) => Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+                image::Rgba(image.get_pixel(x, y).0.map(|c| f32::from(c) / 255.0))
+            }),
+            Self::Hdr(image) => image.clone(),
+        }
+    }
+
+    /// Encodes the image into a VTF file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the image can't be encoded with the settings.
+    pub fn encode_vtf(&self, settings: &EncodeSettings) -> Result<Vec<u8>, plumber_vtf::Error> {
+        let image = match self {
+            Self::Sdr(image) => SourceImage::Rgba8(image.as_raw()),
+            Self::Hdr(image) => SourceImage::Rgba32F(image.as_raw()),
+        };
+
+        plumber_vtf::encode(image, self.width(), self.height(), settings)
+    }
+}
+
+impl From<RgbaImage> for TextureData {
+    fn from(image: RgbaImage) -> Self {
+        Self::Sdr(image)
+    }
+}
+
+impl From<Rgba32FImage> for TextureData {
+    fn from(image: Rgba32FImage) -> Self {
+        Self::Hdr(image)
+    }
+}
+
+impl Debug for TextureData {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Sdr(_) => f.debug_struct("Sdr").finish_non_exhaustive(),
+            Self::Hdr(_) => f.debug_struct("Hdr").finish_non_exhaustive(),
+        }
+    }
 }
 
 #[derive(Debug, Error, Clone, Hash, PartialEq, Eq)]
@@ -90,6 +294,11 @@ impl VtfError {
             error: error.into(),
         }
     }
+
+    #[must_use]
+    pub fn into_inner(self) -> VtfErrorInner {
+        self.error
+    }
 }
 
 #[derive(Debug, Error, Clone, Hash, PartialEq, Eq)]
@@ -97,7 +306,7 @@ pub enum VtfErrorInner {
     #[error("io error reading `{path}`: {error}")]
     Io { path: String, error: String },
     #[error("error loading vtf: {0}")]
-    Vtf(#[from] vtflib2::Error),
+    Vtf(#[from] plumber_vtf::Error),
 }
 
 impl VtfErrorInner {
@@ -114,6 +323,27 @@ impl VtfErrorInner {
 pub struct VtfInfo {
     pub width: u32,
     pub height: u32,
+    /// Depth of volume textures, 1 for other textures.
+    pub depth: u32,
+    pub format: ImageFormat,
+    pub flags: TextureFlags,
+    pub frames: u32,
+    /// 6 or 7 for cubemaps, 1 for other textures.
+    pub faces: u32,
+    pub mipmaps: u32,
+    pub reflectivity: [f32; 3],
+    pub bumpmap_scale: f32,
+    /// `true` if the image data is decoded as HDR.
+    pub hdr: bool,
+    /// Sprite sheet of animated particle textures.
+    pub sheet: Option<Sheet>,
+    pub lod_control: Option<LodControl>,
+    /// CRC of the source image.
+    pub crc: Option<u32>,
+    /// Extended texture settings flags.
+    pub extended_flags: Option<u32>,
+    /// Embedded key values, such as the settings the texture was compiled with.
+    pub key_values: Option<Document>,
 }
 
 #[derive(Debug, Clone)]
@@ -121,5 +351,227 @@ pub struct VtfInfo {
 pub struct LoadedVtf {
     pub name: PathBuf,
     pub info: VtfInfo,
-    pub data: RgbaImage,
+    /// The largest mipmap of the first frame, face and depth slice.
+    pub data: TextureData,
+    /// Every mipmap, frame, face and depth slice,
+    /// if `VtfConfig::load_all_images` is set.
+    pub images: Option<VtfImages>,
+    /// The raw file, if `VtfConfig::keep_file` is set.
+    bytes: Option<VtfBytes>,
+}
+
+impl LoadedVtf {
+    /// Returns the parsed VTF file for access to the raw image data,
+    /// or `None` if the file wasn't kept.
+    pub(crate) fn vtf(&self) -> Option<Vtf<'_>> {
+        self.bytes.as_ref().map(|bytes| {
+            Vtf::from_bytes(&bytes.0).expect("vtf should have been validated when loading")
+        })
+    }
+}
+
+/// The raw file contents, kept out of the `Debug` output.
+#[derive(Clone)]
+struct VtfBytes(Vec<u8>);
+
+impl Debug for VtfBytes {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<{} bytes>", self.0.len())
+    }
+}
+
+/// Every image of a texture.
+#[derive(Debug, Clone)]
+pub struct VtfImages {
+    frames: u32,
+    faces: u32,
+    /// Images of each mipmap level, ordered by frame, face and depth slice.
+    mipmaps: Vec<Vec<TextureData>>,
+}
+
+impl VtfImages {
+    fn load(vtf: &Vtf, info: &VtfInfo, compressed_hdr: bool) -> Result<Self, plumber_vtf::Error> {
+        let mipmaps = (0..info.mipmaps)
+            .map(|mipmap| {
+                let (_, _, depth) = vtf.header().mipmap_dimensions(mipmap);
+                let mut images = Vec::new();
+
+                for frame in 0..info.frames {
+                    for face in 0..info.faces {
+                        for slice in 0..depth {
+                            images.push(decode_image(
+                                vtf,
+                                info.hdr,
+                                compressed_hdr,
+                                mipmap,
+                                frame,
+                                face,
+                                slice,
+                            )?);
+                        }
+                    }
+                }
+
+                Ok(images)
+            })
+            .collect::<Result<_, plumber_vtf::Error>>()?;
+
+        Ok(Self {
+            frames: info.frames,
+            faces: info.faces,
+            mipmaps,
+        })
+    }
+
+    /// Returns the image of a mipmap level, frame, face and depth slice,
+    /// or `None` if any of them is out of range.
+    /// Mipmap level 0 is the largest.
+    #[must_use]
+    pub fn get(&self, mipmap: u32, frame: u32, face: u32, slice: u32) -> Option<&TextureData> {
+        let images = self.mipmaps.get(mipmap as usize)?;
+        let depth = images.len() as u32 / (self.frames * self.faces);
+
+        if frame >= self.frames || face >= self.faces || slice >= depth {
+            return None;
+        }
+
+        images.get(((frame * self.faces + face) * depth + slice) as usize)
+    }
+
+    /// Returns the largest mipmap of every frame of the first face and depth slice.
+    pub fn frames(&self) -> impl Iterator<Item = &TextureData> {
+        (0..self.frames).filter_map(|frame| self.get(0, frame, 0, 0))
+    }
+
+    /// Returns the largest mipmap of every face of the first frame and depth slice.
+    pub fn faces(&self) -> impl Iterator<Item = &TextureData> {
+        (0..self.faces).filter_map(|face| self.get(0, 0, face, 0))
+    }
+
+    /// Returns every mipmap level of the first frame, face and depth slice,
+    /// from the largest to the smallest.
+    pub fn mipmaps(&self) -> impl Iterator<Item = &TextureData> {
+        (0..self.mipmaps.len() as u32).filter_map(|mipmap| self.get(mipmap, 0, 0, 0))
+    }
+
+    /// Returns every depth slice of the largest mipmap of the first frame and face.
+    pub fn slices(&self) -> impl Iterator<Item = &TextureData> {
+        let depth = self.mipmaps[0].len() as u32 / (self.frames * self.faces);
+        (0..depth).filter_map(|slice| self.get(0, 0, 0, slice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor, process};
+
+    use plumber_fs::{FileSystem, GamePathBuf, SearchPath};
+
+    use super::*;
+    use crate::export::{ExportError, ExportFormat};
+
+    /// Encodes a 2x2 texture with two mipmaps, filled with the given color.
+    fn encode(color: [u8; 4]) -> Vec<u8> {
+        let pixels = color.repeat(4);
+        let mut settings = EncodeSettings::new();
+        settings.format(ImageFormat::Rgba8888);
+        settings.low_res_image(false);
+        plumber_vtf::encode(SourceImage::Rgba8(&pixels), 2, 2, &settings).unwrap()
+    }
+
+    /// Loads the texture from a temporary file system.
+    fn load(name: &str, bytes: &[u8], config: VtfConfig) -> Result<LoadedVtf, VtfError> {
+        let root = std::env::temp_dir().join(format!("plumber_vtf_{}_{name}", process::id()));
+        fs::create_dir_all(root.join("materials")).unwrap();
+        fs::write(root.join(format!("materials/{name}.vtf")), bytes).unwrap();
+
+        let file_system = FileSystem {
+            name: String::new(),
+            search_paths: vec![SearchPath::Directory(root.clone())],
+        }
+        .open()
+        .unwrap();
+
+        let loaded = config.load_vtf(
+            PathBuf::Game(GamePathBuf::from(format!("materials/{name}"))),
+            &file_system,
+        );
+        fs::remove_dir_all(root).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn keep_file() {
+        let bytes = encode([255, 0, 0, 255]);
+
+        let loaded = load("discarded", &bytes, VtfConfig::default()).unwrap();
+        assert!(loaded.bytes.is_none());
+        assert_eq!(
+            loaded.export(ExportFormat::Dds, &mut Cursor::new(Vec::new())),
+            Err(ExportError::FileNotKept(ExportFormat::Dds))
+        );
+        assert!(loaded
+            .export(ExportFormat::Tga, &mut Cursor::new(Vec::new()))
+            .is_ok());
+
+        let config = VtfConfig {
+            keep_file: true,
+            ..VtfConfig::default()
+        };
+        let loaded = load("kept", &bytes, config).unwrap();
+        assert!(loaded
+            .export(ExportFormat::Dds, &mut Cursor::new(Vec::new()))
+            .is_ok());
+    }
+
+    #[test]
+    fn multiple_frames() {
+        // two frames, stored per mipmap from the smallest to the largest
+        let red = encode([255, 0, 0, 255]);
+        let blue = encode([0, 0, 255, 255]);
+        let data_start = red.len() - (1 + 4) * 4;
+
+        let mut bytes = red[..data_start].to_vec();
+        bytes[24..26].copy_from_slice(&2_u16.to_le_bytes());
+        for (start, end) in [(data_start, data_start + 4), (data_start + 4, red.len())] {
+            bytes.extend_from_slice(&red[start..end]);
+            bytes.extend_from_slice(&blue[start..end]);
+        }
+
+        let config = VtfConfig {
+            load_all_images: true,
+            ..VtfConfig::default()
+        };
+        let loaded = load("frames", &bytes, config).unwrap();
+
+        assert_eq!(loaded.info.frames, 2);
+        let images = loaded.images.unwrap();
+
+        let color = |image: &TextureData| match image {
+            TextureData::Sdr(image) => (image.width(), image.get_pixel(0, 0).0),
+            TextureData::Hdr(_) => panic!("expected an sdr image"),
+        };
+
+        assert_eq!(
+            color(images.get(0, 0, 0, 0).unwrap()),
+            (2, [255, 0, 0, 255])
+        );
+        assert_eq!(
+            color(images.get(0, 1, 0, 0).unwrap()),
+            (2, [0, 0, 255, 255])
+        );
+        assert_eq!(
+            color(images.get(1, 1, 0, 0).unwrap()),
+            (1, [0, 0, 255, 255])
+        );
+
+        assert!(images.get(2, 0, 0, 0).is_none());
+        assert!(images.get(0, 2, 0, 0).is_none());
+        assert!(images.get(0, 0, 1, 0).is_none());
+        assert!(images.get(0, 0, 0, 1).is_none());
+
+        assert_eq!(images.frames().count(), 2);
+        assert_eq!(images.mipmaps().count(), 2);
+        assert_eq!(images.faces().count(), 1);
+    }
 }
@@ -0,0 +1,123 @@
+//! Conversions between the normal map formats used by Source.
+//!
+//! Source normal maps use the `DirectX` convention, with green pointing down.
+//! Use [`flip_green`] to convert them to the `OpenGL` convention.
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+/// The tangent space basis vectors of self-shadowing bump maps,
+/// stored in the red, green and blue channels respectively.
+const SSBUMP_BASIS: [[f32; 3]; 3] = [
+    [0.816_496_6, 0.0, 0.577_350_3],
+    [-0.408_248_3, FRAC_1_SQRT_2, 0.577_350_3],
+    [-0.408_248_3, -FRAC_1_SQRT_2, 0.577_350_3],
+];
+
+fn to_unit(value: u8) -> f32 {
+    f32::from(value) / 255.0 * 2.0 - 1.0
+}
+
+fn from_unit(value: f32) -> u8 {
+    ((value.clamp(-1.0, 1.0) * 0.5 + 0.5) * 255.0).round() as u8
+}
+
+/// Converts a self-shadowing bump map (`$ssbump`) into a tangent space normal map.
+/// The alpha channel is kept as is.
+#[must_use]
+pub fn ssbump_to_normal(image: &RgbaImage) -> RgbaImage {
+    let mut output = image.clone();
+
+    for pixel in output.pixels_mut() {
+        let weights = [pixel[0], pixel[1], pixel[2]].map(|c| f32::from(c) / 255.0);
+
+        let mut normal = [0.0; 3];
+        for (weight, basis) in weights.iter().zip(SSBUMP_BASIS) {
+            for (n, b) in normal.iter_mut().zip(basis) {
+                *n += weight * b;
+            }
+        }
+
+        let length = normal.iter().map(|n| n * n).sum::<f32>().sqrt();
+        let normal = if length > 0.0 {
+            normal.map(|n| n / length)
+        } else {
+            [0.0, 0.0, 1.0]
+        };
+
+        *pixel = Rgba([
+            from_unit(normal[0]),
+            from_unit(normal[1]),
+            from_unit(normal[2]),
+            pixel[3],
+        ]);
+    }
+
+    output
+}
+
+/// Inverts the green channel, converting between the `DirectX` and `OpenGL` conventions.
+pub fn flip_green(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        pixel[1] = 255 - pixel[1];
+    }
+}
+
+/// Reconstructs the blue channel of a two-channel normal map (`ATI2N` or `UV88`)
+/// from the red and green channels.
+pub fn reconstruct_z(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        let x = to_unit(pixel[0]);
+        let y = to_unit(pixel[1]);
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+
+        pixel[2] = from_unit(z);
+    }
+}
+
+/// Splits a mask packed in the alpha channel, such as `$normalmapalphaenvmapmask`,
+/// into a separate image. The alpha channel of the returned color image is opaque.
+#[must_use]
+pub fn split_alpha(image: &RgbaImage) -> (RgbaImage, GrayImage) {
+    let mut color = image.clone();
+    let mut mask = GrayImage::new(image.width(), image.height());
+
+    for (pixel, mask) in color.pixels_mut().zip(mask.pixels_mut()) {
+        *mask = Luma([pixel[3]]);
+        pixel[3] = 255;
+    }
+
+    (color, mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_normals() {
+        // equal weights point straight out of the surface
+        let ssbump = RgbaImage::from_pixel(1, 1, Rgba([128, 128, 128, 7]));
+        assert_eq!(
+            ssbump_to_normal(&ssbump).get_pixel(0, 0),
+            &Rgba([128, 128, 255, 7])
+        );
+
+        let mut uv = RgbaImage::from_pixel(1, 1, Rgba([128, 128, 0, 255]));
+        reconstruct_z(&mut uv);
+        assert_eq!(uv.get_pixel(0, 0), &Rgba([128, 128, 255, 255]));
+
+        flip_green(&mut uv);
+        assert_eq!(uv.get_pixel(0, 0), &Rgba([128, 127, 255, 255]));
+    }
+
+    #[test]
+    fn alpha_mask() {
+        let image = RgbaImage::from_pixel(2, 1, Rgba([1, 2, 3, 4]));
+        let (color, mask) = split_alpha(&image);
+
+        assert_eq!(color.get_pixel(1, 0), &Rgba([1, 2, 3, 255]));
+        assert_eq!(mask.get_pixel(1, 0), &Luma([4]));
+    }
+}
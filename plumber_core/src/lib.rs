@@ -8,8 +8,10 @@ pub use plumber_asset_vtf as asset_vtf;
 pub use plumber_fs as fs;
 pub use plumber_mdl as mdl;
 pub use plumber_steam as steam;
+pub use plumber_surfaceprop as surfaceprop;
 pub use plumber_uncased as uncased;
 pub use plumber_vdf as vdf;
 pub use plumber_vmf as vmf;
 pub use plumber_vmt as vmt;
 pub use plumber_vpk as vpk;
+pub use plumber_vtf as vtf;
@@ -0,0 +1,506 @@
+#![warn(clippy::all, clippy::pedantic, clippy::multiple_crate_versions)]
+
+//! Surface properties, as defined in `scripts/surfaceproperties*.txt`.
+//!
+//! Surface properties are referenced by name from materials (`$surfaceprop`)
+//! and models (bone surface properties), and define physical and audio properties of the surface.
+
+use std::{collections::BTreeMap, io};
+
+use thiserror::Error;
+use tracing::warn;
+
+use plumber_fs::{GamePath, GamePathBuf, OpenFileSystem};
+use plumber_uncased::{AsUncased, UncasedString};
+use plumber_vdf::{self as vdf, Document, Node};
+
+const MANIFEST_PATH: &str = "scripts/surfaceproperties_manifest.txt";
+const DEFAULT_SURFACE: &str = "default";
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum SurfacePropertyError {
+    #[error("io error reading `{path}`: {error}")]
+    Io { path: String, error: String },
+    #[error("error parsing `{path}`: {error}")]
+    Deserialization { path: String, error: vdf::Error },
+}
+
+impl SurfacePropertyError {
+    fn from_io(err: &io::Error, path: &GamePath) -> Self {
+        Self::Io {
+            path: path.to_string(),
+            error: err.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicsProperties {
+    /// Density in kg/m³.
+    pub density: f32,
+    pub elasticity: f32,
+    pub friction: f32,
+    pub dampening: f32,
+    /// If nonzero, the surface is treated as a hollow shell of this thickness in inches.
+    pub thickness: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioProperties {
+    pub reflectivity: f32,
+    pub hardness_factor: f32,
+    pub roughness_factor: f32,
+    pub rough_threshold: f32,
+    pub hard_threshold: f32,
+    pub hard_velocity_threshold: f32,
+}
+
+/// Sound script names played on the surface.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SoundProperties {
+    pub step_left: Option<String>,
+    pub step_right: Option<String>,
+    pub bullet_impact: Option<String>,
+    pub scrape_rough: Option<String>,
+    pub scrape_smooth: Option<String>,
+    pub impact_hard: Option<String>,
+    pub impact_soft: Option<String>,
+    pub rolling: Option<String>,
+    pub break_sound: Option<String>,
+    pub strain: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameProperties {
+    /// Game material character, for example `C` for concrete or `M` for metal.
+    pub material: char,
+    pub jump_factor: f32,
+    pub max_speed_factor: f32,
+    pub climbable: bool,
+}
+
+/// A single surface property with `base` inheritance resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurfaceProperty {
+    pub name: String,
+    /// The surface property this one inherits from, if any.
+    pub base: Option<String>,
+    pub physics: PhysicsProperties,
+    pub audio: AudioProperties,
+    pub sounds: SoundProperties,
+    pub game: GameProperties,
+}
+
+impl SurfaceProperty {
+    /// The values a surface property starts from if there is no `default` surface property,
+    /// same as in the engine.
+    fn empty(name: String) -> Self {
+        Self {
+            name,
+            base: None,
+            physics: PhysicsProperties {
+                density: 0.0,
+                elasticity: 0.0,
+                friction: 0.0,
+                dampening: 0.0,
+                thickness: 0.0,
+            },
+            audio: AudioProperties {
+                reflectivity: 0.0,
+                hardness_factor: 0.0,
+                roughness_factor: 0.0,
+                rough_threshold: 0.0,
+                hard_threshold: 0.0,
+                hard_velocity_threshold: 0.0,
+            },
+            sounds: SoundProperties::default(),
+            game: GameProperties {
+                material: '\0',
+                jump_factor: 1.0,
+                max_speed_factor: 1.0,
+                climbable: false,
+            },
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let key = key.to_ascii_lowercase();
+
+        let float = match key.as_str() {
+            "density" => &mut self.physics.density,
+            "elasticity" => &mut self.physics.elasticity,
+            "friction" => &mut self.physics.friction,
+            "dampening" => &mut self.physics.dampening,
+            "thickness" => &mut self.physics.thickness,
+            "audioreflectivity" => &mut self.audio.reflectivity,
+            "audiohardnessfactor" => &mut self.audio.hardness_factor,
+            "audioroughnessfactor" => &mut self.audio.roughness_factor,
+            "scraperoughthreshold" => &mut self.audio.rough_threshold,
+            "impacthardthreshold" => &mut self.audio.hard_threshold,
+            "audiohardminvelocity" => &mut self.audio.hard_velocity_threshold,
+            "jumpfactor" => &mut self.game.jump_factor,
+            "maxspeedfactor" => &mut self.game.max_speed_factor,
+            _ => {
+                self.set_other(&key, value);
+                return;
+            }
+        };
+
+        if let Ok(value) = value.trim().parse() {
+            *float = value;
+        } else {
+            warn!(
+                "surface property `{}`: invalid value `{}` for `{}`",
+                self.name, value, key
+            );
+        }
+    }
+
+    fn set_other(&mut self, key: &str, value: &str) {
+        let sound = match key {
+            "stepleft" => &mut self.sounds.step_left,
+            "stepright" => &mut self.sounds.step_right,
+            "bulletimpact" => &mut self.sounds.bullet_impact,
+            "scraperough" => &mut self.sounds.scrape_rough,
+            "scrapesmooth" => &mut self.sounds.scrape_smooth,
+            "impacthard" => &mut self.sounds.impact_hard,
+            "impactsoft" => &mut self.sounds.impact_soft,
+            "roll" => &mut self.sounds.rolling,
+            "break" => &mut self.sounds.break_sound,
+            "strain" => &mut self.sounds.strain,
+            "gamematerial" => {
+                if let Some(material) = value.trim().chars().next() {
+                    self.game.material = material;
+                } else {
+                    warn!("surface property `{}`: empty gamematerial", self.name);
+                }
+                return;
+            }
+            "climbable" => {
+                self.game.climbable = value.trim().parse::<f32>().is_ok_and(|v| v != 0.0);
+                return;
+            }
+            // already handled
+            "base" => return,
+            _ => {
+                warn!("surface property `{}`: unknown key `{}`", self.name, key);
+                return;
+            }
+        };
+
+        *sound = Some(value.to_owned());
+    }
+}
+
+/// Surface properties by name.
+///
+/// Like in the engine, a surface property that is defined more than once keeps its first definition,
+/// and `base` may only refer to surface properties defined before it.
+/// Surface properties without a `base` inherit from `default`.
+#[derive(Debug, Clone, Default)]
+pub struct SurfacePropertyDatabase {
+    properties: Vec<SurfaceProperty>,
+    indices: BTreeMap<UncasedString, usize>,
+}
+
+impl SurfacePropertyDatabase {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads `scripts/surfaceproperties_manifest.txt` and all files it lists.
+    /// Listed files that can't be read or parsed are skipped with a warning.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the manifest can't be read or parsed.
+    pub fn load(fs: &OpenFileSystem) -> Result<Self, SurfacePropertyError> {
+        let manifest = read_document(fs, &GamePathBuf::from(MANIFEST_PATH))?;
+
+        let mut database = Self::new();
+
+        let files = manifest
+            .pairs()
+            .filter_map(|pair| pair.value.as_block())
+            .flat_map(|block| block.get_all("file"))
+            .filter_map(Node::as_str);
+
+        for file in files {
+            let path = GamePathBuf::from(file);
+
+            match read_document(fs, &path) {
+                Ok(document) => database.add_document(&document),
+                Err(err) => warn!("skipping surface property file: {}", err),
+            }
+        }
+
+        Ok(database)
+    }
+
+    /// Adds the surface properties in a `surfaceproperties*.txt` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the parsing fails.
+    pub fn add_file(&mut self, contents: &[u8]) -> vdf::Result<()> {
+        let document = Document::escaped_from_bytes(contents)?;
+        self.add_document(&document);
+        Ok(())
+    }
+
+    fn add_document(&mut self, document: &Document) {
+        for pair in document.pairs() {
+            if !applies_on_pc(pair.conditional.as_deref()) {
+                continue;
+            }
+
+            let Some(block) = pair.value.as_block() else {
+                continue;
+            };
+
+            if self.indices.contains_key(pair.key.as_uncased()) {
+                continue;
+            }
+
+            let property = self.parse_property(&pair.key, block);
+            self.indices
+                .insert(pair.key.as_str().into(), self.properties.len());
+            self.properties.push(property);
+        }
+    }
+
+    fn parse_property(&self, name: &str, block: &Document) -> SurfaceProperty {
+        let base = block.get("base").and_then(Node::as_str);
+
+        let inherited = match base {
+            Some(base) => self.get(base).or_else(|| {
+                warn!(
+                    "surface property `{}`: base `{}` not found, using `{}`",
+                    name, base, DEFAULT_SURFACE
+                );
+                self.get(DEFAULT_SURFACE)
+            }),
+            None => self.get(DEFAULT_SURFACE),
+        };
+
+        let mut property = match inherited {
+            Some(inherited) => SurfaceProperty {
+                name: name.to_owned(),
+                ..inherited.clone()
+            },
+            None => SurfaceProperty::empty(name.to_owned()),
+        };
+        property.base = base.map(ToOwned::to_owned);
+
+        for pair in block.pairs() {
+            if !applies_on_pc(pair.conditional.as_deref()) {
+                continue;
+            }
+
+            if let Some(value) = pair.value.as_str() {
+                property.set(&pair.key, value);
+            }
+        }
+
+        property
+    }
+
+    /// Returns the surface property with the given name, compared case-insensitively.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&SurfaceProperty> {
+        self.indices
+            .get(name.as_uncased())
+            .map(|&index| &self.properties[index])
+    }
+
+    /// Returns the surface property with the given name,
+    /// or the `default` surface property if it's not found, like the engine does.
+    #[must_use]
+    pub fn get_or_default(&self, name: &str) -> Option<&SurfaceProperty> {
+        self.get(name).or_else(|| self.get(DEFAULT_SURFACE))
+    }
+
+    /// Returns an iterator over the surface properties in definition order.
+    pub fn iter(&self) -> impl Iterator<Item = &SurfaceProperty> {
+        self.properties.iter()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.properties.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty()
+    }
+}
+
+fn read_document(fs: &OpenFileSystem, path: &GamePath) -> Result<Document, SurfacePropertyError> {
+    let contents = fs
+        .read(path)
+        .map_err(|err| SurfacePropertyError::from_io(&err, path))?;
+
+    Document::escaped_from_bytes(&contents).map_err(|error| SurfacePropertyError::Deserialization {
+        path: path.to_string(),
+        error,
+    })
+}
+
+/// Returns `true` if a pair with the conditional is used on PC.
+fn applies_on_pc(conditional: Option<&str>) -> bool {
+    let Some(conditional) = conditional else {
+        return true;
+    };
+
+    let (negated, conditional) = match conditional.trim().strip_prefix('!') {
+        Some(conditional) => (true, conditional),
+        None => (false, conditional.trim()),
+    };
+
+    let is_pc = matches!(
+        conditional.to_ascii_lowercase().as_str(),
+        "$win32" | "$windows" | "$osx" | "$linux" | "$posix"
+    );
+
+    is_pc != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use plumber_fs::{FileSystem, SearchPath};
+
+    use super::*;
+
+    const SURFACE_PROPERTIES: &str = r#"
+"default"
+{
+    "density"       "2000"
+    "elasticity"    "0.25"
+    "friction"      "0.8"
+    "dampening"     "0.0"
+    "stepleft"      "Default.StepLeft"
+    "stepright"     "Default.StepRight"
+    "gamematerial"  "C"
+}
+
+"metal"
+{
+    "base"          "default"
+    "density"       "2700"
+    "elasticity"    "0.1"
+    "stepleft"      "SolidMetal.StepLeft"
+    "gamematerial"  "M"
+}
+
+"metal_box"
+{
+    "base"          "metal"
+    "thickness"     "0.1"
+    "friction"      "0.5" [$X360]
+}
+
+"dirt"
+{
+    "friction"      "0.9"
+    "climbable"     "1"
+}
+
+"Metal"
+{
+    "density"       "1"
+}
+"#;
+
+    #[test]
+    fn inheritance() {
+        let mut database = SurfacePropertyDatabase::new();
+        database.add_file(SURFACE_PROPERTIES.as_bytes()).unwrap();
+
+        assert_eq!(database.len(), 4);
+
+        let metal_box = database.get("METAL_BOX").unwrap();
+        assert_eq!(metal_box.base.as_deref(), Some("metal"));
+        assert!((metal_box.physics.density - 2700.0).abs() < f32::EPSILON);
+        assert!((metal_box.physics.friction - 0.8).abs() < f32::EPSILON);
+        assert!((metal_box.physics.thickness - 0.1).abs() < f32::EPSILON);
+        assert_eq!(metal_box.game.material, 'M');
+        assert_eq!(
+            metal_box.sounds.step_left.as_deref(),
+            Some("SolidMetal.StepLeft")
+        );
+        assert_eq!(
+            metal_box.sounds.step_right.as_deref(),
+            Some("Default.StepRight")
+        );
+
+        let dirt = database.get("dirt").unwrap();
+        assert!(dirt.base.is_none());
+        assert!((dirt.physics.density - 2000.0).abs() < f32::EPSILON);
+        assert!(dirt.game.climbable);
+
+        assert_eq!(database.get_or_default("missing").unwrap().name, "default");
+    }
+
+    const MANIFEST: &str = r#"
+"surfaceproperties_manifest"
+{
+    "file"  "scripts/surfaceproperties.txt"
+    "file"  "scripts/surfaceproperties_game.txt"
+    "file"  "scripts/missing.txt"
+}
+"#;
+
+    const GAME_SURFACE_PROPERTIES: &str = r#"
+"metal"
+{
+    "density"       "1"
+}
+
+"metal_grate"
+{
+    "base"          "metal"
+    "elasticity"    "0.5"
+}
+"#;
+
+    #[test]
+    fn manifest() {
+        let root = std::env::temp_dir().join(format!("plumber_surfaceprop_{}", process::id()));
+        let files = [
+            ("surfaceproperties_manifest.txt", MANIFEST),
+            ("surfaceproperties.txt", SURFACE_PROPERTIES),
+            ("surfaceproperties_game.txt", GAME_SURFACE_PROPERTIES),
+        ];
+        fs::create_dir_all(root.join("scripts")).unwrap();
+        for (name, contents) in files {
+            fs::write(root.join("scripts").join(name), contents).unwrap();
+        }
+
+        let file_system = FileSystem {
+            name: "surfaceprop".to_owned(),
+            search_paths: vec![SearchPath::Directory(root.clone())],
+        }
+        .open()
+        .unwrap();
+        let database = SurfacePropertyDatabase::load(&file_system);
+        let _ = fs::remove_dir_all(&root);
+        let database = database.unwrap();
+
+        assert_eq!(database.len(), 5);
+
+        // the first definition wins, the later file can't override it
+        let metal = database.get("metal").unwrap();
+        assert!((metal.physics.density - 2700.0).abs() < f32::EPSILON);
+
+        // bases are inherited from earlier files
+        let grate = database.get("metal_grate").unwrap();
+        assert_eq!(grate.base.as_deref(), Some("metal"));
+        assert!((grate.physics.density - 2700.0).abs() < f32::EPSILON);
+        assert!((grate.physics.elasticity - 0.5).abs() < f32::EPSILON);
+        assert_eq!(grate.game.material, 'M');
+    }
+}
@@ -107,10 +107,7 @@ impl<'de> Deserializer<'de> {
     #[must_use]
     pub fn get_position(&self) -> Position {
         let offset = self.original_input.offset(self.input);
-        let prefix = &self.original_input[..offset];
-        let line = bytecount::count(prefix, b'\n') + 1;
-        let column = prefix.iter().rev().position(|&b| b == b'\n').unwrap_or(0) + 1;
-        Position { line, column }
+        Position::from_offset(self.original_input, offset)
     }
 
     fn parse<O, P>(
@@ -0,0 +1,361 @@
+//! Order-preserving VDF document model.
+//!
+//! Unlike [`Value`](crate::Value), a [`Document`] keeps duplicate keys, key order,
+//! conditionals (`[$WIN32]`) and comments,
+//! so a file can be read and written back without losing anything.
+
+mod parser;
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use super::{
+    error::Result,
+    format::{write_document, FormatOptions},
+    value::Value,
+};
+
+/// A block of VDF items.
+/// Used both for the root of a file and for nested blocks.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Document {
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Pair(Pair),
+    /// A comment on its own line, without the `//` or `/* */` delimiters.
+    Comment(String, CommentKind),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentKind {
+    /// A `//` comment, ending at the end of the line.
+    Line,
+    /// A `/* */` comment, which can span multiple lines.
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pair {
+    pub key: String,
+    pub value: Node,
+    /// Conditional without the surrounding brackets, for example `$X360`.
+    pub conditional: Option<String>,
+    /// Comment on the same line after the value, without the leading `//`.
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    String(String),
+    Block(Document),
+}
+
+impl Document {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Errors
+    ///
+    /// Returns `Err` if the parsing fails.
+    pub fn from_str(input: &str) -> Result<Self> {
+        parser::parse(input.as_bytes(), false)
+    }
+
+    /// # Errors
+    ///
+    /// Returns `Err` if the parsing fails.
+    pub fn from_bytes(input: &[u8]) -> Result<Self> {
+        parser::parse(input, false)
+    }
+
+    /// # Errors
+    ///
+    /// Returns `Err` if the parsing fails.
+    pub fn escaped_from_str(input: &str) -> Result<Self> {
+        parser::parse(input.as_bytes(), true)
+    }
+
+    /// # Errors
+    ///
+    /// Returns `Err` if the parsing fails.
+    pub fn escaped_from_bytes(input: &[u8]) -> Result<Self> {
+        parser::parse(input, true)
+    }
+
+    /// Formats the document using the given options.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if a key or a value contains a `"` and escaping is not enabled.
+    pub fn to_string_with_options(&self, options: &FormatOptions) -> Result<String> {
+        let mut output = String::new();
+        write_document(&mut output, self, options)?;
+        Ok(output)
+    }
+
+    /// Appends a pair without a conditional or a comment.
+    pub fn push(&mut self, key: impl Into<String>, value: impl Into<Node>) {
+        self.items.push(Item::Pair(Pair::new(key, value)));
+    }
+
+    /// Returns an iterator over the pairs, skipping comments.
+    pub fn pairs(&self) -> impl Iterator<Item = &Pair> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Pair(pair) => Some(pair),
+            Item::Comment(..) => None,
+        })
+    }
+
+    /// Returns the value of the first pair with the given key.
+    /// The key is compared case-insensitively, like the engine does.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        self.pairs()
+            .find(|pair| pair.key.eq_ignore_ascii_case(key))
+            .map(|pair| &pair.value)
+    }
+
+    /// Returns the values of all pairs with the given key, in order.
+    /// The key is compared case-insensitively, like the engine does.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a Node> + 'a {
+        self.pairs()
+            .filter(move |pair| pair.key.eq_ignore_ascii_case(key))
+            .map(|pair| &pair.value)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl Pair {
+    #[must_use]
+    pub fn new(key: impl Into<String>, value: impl Into<Node>) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+            conditional: None,
+            comment: None,
+        }
+    }
+}
+
+impl Node {
+    /// Returns `true` if the node is a string.
+    #[must_use]
+    pub fn is_string(&self) -> bool {
+        matches!(self, Self::String(..))
+    }
+
+    /// Returns `true` if the node is a block.
+    #[must_use]
+    pub fn is_block(&self) -> bool {
+        matches!(self, Self::Block(..))
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        if let Self::String(v) = self {
+            Some(v)
+        } else {
+            None
+        }
+    }
+
+    #[must_use]
+    pub fn as_block(&self) -> Option<&Document> {
+        if let Self::Block(v) = self {
+            Some(v)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<String> for Node {
+    fn from(v: String) -> Self {
+        Self::String(v)
+    }
+}
+
+impl From<&str> for Node {
+    fn from(v: &str) -> Self {
+        Self::String(v.into())
+    }
+}
+
+impl From<Document> for Node {
+    fn from(v: Document) -> Self {
+        Self::Block(v)
+    }
+}
+
+impl From<Value> for Node {
+    fn from(v: Value) -> Self {
+        match v {
+            Value::String(string) => Self::String(string),
+            Value::Class(class) => Self::Block(Document {
+                items: class
+                    .into_iter()
+                    .map(|(key, value)| Item::Pair(Pair::new(key, value)))
+                    .collect(),
+            }),
+        }
+    }
+}
+
+/// Drops comments and conditionals.
+/// Like when deserializing a [`Value`], later duplicate keys replace earlier ones.
+impl From<Node> for Value {
+    fn from(v: Node) -> Self {
+        match v {
+            Node::String(string) => Self::String(string),
+            Node::Block(block) => Self::Class(
+                block
+                    .items
+                    .into_iter()
+                    .filter_map(|item| match item {
+                        Item::Pair(pair) => Some((pair.key, pair.value.into())),
+                        Item::Comment(..) => None,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl Serialize for Document {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        for pair in self.pairs() {
+            map.serialize_entry(&pair.key, &pair.value)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Node {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Node::String(str) => str.serialize(serializer),
+            Node::Block(block) => block.serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = r#"// leading comment
+"LightmappedGeneric"
+{
+	"$basetexture" "concrete/concrete01" // trailing
+	$surfaceprop concrete
+	"$envmap" "env_cubemap" [$WIN32]
+	"$envmap" "" [$X360]
+	">=dx90"
+	{
+		"$detail" "detail/noise"
+	}
+	"Proxies"
+	{
+		"Sine" { "resultVar" "$alpha" }
+		"Sine" { "resultVar" "$color" }
+	}
+}
+"#;
+
+    #[test]
+    fn parsing_preserves_everything() {
+        let document = Document::from_str(INPUT).unwrap();
+
+        assert_eq!(
+            document.items[0],
+            Item::Comment(" leading comment".into(), CommentKind::Line)
+        );
+
+        let shader = document
+            .get("lightmappedgeneric")
+            .unwrap()
+            .as_block()
+            .unwrap();
+        let first = shader.pairs().next().unwrap();
+        assert_eq!(first.key, "$basetexture");
+        assert_eq!(first.comment.as_deref(), Some(" trailing"));
+        assert_eq!(
+            shader.get("$surfaceprop").and_then(Node::as_str),
+            Some("concrete")
+        );
+
+        let envmaps = shader
+            .pairs()
+            .filter(|pair| pair.key == "$envmap")
+            .map(|pair| pair.conditional.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(envmaps, vec![Some("$WIN32"), Some("$X360")]);
+
+        let proxies = shader.get("proxies").unwrap().as_block().unwrap();
+        assert_eq!(proxies.get_all("sine").count(), 2);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let document = Document::from_str(INPUT).unwrap();
+        let output = document
+            .to_string_with_options(&FormatOptions::default())
+            .unwrap();
+        assert_eq!(Document::from_str(&output).unwrap(), document);
+    }
+
+    #[test]
+    fn block_comment_roundtrip() {
+        let input = "/* first line\nsecond line */\nkey value\n/* a */ other \"value\"\n";
+        let document = Document::from_str(input).unwrap();
+
+        assert_eq!(
+            document.items[0],
+            Item::Comment(" first line\nsecond line ".into(), CommentKind::Block)
+        );
+        assert_eq!(document.pairs().count(), 2);
+
+        let output = document
+            .to_string_with_options(&FormatOptions::default())
+            .unwrap();
+        assert_eq!(Document::from_str(&output).unwrap(), document);
+    }
+
+    #[test]
+    fn block_pair_comment_roundtrip() {
+        let input = "shader\n{\n\tProxies [$WIN32]\n\t{\n\t\tSine { resultVar $alpha }\n\t} // proxies\n}\n";
+        let document = Document::from_str(input).unwrap();
+
+        let shader = document.get("shader").unwrap().as_block().unwrap();
+        let proxies = shader.pairs().next().unwrap();
+        assert_eq!(proxies.comment.as_deref(), Some(" proxies"));
+        assert_eq!(proxies.conditional.as_deref(), Some("$WIN32"));
+
+        let output = document
+            .to_string_with_options(&FormatOptions::default())
+            .unwrap();
+        assert_eq!(Document::from_str(&output).unwrap(), document);
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        assert!(Document::from_str("key \"value").is_err());
+        assert!(Document::escaped_from_str("key \"abc\\").is_err());
+        assert!(Document::from_str("}").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Lossless parser for [`Document`], built on the same token parsers as the deserializer.
+
+use std::str;
+
+use nom::{
+    character::complete::{multispace0, space0},
+    IResult,
+};
+
+use crate::{
+    error::{Error, Position, Reason, Result},
+    escape::maybe_unescape_str,
+    parsers,
+};
+
+use super::{CommentKind, Document, Item, Node, Pair};
+
+pub(crate) fn parse(input: &[u8], escaped: bool) -> Result<Document> {
+    let mut parser = Parser {
+        input,
+        offset: 0,
+        remaining_depth: 128,
+        escaped,
+    };
+    parser.block(false)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    offset: usize,
+    remaining_depth: u8,
+    escaped: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, reason: Reason) -> Error {
+        Error::new(reason).at(Position::from_offset(self.input, self.offset))
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.input[self.offset..]
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.offset).copied()
+    }
+
+    /// Runs `parser` on the remaining input, advancing past what it consumed if it succeeds.
+    fn run<O>(&mut self, mut parser: impl FnMut(&'a [u8]) -> IResult<&'a [u8], O>) -> Option<O> {
+        let (rest, output) = parser(self.rest()).ok()?;
+        self.offset = self.input.len() - rest.len();
+        Some(output)
+    }
+
+    fn skip_inline_space(&mut self) {
+        self.run(space0);
+    }
+
+    fn skip_space(&mut self) {
+        self.run(multispace0);
+    }
+
+    /// Parses a comment if there is one at the current position.
+    fn comment(&mut self) -> Result<Option<(String, CommentKind)>> {
+        let (comment, kind) = if let Some(comment) = self.run(parsers::singleline_comment) {
+            (comment, CommentKind::Line)
+        } else if let Some(comment) = self.run(parsers::multiline_comment) {
+            (comment, CommentKind::Block)
+        } else if self.rest().starts_with(b"/*") {
+            return Err(self.error(Reason::UnexpectedEof));
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some((str::from_utf8(comment)?.to_owned(), kind)))
+    }
+
+    fn block(&mut self, nested: bool) -> Result<Document> {
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_space();
+
+            if let Some((comment, kind)) = self.comment()? {
+                items.push(Item::Comment(comment, kind));
+                continue;
+            }
+
+            match self.peek() {
+                // be forgiving about unclosed blocks at the end of the file
+                None => return Ok(Document { items }),
+                Some(b'}') => {
+                    if nested {
+                        self.offset += 1;
+                        return Ok(Document { items });
+                    }
+                    return Err(self.error(Reason::ExpectedValue));
+                }
+                Some(_) => self.pair(&mut items)?,
+            }
+        }
+    }
+
+    fn pair(&mut self, items: &mut Vec<Item>) -> Result<()> {
+        let key = self.token(false)?;
+        let mut conditional = None;
+
+        // the value may be on a later line, possibly after comments and a conditional
+        let value = loop {
+            self.skip_space();
+
+            if let Some((comment, kind)) = self.comment()? {
+                items.push(Item::Comment(comment, kind));
+                continue;
+            }
+
+            match self.peek() {
+                None => return Err(self.error(Reason::UnexpectedEof)),
+                Some(b'}') => return Err(self.error(Reason::ExpectedValue)),
+                Some(b'[') if conditional.is_none() => conditional = Some(self.conditional()?),
+                Some(b'{') => {
+                    self.offset += 1;
+                    self.remaining_depth -= 1;
+                    if self.remaining_depth == 0 {
+                        return Err(self.error(Reason::Recursion));
+                    }
+                    let block = self.block(true)?;
+                    self.remaining_depth += 1;
+                    break Node::Block(block);
+                }
+                Some(_) => break Node::String(self.token(true)?),
+            }
+        };
+
+        self.skip_inline_space();
+        if conditional.is_none() && self.peek() == Some(b'[') {
+            conditional = Some(self.conditional()?);
+            self.skip_inline_space();
+        }
+
+        let comment = self
+            .run(parsers::singleline_comment)
+            .map(str::from_utf8)
+            .transpose()?
+            .map(str::to_owned);
+
+        items.push(Item::Pair(Pair {
+            key,
+            value,
+            conditional,
+            comment,
+        }));
+
+        Ok(())
+    }
+
+    fn conditional(&mut self) -> Result<String> {
+        let conditional = self
+            .run(parsers::conditional)
+            .ok_or_else(|| self.error(Reason::ExpectedClosingSquareBracket))?;
+        Ok(str::from_utf8(conditional)?.trim().to_owned())
+    }
+
+    fn token(&mut self, is_value: bool) -> Result<String> {
+        let token = if self.peek() == Some(b'"') {
+            let token = if self.escaped {
+                self.run(parsers::escaped_quoted_token)
+            } else {
+                self.run(parsers::quoted_token)
+            };
+            token.ok_or_else(|| self.error(Reason::UnterminatedString))?
+        } else if is_value {
+            self.run(parsers::unquoted_value)
+                .ok_or_else(|| self.error(Reason::ExpectedValue))?
+        } else {
+            self.run(parsers::unquoted_key)
+                .ok_or_else(|| self.error(Reason::ExpectedValue))?
+        };
+
+        let token = if self.escaped {
+            maybe_unescape_str(token)
+        } else {
+            token.into()
+        };
+
+        Ok(String::from_utf8(token.into_owned())?)
+    }
+}
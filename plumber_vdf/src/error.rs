@@ -25,6 +25,10 @@ pub enum Reason {
     ExpectedClosingBracket,
     #[error("expected a newline")]
     ExpectedNewline,
+    #[error("unterminated string")]
+    UnterminatedString,
+    #[error("expected a `]`")]
+    ExpectedClosingSquareBracket,
     #[error("invalid int")]
     InvalidInt,
     #[error("invalid float")]
@@ -41,6 +45,18 @@ pub enum Reason {
     InvalidUtf8,
     #[error("recursion limit exceeded")]
     Recursion,
+    #[error("root value must be a class")]
+    RootMustBeClass,
+    #[cfg(feature = "json")]
+    #[error("conditionals must be strings matching their values")]
+    InvalidConditional,
+    #[cfg(feature = "json")]
+    #[error("key order must list every key of the object")]
+    InvalidKeyOrder,
+    #[error("bytes can't be serialized")]
+    UnsupportedBytes,
+    #[error("`\"` can't be written without escaping")]
+    UnescapedQuote,
     #[error("{0}")]
     Custom(String),
 }
@@ -51,6 +67,15 @@ pub struct Position {
     pub column: usize,
 }
 
+impl Position {
+    pub(crate) fn from_offset(input: &[u8], offset: usize) -> Self {
+        let prefix = &input[..offset];
+        let line = bytecount::count(prefix, b'\n') + 1;
+        let column = prefix.iter().rev().position(|&b| b == b'\n').unwrap_or(0) + 1;
+        Self { line, column }
+    }
+}
+
 impl Display for Position {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "line {}, column {}", self.line, self.column)
@@ -82,11 +107,15 @@ impl Error {
         }
     }
 
-    #[must_use]
-    pub fn with_position(mut self, deserializer: &Deserializer) -> Self {
-        self.position = Some(deserializer.get_position());
+    pub(crate) fn at(mut self, position: Position) -> Self {
+        self.position = Some(position);
         self
     }
+
+    #[must_use]
+    pub fn with_position(self, deserializer: &Deserializer) -> Self {
+        self.at(deserializer.get_position())
+    }
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,363 @@
+use serde::Serialize;
+
+use super::{
+    document::{CommentKind, Document, Item, Node, Pair},
+    error::{Error, Reason, Result},
+    escape::write_escape_str,
+    ser::to_document,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indentation {
+    Tabs,
+    Spaces(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quoting {
+    /// Quote every key and value.
+    Always,
+    /// Quote only keys and values that would not parse back unquoted.
+    Minimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comments {
+    Keep,
+    Drop,
+}
+
+impl Comments {
+    #[must_use]
+    pub fn keep(self) -> bool {
+        matches!(self, Self::Keep)
+    }
+
+    #[must_use]
+    pub fn drop(self) -> bool {
+        matches!(self, Self::Drop)
+    }
+}
+
+/// Output style for [`to_string_with_options`] and [`Document::to_string_with_options`].
+///
+/// The default style is the same as [`to_string`](crate::to_string) uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FormatOptions {
+    pub indentation: Indentation,
+    /// Pad keys so that the values in a block start at the same column.
+    pub align_values: bool,
+    pub quoting: Quoting,
+    pub line_ending: LineEnding,
+    /// Escape `"`, `\`, tabs and newlines, like [`escaped_to_string`](crate::escaped_to_string).
+    pub escaped: bool,
+    pub comments: Comments,
+}
+
+impl FormatOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn indentation(&mut self, indentation: Indentation) {
+        self.indentation = indentation;
+    }
+
+    pub fn align_values(&mut self, align_values: bool) {
+        self.align_values = align_values;
+    }
+
+    pub fn quoting(&mut self, quoting: Quoting) {
+        self.quoting = quoting;
+    }
+
+    pub fn line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    pub fn escaped(&mut self, escaped: bool) {
+        self.escaped = escaped;
+    }
+
+    pub fn comments(&mut self, comments: Comments) {
+        self.comments = comments;
+    }
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indentation: Indentation::Tabs,
+            align_values: false,
+            quoting: Quoting::Always,
+            line_ending: LineEnding::Lf,
+            escaped: false,
+            comments: Comments::Keep,
+        }
+    }
+}
+
+/// # Errors
+///
+/// Returns `Err` if the serialization fails.
+pub fn to_string_with_options<T>(value: &T, options: &FormatOptions) -> Result<String>
+where
+    T: Serialize + ?Sized,
+{
+    to_document(value)?.to_string_with_options(options)
+}
+
+pub(crate) fn write_document(
+    output: &mut String,
+    document: &Document,
+    options: &FormatOptions,
+) -> Result<()> {
+    Writer { output, options }.write_block(document, 0)
+}
+
+struct Writer<'a> {
+    output: &'a mut String,
+    options: &'a FormatOptions,
+}
+
+impl Writer<'_> {
+    fn newline(&mut self) {
+        *self.output += self.options.line_ending.as_str();
+    }
+
+    fn indent(&mut self, depth: usize) {
+        match self.options.indentation {
+            Indentation::Tabs => self.output.extend((0..depth).map(|_| '\t')),
+            Indentation::Spaces(width) => self.output.extend((0..depth * width).map(|_| ' ')),
+        }
+    }
+
+    fn token(&self, token: &str) -> Result<String> {
+        if !self.options.escaped && token.contains('"') {
+            return Err(Error::new(Reason::UnescapedQuote));
+        }
+
+        let mut output = String::with_capacity(token.len() + 2);
+
+        let quote = match self.options.quoting {
+            Quoting::Always => true,
+            Quoting::Minimal => needs_quotes(token),
+        };
+
+        if quote {
+            output.push('"');
+        }
+        if self.options.escaped {
+            write_escape_str(token, &mut output);
+        } else {
+            output += token;
+        }
+        if quote {
+            output.push('"');
+        }
+
+        Ok(output)
+    }
+
+    fn write_block(&mut self, document: &Document, depth: usize) -> Result<()> {
+        let mut value_column = 0;
+        if self.options.align_values {
+            for pair in document.pairs().filter(|pair| pair.value.is_string()) {
+                value_column = value_column.max(self.token(&pair.key)?.chars().count());
+            }
+        }
+
+        for item in &document.items {
+            match item {
+                Item::Pair(pair) => self.write_pair(pair, depth, value_column)?,
+                Item::Comment(comment, kind) => {
+                    if self.options.comments.keep() {
+                        self.write_comment(comment, *kind, depth);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_comment(&mut self, comment: &str, kind: CommentKind, depth: usize) {
+        match kind {
+            CommentKind::Line => {
+                // a line comment can't continue on the next line
+                for line in comment.lines() {
+                    self.indent(depth);
+                    *self.output += "//";
+                    *self.output += line;
+                    self.newline();
+                }
+            }
+            CommentKind::Block => {
+                self.indent(depth);
+                *self.output += "/*";
+                *self.output += comment;
+                *self.output += "*/";
+                self.newline();
+            }
+        }
+    }
+
+    fn write_pair(&mut self, pair: &Pair, depth: usize, value_column: usize) -> Result<()> {
+        self.indent(depth);
+        let key = self.token(&pair.key)?;
+        *self.output += &key;
+
+        match &pair.value {
+            Node::String(value) => {
+                let padding = value_column.saturating_sub(key.chars().count()) + 1;
+                self.output.extend((0..padding).map(|_| ' '));
+                let value = self.token(value)?;
+                *self.output += &value;
+                self.write_conditional(pair);
+                self.write_pair_comment(pair);
+                self.newline();
+            }
+            Node::Block(block) => {
+                self.write_conditional(pair);
+                self.newline();
+                self.indent(depth);
+                self.output.push('{');
+                self.newline();
+                self.write_block(block, depth + 1)?;
+                self.indent(depth);
+                self.output.push('}');
+                // the parser attaches the comment after the closing brace to the pair
+                self.write_pair_comment(pair);
+                self.newline();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_conditional(&mut self, pair: &Pair) {
+        if let Some(conditional) = &pair.conditional {
+            *self.output += " [";
+            *self.output += conditional;
+            self.output.push(']');
+        }
+    }
+
+    fn write_pair_comment(&mut self, pair: &Pair) {
+        if let Some(comment) = &pair.comment {
+            if self.options.comments.keep() {
+                *self.output += " //";
+                *self.output += comment;
+            }
+        }
+    }
+}
+
+fn needs_quotes(token: &str) -> bool {
+    token.is_empty()
+        || token.starts_with('[')
+        || token.contains("//")
+        || token.contains("/*")
+        || token
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '{' | '}'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::Value;
+
+    #[test]
+    fn default_style() {
+        let mut inner = BTreeMap::new();
+        inner.insert("key3".to_owned(), Value::String("value3".into()));
+        let mut value = BTreeMap::new();
+        value.insert("key1".to_owned(), Value::String("value1".into()));
+        value.insert("key2".to_owned(), Value::Class(inner));
+
+        assert_eq!(
+            to_string_with_options(&value, &FormatOptions::default()).unwrap(),
+            "\"key1\" \"value1\"\n\"key2\"\n{\n\t\"key3\" \"value3\"\n}\n",
+        );
+    }
+
+    #[test]
+    fn custom_style() {
+        let document = Document::from_str(
+            "// header\nshader {\n\t\"$basetexture\" \"a/b\" // base\n\t$alpha 1\n\t$translucent \"\"\n}\n",
+        )
+        .unwrap();
+
+        let mut options = FormatOptions::new();
+        options.indentation(Indentation::Spaces(2));
+        options.align_values(true);
+        options.quoting(Quoting::Minimal);
+        options.line_ending(LineEnding::CrLf);
+        options.comments(Comments::Drop);
+
+        assert_eq!(
+            document.to_string_with_options(&options).unwrap(),
+            "shader\r\n{\r\n  $basetexture a/b\r\n  $alpha       1\r\n  $translucent \"\"\r\n}\r\n",
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let mut document = Document::new();
+        document.push("key", "a \"quoted\" value");
+
+        let mut options = FormatOptions::new();
+        options.escaped(true);
+
+        let output = document.to_string_with_options(&options).unwrap();
+        assert_eq!(output, "\"key\" \"a \\\"quoted\\\" value\"\n");
+        assert_eq!(Document::escaped_from_str(&output).unwrap(), document);
+    }
+
+    #[test]
+    fn minimal_quoting() {
+        let mut document = Document::new();
+        document.push("key", "a/*b");
+        document.push("other", "a//b");
+
+        let mut options = FormatOptions::new();
+        options.quoting(Quoting::Minimal);
+
+        let output = document.to_string_with_options(&options).unwrap();
+        assert_eq!(output, "key \"a/*b\"\nother \"a//b\"\n");
+        assert_eq!(Document::from_str(&output).unwrap(), document);
+    }
+
+    #[test]
+    fn unescaped_quote() {
+        let mut document = Document::new();
+        document.push("key", "a \"quoted\" value");
+
+        assert_eq!(
+            document.to_string_with_options(&FormatOptions::default()),
+            Err(Error::new(Reason::UnescapedQuote))
+        );
+    }
+}
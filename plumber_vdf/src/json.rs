@@ -0,0 +1,337 @@
+//! Conversion between VDF and JSON.
+//!
+//! Blocks become JSON objects with their keys in the original order.
+//! Keys that appear multiple times in a block become arrays.
+//! Conditionals are stored in the containing object under [`CONDITIONALS_KEY`],
+//! as a string for a single value or as an array with `null` for unconditional values.
+//! If keys that appear multiple times are interleaved with other keys,
+//! the original order of the keys is stored under [`ORDER_KEY`].
+//! Keys starting with `[` get an extra `[` in front, so they can't collide with the reserved keys.
+//! Comments are not kept.
+//!
+//! ```json
+//! {
+//!     "$envmap": ["env_cubemap", ""],
+//!     "[conditionals]": { "$envmap": [null, "$X360"] }
+//! }
+//! ```
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use serde_json::{Map, Value as Json};
+
+use super::{
+    document::{Document, Item, Node, Pair},
+    error::{Error, Reason, Result},
+    value::Value,
+};
+
+/// Reserved key used for storing conditionals in JSON objects.
+pub const CONDITIONALS_KEY: &str = "[conditionals]";
+
+/// Reserved key used for storing the order of interleaved duplicate keys in JSON objects.
+pub const ORDER_KEY: &str = "[order]";
+
+/// Converts a document into JSON.
+#[must_use]
+pub fn to_json(document: &Document) -> Json {
+    Json::Object(block_to_json(document))
+}
+
+/// Converts JSON created by [`to_json`] back into a document.
+/// Numbers are converted into strings, booleans into `1` or `0` and `null` into an empty string.
+///
+/// # Errors
+///
+/// Returns `Err` if the JSON is not an object, contains nested arrays
+/// or has conditionals or a key order that don't match the values.
+pub fn from_json(json: &Json) -> Result<Document> {
+    match json {
+        Json::Object(object) => block_from_json(object),
+        _ => Err(Error::new(Reason::RootMustBeClass)),
+    }
+}
+
+/// Converts a [`Value`] into JSON.
+#[must_use]
+pub fn value_to_json(value: &Value) -> Json {
+    node_to_json(&value.clone().into())
+}
+
+/// Converts JSON into a [`Value`].
+/// Conditionals are dropped and later array elements replace earlier ones.
+///
+/// # Errors
+///
+/// Returns `Err` if the JSON contains nested arrays or invalid conditionals.
+pub fn value_from_json(json: &Json) -> Result<Value> {
+    node_from_json(json).map(Value::from)
+}
+
+fn node_to_json(node: &Node) -> Json {
+    match node {
+        Node::String(string) => Json::String(string.clone()),
+        Node::Block(block) => Json::Object(block_to_json(block)),
+    }
+}
+
+fn escape_key(key: &str) -> String {
+    if key.starts_with('[') {
+        format!("[{key}")
+    } else {
+        key.to_owned()
+    }
+}
+
+fn unescape_key(key: &str) -> &str {
+    key.strip_prefix('[')
+        .filter(|key| key.starts_with('['))
+        .unwrap_or(key)
+}
+
+fn block_to_json(block: &Document) -> Map<String, Json> {
+    let mut groups: Vec<(String, Vec<&Pair>)> = Vec::new();
+    let mut group_indices = HashMap::new();
+    let mut order = Vec::new();
+    let mut interleaved = false;
+
+    for pair in block.pairs() {
+        let index = match group_indices.entry(pair.key.as_str()) {
+            Entry::Occupied(entry) => {
+                interleaved |= *entry.get() != groups.len() - 1;
+                *entry.get()
+            }
+            Entry::Vacant(entry) => {
+                groups.push((escape_key(&pair.key), Vec::new()));
+                *entry.insert(groups.len() - 1)
+            }
+        };
+        groups[index].1.push(pair);
+        order.push(index);
+    }
+
+    let mut object = Map::with_capacity(groups.len());
+    let mut conditionals = Map::new();
+
+    if interleaved {
+        object.insert(
+            ORDER_KEY.into(),
+            Json::Array(
+                order
+                    .into_iter()
+                    .map(|index| Json::String(groups[index].0.clone()))
+                    .collect(),
+            ),
+        );
+    }
+
+    for (key, pairs) in groups {
+        let has_conditionals = pairs.iter().any(|pair| pair.conditional.is_some());
+
+        if let [pair] = pairs.as_slice() {
+            object.insert(key.clone(), node_to_json(&pair.value));
+            if let Some(conditional) = &pair.conditional {
+                conditionals.insert(key, Json::String(conditional.clone()));
+            }
+        } else {
+            object.insert(
+                key.clone(),
+                Json::Array(pairs.iter().map(|pair| node_to_json(&pair.value)).collect()),
+            );
+            if has_conditionals {
+                conditionals.insert(
+                    key,
+                    Json::Array(
+                        pairs
+                            .iter()
+                            .map(|pair| pair.conditional.clone().map_or(Json::Null, Json::String))
+                            .collect(),
+                    ),
+                );
+            }
+        }
+    }
+
+    if !conditionals.is_empty() {
+        object.insert(CONDITIONALS_KEY.into(), Json::Object(conditionals));
+    }
+
+    object
+}
+
+fn node_from_json(json: &Json) -> Result<Node> {
+    match json {
+        Json::Null => Ok(Node::String(String::new())),
+        Json::Bool(v) => Ok(Node::String(if *v { "1" } else { "0" }.into())),
+        Json::Number(v) => Ok(Node::String(v.to_string())),
+        Json::String(v) => Ok(Node::String(v.clone())),
+        Json::Array(_) => Err(Error::new(Reason::SequenceUnknownKey)),
+        Json::Object(object) => block_from_json(object).map(Node::Block),
+    }
+}
+
+fn conditional_from_json(json: Option<&Json>) -> Result<Option<String>> {
+    match json {
+        None | Some(Json::Null) => Ok(None),
+        Some(Json::String(conditional)) => Ok(Some(conditional.clone())),
+        Some(_) => Err(Error::new(Reason::InvalidConditional)),
+    }
+}
+
+fn block_from_json(object: &Map<String, Json>) -> Result<Document> {
+    let conditionals = match object.get(CONDITIONALS_KEY) {
+        None => None,
+        Some(Json::Object(conditionals)) => Some(conditionals),
+        Some(_) => return Err(Error::new(Reason::InvalidConditional)),
+    };
+
+    let mut groups = Vec::with_capacity(object.len());
+
+    for (json_key, value) in object {
+        if json_key == CONDITIONALS_KEY || json_key == ORDER_KEY {
+            continue;
+        }
+
+        let conditional = conditionals.and_then(|conditionals| conditionals.get(json_key));
+        let key = unescape_key(json_key);
+        let mut items = Vec::new();
+
+        if let Json::Array(elements) = value {
+            let conditionals = match conditional {
+                None | Some(Json::Null) => None,
+                Some(Json::Array(conditionals)) if conditionals.len() == elements.len() => {
+                    Some(conditionals)
+                }
+                Some(_) => return Err(Error::new(Reason::InvalidConditional)),
+            };
+
+            for (i, element) in elements.iter().enumerate() {
+                let mut pair = Pair::new(key, node_from_json(element)?);
+                pair.conditional =
+                    conditional_from_json(conditionals.map(|conditionals| &conditionals[i]))?;
+                items.push(Item::Pair(pair));
+            }
+        } else {
+            let mut pair = Pair::new(key, node_from_json(value)?);
+            pair.conditional = conditional_from_json(conditional)?;
+            items.push(Item::Pair(pair));
+        }
+
+        groups.push((json_key.as_str(), items.into_iter()));
+    }
+
+    let items = match object.get(ORDER_KEY) {
+        None => groups.into_iter().flat_map(|(_, items)| items).collect(),
+        Some(Json::Array(order)) => {
+            let mut items = Vec::with_capacity(order.len());
+
+            for key in order {
+                let item = groups
+                    .iter_mut()
+                    .find(|(group_key, _)| Some(*group_key) == key.as_str())
+                    .and_then(|(_, items)| items.next())
+                    .ok_or_else(|| Error::new(Reason::InvalidKeyOrder))?;
+                items.push(item);
+            }
+
+            if groups.iter_mut().any(|(_, items)| items.next().is_some()) {
+                return Err(Error::new(Reason::InvalidKeyOrder));
+            }
+
+            items
+        }
+        Some(_) => return Err(Error::new(Reason::InvalidKeyOrder)),
+    };
+
+    Ok(Document { items })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::FormatOptions;
+
+    const INPUT: &str = r#"
+"UnlitGeneric"
+{
+	"$basetexture" "sprites/glow"
+	"$envmap" "env_cubemap"
+	"$envmap" "" [$X360]
+	"Proxies"
+	{
+		"Sine" { "resultVar" "$alpha" }
+		"Sine" { "resultVar" "$color" }
+	}
+}
+"#;
+
+    #[test]
+    fn document_to_json() {
+        let document = Document::from_str(INPUT).unwrap();
+
+        assert_eq!(
+            to_json(&document),
+            json!({
+                "UnlitGeneric": {
+                    "$basetexture": "sprites/glow",
+                    "$envmap": ["env_cubemap", ""],
+                    "Proxies": {
+                        "Sine": [
+                            { "resultVar": "$alpha" },
+                            { "resultVar": "$color" },
+                        ],
+                    },
+                    "[conditionals]": { "$envmap": [null, "$X360"] },
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let document = Document::from_str(INPUT).unwrap();
+        assert_eq!(from_json(&to_json(&document)).unwrap(), document);
+    }
+
+    #[test]
+    fn json_scalars() {
+        let document = from_json(&json!({ "a": 1.5, "b": true, "c": null })).unwrap();
+        assert_eq!(
+            document
+                .to_string_with_options(&FormatOptions::default())
+                .unwrap(),
+            "\"a\" \"1.5\"\n\"b\" \"1\"\n\"c\" \"\"\n"
+        );
+
+        assert!(from_json(&json!({ "a": [[1]] })).is_err());
+        assert!(from_json(&json!({ "a": [1, 2], "[conditionals]": { "a": "$X360" } })).is_err());
+        assert!(from_json(&json!("not an object")).is_err());
+    }
+
+    #[test]
+    fn interleaved_keys() {
+        let document =
+            Document::from_str("a 1\nb 2\na 3 [$X360]\n\"[conditionals]\" 4\n\"[order]\" 5\n")
+                .unwrap();
+
+        let json = to_json(&document);
+        assert_eq!(
+            json,
+            json!({
+                "[order]": ["a", "b", "a", "[[conditionals]", "[[order]"],
+                "a": ["1", "3"],
+                "b": "2",
+                "[[conditionals]": "4",
+                "[[order]": "5",
+                "[conditionals]": { "a": [null, "$X360"] },
+            })
+        );
+        assert_eq!(from_json(&json).unwrap(), document);
+
+        assert!(from_json(&json!({ "a": ["1", "2"], "[order]": ["a"] })).is_err());
+        assert!(from_json(&json!({ "a": "1", "[order]": ["a", "b"] })).is_err());
+    }
+}
@@ -3,14 +3,22 @@
 #![allow(clippy::should_implement_trait)]
 
 mod de;
+mod document;
 mod error;
 mod escape;
+mod format;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod nom_utils;
 mod parsers;
 mod ser;
 mod value;
 
 pub use de::{escaped_from_bytes, escaped_from_str, from_bytes, from_str, Deserializer};
+pub use document::{CommentKind, Document, Item, Node, Pair};
 pub use error::{Error, Result};
-pub use ser::{escaped_to_string, to_string, Serializer};
+pub use format::{
+    to_string_with_options, Comments, FormatOptions, Indentation, LineEnding, Quoting,
+};
+pub use ser::{escaped_to_string, to_document, to_string, Serializer};
 pub use value::Value;
@@ -4,7 +4,7 @@ use nom::{
     character::complete::{anychar, char, multispace1, none_of, one_of, space0, space1},
     combinator::{all_consuming, cut, eof, not, opt, peek, recognize, value},
     error::{ErrorKind, ParseError},
-    sequence::{delimited, preceded, terminated},
+    sequence::{delimited, pair, preceded, terminated},
     Err, IResult, Parser,
 };
 
@@ -76,11 +76,15 @@ where
 }
 
 // this shouldn't probably be legal but someone seems to be using it
-fn multiline_comment<'a, E: ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'a [u8], &'a [u8], E> {
+pub(crate) fn multiline_comment<'a, E: ParseError<&'a [u8]>>(
+    i: &'a [u8],
+) -> IResult<&'a [u8], &'a [u8], E> {
     delimited(tag(b"/*"), take_until(b"*/".as_ref()), tag(b"*/"))(i)
 }
 
-fn singleline_comment<'a, E: ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'a [u8], &'a [u8], E> {
+pub(crate) fn singleline_comment<'a, E: ParseError<&'a [u8]>>(
+    i: &'a [u8],
+) -> IResult<&'a [u8], &'a [u8], E> {
     preceded(tag(b"//"), take_till(|c| c == b'\r' || c == b'\n'))(i)
 }
 
@@ -101,11 +105,13 @@ fn space_comment_trash0<'a, E: ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'a
     delimited(space0, unit(opt(comment)), trash)(i)
 }
 
-fn quoted_token<'a, E: ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'a [u8], &'a [u8], E> {
+pub(crate) fn quoted_token<'a, E: ParseError<&'a [u8]>>(
+    i: &'a [u8],
+) -> IResult<&'a [u8], &'a [u8], E> {
     delimited(char('"'), take_till(|c| c == b'"'), char('"'))(i)
 }
 
-fn escaped_quoted_token<'a, E: ParseError<&'a [u8]>>(
+pub(crate) fn escaped_quoted_token<'a, E: ParseError<&'a [u8]>>(
     i: &'a [u8],
 ) -> IResult<&'a [u8], &'a [u8], E> {
     alt((
@@ -125,17 +131,35 @@ fn unquoted_char_nonspace<'a, E: ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&
     ))(i)
 }
 
-fn unquoted_key<'a, E: ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'a [u8], &'a [u8], E> {
+pub(crate) fn unquoted_key<'a, E: ParseError<&'a [u8]>>(
+    i: &'a [u8],
+) -> IResult<&'a [u8], &'a [u8], E> {
     recognize(ignore_many1(unquoted_char_nonspace))(i)
 }
 
-fn unquoted_value<'a, E: ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'a [u8], &'a [u8], E> {
+/// Unquoted values can contain spaces, but end before a conditional.
+pub(crate) fn unquoted_value<'a, E: ParseError<&'a [u8]>>(
+    i: &'a [u8],
+) -> IResult<&'a [u8], &'a [u8], E> {
     recognize(ignore_many1(alt((
         unit(unquoted_char_nonspace),
-        unit(terminated(space1, unquoted_char_nonspace)),
+        unit(pair(
+            space1,
+            preceded(not(char('[')), unquoted_char_nonspace),
+        )),
     ))))(i)
 }
 
+pub(crate) fn conditional<'a, E: ParseError<&'a [u8]>>(
+    i: &'a [u8],
+) -> IResult<&'a [u8], &'a [u8], E> {
+    delimited(
+        char('['),
+        take_till(|c| c == b']' || c == b'\r' || c == b'\n'),
+        char(']'),
+    )(i)
+}
+
 fn specific_token<'a: 'b, 'b, E: ParseError<&'a [u8]> + 'a>(
     key: &'b [u8],
 ) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], &'a [u8], E> + 'b {
@@ -273,6 +297,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn unquoted_value_before_conditional() {
+        assert_eq!(
+            any_value::<VerboseError<&[u8]>>(b" env_cubemap [$X360]".as_ref()),
+            IResult::Ok((b" [$X360]".as_ref(), b"env_cubemap".as_ref()))
+        );
+        assert_eq!(
+            any_value::<VerboseError<&[u8]>>(b" [1 1 1]".as_ref()),
+            IResult::Ok((b"".as_ref(), b"[1 1 1]".as_ref()))
+        );
+    }
+
     #[test]
     fn comment_preceded_key() {
         assert_eq!(
@@ -1,4 +1,4 @@
-use std::fmt::{Display, Write};
+use std::fmt::Display;
 
 use serde::{
     ser::{self, Impossible},
@@ -6,8 +6,9 @@ use serde::{
 };
 
 use super::{
+    document::{Document, Item, Node, Pair},
     error::{Error, Reason, Result},
-    escape::write_escape_str,
+    format::{to_string_with_options, FormatOptions},
 };
 
 /// # Errors
@@ -15,16 +16,9 @@ use super::{
 /// Returns `Err` if the serialization fails.
 pub fn to_string<T>(value: &T) -> Result<String>
 where
-    T: Serialize,
+    T: Serialize + ?Sized,
 {
-    let mut serializer = Serializer {
-        output: String::new(),
-        last_key: None,
-        indentation: 0,
-        escaped: false,
-    };
-    value.serialize(&mut serializer)?;
-    Ok(serializer.output)
+    to_string_with_options(value, &FormatOptions::default())
 }
 
 /// # Errors
@@ -32,143 +26,119 @@ where
 /// Returns `Err` if the serialization fails.
 pub fn escaped_to_string<T>(value: &T) -> Result<String>
 where
-    T: Serialize,
+    T: Serialize + ?Sized,
 {
-    let mut serializer = Serializer {
-        output: String::new(),
-        last_key: None,
-        indentation: 0,
-        escaped: true,
-    };
-    value.serialize(&mut serializer)?;
-    Ok(serializer.output)
+    let mut options = FormatOptions::default();
+    options.escaped(true);
+    to_string_with_options(value, &options)
 }
 
-pub struct Serializer {
-    output: String,
-    last_key: Option<String>,
-    indentation: usize,
-    escaped: bool,
-}
-
-impl Serializer {
-    fn indent(&mut self) {
-        self.output.reserve(self.indentation);
-        for _ in 0..self.indentation {
-            self.output += "\t";
-        }
-    }
-
-    fn is_root(&self) -> bool {
-        self.output.is_empty()
+/// Serializes a value into an order-preserving [`Document`].
+///
+/// Sequences become repeated keys and enum variants become blocks.
+///
+/// # Errors
+///
+/// Returns `Err` if the serialization fails or the value is not a class.
+pub fn to_document<T>(value: &T) -> Result<Document>
+where
+    T: Serialize + ?Sized,
+{
+    match value.serialize(Serializer)? {
+        Serialized::Node(Node::Block(document)) => Ok(document),
+        _ => Err(Error::new(Reason::RootMustBeClass)),
     }
+}
 
-    fn serialize_escaped_str(&mut self, str: &str) {
-        write_escape_str(str, &mut self.output);
-    }
+/// A sequence has no key of its own, it repeats the key of its parent.
+pub enum Serialized {
+    Node(Node),
+    Seq(Vec<Node>),
+}
 
-    fn serialize_class(&mut self) -> SerializeClass {
-        let is_root = self.is_root();
-        if !is_root {
-            self.output += "\n";
-            self.indent();
-            self.output += "{\n";
-            self.indentation += 1;
-            self.indent();
-        }
-        let key_before = self.last_key.take();
-        SerializeClass {
-            serializer: self,
-            key_before,
-            is_root,
-            first: true,
-        }
+fn push_serialized(items: &mut Vec<Item>, key: &str, value: Serialized) {
+    match value {
+        Serialized::Node(node) => items.push(Item::Pair(Pair::new(key, node))),
+        Serialized::Seq(nodes) => items.extend(
+            nodes
+                .into_iter()
+                .map(|node| Item::Pair(Pair::new(key, node))),
+        ),
     }
+}
 
-    fn begin_serialize_enum(&mut self, variant: &'static str) -> Result<()> {
-        if !self.is_root() {
-            self.output += "\n";
-            self.indent();
-            self.output += "{\n";
-            self.indentation += 1;
-        }
-        self.indent();
-        ser::Serializer::serialize_str(self, variant)
-    }
+fn string(value: impl Display) -> Serialized {
+    Serialized::Node(Node::String(value.to_string()))
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
-    type Ok = ();
+pub struct Serializer;
 
+impl ser::Serializer for Serializer {
+    type Ok = Serialized;
     type Error = Error;
 
-    type SerializeSeq = SerializeSeq<'a>;
-    type SerializeTuple = SerializeSeq<'a>;
-    type SerializeTupleStruct = SerializeSeq<'a>;
-    type SerializeTupleVariant = SerializeSeq<'a>;
-    type SerializeMap = SerializeClass<'a>;
-    type SerializeStruct = SerializeClass<'a>;
-    type SerializeStructVariant = SerializeClass<'a>;
+    type SerializeSeq = SerializeSeq;
+    type SerializeTuple = SerializeSeq;
+    type SerializeTupleStruct = SerializeSeq;
+    type SerializeTupleVariant = SerializeSeq;
+    type SerializeMap = SerializeBlock;
+    type SerializeStruct = SerializeBlock;
+    type SerializeStructVariant = SerializeBlock;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
-        self.serialize_str(if v { "1" } else { "0" })
+        Ok(string(if v { "1" } else { "0" }))
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok> {
-        self.collect_str(&v)
+        Ok(string(v))
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok> {
-        if self.escaped {
-            self.serialize_escaped_str(v);
-        } else {
-            write!(self.output, "\"{v}\"").expect("write to string should be infallible");
-        }
-        Ok(())
+        Ok(string(v))
     }
 
     fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok> {
-        unimplemented!("vdf cannot serialize bytes")
+        Err(Error::new(Reason::UnsupportedBytes))
     }
 
     fn serialize_none(self) -> Result<Self::Ok> {
@@ -180,8 +150,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_unit(self) -> Result<Self::Ok> {
-        self.output += "\"\"";
-        Ok(())
+        Ok(string(""))
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
@@ -212,27 +181,16 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant: &'static str,
         value: &T,
     ) -> Result<Self::Ok> {
-        self.begin_serialize_enum(variant)?;
-        self.output += " ";
-        value.serialize(&mut *self)?;
-        self.output += "\n";
-        self.indentation -= 1;
-        self.indent();
-        self.output += "}";
-        Ok(())
+        let mut items = Vec::new();
+        push_serialized(&mut items, variant, value.serialize(self)?);
+        Ok(Serialized::Node(Node::Block(Document { items })))
     }
 
-    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        self.last_key.take().map_or_else(
-            || Err(Error::new(Reason::SequenceUnknownKey)),
-            move |key| {
-                Ok(SerializeSeq {
-                    serializer: self,
-                    key,
-                    first: true,
-                })
-            },
-        )
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(SerializeSeq {
+            nodes: Vec::with_capacity(len.unwrap_or_default()),
+            variant: None,
+        })
     }
 
     fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
@@ -252,23 +210,24 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         _name: &'static str,
         _variant_index: u32,
         variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        self.begin_serialize_enum(variant)?;
-        self.output += " ";
         Ok(SerializeSeq {
-            serializer: self,
-            key: variant.into(),
-            first: true,
+            nodes: Vec::with_capacity(len),
+            variant: Some(variant),
         })
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Ok(self.serialize_class())
+        Ok(SerializeBlock {
+            items: Vec::new(),
+            next_key: None,
+            variant: None,
+        })
     }
 
     fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
-        Ok(self.serialize_class())
+        self.serialize_map(None)
     }
 
     fn serialize_struct_variant(
@@ -278,64 +237,47 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        self.begin_serialize_enum(variant)?;
-        self.output += "\n";
-        self.indent();
-        self.output += "{";
-        self.indentation += 1;
-        let is_root = self.is_root();
-        if !is_root {
-            self.output += "\n";
-            self.indent();
-        }
-        let key_before = self.last_key.take();
-        Ok(SerializeClass {
-            serializer: self,
-            key_before,
-            is_root,
-            first: true,
+        Ok(SerializeBlock {
+            items: Vec::new(),
+            next_key: None,
+            variant: Some(variant),
         })
     }
-
-    fn collect_str<T: ?Sized + Display>(self, value: &T) -> Result<Self::Ok> {
-        if self.escaped {
-            self.serialize_escaped_str(&value.to_string());
-        } else {
-            write!(self.output, "\"{value}\"").expect("write to string should be infallible");
-        }
-        Ok(())
-    }
 }
 
-pub struct SerializeSeq<'a> {
-    serializer: &'a mut Serializer,
-    key: String,
-    first: bool,
+pub struct SerializeSeq {
+    nodes: Vec<Node>,
+    variant: Option<&'static str>,
 }
 
-impl<'a> SerializeSeq<'a> {
+impl SerializeSeq {
     fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
-        if !self.first {
-            self.serializer.output += "\n";
-            self.serializer.indent();
-            ser::Serializer::serialize_str(&mut *self.serializer, &self.key)?;
+        match value.serialize(Serializer)? {
+            Serialized::Node(node) => {
+                self.nodes.push(node);
+                Ok(())
+            }
+            Serialized::Seq(_) => Err(Error::new(Reason::SequenceUnknownKey)),
         }
-        self.first = false;
-        self.serializer.output += " ";
-        value.serialize(&mut *self.serializer)
     }
 
-    fn end(self) -> Result<()> {
-        if self.first {
+    fn end(self) -> Result<Serialized> {
+        if self.nodes.is_empty() {
             return Err(Error::new(Reason::EmptySequence));
         }
-        self.serializer.last_key = Some(self.key);
-        Ok(())
+
+        if let Some(variant) = self.variant {
+            let mut items = Vec::new();
+            push_serialized(&mut items, variant, Serialized::Seq(self.nodes));
+            Ok(Serialized::Node(Node::Block(Document { items })))
+        } else {
+            Ok(Serialized::Seq(self.nodes))
+        }
     }
 }
 
-impl<'a> ser::SerializeSeq for SerializeSeq<'a> {
-    type Ok = ();
+impl ser::SerializeSeq for SerializeSeq {
+    type Ok = Serialized;
     type Error = Error;
 
     fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
@@ -347,8 +289,8 @@ impl<'a> ser::SerializeSeq for SerializeSeq<'a> {
     }
 }
 
-impl<'a> ser::SerializeTuple for SerializeSeq<'a> {
-    type Ok = ();
+impl ser::SerializeTuple for SerializeSeq {
+    type Ok = Serialized;
     type Error = Error;
 
     fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
@@ -360,8 +302,8 @@ impl<'a> ser::SerializeTuple for SerializeSeq<'a> {
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for SerializeSeq<'a> {
-    type Ok = ();
+impl ser::SerializeTupleStruct for SerializeSeq {
+    type Ok = Serialized;
     type Error = Error;
 
     fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
@@ -373,8 +315,8 @@ impl<'a> ser::SerializeTupleStruct for SerializeSeq<'a> {
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for SerializeSeq<'a> {
-    type Ok = ();
+impl ser::SerializeTupleVariant for SerializeSeq {
+    type Ok = Serialized;
     type Error = Error;
 
     fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
@@ -382,84 +324,153 @@ impl<'a> ser::SerializeTupleVariant for SerializeSeq<'a> {
     }
 
     fn end(self) -> Result<Self::Ok> {
-        self.serializer.output += "\n";
-        self.serializer.indentation -= 1;
-        self.serializer.indent();
-        self.serializer.output += "}";
         self.end()
     }
 }
 
-struct KeySerializer<'a>(&'a mut Serializer);
+pub struct SerializeBlock {
+    items: Vec<Item>,
+    next_key: Option<String>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeBlock {
+    fn end(self) -> Serialized {
+        let block = Node::Block(Document { items: self.items });
+
+        if let Some(variant) = self.variant {
+            let mut items = Vec::new();
+            push_serialized(&mut items, variant, Serialized::Node(block));
+            Serialized::Node(Node::Block(Document { items }))
+        } else {
+            Serialized::Node(block)
+        }
+    }
+}
 
-impl<'a> ser::Serializer for KeySerializer<'a> {
-    type Ok = ();
+impl ser::SerializeMap for SerializeBlock {
+    type Ok = Serialized;
     type Error = Error;
 
-    type SerializeSeq = Impossible<(), Error>;
-    type SerializeTuple = Impossible<(), Error>;
-    type SerializeTupleStruct = Impossible<(), Error>;
-    type SerializeTupleVariant = Impossible<(), Error>;
-    type SerializeMap = Impossible<(), Error>;
-    type SerializeStruct = Impossible<(), Error>;
-    type SerializeStructVariant = Impossible<(), Error>;
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        self.next_key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| Error::new(Reason::SequenceUnknownKey))?;
+        push_serialized(&mut self.items, &key, value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(self.end())
+    }
+}
+
+impl ser::SerializeStruct for SerializeBlock {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        push_serialized(&mut self.items, key, value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(self.end())
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeBlock {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        push_serialized(&mut self.items, key, value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(self.end())
+    }
+}
+
+struct KeySerializer;
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = Error;
+
+    type SerializeSeq = Impossible<String, Error>;
+    type SerializeTuple = Impossible<String, Error>;
+    type SerializeTupleStruct = Impossible<String, Error>;
+    type SerializeTupleVariant = Impossible<String, Error>;
+    type SerializeMap = Impossible<String, Error>;
+    type SerializeStruct = Impossible<String, Error>;
+    type SerializeStructVariant = Impossible<String, Error>;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
-        ser::Serializer::serialize_bool(self.0, v)
+        Ok(if v { "1" } else { "0" }.into())
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
-        ser::Serializer::serialize_i8(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
-        ser::Serializer::serialize_i16(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
-        ser::Serializer::serialize_i32(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
-        ser::Serializer::serialize_i64(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
-        ser::Serializer::serialize_u8(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
-        ser::Serializer::serialize_u16(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
-        ser::Serializer::serialize_u32(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
-        ser::Serializer::serialize_u64(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
-        ser::Serializer::serialize_f32(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
-        ser::Serializer::serialize_f64(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok> {
-        ser::Serializer::serialize_char(self.0, v)
+        Ok(v.to_string())
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok> {
-        ser::Serializer::serialize_str(&mut *self.0, v)?;
-        if let Some(last_key) = &mut self.0.last_key {
-            last_key.replace_range(.., v);
-        } else {
-            self.0.last_key = Some(v.into());
-        }
-        Ok(())
+        Ok(v.into())
     }
 
     fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok> {
@@ -553,111 +564,3 @@ impl<'a> ser::Serializer for KeySerializer<'a> {
         Err(Error::new(Reason::KeyMustBeString))
     }
 }
-
-pub struct SerializeClass<'a> {
-    serializer: &'a mut Serializer,
-    key_before: Option<String>,
-    is_root: bool,
-    first: bool,
-}
-
-impl<'a> SerializeClass<'a> {
-    fn serialize_field<T: ?Sized + Serialize>(
-        &mut self,
-        key: &'static str,
-        value: &T,
-    ) -> Result<()> {
-        if !self.first {
-            self.serializer.output += "\n";
-            self.serializer.indent();
-        }
-        self.first = false;
-        ser::Serializer::serialize_str(&mut *self.serializer, key)?;
-        if let Some(last_key) = &mut self.serializer.last_key {
-            last_key.replace_range(.., key);
-        } else {
-            self.serializer.last_key = Some(key.into());
-        }
-        self.serializer.output += " ";
-        value.serialize(&mut *self.serializer)
-    }
-
-    fn end(self) {
-        self.serializer.output += "\n";
-        if !self.is_root {
-            self.serializer.indentation -= 1;
-            self.serializer.indent();
-            self.serializer.output += "}";
-        }
-        self.serializer.last_key = self.key_before;
-    }
-}
-
-impl<'a> ser::SerializeStruct for SerializeClass<'a> {
-    type Ok = ();
-    type Error = Error;
-
-    fn serialize_field<T: ?Sized + Serialize>(
-        &mut self,
-        key: &'static str,
-        value: &T,
-    ) -> Result<()> {
-        self.serialize_field(key, value)
-    }
-
-    fn end(self) -> Result<()> {
-        self.end();
-        Ok(())
-    }
-}
-
-impl<'a> ser::SerializeStructVariant for SerializeClass<'a> {
-    type Ok = ();
-    type Error = Error;
-
-    fn serialize_field<T: ?Sized + Serialize>(
-        &mut self,
-        key: &'static str,
-        value: &T,
-    ) -> Result<()> {
-        self.serialize_field(key, value)
-    }
-
-    fn end(self) -> Result<()> {
-        self.serializer.output += "\n";
-        self.serializer.indentation -= 1;
-        self.serializer.indent();
-        self.serializer.output += "}\n";
-        if !self.is_root {
-            self.serializer.indentation -= 1;
-            self.serializer.indent();
-            self.serializer.output += "}";
-        }
-        self.serializer.last_key = self.key_before;
-        Ok(())
-    }
-}
-
-impl<'a> ser::SerializeMap for SerializeClass<'a> {
-    type Ok = ();
-    type Error = Error;
-
-    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
-        if !self.first {
-            self.serializer.output += "\n";
-            self.serializer.indent();
-        }
-        self.first = false;
-        key.serialize(KeySerializer(&mut *self.serializer))
-    }
-
-    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
-        self.serializer.output += " ";
-        value.serialize(&mut *self.serializer)
-    }
-
-    fn end(self) -> Result<()> {
-        self.end();
-        Ok(())
-    }
-}
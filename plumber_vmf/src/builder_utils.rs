@@ -43,6 +43,40 @@ impl InvisibleSolids {
     }
 }
 
+/// Normals of brush faces. Displacements always have smooth normals.
+#[derive(Debug, Clone, Copy)]
+pub enum BrushNormals {
+    /// Every face vertice has the normal of its face.
+    Flat,
+    /// Normals of faces sharing a smoothing group are averaged where they meet.
+    Smooth,
+}
+
+impl BrushNormals {
+    #[must_use]
+    pub fn smooth(self) -> bool {
+        matches!(self, Self::Smooth)
+    }
+}
+
+/// How vertices are shared between faces with different normals.
+#[derive(Debug, Clone, Copy)]
+pub enum NormalOutput {
+    /// Vertices are shared regardless of normals,
+    /// so a vertice can have a different normal on each face it's part of.
+    Split,
+    /// Vertices are duplicated where the normals of the faces using them differ,
+    /// so every vertice has a single normal.
+    Welded,
+}
+
+impl NormalOutput {
+    #[must_use]
+    pub fn welded(self) -> bool {
+        matches!(self, Self::Welded)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[non_exhaustive]
 pub struct GeometrySettings {
@@ -50,6 +84,10 @@ pub struct GeometrySettings {
     pub cut_threshold: f32,
     pub merge_solids: MergeSolids,
     pub invisible_solids: InvisibleSolids,
+    pub normals: BrushNormals,
+    pub normal_output: NormalOutput,
+    /// Generate lightmap uvs, packed into an atlas per brush entity.
+    pub lightmap_uvs: bool,
 }
 
 impl GeometrySettings {
@@ -73,6 +111,18 @@ impl GeometrySettings {
     pub fn invisible_solids(&mut self, invisible: InvisibleSolids) {
         self.invisible_solids = invisible;
     }
+
+    pub fn normals(&mut self, normals: BrushNormals) {
+        self.normals = normals;
+    }
+
+    pub fn normal_output(&mut self, output: NormalOutput) {
+        self.normal_output = output;
+    }
+
+    pub fn lightmap_uvs(&mut self, lightmap_uvs: bool) {
+        self.lightmap_uvs = lightmap_uvs;
+    }
 }
 
 impl Default for GeometrySettings {
@@ -82,6 +132,9 @@ impl Default for GeometrySettings {
             cut_threshold: 1e-3,
             merge_solids: MergeSolids::Merge,
             invisible_solids: InvisibleSolids::Skip,
+            normals: BrushNormals::Smooth,
+            normal_output: NormalOutput::Split,
+            lightmap_uvs: false,
         }
     }
 }
@@ -0,0 +1,242 @@
+use std::{f32::consts::FRAC_1_SQRT_2, fmt::Debug};
+
+use approx::relative_eq;
+use glam::{Vec2, Vec3};
+use itertools::Itertools;
+use thiserror::Error;
+
+use plumber_fs::GamePathBuf;
+use plumber_vmt::MaterialInfo;
+
+use super::{
+    builder_utils::{polygon_center, polygon_normal, GeometrySettings, NdPlane},
+    entities::{Decal, EntityParseError, PointEntity},
+    overlay_builder::{BuiltOverlayFace, SideFacesMap},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Largest distance from the decal origin to the plane of a face the decal is applied to.
+const DECAL_DISTANCE: f32 = 4.0;
+/// Distance the decal is offset from the faces it's applied to.
+const DECAL_OFFSET: f32 = 0.1;
+
+#[derive(Debug, Error, Clone, Hash, PartialEq, Eq)]
+pub enum DecalError {
+    #[error("error parsing decal: {0}")]
+    Parse(#[from] EntityParseError),
+    #[error("no geometry was found near the decal")]
+    NoGeometry,
+}
+
+#[derive(Debug)]
+pub struct BuiltDecal<'a> {
+    pub decal: Decal<'a>,
+    pub position: Vec3,
+    pub scale: f32,
+    pub vertices: Vec<Vec3>,
+    pub faces: Vec<BuiltOverlayFace>,
+    pub material: GamePathBuf,
+}
+
+struct DecalBuilder<'a> {
+    decal: Decal<'a>,
+    origin: Vec3,
+    width: f32,
+    height: f32,
+    faces: Vec<BuiltOverlayFace>,
+    vertices: Vec<Vec3>,
+    vertice_normals: Vec<Vec3>,
+}
+
+impl<'a> DecalBuilder<'a> {
+    fn new(decal: Decal<'a>, material_info: &MaterialInfo) -> Result<Self, DecalError> {
+        Ok(Self {
+            origin: decal.origin()?,
+            decal,
+            width: material_info.width() as f32,
+            height: material_info.height() as f32,
+            faces: Vec::new(),
+            vertices: Vec::new(),
+            vertice_normals: Vec::new(),
+        })
+    }
+
+    fn create_faces(&mut self, side_faces_map: &SideFacesMap, epsilon: f32) {
+        for polygon in side_faces_map.values().flatten() {
+            let normal = polygon_normal(polygon.iter().copied());
+            let plane = NdPlane::from_point_normal(polygon[0], normal);
+
+            // decals are only applied to faces that face the origin
+            let distance = plane.distance_to_point(self.origin);
+            if !(0.0..=DECAL_DISTANCE).contains(&distance) {
+                continue;
+            }
+
+            // the decal is projected onto each face separately, centered on the origin
+            let center = self.origin - normal * distance;
+            let [s_axis, t_axis] = decal_basis(normal);
+            let half_width = self.width * 0.5;
+            let half_height = self.height * 0.5;
+
+            let borders = [
+                NdPlane::from_point_normal(center + s_axis * half_width, s_axis),
+                NdPlane::from_point_normal(center - s_axis * half_width, -s_axis),
+                NdPlane::from_point_normal(center + t_axis * half_height, t_axis),
+                NdPlane::from_point_normal(center - t_axis * half_height, -t_axis),
+            ];
+
+            let mut clipped = borders.iter().fold(polygon.clone(), |polygon, border| {
+                clip_polygon(&polygon, border)
+            });
+
+            // make sure there are no sequential duplicate vertices, which would be an invalid face
+            clipped.dedup_by(|a, b| relative_eq!(a, b, epsilon = epsilon));
+            while clipped.len() > 1
+                && relative_eq!(clipped[0], clipped[clipped.len() - 1], epsilon = epsilon)
+            {
+                clipped.pop();
+            }
+
+            if clipped.len() < 3 || polygon_area(&clipped) <= epsilon {
+                continue;
+            }
+
+            let vertice_uvs = clipped
+                .iter()
+                .map(|&vertice| {
+                    let offset = vertice - center;
+                    Vec2::new(
+                        0.5 + offset.dot(s_axis) / self.width,
+                        0.5 + offset.dot(t_axis) / self.height,
+                    )
+                })
+                .collect();
+
+            let vertice_indices = clipped
+                .iter()
+                .map(|vertice| {
+                    let i = self
+                        .vertices
+                        .iter()
+                        .position(|v| relative_eq!(v, vertice, epsilon = epsilon))
+                        .unwrap_or_else(|| {
+                            self.vertices.push(*vertice);
+                            self.vertice_normals.push(Vec3::ZERO);
+                            self.vertices.len() - 1
+                        });
+                    self.vertice_normals[i] += normal;
+                    i
+                })
+                .collect();
+
+            self.faces.push(BuiltOverlayFace {
+                vertice_indices,
+                vertice_uvs,
+            });
+        }
+    }
+
+    fn ensure_not_empty(&self) -> Result<(), DecalError> {
+        if self.faces.is_empty() {
+            return Err(DecalError::NoGeometry);
+        }
+        Ok(())
+    }
+
+    fn offset_vertices(&mut self) {
+        for (vertice, normal) in self.vertices.iter_mut().zip(&self.vertice_normals) {
+            *vertice += DECAL_OFFSET * normal.normalize();
+        }
+    }
+
+    fn recenter(&mut self) {
+        let center = polygon_center(self.vertices.iter().copied());
+        for vertice in &mut self.vertices {
+            *vertice -= center;
+        }
+        // vertices are global space before this point
+        self.origin = center;
+    }
+
+    fn finish(self, scale: f32) -> Result<BuiltDecal<'a>, DecalError> {
+        let mut material = GamePathBuf::from("materials");
+        let decal_material = self.decal.material()?;
+        material.push(&decal_material);
+
+        Ok(BuiltDecal {
+            decal: self.decal,
+            position: self.origin * scale,
+            scale,
+            vertices: self.vertices,
+            faces: self.faces,
+            material,
+        })
+    }
+}
+
+/// Returns the axes pointing right and down in the decal texture on a face with the given normal.
+/// The decal is oriented along the x axis on floors and ceilings, and upright on walls.
+fn decal_basis(normal: Vec3) -> [Vec3; 2] {
+    if normal.z.abs() > FRAC_1_SQRT_2 {
+        let t_axis = Vec3::X.cross(normal);
+        let s_axis = normal.cross(t_axis);
+        [s_axis.normalize(), t_axis.normalize()]
+    } else {
+        let s_axis = normal.cross(-Vec3::Z);
+        let t_axis = s_axis.cross(normal);
+        [s_axis.normalize(), t_axis.normalize()]
+    }
+}
+
+/// Clips a convex polygon, keeping the part behind the plane.
+fn clip_polygon(polygon: &[Vec3], plane: &NdPlane) -> Vec<Vec3> {
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+
+    for (&a, &b) in polygon.iter().circular_tuple_windows() {
+        let distance_a = plane.distance_to_point(a);
+        let distance_b = plane.distance_to_point(b);
+
+        if distance_a <= 0.0 {
+            clipped.push(a);
+        }
+
+        if (distance_a < 0.0 && distance_b > 0.0) || (distance_a > 0.0 && distance_b < 0.0) {
+            clipped.push(a.lerp(b, distance_a / (distance_a - distance_b)));
+        }
+    }
+
+    clipped
+}
+
+fn polygon_area(polygon: &[Vec3]) -> f32 {
+    let mut cross = Vec3::ZERO;
+    for (&a, &b) in polygon.iter().circular_tuple_windows() {
+        cross += a.cross(b);
+    }
+    cross.length() * 0.5
+}
+
+impl<'a> Decal<'a> {
+    /// Projects the decal onto the faces near its origin, with the dimensions of its material.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the decal can't be parsed or there are no faces near it.
+    pub fn build_mesh(
+        self,
+        material_info: &MaterialInfo,
+        side_faces_map: &SideFacesMap,
+        settings: &GeometrySettings,
+        scale: f32,
+    ) -> Result<BuiltDecal<'a>, DecalError> {
+        let mut builder = DecalBuilder::new(self, material_info)?;
+        builder.create_faces(side_faces_map, settings.epsilon);
+        builder.ensure_not_empty()?;
+
+        builder.offset_vertices();
+        builder.recenter();
+        builder.finish(scale)
+    }
+}
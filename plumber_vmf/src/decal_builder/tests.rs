@@ -0,0 +1,178 @@
+use std::sync::Mutex;
+
+use plumber_vdf as vdf;
+
+use super::*;
+use crate::{entities::TypedEntity, vmf::Entity, vmf::Solid};
+
+fn get_test_side_faces() -> SideFacesMap {
+    side_faces(include_str!("../overlay_builder/test_solids.txt"))
+}
+
+fn side_faces(input: &str) -> SideFacesMap {
+    let solids: Vec<Solid> = vdf::from_str(input).unwrap();
+
+    let side_faces_map = Mutex::new(SideFacesMap::new());
+    for solid in &solids {
+        solid
+            .build_mesh(
+                |_| Some(MaterialInfo::new(1024, 1024, false)),
+                &side_faces_map,
+                &GeometrySettings::default(),
+                1.0,
+            )
+            .unwrap();
+    }
+
+    side_faces_map
+        .into_inner()
+        .expect("mutex shouldn't be poisoned")
+}
+
+fn get_test_decal(origin: &str) -> Entity {
+    let input = format!(
+        r#""id" "400"
+"classname" "infodecal"
+"texture" "decals/test"
+"origin" "{origin}"
+"#
+    );
+    vdf::from_str(&input).unwrap()
+}
+
+fn build<'a>(entity: &'a Entity, side_faces_map: &SideFacesMap) -> BuiltDecal<'a> {
+    let decal = match entity.typed() {
+        TypedEntity::Decal(d) => d,
+        _ => unreachable!(),
+    };
+    decal
+        .build_mesh(
+            &MaterialInfo::new(32, 32, false),
+            side_faces_map,
+            &GeometrySettings::default(),
+            1.0,
+        )
+        .unwrap()
+}
+
+#[test]
+fn decal_on_floor() {
+    let side_faces_map = get_test_side_faces();
+    let entity = get_test_decal("-896 0 64");
+    let built = build(&entity, &side_faces_map);
+
+    assert_eq!(built.material, GamePathBuf::from("materials/decals/test"));
+    assert_eq!(built.faces.len(), 1);
+    assert_eq!(built.vertices.len(), 4);
+    assert!(relative_eq!(
+        built.position,
+        Vec3::new(-896.0, 0.0, 64.1),
+        epsilon = 1e-3
+    ));
+
+    let face = &built.faces[0];
+    for (&i, uv) in face.vertice_indices.iter().zip(&face.vertice_uvs) {
+        let vertice = built.vertices[i];
+        // u follows +x and v follows -y on floors
+        let expected_uv = Vec2::new(0.5 + vertice.x / 32.0, 0.5 - vertice.y / 32.0);
+        assert!(relative_eq!(*uv, expected_uv, epsilon = 1e-3));
+        assert!(relative_eq!(vertice.x.abs(), 16.0, epsilon = 1e-3));
+        assert!(relative_eq!(vertice.y.abs(), 16.0, epsilon = 1e-3));
+    }
+}
+
+#[test]
+fn decal_on_edge() {
+    let side_faces_map = get_test_side_faces();
+    // in front of the top face and the -y side face, but behind the +x side face
+    let entity = get_test_decal("-840 -66 66");
+    let built = build(&entity, &side_faces_map);
+
+    assert_eq!(built.faces.len(), 2);
+    // the vertices on the shared edge are shared
+    assert_eq!(built.vertices.len(), 6);
+
+    for vertice in &built.vertices {
+        let global = *vertice + built.position;
+        assert!(global.x > -856.01 && global.x < -831.99);
+        assert!(global.y > -64.11 && global.y < -43.99);
+        assert!(global.z > 43.99 && global.z < 64.11);
+    }
+}
+
+#[test]
+fn decal_on_thin_brush() {
+    // a brush 2 units thick, with both the top and bottom face within range of the decal
+    let side_faces_map = side_faces(
+        r#"solid
+{
+    "id" "1"
+    side
+    {
+        "id" "2"
+        "plane" "(-64 64 2) (64 64 2) (64 -64 2)"
+        "material" "DE_TEST/GRID"
+        "uaxis" "[1 0 0 0] 0.25"
+        "vaxis" "[0 -1 0 0] 0.25"
+        "lightmapscale" "16"
+    }
+    side
+    {
+        "id" "3"
+        "plane" "(-64 -64 0) (64 -64 0) (64 64 0)"
+        "material" "DE_TEST/GRID"
+        "uaxis" "[1 0 0 0] 0.25"
+        "vaxis" "[0 -1 0 0] 0.25"
+        "lightmapscale" "16"
+    }
+    side
+    {
+        "id" "4"
+        "plane" "(-64 64 2) (-64 -64 2) (-64 -64 0)"
+        "material" "DE_TEST/GRID"
+        "uaxis" "[0 1 0 0] 0.25"
+        "vaxis" "[0 0 -1 0] 0.25"
+        "lightmapscale" "16"
+    }
+    side
+    {
+        "id" "5"
+        "plane" "(64 64 0) (64 -64 0) (64 -64 2)"
+        "material" "DE_TEST/GRID"
+        "uaxis" "[0 1 0 0] 0.25"
+        "vaxis" "[0 0 -1 0] 0.25"
+        "lightmapscale" "16"
+    }
+    side
+    {
+        "id" "6"
+        "plane" "(64 64 2) (-64 64 2) (-64 64 0)"
+        "material" "DE_TEST/GRID"
+        "uaxis" "[1 0 0 0] 0.25"
+        "vaxis" "[0 0 -1 0] 0.25"
+        "lightmapscale" "16"
+    }
+    side
+    {
+        "id" "7"
+        "plane" "(64 -64 0) (-64 -64 0) (-64 -64 2)"
+        "material" "DE_TEST/GRID"
+        "uaxis" "[1 0 0 0] 0.25"
+        "vaxis" "[0 0 -1 0] 0.25"
+        "lightmapscale" "16"
+    }
+}
+"#,
+    );
+    let entity = get_test_decal("0 0 3");
+    let built = build(&entity, &side_faces_map);
+
+    // the bottom face is facing away from the decal
+    assert_eq!(built.faces.len(), 1);
+    assert_eq!(built.vertices.len(), 4);
+    assert!(relative_eq!(
+        built.position,
+        Vec3::new(0.0, 0.0, 2.1),
+        epsilon = 1e-3
+    ));
+}
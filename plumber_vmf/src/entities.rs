@@ -17,6 +17,8 @@ pub enum TypedEntity<'a> {
     EnvLight(EnvLight<'a>),
     SkyCamera(SkyCamera<'a>),
     Overlay(Overlay<'a>),
+    Decal(Decal<'a>),
+    Instance(Instance<'a>),
     Prop(Prop<'a>),
     Unknown(Unknown<'a>),
 }
@@ -30,6 +32,8 @@ impl Entity {
             "light_environment" => TypedEntity::EnvLight(EnvLight::new(self)),
             "sky_camera" => TypedEntity::SkyCamera(SkyCamera::new(self)),
             "info_overlay" => TypedEntity::Overlay(Overlay::new(self)),
+            "infodecal" => TypedEntity::Decal(Decal::new(self)),
+            "func_instance" => TypedEntity::Instance(Instance::new(self)),
             "prop_static"
             | "prop_world"
             | "prop_detail"
@@ -60,6 +64,15 @@ pub trait BaseEntity {
     #[must_use]
     fn entity(&self) -> &Entity;
 
+    /// Ids of the visgroups the entity is in.
+    #[must_use]
+    fn vis_group_ids(&self) -> &[i32] {
+        self.entity()
+            .editor
+            .as_ref()
+            .map_or(&[], |editor| editor.vis_group_ids.as_slice())
+    }
+
     /// # Errors
     ///
     /// Returns `Err` if the parameter can't be parsed.
@@ -864,3 +877,127 @@ pub struct OverlayUvInfo {
     pub basis_normal: Vec3,
     pub uvs: [Vec3; 4],
 }
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decal<'a> {
+    entity: &'a Entity,
+}
+
+impl<'a> BaseEntity for Decal<'a> {
+    fn entity(&self) -> &Entity {
+        self.entity
+    }
+}
+
+impl<'a> PointEntity for Decal<'a> {}
+
+impl<'a> Decal<'a> {
+    #[must_use]
+    pub fn new(entity: &'a Entity) -> Self {
+        Self { entity }
+    }
+
+    /// # Errors
+    ///
+    /// Returns `Err` if the parameter `texture` doesn't exist.
+    pub fn material(&self) -> Result<GamePathBuf, EntityParseError> {
+        self.entity
+            .properties
+            .get("texture".as_uncased())
+            .map(|s| GamePathBuf::from(s.clone()))
+            .ok_or(EntityParseError::MissingParameter("texture"))
+    }
+
+    /// # Errors
+    ///
+    /// Returns `Err` if the parameter `LowPriority` can't be parsed.
+    pub fn low_priority(&self) -> Result<bool, EntityParseError> {
+        self.parse_bool_parameter("LowPriority")
+            .map(Option::unwrap_or_default)
+    }
+}
+
+/// How the names of the entities inside an instance are made unique.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FixupStyle {
+    /// `instance-name`
+    Prefix,
+    /// `name-instance`
+    Postfix,
+    None,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Instance<'a> {
+    entity: &'a Entity,
+}
+
+impl<'a> BaseEntity for Instance<'a> {
+    fn entity(&self) -> &Entity {
+        self.entity
+    }
+}
+
+impl<'a> PointEntity for Instance<'a> {}
+impl<'a> AngledEntity for Instance<'a> {}
+
+impl<'a> Instance<'a> {
+    #[must_use]
+    pub fn new(entity: &'a Entity) -> Self {
+        Self { entity }
+    }
+
+    /// Returns the path of the instance vmf, relative to the map containing the instance.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the parameter `file` doesn't exist or is empty.
+    pub fn file(&self) -> Result<&'a str, EntityParseError> {
+        self.entity
+            .properties
+            .get("file".as_uncased())
+            .map(String::as_str)
+            .filter(|s| !s.is_empty())
+            .ok_or(EntityParseError::MissingParameter("file"))
+    }
+
+    #[must_use]
+    pub fn target_name(&self) -> Option<&'a str> {
+        self.entity
+            .properties
+            .get("targetname".as_uncased())
+            .map(String::as_str)
+            .filter(|s| !s.is_empty())
+    }
+
+    /// # Errors
+    ///
+    /// Returns `Err` if the parameter `fixup_style` can't be parsed.
+    pub fn fixup_style(&self) -> Result<FixupStyle, EntityParseError> {
+        match self.parse_int_parameter("fixup_style")? {
+            None | Some(0) => Ok(FixupStyle::Prefix),
+            Some(1) => Ok(FixupStyle::Postfix),
+            Some(2) => Ok(FixupStyle::None),
+            Some(_) => Err(EntityParseError::InvalidParameterValue {
+                parameter: "fixup_style",
+                reason: "not a valid fixup style (0-2)",
+            }),
+        }
+    }
+
+    /// Returns the `replace01`, `replace02`... parameters as variable and value pairs.
+    /// Variables starting with `$` are replaced in entity parameters,
+    /// variables starting with `#` replace brush materials.
+    #[must_use]
+    pub fn replacements(&self) -> Vec<(&'a str, &'a str)> {
+        self.entity
+            .properties
+            .iter()
+            .filter(|(key, _)| key.starts_with("replace".as_uncased()))
+            .filter_map(|(_, value)| {
+                let (variable, value) = value.split_once(' ').unwrap_or((value, ""));
+                (!variable.is_empty()).then_some((variable, value))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,500 @@
+//! Collapsing of `func_instance` entities into the map containing them.
+
+use std::{cmp::Reverse, io, mem};
+
+use glam::{EulerRot, Quat, Vec3};
+use thiserror::Error;
+
+use plumber_fs::{GamePathBuf, OpenFileSystem, PathBuf};
+use plumber_uncased::{AsUncased, UncasedString};
+use plumber_vdf as vdf;
+
+use crate::{
+    entities::{
+        AngledEntity, BaseEntity, EntityParseError, FixupStyle, Instance, PointEntity, Unknown,
+    },
+    types::Plane,
+    vmf::{Entity, Solid, Vmf},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Parameters referring to entity names, which get the instance name fixup.
+const NAME_PARAMETERS: [&str; 5] = [
+    "targetname",
+    "parentname",
+    "target",
+    "filtername",
+    "damagefilter",
+];
+
+/// Position parameters of entities, transformed with the instance.
+const POINT_PARAMETERS: [&str; 2] = ["origin", "BasisOrigin"];
+
+/// Direction parameters of entities, rotated with the instance.
+const DIRECTION_PARAMETERS: [&str; 3] = ["BasisU", "BasisV", "BasisNormal"];
+
+#[derive(Debug, Error, Clone, Hash, PartialEq, Eq)]
+pub enum InstanceError {
+    #[error("instance `{id}`: error parsing instance: {error}")]
+    Parse { id: i32, error: EntityParseError },
+    #[error("instance `{id}`: io error reading `{path}`: {error}")]
+    Io {
+        id: i32,
+        path: String,
+        error: String,
+    },
+    #[error("instance `{id}`: error deserializing `{path}`: {error}")]
+    Deserialization {
+        id: i32,
+        path: String,
+        error: vdf::Error,
+    },
+    #[error("instance `{id}`: `{path}` includes itself")]
+    Cycle { id: i32, path: String },
+}
+
+struct PendingInstance {
+    entity: Entity,
+    /// Path of the map containing the instance.
+    map_path: PathBuf,
+    /// Paths of the maps the instance is nested in, including `map_path`.
+    ancestors: Vec<PathBuf>,
+}
+
+impl Vmf {
+    /// Collapses the `func_instance` entities into this map, including nested instances.
+    ///
+    /// Instance files are read relative to `map_path`,
+    /// or relative to the `maps` directory of the game file system.
+    /// Instances that fail to load are removed, and their errors returned.
+    pub fn collapse_instances(
+        &mut self,
+        map_path: &PathBuf,
+        fs: &OpenFileSystem,
+    ) -> Vec<InstanceError> {
+        self.collapse_instances_with(map_path, |path| fs.read(path))
+    }
+
+    /// Like [`Vmf::collapse_instances`], but reads the instance files with `read`.
+    pub fn collapse_instances_with(
+        &mut self,
+        map_path: &PathBuf,
+        mut read: impl FnMut(&PathBuf) -> io::Result<Vec<u8>>,
+    ) -> Vec<InstanceError> {
+        let mut collapser = InstanceCollapser {
+            next_ids: NextIds::new(self),
+            auto_names: 0,
+            errors: Vec::new(),
+        };
+
+        let (instances, entities): (Vec<_>, Vec<_>) = mem::take(&mut self.entities)
+            .into_iter()
+            .partition(|entity| entity.class_name == "func_instance");
+        self.entities = entities;
+
+        let mut pending = instances
+            .into_iter()
+            .rev()
+            .map(|entity| PendingInstance {
+                entity,
+                map_path: map_path.clone(),
+                ancestors: vec![map_path.clone()],
+            })
+            .collect::<Vec<_>>();
+
+        while let Some(instance) = pending.pop() {
+            match collapser.merge(self, instance, &mut read) {
+                Ok(nested) => pending.extend(nested.into_iter().rev()),
+                Err(error) => collapser.errors.push(error),
+            }
+        }
+
+        collapser.errors
+    }
+}
+
+struct InstanceCollapser {
+    next_ids: NextIds,
+    auto_names: usize,
+    errors: Vec<InstanceError>,
+}
+
+impl InstanceCollapser {
+    /// Merges the contents of an instance into the map, returning the instances nested in it.
+    fn merge(
+        &mut self,
+        vmf: &mut Vmf,
+        pending: PendingInstance,
+        read: &mut impl FnMut(&PathBuf) -> io::Result<Vec<u8>>,
+    ) -> Result<Vec<PendingInstance>, InstanceError> {
+        let id = pending.entity.id;
+        let instance = Instance::new(&pending.entity);
+        let parse_error = |error| InstanceError::Parse { id, error };
+
+        let file = instance.file().map_err(parse_error)?;
+        let transform = InstanceTransform::new(
+            instance.origin().map_err(parse_error)?,
+            instance.angles().map_err(parse_error)?,
+        );
+        let fixup = NameFixup {
+            name: instance.target_name().map_or_else(
+                || {
+                    self.auto_names += 1;
+                    format!("AutoInstance{}", self.auto_names)
+                },
+                str::to_owned,
+            ),
+            style: instance.fixup_style().map_err(parse_error)?,
+        };
+
+        // replace longer variables first, so that variables sharing a prefix are not mixed up
+        let mut replacements = instance.replacements();
+        replacements.sort_by_key(|(variable, _)| Reverse(variable.len()));
+
+        let (path, bytes) =
+            read_instance(&pending.map_path, file, read).map_err(|error| InstanceError::Io {
+                id,
+                path: file.to_owned(),
+                error: error.to_string(),
+            })?;
+
+        if pending.ancestors.contains(&path) {
+            return Err(InstanceError::Cycle {
+                id,
+                path: path.to_string(),
+            });
+        }
+
+        let mut instance_vmf =
+            Vmf::from_bytes(&bytes).map_err(|error| InstanceError::Deserialization {
+                id,
+                path: path.to_string(),
+                error,
+            })?;
+
+        self.next_ids.remap(&mut instance_vmf);
+
+        for mut solid in instance_vmf.world.solids {
+            transform.solid(&mut solid);
+            replace_materials(&mut solid, &replacements);
+            vmf.world.solids.push(solid);
+        }
+
+        let mut ancestors = pending.ancestors;
+        ancestors.push(path.clone());
+
+        let mut nested = Vec::new();
+
+        for mut entity in instance_vmf.entities {
+            replace_variables(&mut entity, &replacements);
+            for solid in &mut entity.solids {
+                transform.solid(solid);
+                replace_materials(solid, &replacements);
+            }
+            if entity.class_name == "func_instance" {
+                // nested instances must be rotated with this instance even without angles
+                entity
+                    .properties
+                    .entry(UncasedString::from("angles"))
+                    .or_insert_with(|| "0 0 0".to_owned());
+            }
+            transform.entity(&mut entity);
+            fixup.apply(&mut entity);
+
+            match entity.class_name.as_str() {
+                // only holds the parameter definitions for the editor
+                "func_instance_parms" => {}
+                "func_instance" => nested.push(PendingInstance {
+                    entity,
+                    map_path: path.clone(),
+                    ancestors: ancestors.clone(),
+                }),
+                _ => vmf.entities.push(entity),
+            }
+        }
+
+        Ok(nested)
+    }
+}
+
+/// Reads an instance file relative to the containing map, falling back to the `maps` directory.
+fn read_instance(
+    map_path: &PathBuf,
+    file: &str,
+    read: &mut impl FnMut(&PathBuf) -> io::Result<Vec<u8>>,
+) -> io::Result<(PathBuf, Vec<u8>)> {
+    let file = GamePathBuf::from(file);
+
+    let mut relative_path = map_path.clone();
+    relative_path.set_file_name(file.as_str());
+
+    match read(&relative_path) {
+        Ok(bytes) => Ok((relative_path, bytes)),
+        Err(relative_error) => {
+            let game_path = PathBuf::Game(GamePathBuf::from("maps").join(&file));
+            read(&game_path)
+                .map(|bytes| (game_path, bytes))
+                .map_err(|_| relative_error)
+        }
+    }
+}
+
+/// The next free entity, solid and side ids.
+struct NextIds {
+    entity: i32,
+    solid: i32,
+    side: i32,
+}
+
+impl NextIds {
+    fn new(vmf: &Vmf) -> Self {
+        let solids = || {
+            vmf.world
+                .solids
+                .iter()
+                .chain(vmf.entities.iter().flat_map(|e| &e.solids))
+        };
+
+        Self {
+            entity: vmf.entities.iter().map(|e| e.id).max().unwrap_or(0).max(0) + 1,
+            solid: solids().map(|s| s.id).max().unwrap_or(0).max(0) + 1,
+            side: solids()
+                .flat_map(|s| &s.sides)
+                .map(|s| s.id)
+                .max()
+                .unwrap_or(0)
+                .max(0)
+                + 1,
+        }
+    }
+
+    /// Offsets the ids of an instance to follow the ids used so far.
+    fn remap(&mut self, instance_vmf: &mut Vmf) {
+        let instance_ids = Self::new(instance_vmf);
+
+        for solid in instance_vmf
+            .world
+            .solids
+            .iter_mut()
+            .chain(instance_vmf.entities.iter_mut().flat_map(|e| &mut e.solids))
+        {
+            solid.id += self.solid;
+            for side in &mut solid.sides {
+                side.id += self.side;
+            }
+        }
+
+        for entity in &mut instance_vmf.entities {
+            entity.id += self.entity;
+
+            // overlays and cubemaps refer to sides by id
+            if let Some(sides) = entity.properties.get_mut("sides".as_uncased()) {
+                *sides = sides
+                    .split_ascii_whitespace()
+                    .map(|side| {
+                        side.parse::<i32>()
+                            .map_or_else(|_| side.to_owned(), |id| (id + self.side).to_string())
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+            }
+        }
+
+        self.entity += instance_ids.entity;
+        self.solid += instance_ids.solid;
+        self.side += instance_ids.side;
+    }
+}
+
+struct InstanceTransform {
+    origin: Vec3,
+    rotation: Quat,
+}
+
+impl InstanceTransform {
+    fn new(origin: Vec3, angles: [f32; 3]) -> Self {
+        Self {
+            origin,
+            rotation: angles_to_quat(angles),
+        }
+    }
+
+    fn point(&self, point: Vec3) -> Vec3 {
+        self.rotation * point + self.origin
+    }
+
+    fn solid(&self, solid: &mut Solid) {
+        for side in &mut solid.sides {
+            let Plane(a, b, c) = side.plane;
+            side.plane = Plane(self.point(a), self.point(b), self.point(c));
+
+            for uv_axis in [&mut side.u_axis, &mut side.v_axis] {
+                uv_axis.axis = self.rotation * uv_axis.axis;
+                // keep the texture locked to the face
+                if uv_axis.scale != 0.0 {
+                    uv_axis.translation -= f64::from(self.origin.dot(uv_axis.axis)) / uv_axis.scale;
+                }
+            }
+
+            if let Some(disp_info) = &mut side.disp_info {
+                disp_info.start_position = self.point(disp_info.start_position);
+                for data in [
+                    &mut disp_info.normals,
+                    &mut disp_info.offsets,
+                    &mut disp_info.offset_normals,
+                ] {
+                    data.data.mapv_inplace(|v| self.rotation * v);
+                }
+            }
+        }
+    }
+
+    fn entity(&self, entity: &mut Entity) {
+        for parameter in POINT_PARAMETERS {
+            transform_parameter(entity, parameter, |v| self.point(v));
+        }
+
+        for parameter in DIRECTION_PARAMETERS {
+            transform_parameter(entity, parameter, |v| self.rotation * v);
+        }
+
+        let unknown = Unknown::new(entity);
+        let angles = unknown.parse_vector3_parameter("angles").ok().flatten();
+        // lights override the pitch of `angles` with a separate, negated `pitch`
+        let pitch = unknown.parse_float_parameter("pitch").ok().flatten();
+
+        if angles.is_none() && pitch.is_none() {
+            return;
+        }
+
+        let mut angles = angles.unwrap_or_default();
+        if let Some(pitch) = pitch {
+            angles[0] = -pitch;
+        }
+        let angles = quat_to_angles(self.rotation * angles_to_quat(angles));
+
+        if pitch.is_some() {
+            entity
+                .properties
+                .insert(UncasedString::from("pitch"), format_float(-angles[0]));
+        }
+        entity
+            .properties
+            .insert(UncasedString::from("angles"), format_vector(angles));
+    }
+}
+
+fn transform_parameter(entity: &mut Entity, parameter: &'static str, f: impl Fn(Vec3) -> Vec3) {
+    if let Ok(Some(value)) = Unknown::new(entity).parse_vector3_parameter(parameter) {
+        let value = f(value.into()).to_array();
+        entity
+            .properties
+            .insert(UncasedString::from(parameter), format_vector(value));
+    }
+}
+
+/// Converts pitch, yaw, roll angles in degrees into a rotation.
+fn angles_to_quat([pitch, yaw, roll]: [f32; 3]) -> Quat {
+    Quat::from_euler(
+        EulerRot::ZYX,
+        yaw.to_radians(),
+        pitch.to_radians(),
+        roll.to_radians(),
+    )
+}
+
+fn quat_to_angles(rotation: Quat) -> [f32; 3] {
+    let (yaw, pitch, roll) = rotation.to_euler(EulerRot::ZYX);
+    [pitch.to_degrees(), yaw.to_degrees(), roll.to_degrees()]
+}
+
+fn format_vector(vector: [f32; 3]) -> String {
+    let [x, y, z] = vector.map(round_float);
+    format!("{x} {y} {z}")
+}
+
+fn format_float(value: f32) -> String {
+    round_float(value).to_string()
+}
+
+/// Rounds away floating point noise from the rotations.
+fn round_float(value: f32) -> f32 {
+    (value * 1000.0).round() / 1000.0 + 0.0
+}
+
+struct NameFixup {
+    name: String,
+    style: FixupStyle,
+}
+
+impl NameFixup {
+    fn fix(&self, value: &str) -> Option<String> {
+        // names starting with @ are global, ! are special names like !player
+        if value.is_empty() || value.starts_with(['@', '!']) {
+            return None;
+        }
+
+        match self.style {
+            FixupStyle::Prefix => Some(format!("{}-{}", self.name, value)),
+            FixupStyle::Postfix => Some(format!("{}-{}", value, self.name)),
+            FixupStyle::None => None,
+        }
+    }
+
+    fn apply(&self, entity: &mut Entity) {
+        for parameter in NAME_PARAMETERS {
+            if let Some(value) = entity.properties.get_mut(parameter.as_uncased()) {
+                if let Some(fixed) = self.fix(value) {
+                    *value = fixed;
+                }
+            }
+        }
+
+        // the connection target is the first field
+        for connection in entity.connections.values_mut() {
+            let separator = if connection.contains('\x1b') {
+                '\x1b'
+            } else {
+                ','
+            };
+
+            if let Some((target, rest)) = connection.split_once(separator) {
+                if let Some(fixed) = self.fix(target) {
+                    *connection = format!("{fixed}{separator}{rest}");
+                }
+            }
+        }
+    }
+}
+
+fn replace_variables(entity: &mut Entity, replacements: &[(&str, &str)]) {
+    let variables = replacements
+        .iter()
+        .filter(|(variable, _)| variable.starts_with('$'));
+
+    for value in entity
+        .properties
+        .values_mut()
+        .chain(entity.connections.values_mut())
+    {
+        for (variable, replacement) in variables.clone() {
+            if value.contains(variable) {
+                *value = value.replace(variable, replacement);
+            }
+        }
+    }
+}
+
+fn replace_materials(solid: &mut Solid, replacements: &[(&str, &str)]) {
+    for side in &mut solid.sides {
+        if let Some((_, replacement)) = replacements.iter().find(|(variable, _)| {
+            variable
+                .strip_prefix('#')
+                .is_some_and(|material| side.material.as_str().eq_ignore_ascii_case(material))
+        }) {
+            side.material = GamePathBuf::from(*replacement);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+
+use approx::assert_relative_eq;
+
+use super::*;
+use crate::entities::TypedEntity;
+
+const MAP: &str = r##"
+world
+{
+    "id" "1"
+    "mapversion" "1"
+    "classname" "worldspawn"
+    "skyname" "sky_day01_01"
+}
+entity
+{
+    "id" "2"
+    "classname" "func_instance"
+    "targetname" "door"
+    "file" "instances/door.vmf"
+    "origin" "100 0 0"
+    "angles" "0 90 0"
+    "replace01" "$speed 200"
+    "replace02" "#dev/dev_measuregeneric01 tools/toolsnodraw"
+}
+"##;
+
+const DOOR: &str = r##"
+world
+{
+    "id" "1"
+    "mapversion" "1"
+    "classname" "worldspawn"
+    "skyname" "sky_day01_01"
+    solid
+    {
+        "id" "2"
+        side
+        {
+            "id" "3"
+            "plane" "(0 0 64) (64 0 64) (64 -64 64)"
+            "material" "DEV/DEV_MEASUREGENERIC01"
+            "uaxis" "[1 0 0 0] 0.25"
+            "vaxis" "[0 -1 0 0] 0.25"
+            "lightmapscale" "16"
+        }
+    }
+}
+entity
+{
+    "id" "4"
+    "classname" "func_instance_parms"
+    "parm1" "$speed integer 100"
+}
+entity
+{
+    "id" "5"
+    "classname" "prop_dynamic"
+    "targetname" "model"
+    "parentname" "@global"
+    "speed" "$speed"
+    "origin" "10 0 0"
+    "angles" "0 0 0"
+    connections
+    {
+        "OnUser1" "model,SetAnimation,open,0,-1"
+    }
+}
+entity
+{
+    "id" "6"
+    "classname" "func_instance"
+    "file" "handle.vmf"
+    "origin" "0 10 0"
+}
+"##;
+
+const HANDLE: &str = r##"
+world
+{
+    "id" "1"
+    "mapversion" "1"
+    "classname" "worldspawn"
+    "skyname" "sky_day01_01"
+}
+entity
+{
+    "id" "1"
+    "classname" "info_target"
+    "targetname" "handle"
+    "origin" "1 0 0"
+}
+"##;
+
+fn files() -> BTreeMap<String, &'static str> {
+    let mut files = BTreeMap::new();
+    files.insert("maps/test.vmf".to_owned(), MAP);
+    files.insert("maps/instances/door.vmf".to_owned(), DOOR);
+    files.insert("maps/instances/handle.vmf".to_owned(), HANDLE);
+    files
+}
+
+fn read<'a>(
+    files: &'a BTreeMap<String, &'static str>,
+) -> impl FnMut(&PathBuf) -> io::Result<Vec<u8>> + 'a {
+    |path: &PathBuf| {
+        files
+            .get(&path.to_string())
+            .map(|s| s.as_bytes().to_vec())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+fn parameter<'a>(entity: &'a Entity, parameter: &str) -> &'a str {
+    &entity.properties[parameter.as_uncased()]
+}
+
+#[test]
+fn collapse() {
+    let files = files();
+    let map_path = PathBuf::Game(GamePathBuf::from("maps/test.vmf"));
+    let mut vmf = Vmf::from_bytes(MAP.as_bytes()).unwrap();
+
+    let errors = vmf.collapse_instances_with(&map_path, read(&files));
+    assert_eq!(errors, Vec::new());
+
+    // instance parameters are not imported
+    assert_eq!(vmf.entities.len(), 2);
+    let prop = &vmf.entities[0];
+    let target = &vmf.entities[1];
+
+    assert!(matches!(prop.typed(), TypedEntity::Prop(..)));
+    assert_eq!(parameter(prop, "targetname"), "door-model");
+    assert_eq!(parameter(prop, "parentname"), "@global");
+    assert_eq!(parameter(prop, "speed"), "200");
+    assert_eq!(parameter(prop, "origin"), "100 10 0");
+    assert_eq!(parameter(prop, "angles"), "0 90 0");
+    assert_eq!(
+        prop.connections["OnUser1".as_uncased()],
+        "door-model,SetAnimation,open,0,-1"
+    );
+
+    // nested instances are relative to their own map and transformed by the parent instance
+    assert_eq!(parameter(target, "targetname"), "AutoInstance1-handle");
+    assert_eq!(parameter(target, "origin"), "90 1 0");
+
+    // ids don't collide with the parent map
+    assert_eq!(vmf.world.solids.len(), 1);
+    let solid = &vmf.world.solids[0];
+    assert!(solid.id > 2);
+    assert!(prop.id > 2 && target.id > prop.id);
+
+    let side = &solid.sides[0];
+    assert_eq!(side.material, GamePathBuf::from("tools/toolsnodraw"));
+    assert_relative_eq!(side.plane.1, Vec3::new(100.0, 64.0, 64.0), epsilon = 1e-3);
+    assert_relative_eq!(side.u_axis.axis, Vec3::new(0.0, 1.0, 0.0), epsilon = 1e-6);
+}
+
+#[test]
+fn rotated_spotlight() {
+    let mut entity: Entity = vdf::from_str(
+        r#"
+        "id" "1"
+        "classname" "light_spot"
+        "origin" "0 0 0"
+        "angles" "0 45 0"
+        "pitch" "-45"
+        "#,
+    )
+    .unwrap();
+    let original = match entity.typed() {
+        TypedEntity::SpotLight(light) => angles_to_quat(light.angles().unwrap()),
+        _ => panic!("not a spotlight"),
+    };
+
+    // the pitch of the instance changes the pitch of the light
+    let transform = InstanceTransform::new(Vec3::ZERO, [30.0, 90.0, 0.0]);
+    transform.entity(&mut entity);
+
+    let rotated = match entity.typed() {
+        TypedEntity::SpotLight(light) => angles_to_quat(light.angles().unwrap()),
+        _ => panic!("not a spotlight"),
+    };
+    assert_relative_eq!(
+        rotated * Vec3::X,
+        transform.rotation * original * Vec3::X,
+        epsilon = 1e-3
+    );
+}
+
+#[test]
+fn cycle() {
+    let mut files = files();
+    files.insert("maps/instances/handle.vmf".to_owned(), DOOR);
+
+    let map_path = PathBuf::Game(GamePathBuf::from("maps/test.vmf"));
+    let mut vmf = Vmf::from_bytes(MAP.as_bytes()).unwrap();
+
+    let errors = vmf.collapse_instances_with(&map_path, read(&files));
+    assert!(matches!(errors.as_slice(), [InstanceError::Cycle { .. }]));
+}
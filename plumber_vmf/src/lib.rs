@@ -1,12 +1,17 @@
 mod builder_utils;
+mod decal_builder;
 pub mod entities;
+pub mod instance;
 mod overlay_builder;
 mod solid_builder;
 mod types;
 pub mod vmf;
 
 pub mod builder {
-    pub use super::builder_utils::{GeometrySettings, InvisibleSolids, MergeSolids};
+    pub use super::builder_utils::{
+        BrushNormals, GeometrySettings, InvisibleSolids, MergeSolids, NormalOutput,
+    };
+    pub use super::decal_builder::{BuiltDecal, DecalError};
     pub use super::overlay_builder::{BuiltOverlay, BuiltOverlayFace, OverlayError};
     pub use super::solid_builder::{
         BuiltBrushEntity, BuiltSolid, MergedSolids, SolidError, SolidFace,
@@ -30,3 +35,10 @@ pub fn from_bytes(input: &[u8]) -> vdf::Result<Vmf> {
 pub fn to_string(vmf: &Vmf) -> vdf::Result<String> {
     vmf.to_string()
 }
+
+/// # Errors
+///
+/// Returns `Err` if the serialization fails.
+pub fn to_string_with_options(vmf: &Vmf, options: &vdf::FormatOptions) -> vdf::Result<String> {
+    vmf.to_string_with_options(options)
+}
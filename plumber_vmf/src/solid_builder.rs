@@ -1,6 +1,6 @@
 #![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
 
-use std::{fmt::Debug, mem, ptr, sync::Mutex};
+use std::{collections::BTreeMap, fmt::Debug, mem, ops::Range, ptr, slice, sync::Mutex};
 
 use approx::relative_eq;
 use glam::{Vec2, Vec3};
@@ -18,7 +18,8 @@ use super::{
         PolygonClassification,
     },
     overlay_builder::SideFacesMap,
-    vmf::{Entity, Side, Solid, World},
+    types::{Plane, UvAxis},
+    vmf::{CordonBox, Editor, Entity, Side, Solid, World},
 };
 
 #[cfg(test)]
@@ -33,6 +34,8 @@ pub enum SolidError {
 #[derive(Debug)]
 pub struct BuiltSolid {
     pub id: i32,
+    /// Ids of the visgroups the solid is in.
+    pub vis_group_ids: Vec<i32>,
     pub position: Vec3,
     pub scale: f32,
     pub vertices: Vec<Vec3>,
@@ -59,6 +62,11 @@ pub struct SolidFace {
     pub material_index: usize,
     pub vertice_alphas: Vec<f32>,
     pub vertice_multiblends: Option<Vec<[f32; 4]>>,
+    /// Smooth normals on displacements, normals following the smoothing groups of the side elsewhere.
+    /// With welded normals, every face using a vertice has the same normal for it.
+    pub vertice_normals: Vec<Vec3>,
+    /// Uvs in the lightmap atlas of the brush entity, if they were generated.
+    pub lightmap_uvs: Option<Vec<Vec2>>,
 }
 
 #[derive(Clone)]
@@ -70,6 +78,9 @@ struct FaceBuilder<'a> {
     material_index: usize,
     vertice_alphas: Vec<f32>,
     vertice_multiblends: Option<Vec<[f32; 4]>>,
+    vertice_normals: Vec<Vec3>,
+    /// Lightmap coordinates in luxels until the lightmap is packed.
+    lightmap_uvs: Option<Vec<Vec2>>,
 }
 
 impl<'a> PartialEq for FaceBuilder<'a> {
@@ -105,6 +116,8 @@ impl<'a> FaceBuilder<'a> {
             material_index: 0,
             vertice_alphas: Vec::new(),
             vertice_multiblends: None,
+            vertice_normals: Vec::new(),
+            lightmap_uvs: None,
         }
     }
 
@@ -117,6 +130,8 @@ impl<'a> FaceBuilder<'a> {
             material_index: self.material_index,
             vertice_alphas: Vec::new(),
             vertice_multiblends: None,
+            vertice_normals: Vec::new(),
+            lightmap_uvs: None,
         }
     }
 
@@ -280,6 +295,7 @@ impl<'a> FaceBuilder<'a> {
         old_vertices: &[Vec3],
         vertices: &mut Vec<Vec3>,
         faces: &mut Vec<FaceBuilder<'a>>,
+        grids: &mut Vec<DisplacementGrid>,
     ) -> Result<(), SolidError> {
         if let Some(info) = &self.side.disp_info {
             self.verify_displacement()?;
@@ -375,6 +391,8 @@ impl<'a> FaceBuilder<'a> {
                     material_index: self.material_index,
                     vertice_alphas: Vec::new(),
                     vertice_multiblends: None,
+                    vertice_normals: Vec::new(),
+                    lightmap_uvs: None,
                 },
             );
 
@@ -404,6 +422,12 @@ impl<'a> FaceBuilder<'a> {
             }
 
             faces.append(&mut disp_faces.into_raw_vec());
+
+            grids.push(DisplacementGrid {
+                corners: [top_left_vert, top_right_vert, btm_right_vert, btm_left_vert]
+                    .map(|corner| corner + center),
+                vertice_indices: disp_vertice_is,
+            });
         }
         Ok(())
     }
@@ -536,29 +560,39 @@ impl<'a> FaceBuilder<'a> {
         let mut front = self.new_split();
         let mut back = self.new_split();
 
+        // alphas, multiblends, normals and lightmap uvs are only split if they have been built already
+        if self.vertice_multiblends.is_some() {
+            front.vertice_multiblends = Some(Vec::new());
+            back.vertice_multiblends = Some(Vec::new());
+        }
+        if self.lightmap_uvs.is_some() {
+            front.lightmap_uvs = Some(Vec::new());
+            back.lightmap_uvs = Some(Vec::new());
+        }
+
         // iterate pairs of vertices that form edges
-        for ((&i, &uv, pos), (&i_next, &uv_next, pos_next)) in
-            izip!(&self.vertice_indices, &self.vertice_uvs, vertice_positions)
-                .circular_tuple_windows()
+        for ((j, &i, &uv, pos), (j_next, &i_next, &uv_next, pos_next)) in izip!(
+            0..self.vertice_indices.len(),
+            &self.vertice_indices,
+            &self.vertice_uvs,
+            vertice_positions
+        )
+        .circular_tuple_windows()
         {
             let vert = vertices[i] + self_center - plane_center;
             let vert_next = vertices[i_next] + self_center - plane_center;
 
-            match pos {
-                PointClassification::Front => {
-                    front.vertice_indices.push(i);
-                    front.vertice_uvs.push(uv);
-                }
-                PointClassification::Back => {
-                    back.vertice_indices.push(i);
-                    back.vertice_uvs.push(uv);
-                }
-                PointClassification::OnPlane => {
-                    front.vertice_indices.push(i);
-                    front.vertice_uvs.push(uv);
-                    back.vertice_indices.push(i);
-                    back.vertice_uvs.push(uv);
-                }
+            if matches!(
+                pos,
+                PointClassification::Front | PointClassification::OnPlane
+            ) {
+                front.push_split_vertice(self, i, uv, j, j, 0.0);
+            }
+            if matches!(
+                pos,
+                PointClassification::Back | PointClassification::OnPlane
+            ) {
+                back.push_split_vertice(self, i, uv, j, j, 0.0);
             }
 
             if (pos == PointClassification::OnPlane) ^ (pos_next == PointClassification::OnPlane) {
@@ -589,25 +623,115 @@ impl<'a> FaceBuilder<'a> {
                     vertices.push(new_vert);
                     vertices.len() - 1
                 });
-            front.vertice_indices.push(new_i);
-            back.vertice_indices.push(new_i);
-
             // calculate uvs
             let new_uv = uv.lerp(uv_next, factor);
-            front.vertice_uvs.push(new_uv);
-            back.vertice_uvs.push(new_uv);
+            front.push_split_vertice(self, new_i, new_uv, j, j_next, factor);
+            back.push_split_vertice(self, new_i, new_uv, j, j_next, factor);
         }
 
         (front, back)
     }
 
+    /// Pushes a vertice created by splitting `source`,
+    /// interpolating the per-vertice data of the vertices `a` and `b` of `source`.
+    fn push_split_vertice(
+        &mut self,
+        source: &Self,
+        vertice_index: usize,
+        uv: Vec2,
+        a: usize,
+        b: usize,
+        factor: f32,
+    ) {
+        self.vertice_indices.push(vertice_index);
+        self.vertice_uvs.push(uv);
+
+        if !source.vertice_alphas.is_empty() {
+            let alpha = source.vertice_alphas[a]
+                + (source.vertice_alphas[b] - source.vertice_alphas[a]) * factor;
+            self.vertice_alphas.push(alpha);
+        }
+
+        if let (Some(multiblends), Some(source_multiblends)) =
+            (&mut self.vertice_multiblends, &source.vertice_multiblends)
+        {
+            let (from, to) = (source_multiblends[a], source_multiblends[b]);
+            multiblends.push([0, 1, 2, 3].map(|k| from[k] + (to[k] - from[k]) * factor));
+        }
+
+        if !source.vertice_normals.is_empty() {
+            let normal = source.vertice_normals[a].lerp(source.vertice_normals[b], factor);
+            self.vertice_normals.push(normal.normalize_or_zero());
+        }
+
+        if let (Some(lightmap_uvs), Some(source_lightmap_uvs)) =
+            (&mut self.lightmap_uvs, &source.lightmap_uvs)
+        {
+            lightmap_uvs.push(source_lightmap_uvs[a].lerp(source_lightmap_uvs[b], factor));
+        }
+    }
+
+    /// Splits the face into the part inside the box, if any, and the parts outside it.
+    fn split_by_box(
+        &self,
+        self_center: Vec3,
+        vertices: &mut Vec<Vec3>,
+        cordon: &CordonBox,
+        epsilon: f32,
+    ) -> (Option<Self>, Vec<Self>) {
+        let planes = [
+            NdPlane::from_point_normal(cordon.maxs, Vec3::X),
+            NdPlane::from_point_normal(cordon.mins, -Vec3::X),
+            NdPlane::from_point_normal(cordon.maxs, Vec3::Y),
+            NdPlane::from_point_normal(cordon.mins, -Vec3::Y),
+            NdPlane::from_point_normal(cordon.maxs, Vec3::Z),
+            NdPlane::from_point_normal(cordon.mins, -Vec3::Z),
+        ];
+
+        let mut face = self.clone();
+        let mut outside = Vec::new();
+
+        for plane in &planes {
+            match plane.classify_polygon(
+                face.vertice_indices
+                    .iter()
+                    .map(|&i| vertices[i] + self_center),
+                epsilon,
+            ) {
+                PolygonClassification::Front => {
+                    outside.push(face);
+                    return (None, outside);
+                }
+                PolygonClassification::Back | PolygonClassification::OnPlane => {}
+                PolygonClassification::Spanning => {
+                    let (front, back) =
+                        face.split(self_center, vertices, plane, Vec3::ZERO, epsilon);
+                    if front.vertice_indices.len() > 2 {
+                        outside.push(front);
+                    }
+                    face = back;
+                }
+            }
+        }
+
+        ((face.vertice_indices.len() > 2).then_some(face), outside)
+    }
+
     fn finish(self) -> SolidFace {
+        let vertice_normals = if self.vertice_normals.is_empty() {
+            vec![self.plane.normal; self.vertice_indices.len()]
+        } else {
+            self.vertice_normals
+        };
+
         SolidFace {
             vertice_indices: self.vertice_indices,
             vertice_uvs: self.vertice_uvs,
             material_index: self.material_index,
             vertice_alphas: self.vertice_alphas,
             vertice_multiblends: self.vertice_multiblends,
+            vertice_normals,
+            lightmap_uvs: self.lightmap_uvs,
         }
     }
 }
@@ -620,6 +744,7 @@ struct SolidBuilder<'a> {
     vertices: Vec<Vec3>,
     materials: Vec<SolidMaterial>,
     is_displacement: bool,
+    displacement_grids: Vec<DisplacementGrid>,
     aabb_min: Vec3,
     aabb_max: Vec3,
 }
@@ -656,6 +781,7 @@ impl<'a> SolidBuilder<'a> {
             vertices: Vec::new(),
             materials: Vec::new(),
             is_displacement: false,
+            displacement_grids: Vec::new(),
             aabb_min: Vec3::new(0.0, 0.0, 0.0),
             aabb_max: Vec3::new(0.0, 0.0, 0.0),
         }
@@ -748,6 +874,7 @@ impl<'a> SolidBuilder<'a> {
                 old_vertices,
                 &mut vertices,
                 &mut faces,
+                &mut self.displacement_grids,
             )?;
         }
 
@@ -781,7 +908,6 @@ impl<'a> SolidBuilder<'a> {
     fn build(
         &mut self,
         get_material_info: impl FnMut(&PathBuf) -> Option<MaterialInfo>,
-        side_faces_map: &Mutex<SideFacesMap>,
         settings: &GeometrySettings,
     ) -> Result<(), SolidError> {
         self.intersect_sides(settings.epsilon, settings.cut_threshold);
@@ -789,13 +915,57 @@ impl<'a> SolidBuilder<'a> {
         self.sort_vertices();
         self.build_uvs(get_material_info);
         self.maybe_build_displacement()?;
+
+        if settings.lightmap_uvs {
+            self.build_lightmap_coords();
+        }
+
+        Ok(())
+    }
+
+    /// Projects the faces onto their texture axes, scaled to luxels by the lightmap scale like vbsp.
+    /// Displacements are projected before they are displaced.
+    fn build_lightmap_coords(&mut self) {
+        let mut base_positions = vec![None; self.vertices.len()];
+        for grid in &self.displacement_grids {
+            for (position, &i) in grid.vertice_indices.indexed_iter() {
+                base_positions[i] = Some(grid.base_position(position));
+            }
+        }
+
+        for face in &mut self.faces {
+            let luxel_size = face.side.light_map_scale.max(1) as f32;
+            let s_axis = face.side.u_axis.axis.normalize_or_zero() / luxel_size;
+            let t_axis = face.side.v_axis.axis.normalize_or_zero() / luxel_size;
+
+            face.lightmap_uvs = Some(
+                face.vertice_indices
+                    .iter()
+                    .map(|&i| {
+                        let position =
+                            base_positions[i].unwrap_or_else(|| self.vertices[i] + self.center);
+                        Vec2::new(position.dot(s_axis), position.dot(t_axis))
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    /// Finishes building after neighbouring displacements have been sewn.
+    fn finish_build(
+        &mut self,
+        side_faces_map: &Mutex<SideFacesMap>,
+        cordons: &[CordonBox],
+        epsilon: f32,
+    ) {
+        if self.is_displacement {
+            self.clip_to_cordons(cordons, epsilon);
+        }
         self.extend_side_faces_map(side_faces_map);
 
         if !self.is_displacement {
             self.create_default_alphas();
         }
-
-        Ok(())
     }
 
     fn calculate_aabb(&mut self) {
@@ -839,7 +1009,34 @@ impl<'a> SolidBuilder<'a> {
             );
         }
 
-        // remove unused vertices
+        self.remove_unused_vertices();
+    }
+
+    /// Clips the faces to the cordon boxes, keeping the parts inside any of them.
+    /// Parts inside overlapping boxes are only kept once.
+    /// Used for displacements, other solids are cut by the boxes before building to stay closed.
+    /// Does nothing if there are no boxes.
+    fn clip_to_cordons(&mut self, cordons: &[CordonBox], epsilon: f32) {
+        if cordons.is_empty() {
+            return;
+        }
+
+        let mut remaining = mem::take(&mut self.faces);
+
+        // each box only gets the parts that weren't inside the previous boxes
+        for cordon in cordons {
+            for builder in mem::take(&mut remaining) {
+                let (inside, outside) =
+                    builder.split_by_box(self.center, &mut self.vertices, cordon, epsilon);
+                self.faces.extend(inside);
+                remaining.extend(outside);
+            }
+        }
+
+        self.remove_unused_vertices();
+    }
+
+    fn remove_unused_vertices(&mut self) {
         let mut vertices_to_retain = vec![false; self.vertices.len()];
 
         for builder in &self.faces {
@@ -884,6 +1081,7 @@ impl<'a> SolidBuilder<'a> {
     fn finish(self, scale: f32) -> BuiltSolid {
         BuiltSolid {
             id: self.solid.id,
+            vis_group_ids: editor_vis_group_ids(&self.solid.editor),
             position: self.center * scale,
             scale,
             vertices: self.vertices,
@@ -893,6 +1091,551 @@ impl<'a> SolidBuilder<'a> {
     }
 }
 
+/// Vertices of a displacement side, used to sew neighbouring displacements together.
+#[derive(Debug, Clone, PartialEq)]
+struct DisplacementGrid {
+    /// Corners of the side before it's displaced, in world space,
+    /// in top left, top right, bottom right, bottom left order.
+    corners: [Vec3; 4],
+    vertice_indices: Array2<usize>,
+}
+
+impl DisplacementGrid {
+    /// Returns the world space position of a grid vertice before it's displaced.
+    fn base_position(&self, (row, col): (usize, usize)) -> Vec3 {
+        let last = (self.vertice_indices.nrows() - 1) as f32;
+        let left = self.corners[0].lerp(self.corners[3], row as f32 / last);
+        let right = self.corners[1].lerp(self.corners[2], row as f32 / last);
+        left.lerp(right, col as f32 / last)
+    }
+
+    /// Returns the grid positions on the border, in order around the grid.
+    fn border(&self) -> Vec<(usize, usize)> {
+        let last = self.vertice_indices.nrows() - 1;
+
+        (0..last)
+            .map(|col| (0, col))
+            .chain((0..last).map(|row| (row, last)))
+            .chain((1..=last).rev().map(|col| (last, col)))
+            .chain((1..=last).rev().map(|row| (row, 0)))
+            .collect()
+    }
+}
+
+/// A vertice on the border of a displacement.
+struct BorderVertice {
+    builder: usize,
+    vertice: usize,
+    /// Position before the displacement is applied, in world space.
+    base: Vec3,
+}
+
+/// The border of a displacement, as a range of border vertices.
+struct BorderLoop {
+    vertices: Range<usize>,
+    aabb_min: Vec3,
+    aabb_max: Vec3,
+}
+
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Welds the border vertices shared by neighbouring displacements,
+/// snapping T-junctions between displacements of different powers to the neighbouring edge,
+/// and calculates smooth vertice normals for the displacements.
+fn sew_displacements(builders: &mut [SolidBuilder], epsilon: f32) {
+    let mut border = Vec::new();
+    let mut loops = Vec::new();
+
+    for (builder_i, builder) in builders.iter().enumerate() {
+        for grid in &builder.displacement_grids {
+            let start = border.len();
+            border.extend(grid.border().into_iter().map(|position| BorderVertice {
+                builder: builder_i,
+                vertice: grid.vertice_indices[position],
+                base: grid.base_position(position),
+            }));
+
+            let (aabb_min, aabb_max) = grid.corners.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), &corner| (min.min(corner), max.max(corner)),
+            );
+
+            loops.push(BorderLoop {
+                vertices: start..border.len(),
+                aabb_min: aabb_min - Vec3::splat(epsilon),
+                aabb_max: aabb_max + Vec3::splat(epsilon),
+            });
+        }
+    }
+
+    if border.is_empty() {
+        return;
+    }
+
+    // group the border vertices that are in the same place before displacing
+    let mut parents = (0..border.len()).collect_vec();
+    let mut order = (0..border.len()).collect_vec();
+    order.sort_by(|&a, &b| border[a].base.x.total_cmp(&border[b].base.x));
+
+    for (k, &a) in order.iter().enumerate() {
+        for &b in &order[k + 1..] {
+            if border[b].base.x - border[a].base.x > epsilon {
+                break;
+            }
+            if relative_eq!(border[a].base, border[b].base, epsilon = epsilon) {
+                let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+                parents[root_b] = root_a;
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..border.len() {
+        groups
+            .entry(find_root(&mut parents, i))
+            .or_default()
+            .push(i);
+    }
+    let groups = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect_vec();
+
+    let position = |builders: &[SolidBuilder], vertice: &BorderVertice| {
+        let builder = &builders[vertice.builder];
+        builder.vertices[vertice.vertice] + builder.center
+    };
+    let set_position = |builders: &mut [SolidBuilder], vertice: &BorderVertice, position: Vec3| {
+        let builder = &mut builders[vertice.builder];
+        builder.vertices[vertice.vertice] = position - builder.center;
+    };
+
+    for group in &groups {
+        let average = group
+            .iter()
+            .fold(Vec3::ZERO, |sum, &i| sum + position(builders, &border[i]))
+            / group.len() as f32;
+
+        for &i in group {
+            set_position(builders, &border[i], average);
+        }
+    }
+
+    // find border vertices in the middle of the border edges of other displacements
+    let mut t_junctions = Vec::new();
+
+    for (loop_i, border_loop) in loops.iter().enumerate() {
+        for i in border_loop.vertices.clone() {
+            let base = border[i].base;
+
+            let edge = loops
+                .iter()
+                .enumerate()
+                .filter(|&(other_i, other)| {
+                    other_i != loop_i
+                        && base.cmpge(other.aabb_min).all()
+                        && base.cmple(other.aabb_max).all()
+                })
+                .find_map(|(_, other)| {
+                    other
+                        .vertices
+                        .clone()
+                        .zip(other.vertices.clone().cycle().skip(1))
+                        .find_map(|(a, b)| {
+                            let start = border[a].base;
+                            let direction = border[b].base - start;
+                            let length = direction.length();
+                            let along = (base - start).dot(direction) / length;
+
+                            (along > epsilon
+                                && along < length - epsilon
+                                && (start + direction * (along / length)).distance(base) <= epsilon)
+                                .then_some((a, b, along / length))
+                        })
+                });
+
+            if let Some((a, b, factor)) = edge {
+                t_junctions.push((i, a, b, factor));
+            }
+        }
+    }
+
+    for &(i, a, b, factor) in &t_junctions {
+        let snapped = position(builders, &border[a]).lerp(position(builders, &border[b]), factor);
+        set_position(builders, &border[i], snapped);
+    }
+
+    // calculate smooth normals, weighted by face area
+    let mut normal_sums = builders
+        .iter()
+        .map(|builder| vec![Vec3::ZERO; builder.vertices.len()])
+        .collect_vec();
+
+    for (builder, sums) in builders.iter().zip(&mut normal_sums) {
+        if !builder.is_displacement {
+            continue;
+        }
+
+        for face in &builder.faces {
+            let &[a, b, c] = face.vertice_indices.as_slice() else {
+                continue;
+            };
+            let vertices = &builder.vertices;
+            let normal = (vertices[b] - vertices[a]).cross(vertices[c] - vertices[a]);
+
+            for i in [a, b, c] {
+                sums[i] += normal;
+            }
+        }
+    }
+
+    for group in &groups {
+        let sum = group.iter().fold(Vec3::ZERO, |sum, &i| {
+            sum + normal_sums[border[i].builder][border[i].vertice]
+        });
+
+        for &i in group {
+            normal_sums[border[i].builder][border[i].vertice] = sum;
+        }
+    }
+
+    for &(i, a, b, factor) in &t_junctions {
+        let neighbour = normal_sums[border[a].builder][border[a].vertice]
+            .lerp(normal_sums[border[b].builder][border[b].vertice], factor);
+        normal_sums[border[i].builder][border[i].vertice] += neighbour;
+    }
+
+    for (builder, sums) in builders.iter_mut().zip(&normal_sums) {
+        if !builder.is_displacement {
+            continue;
+        }
+
+        for face in &mut builder.faces {
+            face.vertice_normals = face
+                .vertice_indices
+                .iter()
+                .map(|&i| sums[i].normalize_or_zero())
+                .collect();
+        }
+    }
+}
+
+/// Averages the normals of brush faces that share a smoothing group where they meet.
+/// Faces without smoothing groups and displacements are left as they are.
+fn smooth_brush_normals(builders: &mut [SolidBuilder], epsilon: f32) {
+    // (position, smoothing groups, normal, builder index, face index, face vertice index)
+    let mut corners = Vec::new();
+
+    for (builder_i, builder) in builders.iter().enumerate() {
+        if builder.is_displacement {
+            continue;
+        }
+
+        for (face_i, face) in builder.faces.iter().enumerate() {
+            let groups = face.side.smoothing_groups;
+            if groups == 0 {
+                continue;
+            }
+
+            corners.extend(face.vertice_indices.iter().enumerate().map(|(i, &vi)| {
+                (
+                    builder.vertices[vi] + builder.center,
+                    groups,
+                    face.plane.normal,
+                    builder_i,
+                    face_i,
+                    i,
+                )
+            }));
+        }
+    }
+
+    corners.sort_by(|a, b| a.0.x.total_cmp(&b.0.x));
+
+    let mut normals = Vec::with_capacity(corners.len());
+
+    for (k, &(position, groups, normal, ..)) in corners.iter().enumerate() {
+        let neighbours = corners[..k]
+            .iter()
+            .rev()
+            .take_while(|other| position.x - other.0.x <= epsilon)
+            .chain(
+                corners[k + 1..]
+                    .iter()
+                    .take_while(|other| other.0.x - position.x <= epsilon),
+            );
+
+        // coplanar faces meeting at the corner only count once
+        let mut plane_normals = vec![normal];
+        for other in neighbours.filter(|other| {
+            other.1 & groups != 0 && relative_eq!(other.0, position, epsilon = epsilon)
+        }) {
+            if !plane_normals
+                .iter()
+                .any(|n| relative_eq!(*n, other.2, epsilon = epsilon))
+            {
+                plane_normals.push(other.2);
+            }
+        }
+
+        normals.push(
+            plane_normals
+                .into_iter()
+                .fold(Vec3::ZERO, |sum, n| sum + n)
+                .normalize_or_zero(),
+        );
+    }
+
+    for (&(.., builder_i, face_i, i), normal) in corners.iter().zip(normals) {
+        let face = &mut builders[builder_i].faces[face_i];
+        if face.vertice_normals.is_empty() {
+            face.vertice_normals = vec![face.plane.normal; face.vertice_indices.len()];
+        }
+        face.vertice_normals[i] = normal;
+    }
+}
+
+/// Duplicates the vertices used with different normals, so each vertice has a single normal.
+fn weld_normals(vertices: &mut Vec<Vec3>, faces: &mut [SolidFace], epsilon: f32) {
+    // the normals each vertice is used with, and the index of the vertice with that normal
+    let mut copies: Vec<Vec<(Vec3, usize)>> = vec![Vec::new(); vertices.len()];
+
+    for face in faces {
+        for (vertice_index, &normal) in face.vertice_indices.iter_mut().zip(&face.vertice_normals) {
+            let vertice_copies = &mut copies[*vertice_index];
+
+            if let Some(&(_, copy)) = vertice_copies
+                .iter()
+                .find(|(n, _)| relative_eq!(*n, normal, epsilon = epsilon))
+            {
+                *vertice_index = copy;
+                continue;
+            }
+
+            // the first normal keeps the original vertice
+            let copy = if vertice_copies.is_empty() {
+                *vertice_index
+            } else {
+                vertices.push(vertices[*vertice_index]);
+                vertices.len() - 1
+            };
+
+            vertice_copies.push((normal, copy));
+            *vertice_index = copy;
+        }
+    }
+}
+
+/// Material of the faces closing solids where they are cut by a cordon.
+const CORDON_CAP_MATERIAL: &str = "TOOLS/TOOLSNODRAW";
+
+/// Returns a copy of the solid with the planes of the box added as sides,
+/// so building it results in the closed part of the solid inside the box.
+fn cut_to_box(solid: &Solid, cordon: &CordonBox) -> Solid {
+    // (outward normal, in-plane axes with u x v = normal)
+    let planes = [
+        (Vec3::X, Vec3::Y, Vec3::Z),
+        (-Vec3::X, Vec3::Z, Vec3::Y),
+        (Vec3::Y, Vec3::Z, Vec3::X),
+        (-Vec3::Y, Vec3::X, Vec3::Z),
+        (Vec3::Z, Vec3::X, Vec3::Y),
+        (-Vec3::Z, Vec3::Y, Vec3::X),
+    ];
+
+    let mut cut = solid.clone();
+
+    cut.sides.extend(planes.into_iter().map(|(normal, u, v)| {
+        let point = if normal.max_element() > 0.0 {
+            cordon.maxs
+        } else {
+            cordon.mins
+        };
+        // vmf plane points are in cw winding order
+        let plane = Plane(point + v * 64.0, point + u * 64.0, point);
+
+        Side {
+            plane,
+            material: GamePathBuf::from(CORDON_CAP_MATERIAL),
+            u_axis: UvAxis {
+                axis: u,
+                translation: 0.0,
+                scale: 0.25,
+            },
+            v_axis: UvAxis {
+                axis: -v,
+                translation: 0.0,
+                scale: 0.25,
+            },
+            light_map_scale: 16,
+            ..Side::default()
+        }
+    }));
+
+    cut
+}
+
+/// Splits the cordon boxes into boxes that don't overlap, covering the same space.
+fn disjoint_boxes(cordons: &[CordonBox]) -> Vec<CordonBox> {
+    let mut disjoint: Vec<CordonBox> = Vec::new();
+
+    for cordon in cordons {
+        let mut pieces = vec![*cordon];
+
+        for other in &disjoint {
+            pieces = pieces
+                .into_iter()
+                .flat_map(|piece| subtract_box(piece, other))
+                .collect();
+        }
+
+        disjoint.extend(pieces);
+    }
+
+    disjoint
+}
+
+/// Returns boxes covering the part of `cordon` outside `other`.
+fn subtract_box(mut cordon: CordonBox, other: &CordonBox) -> Vec<CordonBox> {
+    if (0..3)
+        .any(|axis| other.mins[axis] >= cordon.maxs[axis] || other.maxs[axis] <= cordon.mins[axis])
+    {
+        return vec![cordon];
+    }
+
+    let mut pieces = Vec::new();
+
+    // cut off the slabs on both sides of `other`, one axis at a time
+    for axis in 0..3 {
+        if other.mins[axis] > cordon.mins[axis] {
+            let mut below = cordon;
+            below.maxs[axis] = other.mins[axis];
+            pieces.push(below);
+            cordon.mins[axis] = other.mins[axis];
+        }
+        if other.maxs[axis] < cordon.maxs[axis] {
+            let mut above = cordon;
+            above.mins[axis] = other.maxs[axis];
+            pieces.push(above);
+            cordon.maxs[axis] = other.maxs[axis];
+        }
+    }
+
+    pieces
+}
+
+/// Space left around each lightmap chart, in luxels.
+const LIGHTMAP_PADDING: f32 = 1.0;
+
+/// Packs the lightmap charts into a square atlas, converting the lightmap coordinates to uvs in it.
+/// Every brush face is its own chart, displacements have a chart per side.
+/// Nodraw faces aren't lit, so they get no space in the atlas and zeroed uvs.
+fn pack_lightmaps(builders: &mut [SolidBuilder]) {
+    let mut charts: Vec<(usize, Vec<usize>)> = Vec::new();
+
+    for (builder_i, builder) in builders.iter_mut().enumerate() {
+        let mut drawn_faces = Vec::with_capacity(builder.faces.len());
+
+        for (face_i, face) in builder.faces.iter_mut().enumerate() {
+            if builder.materials[face.material_index].info.no_draw() {
+                if let Some(lightmap_uvs) = &mut face.lightmap_uvs {
+                    lightmap_uvs.fill(Vec2::ZERO);
+                }
+            } else {
+                drawn_faces.push(face_i);
+            }
+        }
+
+        if builder.is_displacement {
+            let mut sides: Vec<(i32, Vec<usize>)> = Vec::new();
+
+            for face_i in drawn_faces {
+                let side_id = builder.faces[face_i].side.id;
+                if let Some((_, faces)) = sides.iter_mut().find(|(id, _)| *id == side_id) {
+                    faces.push(face_i);
+                } else {
+                    sides.push((side_id, vec![face_i]));
+                }
+            }
+
+            charts.extend(sides.into_iter().map(|(_, faces)| (builder_i, faces)));
+        } else {
+            charts.extend(
+                drawn_faces
+                    .into_iter()
+                    .map(|face_i| (builder_i, vec![face_i])),
+            );
+        }
+    }
+
+    if charts.is_empty() {
+        return;
+    }
+
+    // chart bounds in whole luxels
+    let bounds = charts
+        .iter()
+        .map(|(builder_i, faces)| {
+            let (min, max) = faces
+                .iter()
+                .filter_map(|&face_i| builders[*builder_i].faces[face_i].lightmap_uvs.as_ref())
+                .flatten()
+                .fold(
+                    (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                    |(min, max), &coords| (min.min(coords), max.max(coords)),
+                );
+            (min.floor(), max.ceil())
+        })
+        .collect_vec();
+
+    let sizes = bounds
+        .iter()
+        .map(|&(min, max)| max - min + Vec2::splat(2.0 * LIGHTMAP_PADDING))
+        .collect_vec();
+
+    let area: f32 = sizes.iter().map(|size| size.x * size.y).sum();
+    let width = sizes
+        .iter()
+        .map(|size| size.x)
+        .fold(area.sqrt().ceil(), f32::max);
+
+    // pack in shelves, tallest charts first
+    let mut order = (0..charts.len()).collect_vec();
+    order.sort_by(|&a, &b| sizes[b].y.total_cmp(&sizes[a].y));
+
+    let mut offsets = vec![Vec2::ZERO; charts.len()];
+    let mut cursor = Vec2::ZERO;
+    let mut shelf_height = 0.0_f32;
+
+    for i in order {
+        if cursor.x + sizes[i].x > width {
+            cursor = Vec2::new(0.0, cursor.y + shelf_height);
+            shelf_height = 0.0;
+        }
+
+        offsets[i] = cursor + Vec2::splat(LIGHTMAP_PADDING) - bounds[i].0;
+        cursor.x += sizes[i].x;
+        shelf_height = shelf_height.max(sizes[i].y);
+    }
+
+    let atlas_size = width.max(cursor.y + shelf_height);
+
+    for ((builder_i, faces), offset) in charts.iter().zip(offsets) {
+        for &face_i in faces {
+            if let Some(lightmap_uvs) = &mut builders[*builder_i].faces[face_i].lightmap_uvs {
+                for uv in lightmap_uvs {
+                    *uv = (*uv + offset) / atlas_size;
+                }
+            }
+        }
+    }
+}
+
 impl Solid {
     /// # Errors
     ///
@@ -905,9 +1648,21 @@ impl Solid {
         scale: f32,
     ) -> Result<BuiltSolid, SolidError> {
         let mut builder = SolidBuilder::new(self);
-        builder.build(get_material_info, side_faces_map, settings)?;
+        builder.build(get_material_info, settings)?;
+        sew_displacements(slice::from_mut(&mut builder), settings.epsilon);
+        builder.finish_build(side_faces_map, &[], settings.epsilon);
+        if settings.normals.smooth() {
+            smooth_brush_normals(slice::from_mut(&mut builder), settings.epsilon);
+        }
+        if settings.lightmap_uvs {
+            pack_lightmaps(slice::from_mut(&mut builder));
+        }
         builder.recenter();
-        Ok(builder.finish(scale))
+        let mut built = builder.finish(scale);
+        if settings.normal_output.welded() {
+            weld_normals(&mut built.vertices, &mut built.faces, settings.epsilon);
+        }
+        Ok(built)
     }
 }
 
@@ -1063,11 +1818,14 @@ impl MergedSolids {
 pub struct BuiltBrushEntity<'a> {
     pub id: i32,
     pub class_name: &'a str,
+    /// Ids of the visgroups the entity is in, empty for the world.
+    pub vis_group_ids: Vec<i32>,
     pub merged_solids: Option<MergedSolids>,
     pub solids: Vec<BuiltSolid>,
 }
 
 impl<'a> BuiltBrushEntity<'a> {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         solids: &[Solid],
         id: i32,
@@ -1075,58 +1833,98 @@ impl<'a> BuiltBrushEntity<'a> {
         mut get_material_info: impl FnMut(&PathBuf) -> Option<MaterialInfo>,
         side_faces_map: &Mutex<SideFacesMap>,
         settings: &GeometrySettings,
+        cordons: &[CordonBox],
         scale: f32,
     ) -> Self {
-        if settings.merge_solids.merge() {
-            let mut mergable_solids = Vec::new();
+        // solids crossing the cordons are cut into closed pieces, one per disjoint box
+        let cut_solids;
+        let solids = if cordons.is_empty() {
+            solids
+        } else {
+            let boxes = disjoint_boxes(cordons);
+            cut_solids = solids
+                .iter()
+                .flat_map(|solid| {
+                    if solid.sides.iter().any(|side| side.disp_info.is_some()) {
+                        vec![solid.clone()]
+                    } else {
+                        boxes
+                            .iter()
+                            .map(|cordon| cut_to_box(solid, cordon))
+                            .collect()
+                    }
+                })
+                .collect_vec();
+            &cut_solids
+        };
 
-            for solid in solids {
+        let mut builders = solids
+            .iter()
+            .filter_map(|solid| {
                 let mut builder = SolidBuilder::new(solid);
-                if let Err(err) = builder.build(&mut get_material_info, side_faces_map, settings) {
+                if let Err(err) = builder.build(&mut get_material_info, settings) {
                     warn!("brush `{}`: {}", id, err);
-                    continue;
+                    return None;
                 }
+                Some(builder)
+            })
+            .collect_vec();
 
-                if !settings.invisible_solids.import() && builder.is_nodraw() {
-                    continue;
-                }
+        sew_displacements(&mut builders, settings.epsilon);
+
+        for builder in &mut builders {
+            builder.finish_build(side_faces_map, cordons, settings.epsilon);
+        }
+
+        builders.retain(|builder| {
+            !builder.faces.is_empty()
+                && (settings.invisible_solids.import() || !builder.is_nodraw())
+        });
 
-                mergable_solids.push(builder);
+        if settings.normals.smooth() {
+            smooth_brush_normals(&mut builders, settings.epsilon);
+        }
+
+        if settings.lightmap_uvs {
+            pack_lightmaps(&mut builders);
+        }
+
+        if settings.merge_solids.merge() {
+            let mut merged_solids = MergedSolids::merge(
+                builders,
+                settings.epsilon,
+                settings.merge_solids.optimize(),
+                scale,
+            );
+
+            if let Some(merged) = &mut merged_solids {
+                if settings.normal_output.welded() {
+                    weld_normals(&mut merged.vertices, &mut merged.faces, settings.epsilon);
+                }
             }
 
             BuiltBrushEntity {
                 id,
                 class_name,
-                merged_solids: MergedSolids::merge(
-                    mergable_solids,
-                    settings.epsilon,
-                    settings.merge_solids.optimize(),
-                    scale,
-                ),
+                vis_group_ids: Vec::new(),
+                merged_solids,
                 solids: Vec::new(),
             }
         } else {
             BuiltBrushEntity {
                 id,
                 class_name,
+                vis_group_ids: Vec::new(),
                 merged_solids: None,
-                solids: solids
-                    .iter()
-                    .filter_map(|solid| {
-                        let mut builder = SolidBuilder::new(solid);
-                        if let Err(err) =
-                            builder.build(&mut get_material_info, side_faces_map, settings)
-                        {
-                            warn!("brush `{}`: {}", id, err);
-                            return None;
-                        }
-                        if settings.invisible_solids.import() || !builder.is_nodraw() {
-                            builder.recenter();
-
-                            Some(builder.finish(scale))
-                        } else {
-                            None
+                solids: builders
+                    .into_iter()
+                    .map(|mut builder| {
+                        builder.recenter();
+                        let mut built = builder.finish(scale);
+                        if settings.normal_output.welded() {
+                            weld_normals(&mut built.vertices, &mut built.faces, settings.epsilon);
                         }
+                        built
                     })
                     .collect(),
             }
@@ -1134,7 +1932,15 @@ impl<'a> BuiltBrushEntity<'a> {
     }
 }
 
+fn editor_vis_group_ids(editor: &Option<Editor>) -> Vec<i32> {
+    editor
+        .as_ref()
+        .map_or_else(Vec::new, |editor| editor.vis_group_ids.clone())
+}
+
 impl Entity {
+    /// Builds the solids, clipped to the cordon boxes if there are any.
+    ///
     /// # Errors
     ///
     /// Returns `Err` if the building fails.
@@ -1143,21 +1949,27 @@ impl Entity {
         get_material_info: impl FnMut(&PathBuf) -> Option<MaterialInfo>,
         side_faces_map: &Mutex<SideFacesMap>,
         settings: &GeometrySettings,
+        cordons: &[CordonBox],
         scale: f32,
     ) -> BuiltBrushEntity {
-        BuiltBrushEntity::new(
+        let mut built = BuiltBrushEntity::new(
             &self.solids,
             self.id,
             &self.class_name,
             get_material_info,
             side_faces_map,
             settings,
+            cordons,
             scale,
-        )
+        );
+        built.vis_group_ids = editor_vis_group_ids(&self.editor);
+        built
     }
 }
 
 impl World {
+    /// Builds the solids, clipped to the cordon boxes if there are any.
+    ///
     /// # Errors
     ///
     /// Returns `Err` if the building fails.
@@ -1166,6 +1978,7 @@ impl World {
         get_material_info: impl FnMut(&PathBuf) -> Option<MaterialInfo>,
         side_faces_map: &Mutex<SideFacesMap>,
         settings: &GeometrySettings,
+        cordons: &[CordonBox],
         scale: f32,
     ) -> BuiltBrushEntity {
         BuiltBrushEntity::new(
@@ -1175,6 +1988,7 @@ impl World {
             get_material_info,
             side_faces_map,
             settings,
+            cordons,
             scale,
         )
     }
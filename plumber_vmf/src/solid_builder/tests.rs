@@ -3,6 +3,7 @@ use std::ops::Range;
 use plumber_vdf as vdf;
 
 use super::*;
+use crate::builder_utils::{BrushNormals, NormalOutput};
 use approx::assert_relative_eq;
 
 fn get_test_solid() -> Solid {
@@ -266,6 +267,8 @@ fn create_test_face(side: &Side, plane: NdPlane) -> FaceBuilder {
         material_index: 0,
         vertice_alphas: Vec::new(),
         vertice_multiblends: None,
+        vertice_normals: Vec::new(),
+        lightmap_uvs: None,
     }
 }
 
@@ -310,6 +313,8 @@ fn side_clipping() {
         material_index: 0,
         vertice_alphas: Vec::new(),
         vertice_multiblends: None,
+        vertice_normals: Vec::new(),
+        lightmap_uvs: None,
     };
 
     let mut clipped = Vec::new();
@@ -349,6 +354,8 @@ fn side_clipping() {
             material_index: 0,
             vertice_alphas: Vec::new(),
             vertice_multiblends: None,
+            vertice_normals: Vec::new(),
+            lightmap_uvs: None,
         }]
     );
 
@@ -360,6 +367,8 @@ fn side_clipping() {
         material_index: 0,
         vertice_alphas: Vec::new(),
         vertice_multiblends: None,
+        vertice_normals: Vec::new(),
+        lightmap_uvs: None,
     };
 
     let mut clipped = Vec::new();
@@ -384,6 +393,8 @@ fn side_clipping() {
         material_index: 0,
         vertice_alphas: Vec::new(),
         vertice_multiblends: None,
+        vertice_normals: Vec::new(),
+        lightmap_uvs: None,
     };
 
     let mut clipped = Vec::new();
@@ -439,6 +450,7 @@ fn solid_clipping() {
         vertices: Vec::new(),
         materials: Vec::new(),
         is_displacement: false,
+        displacement_grids: Vec::new(),
         aabb_min: Vec3::new(0.0, 0.0, 0.0),
         aabb_max: Vec3::new(0.0, 0.0, 0.0),
     };
@@ -458,6 +470,8 @@ fn solid_clipping() {
                 material_index: 0,
                 vertice_alphas: Vec::new(),
                 vertice_multiblends: None,
+                vertice_normals: Vec::new(),
+                lightmap_uvs: None,
             },
             FaceBuilder {
                 side: &dummy_side,
@@ -470,6 +484,8 @@ fn solid_clipping() {
                 material_index: 0,
                 vertice_alphas: Vec::new(),
                 vertice_multiblends: None,
+                vertice_normals: Vec::new(),
+                lightmap_uvs: None,
             },
             FaceBuilder {
                 side: &dummy_side,
@@ -482,6 +498,8 @@ fn solid_clipping() {
                 material_index: 0,
                 vertice_alphas: Vec::new(),
                 vertice_multiblends: None,
+                vertice_normals: Vec::new(),
+                lightmap_uvs: None,
             },
             FaceBuilder {
                 side: &dummy_side,
@@ -494,6 +512,8 @@ fn solid_clipping() {
                 material_index: 0,
                 vertice_alphas: Vec::new(),
                 vertice_multiblends: None,
+                vertice_normals: Vec::new(),
+                lightmap_uvs: None,
             },
             FaceBuilder {
                 side: &dummy_side,
@@ -506,6 +526,8 @@ fn solid_clipping() {
                 material_index: 0,
                 vertice_alphas: Vec::new(),
                 vertice_multiblends: None,
+                vertice_normals: Vec::new(),
+                lightmap_uvs: None,
             },
             FaceBuilder {
                 side: &dummy_side,
@@ -518,6 +540,8 @@ fn solid_clipping() {
                 material_index: 0,
                 vertice_alphas: Vec::new(),
                 vertice_multiblends: None,
+                vertice_normals: Vec::new(),
+                lightmap_uvs: None,
             },
         ],
         vertices: vec![
@@ -532,6 +556,7 @@ fn solid_clipping() {
         ],
         materials: Vec::new(),
         is_displacement: false,
+        displacement_grids: Vec::new(),
         aabb_min: Vec3::new(0.0, 0.0, 0.0),
         aabb_max: Vec3::new(0.0, 0.0, 0.0),
     };
@@ -577,3 +602,528 @@ fn uv_normalization_finite_results() {
         }
     }
 }
+
+#[test]
+fn cordon_clipping() {
+    let solid = get_test_solid();
+    let mut builder = SolidBuilder::new(&solid);
+    builder.intersect_sides(1e-3, 1e-3);
+    builder.remove_invalid_faces();
+    builder.sort_vertices();
+    builder.build_uvs(|_| None);
+    builder.create_default_alphas();
+
+    let cordon = CordonBox {
+        mins: Vec3::new(-1024.0, -1024.0, -1024.0),
+        maxs: Vec3::new(-896.0, 1024.0, 1024.0),
+    };
+    builder.clip_to_cordons(&[cordon], 1e-3);
+
+    // the face on the +x side is outside the cordon, the faces crossing it are cut in half
+    assert_eq!(builder.faces.len(), 5);
+    assert_eq!(builder.vertices.len(), 8);
+
+    for vertice in &builder.vertices {
+        let x = vertice.x + builder.center.x;
+        assert!(
+            relative_eq!(x, -960.0, epsilon = 1e-3) || relative_eq!(x, -896.0, epsilon = 1e-3),
+            "vertice {} is not clipped",
+            *vertice + builder.center
+        );
+    }
+
+    for face in &builder.faces {
+        assert_eq!(face.vertice_indices.len(), 4);
+        assert_eq!(face.vertice_uvs.len(), 4);
+        assert_eq!(face.vertice_alphas.len(), 4);
+    }
+
+    let outside = CordonBox {
+        mins: Vec3::new(0.0, 0.0, 0.0),
+        maxs: Vec3::new(128.0, 128.0, 128.0),
+    };
+    builder.clip_to_cordons(&[outside], 1e-3);
+
+    assert!(builder.faces.is_empty());
+    assert!(builder.vertices.is_empty());
+}
+
+#[test]
+fn overlapping_cordons() {
+    let solid = get_test_solid();
+    let mut builder = SolidBuilder::new(&solid);
+    builder.intersect_sides(1e-3, 1e-3);
+    builder.remove_invalid_faces();
+    builder.sort_vertices();
+    builder.build_uvs(|_| None);
+    builder.create_default_alphas();
+
+    let face_area = |builder: &SolidBuilder, face: &FaceBuilder| {
+        face.vertice_indices
+            .iter()
+            .map(|&i| builder.vertices[i])
+            .circular_tuple_windows()
+            .fold(Vec3::ZERO, |cross, (a, b)| cross + a.cross(b))
+            .length()
+            * 0.5
+    };
+    let area = |builder: &SolidBuilder| {
+        builder
+            .faces
+            .iter()
+            .map(|face| face_area(builder, face))
+            .sum::<f32>()
+    };
+    let unclipped_area = area(&builder);
+
+    // the boxes overlap between x -928 and -896, and together contain the whole solid
+    let cordons = [
+        CordonBox {
+            mins: Vec3::new(-1024.0, -1024.0, -1024.0),
+            maxs: Vec3::new(-896.0, 1024.0, 1024.0),
+        },
+        CordonBox {
+            mins: Vec3::new(-928.0, -1024.0, -1024.0),
+            maxs: Vec3::new(1024.0, 1024.0, 1024.0),
+        },
+    ];
+    builder.clip_to_cordons(&cordons, 1e-3);
+
+    // the faces crossing the boxes are cut in two, without duplicating the overlap
+    assert_eq!(builder.faces.len(), 10);
+    assert_relative_eq!(area(&builder), unclipped_area, epsilon = 1e-2);
+}
+
+#[test]
+fn closed_cordon_clipping() {
+    let solid = get_test_solid();
+    let cordon = CordonBox {
+        mins: Vec3::new(-1024.0, -1024.0, -1024.0),
+        maxs: Vec3::new(-896.0, 1024.0, 1024.0),
+    };
+    let cut = cut_to_box(&solid, &cordon);
+
+    let mut builder = SolidBuilder::new(&cut);
+    builder.intersect_sides(1e-3, 1e-3);
+    builder.remove_invalid_faces();
+    builder.sort_vertices();
+
+    // the +x face is replaced by a cap on the cordon plane
+    assert_eq!(builder.faces.len(), 6);
+    let cap_material = GamePathBuf::from(CORDON_CAP_MATERIAL);
+    assert!(builder
+        .faces
+        .iter()
+        .any(|face| face.side.material == cap_material));
+
+    for vertice in &builder.vertices {
+        assert!(vertice.x + builder.center.x <= -896.0 + 1e-3);
+    }
+
+    // every edge of a closed mesh is shared by exactly two faces
+    let mut edges = BTreeMap::new();
+    for face in &builder.faces {
+        for (&a, &b) in face.vertice_indices.iter().circular_tuple_windows() {
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    assert_eq!(edges.len(), 12);
+    assert!(edges.values().all(|&count| count == 2));
+}
+
+#[test]
+fn disjoint_cordon_boxes() {
+    let cordons = [
+        CordonBox {
+            mins: Vec3::new(0.0, 0.0, 0.0),
+            maxs: Vec3::new(128.0, 128.0, 128.0),
+        },
+        CordonBox {
+            mins: Vec3::new(64.0, 64.0, 64.0),
+            maxs: Vec3::new(192.0, 192.0, 192.0),
+        },
+    ];
+    let boxes = disjoint_boxes(&cordons);
+
+    let volume = |cordon: &CordonBox| {
+        let size = cordon.maxs - cordon.mins;
+        size.x * size.y * size.z
+    };
+    assert_relative_eq!(
+        boxes.iter().map(volume).sum::<f32>(),
+        2.0 * 128.0f32.powi(3) - 64.0f32.powi(3)
+    );
+
+    for (a, b) in boxes.iter().tuple_combinations() {
+        let overlap = CordonBox {
+            mins: a.mins.max(b.mins),
+            maxs: a.maxs.min(b.maxs),
+        };
+        assert!((overlap.maxs - overlap.mins).min_element() <= 0.0);
+    }
+}
+
+/// Creates a box with a displacement on top, displaced up by `distance`.
+fn create_test_displacement(id: i32, x: [f32; 2], power: u8, distance: f32) -> Solid {
+    let [x0, x1] = x;
+    let (y0, y1, z0, z1) = (0.0, 128.0, -16.0, 0.0);
+    let dimension = 2_usize.pow(power.into()) + 1;
+    let normals_row = vec!["0 0 1"; dimension].join(" ");
+    let distances_row = vec![distance.to_string(); dimension].join(" ");
+    let rows = |row: &str| {
+        (0..dimension)
+            .map(|i| format!("\"row{}\" \"{}\"", i, row))
+            .join("\n")
+    };
+
+    let side = |id: i32, plane: String, disp_info: &str| {
+        format!(
+            "side {{
+                \"id\" \"{id}\"
+                \"plane\" \"{plane}\"
+                \"material\" \"DEV/DEV_MEASUREGENERIC01\"
+                \"uaxis\" \"[1 0 0 0] 0.25\"
+                \"vaxis\" \"[0 -1 0 0] 0.25\"
+                \"lightmapscale\" \"16\"
+                {disp_info}
+            }}"
+        )
+    };
+
+    let disp_info = format!(
+        "dispinfo {{
+            \"power\" \"{power}\"
+            \"startposition\" \"[{x0} {y0} {z1}]\"
+            \"elevation\" \"0\"
+            \"subdiv\" \"0\"
+            normals {{ {} }}
+            distances {{ {} }}
+        }}",
+        rows(&normals_row),
+        rows(&distances_row),
+    );
+
+    let input = [
+        format!("\"id\" \"{id}\""),
+        side(
+            id * 10,
+            format!("({x0} {y1} {z1}) ({x1} {y1} {z1}) ({x1} {y0} {z1})"),
+            &disp_info,
+        ),
+        side(
+            id * 10 + 1,
+            format!("({x0} {y0} {z0}) ({x1} {y0} {z0}) ({x1} {y1} {z0})"),
+            "",
+        ),
+        side(
+            id * 10 + 2,
+            format!("({x0} {y1} {z1}) ({x0} {y0} {z1}) ({x0} {y0} {z0})"),
+            "",
+        ),
+        side(
+            id * 10 + 3,
+            format!("({x1} {y1} {z0}) ({x1} {y0} {z0}) ({x1} {y0} {z1})"),
+            "",
+        ),
+        side(
+            id * 10 + 4,
+            format!("({x1} {y1} {z1}) ({x0} {y1} {z1}) ({x0} {y1} {z0})"),
+            "",
+        ),
+        side(
+            id * 10 + 5,
+            format!("({x1} {y0} {z0}) ({x0} {y0} {z0}) ({x0} {y0} {z1})"),
+            "",
+        ),
+    ]
+    .join("\n");
+
+    vdf::from_str(&input).unwrap()
+}
+
+#[test]
+fn displacement_sewing() {
+    // the displacements meet at x = 128 and have different powers
+    let solids = [
+        create_test_displacement(1, [0.0, 128.0], 2, 8.0),
+        create_test_displacement(2, [128.0, 256.0], 1, 0.0),
+    ];
+
+    let mut builders = solids
+        .iter()
+        .map(|solid| {
+            let mut builder = SolidBuilder::new(solid);
+            builder
+                .build(|_| None, &GeometrySettings::default())
+                .unwrap();
+            builder
+        })
+        .collect_vec();
+
+    sew_displacements(&mut builders, 1e-3);
+
+    let mut seam_normals = Vec::new();
+
+    for builder in &builders {
+        for face in &builder.faces {
+            assert_eq!(face.vertice_normals.len(), face.vertice_indices.len());
+
+            for (&i, normal) in face.vertice_indices.iter().zip(&face.vertice_normals) {
+                assert_relative_eq!(normal.length(), 1.0, epsilon = 1e-3);
+                assert!(normal.z > 0.0, "normal {} points down", normal);
+
+                let vertice = builder.vertices[i] + builder.center;
+                if relative_eq!(vertice.x, 128.0, epsilon = 1e-3) {
+                    assert_relative_eq!(vertice.z, 4.0, epsilon = 1e-3);
+                    seam_normals.push((vertice.y, *normal));
+                }
+            }
+        }
+    }
+
+    // the welded corners have the same normal on both sides of the seam
+    for corner in [0.0, 64.0, 128.0] {
+        let normals = seam_normals
+            .iter()
+            .filter(|(y, _)| relative_eq!(*y, corner, epsilon = 1e-3))
+            .map(|(_, normal)| *normal)
+            .collect_vec();
+
+        assert!(normals.len() > 1);
+        for normal in &normals {
+            assert_relative_eq!(*normal, normals[0], epsilon = 1e-3);
+        }
+    }
+
+    // the higher displacement slopes down to the seam
+    assert!(seam_normals.iter().all(|(_, normal)| normal.x > 0.0));
+}
+
+#[test]
+fn smoothing_group_normals() {
+    let mut solid = get_test_solid();
+    for side in &mut solid.sides {
+        side.smoothing_groups = 1;
+    }
+    // the top side is in a separate group
+    solid.sides[0].smoothing_groups = 2;
+
+    let built = solid
+        .build_mesh(
+            |_| None,
+            &Mutex::new(SideFacesMap::new()),
+            &GeometrySettings::default(),
+            1.0,
+        )
+        .unwrap();
+
+    for face in &built.faces {
+        let face_normal = polygon_normal(face.vertice_indices.iter().map(|&i| built.vertices[i]));
+
+        assert_eq!(face.vertice_normals.len(), face.vertice_indices.len());
+
+        for (&i, &normal) in face.vertice_indices.iter().zip(&face.vertice_normals) {
+            let vertice = built.vertices[i];
+
+            // the other sides are averaged with their neighbours, except the top
+            let expected = if relative_eq!(face_normal, Vec3::Z, epsilon = 1e-3) {
+                Vec3::Z
+            } else {
+                let z = if vertice.z < 0.0 { -1.0 } else { 0.0 };
+                Vec3::new(vertice.x.signum(), vertice.y.signum(), z).normalize()
+            };
+
+            assert_relative_eq!(normal, expected, epsilon = 1e-3);
+        }
+    }
+
+    let mut settings = GeometrySettings::default();
+    settings.normals(BrushNormals::Flat);
+
+    let built = solid
+        .build_mesh(|_| None, &Mutex::new(SideFacesMap::new()), &settings, 1.0)
+        .unwrap();
+
+    for face in &built.faces {
+        let face_normal = polygon_normal(face.vertice_indices.iter().map(|&i| built.vertices[i]));
+
+        for &normal in &face.vertice_normals {
+            assert_relative_eq!(normal, face_normal, epsilon = 1e-3);
+        }
+    }
+}
+
+#[test]
+fn coplanar_smoothing_normals() {
+    // two boxes next to each other along x, with their top sides in the same plane
+    let mut first = get_test_solid();
+    let mut second = get_test_solid();
+    for side in &mut second.sides {
+        for point in [&mut side.plane.0, &mut side.plane.1, &mut side.plane.2] {
+            point.x += 128.0;
+        }
+    }
+
+    for solid in [&mut first, &mut second] {
+        for side in &mut solid.sides {
+            side.smoothing_groups = 1;
+        }
+    }
+    // the sides between the boxes and the -y side of the second box aren't smoothed
+    first.sides[3].smoothing_groups = 0;
+    second.sides[2].smoothing_groups = 0;
+    second.sides[5].smoothing_groups = 0;
+
+    let settings = GeometrySettings::default();
+    let mut builders = [&first, &second]
+        .into_iter()
+        .map(|solid| {
+            let mut builder = SolidBuilder::new(solid);
+            builder.build(|_| None, &settings).unwrap();
+            builder
+        })
+        .collect_vec();
+    smooth_brush_normals(&mut builders, 1e-3);
+
+    let corner = Vec3::new(-832.0, -64.0, 64.0);
+    let builder = &builders[0];
+    let top = builder
+        .faces
+        .iter()
+        .find(|face| relative_eq!(face.plane.normal, Vec3::Z, epsilon = 1e-3))
+        .unwrap();
+    let (_, &normal) = top
+        .vertice_indices
+        .iter()
+        .zip(&top.vertice_normals)
+        .find(|(&i, _)| relative_eq!(builder.vertices[i] + builder.center, corner, epsilon = 1e-3))
+        .unwrap();
+
+    // both top sides have the same plane, so it's only counted once
+    assert_relative_eq!(
+        normal,
+        Vec3::new(0.0, -1.0, 1.0).normalize(),
+        epsilon = 1e-3
+    );
+}
+
+#[test]
+fn welded_normals() {
+    let solid = get_test_solid();
+
+    let mut settings = GeometrySettings::default();
+    settings.normals(BrushNormals::Flat);
+    settings.normal_output(NormalOutput::Welded);
+
+    let built = solid
+        .build_mesh(|_| None, &Mutex::new(SideFacesMap::new()), &settings, 1.0)
+        .unwrap();
+
+    // every corner of the box is split into the three faces meeting there
+    assert_eq!(built.vertices.len(), 24);
+
+    let mut vertice_normals = vec![None; built.vertices.len()];
+    for face in &built.faces {
+        for (&i, &normal) in face.vertice_indices.iter().zip(&face.vertice_normals) {
+            let expected = *vertice_normals[i].get_or_insert(normal);
+            assert_relative_eq!(normal, expected, epsilon = 1e-3);
+        }
+    }
+}
+
+#[test]
+fn lightmap_uvs() {
+    let mut solid = get_test_solid();
+    for side in &mut solid.sides {
+        side.light_map_scale = 16;
+    }
+    // the top side has twice the luxels
+    solid.sides[0].light_map_scale = 8;
+
+    let mut settings = GeometrySettings::default();
+    settings.lightmap_uvs(true);
+
+    let built = solid
+        .build_mesh(|_| None, &Mutex::new(SideFacesMap::new()), &settings, 1.0)
+        .unwrap();
+
+    let mut density = None;
+    let mut charts: Vec<(Vec2, Vec2)> = Vec::new();
+
+    for face in &built.faces {
+        let lightmap_uvs = face.lightmap_uvs.as_ref().unwrap();
+        assert_eq!(lightmap_uvs.len(), face.vertice_indices.len());
+
+        for uv in lightmap_uvs {
+            assert!((0.0..=1.0).contains(&uv.x) && (0.0..=1.0).contains(&uv.y));
+        }
+
+        let (a, b) = (face.vertice_indices[0], face.vertice_indices[1]);
+        let face_density = (lightmap_uvs[1] - lightmap_uvs[0]).length()
+            / (built.vertices[b] - built.vertices[a]).length();
+        let face_normal = polygon_normal(face.vertice_indices.iter().map(|&i| built.vertices[i]));
+        let scale = if relative_eq!(face_normal, Vec3::Z, epsilon = 1e-3) {
+            0.5
+        } else {
+            1.0
+        };
+        let density = *density.get_or_insert(face_density * scale);
+        assert_relative_eq!(face_density * scale, density, epsilon = 1e-5);
+
+        let (min, max) = lightmap_uvs.iter().fold(
+            (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+            |(min, max), &uv| (min.min(uv), max.max(uv)),
+        );
+        for &(other_min, other_max) in &charts {
+            assert!(
+                max.x <= other_min.x
+                    || other_max.x <= min.x
+                    || max.y <= other_min.y
+                    || other_max.y <= min.y,
+                "lightmap charts overlap"
+            );
+        }
+        charts.push((min, max));
+    }
+}
+
+#[test]
+fn nodraw_lightmap_uvs() {
+    let mut solid = get_test_solid();
+    solid.sides[0].material = GamePathBuf::from("TOOLS/TOOLSNODRAW");
+
+    let mut settings = GeometrySettings::default();
+    settings.lightmap_uvs(true);
+
+    let built = solid
+        .build_mesh(
+            |path| {
+                let no_draw = path.to_string().to_lowercase().contains("toolsnodraw");
+                Some(MaterialInfo::new(512, 512, no_draw))
+            },
+            &Mutex::new(SideFacesMap::new()),
+            &settings,
+            1.0,
+        )
+        .unwrap();
+
+    let mut nodraw_faces = 0;
+
+    for face in &built.faces {
+        let lightmap_uvs = face.lightmap_uvs.as_ref().unwrap();
+
+        if built.materials[face.material_index].info.no_draw() {
+            nodraw_faces += 1;
+            assert!(lightmap_uvs.iter().all(|&uv| uv == Vec2::ZERO));
+        } else {
+            let (min, max) = lightmap_uvs.iter().fold(
+                (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                |(min, max), &uv| (min.min(uv), max.max(uv)),
+            );
+            assert!(min.cmpge(Vec2::ZERO).all() && max.cmple(Vec2::ONE).all());
+            assert!(max.x > min.x && max.y > min.y);
+        }
+    }
+
+    assert_eq!(nodraw_faces, 1);
+}
@@ -353,6 +353,81 @@ impl Display for BracketedVector3 {
     }
 }
 
+pub mod parenthesed_vector3 {
+    use super::*;
+
+    pub fn serialize<S>(vector: &Vec3, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        ParenthesedVector3::serialize(&ParenthesedVector3(*vector), serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec3, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        ParenthesedVector3::deserialize(deserializer).map(|v| v.0)
+    }
+}
+
+pub struct ParenthesedVector3(pub Vec3);
+
+impl<'de> Deserialize<'de> for ParenthesedVector3 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ParenthesedVec3Visitor;
+
+        impl<'de> Visitor<'de> for ParenthesedVec3Visitor {
+            type Value = ParenthesedVector3;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("parenthesed vector3")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                match parenthesed(tuple((space_separated, space_separated, space_separated)))(v) {
+                    Ok((_, (x, y, z))) => {
+                        let x = x.parse().map_err(|_| {
+                            de::Error::invalid_value(de::Unexpected::Str(x), &"float")
+                        })?;
+                        let y = y.parse().map_err(|_| {
+                            de::Error::invalid_value(de::Unexpected::Str(y), &"float")
+                        })?;
+                        let z = z.parse().map_err(|_| {
+                            de::Error::invalid_value(de::Unexpected::Str(z), &"float")
+                        })?;
+                        Ok(ParenthesedVector3(Vec3::new(x, y, z)))
+                    }
+                    Err(..) => Err(de::Error::invalid_value(de::Unexpected::Str(v), &Self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_str(ParenthesedVec3Visitor)
+    }
+}
+
+impl Serialize for ParenthesedVector3 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl Display for ParenthesedVector3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({} {} {})", self.0.x, self.0.y, self.0.z)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Plane(pub Vec3, pub Vec3, pub Vec3);
 
@@ -9,7 +9,7 @@ use itertools::Itertools;
 use ndarray::Array2;
 use rgb::RGB8;
 use serde::{
-    de::{self, DeserializeSeed, MapAccess, Visitor},
+    de::{self, DeserializeSeed, IntoDeserializer, MapAccess, Visitor},
     ser::{SerializeMap, SerializeStruct},
     Deserialize, Deserializer, Serialize, Serializer,
 };
@@ -18,7 +18,13 @@ use plumber_fs::GamePathBuf;
 use plumber_uncased::UncasedString;
 use plumber_vdf as vdf;
 
-use crate::types::{bracketed_vector2, bracketed_vector3, color, BracketedVector3, Plane, UvAxis};
+use crate::{
+    entities::{BaseEntity, Unknown},
+    types::{
+        bracketed_vector2, bracketed_vector3, color, parenthesed_vector3, BracketedVector3, Plane,
+        UvAxis,
+    },
+};
 
 #[derive(Debug, PartialEq)]
 pub struct Vmf {
@@ -27,6 +33,7 @@ pub struct Vmf {
     pub view_settings: ViewSettings,
     pub world: World,
     pub entities: Vec<Entity>,
+    pub cordons: Cordons,
 }
 
 impl Serialize for Vmf {
@@ -49,6 +56,10 @@ impl Serialize for Vmf {
             }
         }
 
+        if self.cordons != Cordons::default() {
+            map.serialize_entry("cordons", &self.cordons)?;
+        }
+
         map.end()
     }
 }
@@ -76,6 +87,7 @@ impl<'de> Deserialize<'de> for Vmf {
                 let mut view_settings = None;
                 let mut world = None;
                 let mut entities = Vec::new();
+                let mut cordons = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -118,6 +130,21 @@ impl<'de> Deserialize<'de> for Vmf {
 
                             entities.push(entity);
                         }
+                        "cordons" => {
+                            if cordons.is_some() {
+                                return Err(de::Error::duplicate_field("cordons"));
+                            }
+                            let value = map.next_value()?;
+                            cordons = Some(value);
+                        }
+                        "cordon" => {
+                            // maps saved before multiple cordons were supported
+                            if cordons.is_some() {
+                                return Err(de::Error::duplicate_field("cordon"));
+                            }
+                            let value = map.next_value::<LegacyCordon>()?;
+                            cordons = Some(value.into());
+                        }
                         _ => {
                             map.next_value::<de::IgnoredAny>()?;
                         }
@@ -128,6 +155,7 @@ impl<'de> Deserialize<'de> for Vmf {
                 let vis_groups = vis_groups.unwrap_or_default();
                 let view_settings = view_settings.unwrap_or_default();
                 let world = world.ok_or_else(|| de::Error::missing_field("world"))?;
+                let cordons = cordons.unwrap_or_default();
 
                 Ok(Vmf {
                     version_info,
@@ -135,6 +163,7 @@ impl<'de> Deserialize<'de> for Vmf {
                     view_settings,
                     world,
                     entities,
+                    cordons,
                 })
             }
         }
@@ -157,6 +186,113 @@ impl Vmf {
     pub fn to_string(&self) -> vdf::Result<String> {
         vdf::to_string(self)
     }
+
+    /// # Errors
+    ///
+    /// Returns `Err` if the serialization fails.
+    pub fn to_string_with_options(&self, options: &vdf::FormatOptions) -> vdf::Result<String> {
+        vdf::to_string_with_options(self, options)
+    }
+
+    /// Removes the solids and entities not selected by the filter.
+    /// Brush entities left without solids are removed too.
+    pub fn apply_editor_filter(&mut self, filter: &EditorFilter) {
+        let selected_vis_groups = (!filter.vis_groups.is_empty()).then(|| {
+            filter
+                .vis_groups
+                .iter()
+                .filter_map(|&id| self.vis_groups.find(id))
+                .flat_map(VisGroup::ids)
+                .collect::<Vec<_>>()
+        });
+
+        let is_selected = |editor: &Option<Editor>| {
+            selected_vis_groups.as_ref().map_or(true, |selected| {
+                editor.as_ref().is_some_and(|editor| {
+                    editor.vis_group_ids.iter().any(|id| selected.contains(id))
+                })
+            })
+        };
+
+        self.world.solids.retain(|solid| {
+            !filter.is_hidden(solid.hidden, &solid.editor) && is_selected(&solid.editor)
+        });
+
+        self.entities.retain_mut(|entity| {
+            if filter.is_hidden(entity.hidden, &entity.editor) || !is_selected(&entity.editor) {
+                return false;
+            }
+
+            if entity.solids.is_empty() {
+                return true;
+            }
+
+            // solids of brush entities are selected with the entity
+            entity
+                .solids
+                .retain(|solid| !filter.is_hidden(solid.hidden, &solid.editor));
+            !entity.solids.is_empty()
+        });
+    }
+
+    /// Removes the point entities outside the given cordon boxes.
+    /// Brushes are clipped to the boxes when they are built.
+    pub fn remove_outside_cordons(&mut self, cordons: &[CordonBox]) {
+        if cordons.is_empty() {
+            return;
+        }
+
+        self.entities.retain(|entity| {
+            if !entity.solids.is_empty() {
+                return true;
+            }
+
+            match Unknown::new(entity).parse_vector3_parameter("origin") {
+                Ok(Some(origin)) => cordons.iter().any(|b| b.contains(origin.into())),
+                _ => true,
+            }
+        });
+    }
+}
+
+/// Selects the solids and entities to import based on their editor state.
+#[derive(Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct EditorFilter<'a> {
+    /// Ids of the visgroups to import, including their nested visgroups.
+    /// Everything is imported if empty.
+    pub vis_groups: &'a [i32],
+    /// Skip objects in visgroups that are hidden in the editor.
+    pub skip_hidden_vis_groups: bool,
+    /// Skip objects hidden with the hide tool of the editor.
+    pub skip_hidden: bool,
+}
+
+impl<'a> EditorFilter<'a> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vis_groups(&mut self, vis_groups: &'a [i32]) {
+        self.vis_groups = vis_groups;
+    }
+
+    pub fn skip_hidden_vis_groups(&mut self, skip_hidden_vis_groups: bool) {
+        self.skip_hidden_vis_groups = skip_hidden_vis_groups;
+    }
+
+    pub fn skip_hidden(&mut self, skip_hidden: bool) {
+        self.skip_hidden = skip_hidden;
+    }
+
+    fn is_hidden(&self, hidden: bool, editor: &Option<Editor>) -> bool {
+        (self.skip_hidden && hidden)
+            || (self.skip_hidden_vis_groups
+                && editor
+                    .as_ref()
+                    .is_some_and(|e| !e.vis_group_shown || !e.vis_group_auto_shown))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
@@ -200,6 +336,46 @@ pub struct VisGroup {
     pub vis_group_id: i32,
     #[serde(default, with = "color")]
     pub color: RGB8,
+    #[serde(default, rename = "visgroup", skip_serializing_if = "Vec::is_empty")]
+    pub vis_groups: Vec<VisGroup>,
+}
+
+impl VisGroups {
+    /// Returns the visgroup with the given id, searching nested visgroups too.
+    #[must_use]
+    pub fn find(&self, id: i32) -> Option<&VisGroup> {
+        self.path(id).pop()
+    }
+
+    /// Returns the visgroups from the top level visgroup down to the visgroup with the given id.
+    /// Returns an empty `Vec` if the visgroup doesn't exist.
+    #[must_use]
+    pub fn path(&self, id: i32) -> Vec<&VisGroup> {
+        fn search<'a>(vis_groups: &'a [VisGroup], id: i32, path: &mut Vec<&'a VisGroup>) -> bool {
+            for vis_group in vis_groups {
+                path.push(vis_group);
+                if vis_group.vis_group_id == id || search(&vis_group.vis_groups, id, path) {
+                    return true;
+                }
+                path.pop();
+            }
+            false
+        }
+
+        let mut path = Vec::new();
+        search(&self.vis_groups, id, &mut path);
+        path
+    }
+}
+
+impl VisGroup {
+    /// Returns the ids of this visgroup and all visgroups nested in it.
+    #[must_use]
+    pub fn ids(&self) -> Vec<i32> {
+        let mut ids = vec![self.vis_group_id];
+        ids.extend(self.vis_groups.iter().flat_map(VisGroup::ids));
+        ids
+    }
 }
 
 #[allow(clippy::struct_excessive_bools)]
@@ -230,6 +406,125 @@ impl Default for ViewSettings {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[serde(expecting = "class cordons")]
+pub struct Cordons {
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default, rename = "cordon", skip_serializing_if = "Vec::is_empty")]
+    pub cordons: Vec<Cordon>,
+}
+
+impl Cordons {
+    /// Returns the boxes of the active cordons, or nothing if cordons are disabled.
+    #[must_use]
+    pub fn active_boxes(&self) -> Vec<CordonBox> {
+        if !self.active {
+            return Vec::new();
+        }
+
+        self.cordons
+            .iter()
+            .filter(|cordon| cordon.active)
+            .flat_map(|cordon| cordon.boxes.iter().copied())
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(expecting = "class cordon")]
+pub struct Cordon {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub active: bool,
+    /// Boxes that fail to parse are skipped.
+    #[serde(
+        default,
+        rename = "box",
+        deserialize_with = "deserialize_cordon_boxes",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub boxes: Vec<CordonBox>,
+}
+
+fn deserialize_cordon_boxes<'de, D>(deserializer: D) -> Result<Vec<CordonBox>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let boxes = Vec::<RawCordonBox>::deserialize(deserializer)?;
+    Ok(boxes.iter().filter_map(RawCordonBox::parse).collect())
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(expecting = "class box")]
+pub struct CordonBox {
+    #[serde(with = "parenthesed_vector3")]
+    pub mins: Vec3,
+    #[serde(with = "parenthesed_vector3")]
+    pub maxs: Vec3,
+}
+
+impl CordonBox {
+    #[must_use]
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.mins).all() && point.cmple(self.maxs).all()
+    }
+}
+
+/// Cordon box with unparsed bounds, so that invalid boxes can be skipped.
+#[derive(Deserialize)]
+struct RawCordonBox {
+    #[serde(default)]
+    mins: String,
+    #[serde(default)]
+    maxs: String,
+}
+
+impl RawCordonBox {
+    fn parse(&self) -> Option<CordonBox> {
+        let parse_vector = |value: &str| {
+            let deserializer: de::value::StrDeserializer<de::value::Error> =
+                value.into_deserializer();
+            parenthesed_vector3::deserialize(deserializer).ok()
+        };
+
+        Some(CordonBox {
+            mins: parse_vector(&self.mins)?,
+            maxs: parse_vector(&self.maxs)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct LegacyCordon {
+    #[serde(default)]
+    mins: String,
+    #[serde(default)]
+    maxs: String,
+    #[serde(default)]
+    active: bool,
+}
+
+impl From<LegacyCordon> for Cordons {
+    fn from(legacy: LegacyCordon) -> Self {
+        Self {
+            active: legacy.active,
+            cordons: vec![Cordon {
+                name: "cordon".to_owned(),
+                active: true,
+                boxes: RawCordonBox {
+                    mins: legacy.mins,
+                    maxs: legacy.maxs,
+                }
+                .parse()
+                .into_iter()
+                .collect(),
+            }],
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct World {
     pub id: i32,
@@ -1122,6 +1417,8 @@ pub struct Editor {
     pub color: RGB8,
     #[serde(default, rename = "groupid")]
     pub group_id: i32,
+    #[serde(default, rename = "visgroupid", skip_serializing_if = "Vec::is_empty")]
+    pub vis_group_ids: Vec<i32>,
     #[serde(default, rename = "visgroupshown")]
     pub vis_group_shown: bool,
     #[serde(default, rename = "visgroupautoshown")]
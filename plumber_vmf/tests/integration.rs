@@ -1,4 +1,6 @@
-use plumber_vmf::vmf::Vmf;
+use glam::Vec3;
+use plumber_vdf::{FormatOptions, Indentation, LineEnding, Quoting};
+use plumber_vmf::vmf::{CordonBox, Editor, EditorFilter, Vmf};
 
 fn test_vmf_roundtrip(input: &str) {
     let first_vmf = Vmf::from_bytes(input.as_bytes()).unwrap();
@@ -16,3 +18,222 @@ fn vmf_roundtrip() {
 fn hidden_vmf_roundtrip() {
     test_vmf_roundtrip(include_str!("test_hidden.vmf"));
 }
+
+#[test]
+fn formatted_vmf_roundtrip() {
+    let first_vmf = Vmf::from_bytes(include_bytes!("test.vmf")).unwrap();
+
+    let mut options = FormatOptions::new();
+    options.indentation(Indentation::Spaces(4));
+    options.align_values(true);
+    options.quoting(Quoting::Minimal);
+    options.line_ending(LineEnding::CrLf);
+
+    let serialized_vmf = first_vmf.to_string_with_options(&options).unwrap();
+    let second_vmf = Vmf::from_bytes(serialized_vmf.as_bytes()).unwrap();
+    assert_eq!(first_vmf, second_vmf)
+}
+
+fn vis_groups_vmf() -> Vmf {
+    let input = include_str!("test_hidden.vmf").replacen(
+        "visgroups\n{\n}",
+        r#"visgroups
+{
+	visgroup
+	{
+		"name" "Parent"
+		"visgroupid" "1"
+		"color" "255 0 0"
+		visgroup
+		{
+			"name" "Child"
+			"visgroupid" "2"
+			"color" "0 255 0"
+		}
+	}
+	visgroup
+	{
+		"name" "Other"
+		"visgroupid" "3"
+		"color" "0 0 255"
+	}
+}"#,
+        1,
+    );
+    let mut vmf = Vmf::from_bytes(input.as_bytes()).unwrap();
+
+    let set_ids = |editor: &mut Option<Editor>, ids: Vec<i32>| {
+        editor.as_mut().unwrap().vis_group_ids = ids;
+    };
+    set_ids(&mut vmf.world.solids[1].editor, vec![2]);
+    set_ids(&mut vmf.world.solids[3].editor, vec![3]);
+    set_ids(&mut vmf.entities[0].editor, vec![1, 3]);
+
+    vmf
+}
+
+#[test]
+fn vis_groups_vmf_roundtrip() {
+    let vmf = vis_groups_vmf();
+    assert_eq!(
+        vmf.vis_groups
+            .path(2)
+            .iter()
+            .map(|g| g.name.as_str())
+            .collect::<Vec<_>>(),
+        ["Parent", "Child"]
+    );
+    test_vmf_roundtrip(&vmf.to_string().unwrap());
+}
+
+#[test]
+fn editor_filter() {
+    let solid_ids = |vmf: &Vmf| vmf.world.solids.iter().map(|s| s.id).collect::<Vec<_>>();
+    let entity_ids = |vmf: &Vmf| vmf.entities.iter().map(|e| e.id).collect::<Vec<_>>();
+
+    let mut vmf = vis_groups_vmf();
+    let mut filter = EditorFilter::new();
+    filter.skip_hidden(true);
+    vmf.apply_editor_filter(&filter);
+    assert_eq!(solid_ids(&vmf), [3, 5]);
+    assert_eq!(entity_ids(&vmf), [12, 23]);
+
+    let mut vmf = vis_groups_vmf();
+    let mut filter = EditorFilter::new();
+    filter.vis_groups(&[1]);
+    vmf.apply_editor_filter(&filter);
+    assert_eq!(solid_ids(&vmf), [3]);
+    assert_eq!(entity_ids(&vmf), [12]);
+
+    let mut vmf = vis_groups_vmf();
+    vmf.world.solids[3].editor.as_mut().unwrap().vis_group_shown = false;
+    let mut filter = EditorFilter::new();
+    filter.skip_hidden_vis_groups(true);
+    vmf.apply_editor_filter(&filter);
+    assert_eq!(solid_ids(&vmf), [2, 3, 4]);
+
+    let mut vmf = vis_groups_vmf();
+    let mut filter = EditorFilter::new();
+    filter.vis_groups(&[42]);
+    vmf.apply_editor_filter(&filter);
+    assert!(vmf.world.solids.is_empty());
+    assert!(vmf.entities.is_empty());
+}
+
+#[test]
+fn cordons() {
+    let input = include_str!("test_hidden.vmf").replacen(
+        "cordons\n{\n\t\"active\" \"0\"\n}",
+        r#"cordons
+{
+	"active" "1"
+	cordon
+	{
+		"name" "first"
+		"active" "1"
+		box
+		{
+			"mins" "(-1024 -1024 -1024)"
+			"maxs" "(0 1024 1024)"
+		}
+	}
+	cordon
+	{
+		"name" "second"
+		"active" "0"
+		box
+		{
+			"mins" "(0 -1024 -1024)"
+			"maxs" "(1024 1024 1024)"
+		}
+	}
+}"#,
+        1,
+    );
+    let vmf = Vmf::from_bytes(input.as_bytes()).unwrap();
+
+    assert_eq!(
+        vmf.cordons.active_boxes(),
+        [CordonBox {
+            mins: Vec3::new(-1024.0, -1024.0, -1024.0),
+            maxs: Vec3::new(0.0, 1024.0, 1024.0),
+        }]
+    );
+    test_vmf_roundtrip(&vmf.to_string().unwrap());
+
+    let legacy_input = include_str!("test_hidden.vmf").replacen(
+        "cordons\n{\n\t\"active\" \"0\"\n}",
+        r#"cordon
+{
+	"mins" "(-1024 -1024 -1024)"
+	"maxs" "(0 1024 1024)"
+	"active" "1"
+}"#,
+        1,
+    );
+    let legacy_vmf = Vmf::from_bytes(legacy_input.as_bytes()).unwrap();
+
+    assert_eq!(
+        legacy_vmf.cordons.active_boxes(),
+        vmf.cordons.active_boxes()
+    );
+}
+
+#[test]
+fn invalid_cordons() {
+    let input = include_str!("test_hidden.vmf").replacen(
+        "cordons\n{\n\t\"active\" \"0\"\n}",
+        r#"cordons
+{
+	"active" "1"
+	cordon
+	{
+		"active" "1"
+		box
+		{
+			"mins" "(-1024 -1024 -1024)"
+			"maxs" "(0 1024 1024)"
+		}
+		box
+		{
+			"mins" "(-1024 -1024)"
+			"maxs" "(0 1024 1024)"
+		}
+		box
+		{
+			"mins" "(0 0 0)"
+		}
+	}
+}"#,
+        1,
+    );
+    let vmf = Vmf::from_bytes(input.as_bytes()).unwrap();
+
+    assert_eq!(vmf.cordons.cordons[0].name, "");
+    assert_eq!(
+        vmf.cordons.active_boxes(),
+        [CordonBox {
+            mins: Vec3::new(-1024.0, -1024.0, -1024.0),
+            maxs: Vec3::new(0.0, 1024.0, 1024.0),
+        }]
+    );
+
+    let legacy_input = include_str!("test_hidden.vmf").replacen(
+        "cordons\n{\n\t\"active\" \"0\"\n}",
+        r#"cordon
+{
+	"mins" "(-1024 -1024 -1024)"
+	"maxs" "invalid"
+	"active" "1"
+}"#,
+        1,
+    );
+    let legacy_vmf = Vmf::from_bytes(legacy_input.as_bytes()).unwrap();
+    assert!(legacy_vmf.cordons.active_boxes().is_empty());
+}
+
+#[test]
+fn default_cordons_not_written() {
+    let vmf = Vmf::from_bytes(include_bytes!("test_hidden.vmf")).unwrap();
+    assert!(!vmf.to_string().unwrap().contains("cordons"));
+}
@@ -21,7 +21,21 @@ use plumber_uncased::{AsUncased, UncasedString};
 use plumber_vdf::{
     self as vdf,
     nom_utils::{braced, bracketed, space_separated},
-    Value,
+};
+
+mod patch;
+mod profile;
+mod proxy;
+pub mod shaders;
+
+use patch::{resolve_chain, Patch};
+pub use patch::ResolvedShader;
+use profile::apply_profile;
+pub use profile::TargetProfile;
+use proxy::{RawProxies, RawProxiesRef};
+pub use proxy::{
+    AnimatedTexture, BinaryOperation, Equals, LinearRamp, Proxy, ProxyEvaluator, ProxyValue,
+    ProxyVariable, RawProxy, Sine, TextureScroll, TextureTransform, UniformNoise,
 };
 
 /// # Errors
@@ -38,6 +52,13 @@ pub fn to_string(vmt: &Vmt) -> vdf::Result<String> {
     vmt.to_string()
 }
 
+/// # Errors
+///
+/// Returns `Err` if the serialization fails.
+pub fn to_string_with_options(vmt: &Vmt, options: &vdf::FormatOptions) -> vdf::Result<String> {
+    vmt.to_string_with_options(options)
+}
+
 #[derive(Debug, Clone, PartialEq)]
 enum StringOrPatch {
     Patch,
@@ -87,17 +108,13 @@ impl<'de> Deserialize<'de> for StringOrPatch {
 
 #[derive(Debug, Clone)]
 enum ShaderOrPatch {
-    Shader(Shader),
+    Shader {
+        shader: Shader,
+        blocks: Vec<(UncasedString, Parameters)>,
+    },
     Patch(Patch),
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-struct Patch {
-    include: GamePathBuf,
-    #[serde(default, alias = "replace")]
-    insert: BTreeMap<UncasedString, String>,
-}
-
 #[derive(Debug, Clone, PartialEq)]
 enum StringOrProxies {
     Proxies,
@@ -133,7 +150,7 @@ impl<'de> Deserialize<'de> for StringOrProxies {
             where
                 E: de::Error,
             {
-                if v.eq_ignore_ascii_case("patch") {
+                if v.eq_ignore_ascii_case("proxies") {
                     Ok(StringOrProxies::Proxies)
                 } else {
                     Ok(StringOrProxies::String(v))
@@ -145,9 +162,55 @@ impl<'de> Deserialize<'de> for StringOrProxies {
     }
 }
 
+/// Shader parameters, including nested DX-level and shader fallback blocks.
+#[derive(Debug, Clone, Default)]
 struct Parameters {
     parameters: BTreeMap<UncasedString, String>,
-    proxies: BTreeMap<UncasedString, Value>,
+    proxies: Vec<RawProxy>,
+    blocks: Vec<(UncasedString, Parameters)>,
+}
+
+struct ParametersVisitor;
+
+impl<'de> Visitor<'de> for ParametersVisitor {
+    type Value = Parameters;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("shader parameters")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut parameters = BTreeMap::new();
+        let mut proxies = Vec::new();
+        let mut blocks = Vec::new();
+
+        while let Some(key) = map.next_key()? {
+            match key {
+                StringOrProxies::Proxies => {
+                    let RawProxies(mut new_proxies) = map.next_value()?;
+                    proxies.append(&mut new_proxies);
+                }
+                StringOrProxies::String(key) => match map.next_value() {
+                    Ok(ParameterValue::String(value)) => {
+                        parameters.insert(key.into(), value);
+                    }
+                    Ok(ParameterValue::Block(block)) => blocks.push((key.into(), block)),
+                    Err(_) => {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                },
+            }
+        }
+
+        Ok(Parameters {
+            parameters,
+            proxies,
+            blocks,
+        })
+    }
 }
 
 impl<'de> Deserialize<'de> for Parameters {
@@ -155,45 +218,92 @@ impl<'de> Deserialize<'de> for Parameters {
     where
         D: Deserializer<'de>,
     {
-        struct ParametersVisitor;
+        deserializer.deserialize_map(ParametersVisitor)
+    }
+}
 
-        impl<'de> Visitor<'de> for ParametersVisitor {
-            type Value = Parameters;
+impl Serialize for Parameters {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        ParametersRef {
+            parameters: &self.parameters,
+            proxies: &self.proxies,
+            blocks: &self.blocks,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Serializes the parameters followed by the nested blocks and the proxies.
+struct ParametersRef<'a> {
+    parameters: &'a BTreeMap<UncasedString, String>,
+    proxies: &'a [RawProxy],
+    blocks: &'a [(UncasedString, Parameters)],
+}
+
+impl Serialize for ParametersRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        for (key, value) in self.parameters {
+            map.serialize_entry(key, value)?;
+        }
+        for (key, block) in self.blocks {
+            map.serialize_entry(key, block)?;
+        }
+        if !self.proxies.is_empty() {
+            map.serialize_entry("Proxies", &RawProxiesRef(self.proxies))?;
+        }
+        map.end()
+    }
+}
+
+enum ParameterValue {
+    String(String),
+    Block(Parameters),
+}
+
+impl<'de> Deserialize<'de> for ParameterValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ParameterValueVisitor;
+
+        impl<'de> Visitor<'de> for ParameterValueVisitor {
+            type Value = ParameterValue;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("shader parameters")
+                formatter.write_str("a string or a block of parameters")
             }
 
-            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
             where
-                A: MapAccess<'de>,
+                E: de::Error,
             {
-                let mut parameters = BTreeMap::new();
-                let mut proxies = BTreeMap::new();
+                Ok(ParameterValue::String(v.into()))
+            }
 
-                while let Some(key) = map.next_key()? {
-                    match key {
-                        StringOrProxies::Proxies => {
-                            proxies.append(&mut map.next_value()?);
-                        }
-                        StringOrProxies::String(key) => {
-                            if let Ok(value) = map.next_value() {
-                                parameters.insert(key.into(), value);
-                            } else {
-                                map.next_value::<IgnoredAny>()?;
-                            }
-                        }
-                    }
-                }
+            fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(ParameterValue::String(v))
+            }
 
-                Ok(Parameters {
-                    parameters,
-                    proxies,
-                })
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                ParametersVisitor.visit_map(map).map(ParameterValue::Block)
             }
         }
 
-        deserializer.deserialize_map(ParametersVisitor)
+        deserializer.deserialize_any(ParameterValueVisitor)
     }
 }
 
@@ -201,7 +311,8 @@ impl<'de> Deserialize<'de> for Parameters {
 pub struct Shader {
     pub shader: UncasedString,
     pub parameters: BTreeMap<UncasedString, String>,
-    pub proxies: BTreeMap<UncasedString, Value>,
+    /// Proxies in the order they are defined in the material.
+    pub proxies: Vec<RawProxy>,
 }
 
 #[derive(Debug, Clone, Error, Hash, PartialEq, Eq)]
@@ -212,6 +323,24 @@ pub struct ParameterError {
     value: String,
 }
 
+impl ParameterError {
+    #[must_use]
+    pub fn parameter(&self) -> &'static str {
+        self.parameter
+    }
+
+    /// Name of the expected type.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    #[must_use]
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
 pub trait ParameterType: Sized {
     /// Type name to use for error messages.
     const TYPE_NAME: &'static str;
@@ -255,6 +384,14 @@ impl_parameter_type_from_str!(i64, "integer (i64)");
 impl_parameter_type_from_str!(usize, "integer (usize)");
 impl_parameter_type_from_str!(isize, "integer (isize)");
 
+impl ParameterType for String {
+    const TYPE_NAME: &'static str = "string";
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(s.to_owned())
+    }
+}
+
 impl_parameter_type_from_str!(f32, "float");
 impl_parameter_type_from_str!(f64, "float");
 
@@ -505,6 +642,8 @@ pub enum ShaderResolveError {
     Io { path: String, error: String },
     #[error("error deserializing included material: {0}")]
     Deserialization(#[from] vdf::Error),
+    #[error("patch include cycle: {}", chain.join(" -> "))]
+    PatchCycle { chain: Vec<String> },
 }
 
 impl ShaderResolveError {
@@ -536,64 +675,113 @@ impl Vmt {
         vdf::to_string(self)
     }
 
-    /// Resolve the shader.
-    /// If this is a patch material, applies the patch.
+    /// # Errors
+    ///
+    /// Returns `Err` if the serialization fails.
+    pub fn to_string_with_options(&self, options: &vdf::FormatOptions) -> vdf::Result<String> {
+        vdf::to_string_with_options(self, options)
+    }
+
+    /// Resolve the shader for the given target profile.
+    /// If this is a patch material, follows the patch chain and applies the patches.
+    /// Applies the DX-level blocks, shader fallback blocks and conditional parameters
+    /// that match the profile.
     ///
     /// # Errors
     ///
     /// If this is a patch material,
-    /// returns `Err` if any of the included materials can't be found or parsed.
+    /// returns `Err` if any of the included materials can't be found or parsed,
+    /// or if the patch chain contains a cycle.
     pub fn resolve_shader(
         self,
         file_system: &plumber_fs::OpenFileSystem,
+        profile: &TargetProfile,
     ) -> Result<Shader, ShaderResolveError> {
-        match self.shader {
-            ShaderOrPatch::Shader(shader) => Ok(shader),
-            ShaderOrPatch::Patch(mut patch) => {
-                let base_contents = file_system
-                    .read(&patch.include)
-                    .map_err(|err| ShaderResolveError::from_io(&err, &patch.include))?;
-                let base_vmt = Self::from_bytes(&base_contents)?;
-                let mut base_shader = base_vmt.resolve_shader(file_system)?;
-                base_shader.parameters.append(&mut patch.insert);
-                Ok(base_shader)
-            }
-        }
+        resolve_chain(self, None, profile, |include| {
+            file_system
+                .read(include)
+                .map_err(|err| ShaderResolveError::from_io(&err, include))
+        })
+        .map(|resolved| resolved.shader)
+    }
+
+    /// Resolve the shader for the given target profile like `resolve_shader`,
+    /// also recording which material in the patch chain supplied each parameter.
+    /// `material_path` is the path of this material.
+    ///
+    /// # Errors
+    ///
+    /// If this is a patch material,
+    /// returns `Err` if any of the included materials can't be found or parsed,
+    /// or if the patch chain contains a cycle.
+    pub fn resolve_shader_with_provenance(
+        self,
+        material_path: &GamePath,
+        file_system: &plumber_fs::OpenFileSystem,
+        profile: &TargetProfile,
+    ) -> Result<ResolvedShader, ShaderResolveError> {
+        resolve_chain(self, Some(material_path), profile, |include| {
+            file_system
+                .read(include)
+                .map_err(|err| ShaderResolveError::from_io(&err, include))
+        })
     }
 
-    /// Resolve the shader.
-    /// If this is a patch material, applies the patch.
+    /// Resolve the shader for the given target profile.
+    /// If this is a patch material, follows the patch chain and applies the patches.
     /// Unlike `resolve_shader`, this will look for the patched file in the OS file system using the provided function instead of always looking in the game's file system.
     ///
     /// # Errors
     ///
     /// If this is a patch material,
-    /// returns `Err` if any of the included materials can't be found or parsed.
+    /// returns `Err` if any of the included materials can't be found or parsed,
+    /// or if the patch chain contains a cycle.
     pub fn resolve_shader_os(
         self,
         file_system: &plumber_fs::OpenFileSystem,
+        profile: &TargetProfile,
         mut find_patch_source: impl FnMut(&str) -> Result<std::path::PathBuf, ShaderResolveError>
     ) -> Result<Shader, ShaderResolveError> {
-        match self.shader {
-            ShaderOrPatch::Shader(shader) => Ok(shader),
-            ShaderOrPatch::Patch(mut patch) => {
-                let patch_path = find_patch_source(&patch.include.as_str())?;
-                let base_contents = file_system
-                    .read(&patch_path)
-                    .map_err(|err| ShaderResolveError::from_io(&err, &patch.include))?;
-                let base_vmt = Self::from_bytes(&base_contents)?;
-                let mut base_shader = base_vmt.resolve_shader_os(file_system, find_patch_source)?;
-                base_shader.parameters.append(&mut patch.insert);
-                Ok(base_shader)
-            }
+        resolve_chain(self, None, profile, |include| {
+            let patch_path = find_patch_source(include.as_str())?;
+            file_system
+                .read(&patch_path)
+                .map_err(|err| ShaderResolveError::from_io(&err, include))
+        })
+        .map(|resolved| resolved.shader)
+    }
+
+    /// Returns the material included by this patch material,
+    /// or `None` if this is not a patch material.
+    #[must_use]
+    pub fn patch_include(&self) -> Option<&GamePathBuf> {
+        if let ShaderOrPatch::Patch(patch) = &self.shader {
+            Some(&patch.include)
+        } else {
+            None
         }
     }
 
     /// Convert the material into the inner shader.
     /// Returns `None` if this is a patch material.
+    /// DX-level blocks and shader fallback blocks are dropped,
+    /// and conditional parameters are kept as is.
     #[must_use]
     pub fn into_shader(self) -> Option<Shader> {
-        if let ShaderOrPatch::Shader(shader) = self.shader {
+        if let ShaderOrPatch::Shader { shader, .. } = self.shader {
+            Some(shader)
+        } else {
+            None
+        }
+    }
+
+    /// Convert the material into the inner shader,
+    /// applying the blocks and conditional parameters that match the profile.
+    /// Returns `None` if this is a patch material.
+    #[must_use]
+    pub fn into_shader_with_profile(self, profile: &TargetProfile) -> Option<Shader> {
+        if let ShaderOrPatch::Shader { mut shader, blocks } = self.shader {
+            apply_profile(&mut shader, blocks, profile);
             Some(shader)
         } else {
             None
@@ -626,11 +814,14 @@ impl<'de> Deserialize<'de> for Vmt {
                     StringOrPatch::Patch => ShaderOrPatch::Patch(map.next_value()?),
                     StringOrPatch::String(shader) => {
                         let parameters: Parameters = map.next_value()?;
-                        ShaderOrPatch::Shader(Shader {
-                            shader: shader.into(),
-                            parameters: parameters.parameters,
-                            proxies: parameters.proxies,
-                        })
+                        ShaderOrPatch::Shader {
+                            shader: Shader {
+                                shader: shader.into(),
+                                parameters: parameters.parameters,
+                                proxies: parameters.proxies,
+                            },
+                            blocks: parameters.blocks,
+                        }
                     }
                 };
                 Ok(Vmt { shader })
@@ -648,9 +839,14 @@ impl Serialize for Vmt {
     {
         let mut map = serializer.serialize_map(Some(1))?;
         match &self.shader {
-            ShaderOrPatch::Shader(shader) => {
-                map.serialize_entry(&shader.shader, &shader.parameters)
-            }
+            ShaderOrPatch::Shader { shader, blocks } => map.serialize_entry(
+                &shader.shader,
+                &ParametersRef {
+                    parameters: &shader.parameters,
+                    proxies: &shader.proxies,
+                    blocks,
+                },
+            ),
             ShaderOrPatch::Patch(patch) => map.serialize_entry("patch", patch),
         }?;
         map.end()
@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use plumber_fs::{GamePath, GamePathBuf};
+use plumber_uncased::{AsUncased, UncasedString};
+
+use crate::{
+    profile::{apply_conditional_parameters, apply_profile},
+    Shader, ShaderOrPatch, ShaderResolveError, TargetProfile, Vmt,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct Patch {
+    pub(crate) include: GamePathBuf,
+    /// Parameters that are added if the included material doesn't have them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    insert: BTreeMap<UncasedString, String>,
+    /// Parameters that overwrite the ones in the included material.
+    /// Parameters the included material doesn't have are ignored.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    replace: BTreeMap<UncasedString, String>,
+}
+
+impl Patch {
+    fn apply(
+        self,
+        shader: &mut Shader,
+        provenance: &mut BTreeMap<UncasedString, GamePathBuf>,
+        source: Option<&GamePathBuf>,
+        profile: &TargetProfile,
+    ) {
+        let Self {
+            mut insert,
+            mut replace,
+            ..
+        } = self;
+
+        apply_conditional_parameters(&mut insert, profile);
+        apply_conditional_parameters(&mut replace, profile);
+
+        for (parameter, value) in insert {
+            if shader.parameters.contains_key(&parameter) {
+                continue;
+            }
+            record_source(provenance, &parameter, source);
+            shader.parameters.insert(parameter, value);
+        }
+
+        for (parameter, value) in replace {
+            if let Some(existing) = shader.parameters.get_mut(&parameter) {
+                *existing = value;
+                record_source(provenance, &parameter, source);
+            }
+        }
+    }
+}
+
+fn record_source(
+    provenance: &mut BTreeMap<UncasedString, GamePathBuf>,
+    parameter: &UncasedString,
+    source: Option<&GamePathBuf>,
+) {
+    match source {
+        Some(source) => {
+            provenance.insert(parameter.clone(), source.clone());
+        }
+        None => {
+            provenance.remove(parameter);
+        }
+    }
+}
+
+/// A shader resolved through its patch chain.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ResolvedShader {
+    pub shader: Shader,
+    /// The material that supplied the final value of each parameter.
+    pub provenance: BTreeMap<UncasedString, GamePathBuf>,
+    /// The materials in the patch chain,
+    /// starting from the resolved material and ending with the base material.
+    pub chain: Vec<GamePathBuf>,
+}
+
+impl ResolvedShader {
+    /// Returns the material that supplied the final value of the parameter,
+    /// or `None` if the shader doesn't have the parameter.
+    #[must_use]
+    pub fn source_of(&self, parameter: &str) -> Option<&GamePathBuf> {
+        self.provenance.get(parameter.as_uncased())
+    }
+}
+
+/// Follows the patch chain starting from `vmt`, reading the included materials with `read`.
+/// `material_path` is the path of `vmt` itself, if known.
+/// Without it, the parameters supplied by `vmt` are missing from the provenance map.
+pub(crate) fn resolve_chain(
+    vmt: Vmt,
+    material_path: Option<&GamePath>,
+    profile: &TargetProfile,
+    mut read: impl FnMut(&GamePath) -> Result<Vec<u8>, ShaderResolveError>,
+) -> Result<ResolvedShader, ShaderResolveError> {
+    let mut chain: Vec<GamePathBuf> = material_path
+        .map(|path| path.with_extension("vmt"))
+        .into_iter()
+        .collect();
+    let mut patches = Vec::new();
+    let mut current = vmt;
+
+    let (mut shader, blocks) = loop {
+        match current.shader {
+            ShaderOrPatch::Shader { shader, blocks } => break (shader, blocks),
+            ShaderOrPatch::Patch(patch) => {
+                let include = patch.include.with_extension("vmt");
+                let is_cycle = chain.contains(&include);
+                let source = chain.last().cloned();
+                chain.push(include);
+
+                if is_cycle {
+                    return Err(ShaderResolveError::PatchCycle {
+                        chain: chain.iter().map(ToString::to_string).collect(),
+                    });
+                }
+
+                let contents = read(&patch.include)?;
+                current = Vmt::from_bytes(&contents)?;
+                patches.push((source, patch));
+            }
+        }
+    };
+
+    apply_profile(&mut shader, blocks, profile);
+
+    let mut provenance = BTreeMap::new();
+    if let Some(base) = chain.last() {
+        for parameter in shader.parameters.keys() {
+            provenance.insert(parameter.clone(), base.clone());
+        }
+    }
+
+    // the patch closest to the base material is applied first
+    for (source, patch) in patches.into_iter().rev() {
+        patch.apply(&mut shader, &mut provenance, source.as_ref(), profile);
+    }
+
+    Ok(ResolvedShader {
+        shader,
+        provenance,
+        chain,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"
+"LightmappedGeneric"
+{
+    "$basetexture" "concrete/concrete01"
+    "$surfaceprop" "concrete"
+    "$envmaptint" "[1 1 1]"
+}
+"#;
+
+    const MIDDLE: &str = r#"
+"patch"
+{
+    "include" "materials/concrete/concrete01.vmt"
+    "insert"
+    {
+        "$envmap" "env_cubemap"
+        "$surfaceprop" "metal"
+    }
+    "replace"
+    {
+        "$envmaptint" "[0.5 0.5 0.5]"
+        "$bumpmap" "concrete/concrete01_normal"
+    }
+}
+"#;
+
+    const TOP: &str = r#"
+"patch"
+{
+    "include" "materials/concrete/concrete01_envmap.vmt"
+    "replace"
+    {
+        "$envmap" "maps/test/c0_0_0"
+    }
+}
+"#;
+
+    fn read(path: &GamePath) -> Result<Vec<u8>, ShaderResolveError> {
+        match path.as_str() {
+            "materials/concrete/concrete01.vmt" => Ok(BASE.as_bytes().to_vec()),
+            "materials/concrete/concrete01_envmap.vmt" => Ok(MIDDLE.as_bytes().to_vec()),
+            "materials/maps/test/concrete01.vmt" => Ok(TOP.as_bytes().to_vec()),
+            _ => Err(ShaderResolveError::Io {
+                path: path.to_string(),
+                error: "not found".to_owned(),
+            }),
+        }
+    }
+
+    fn path(path: &str) -> GamePathBuf {
+        GamePath::try_from_str(path).unwrap().to_owned()
+    }
+
+    #[test]
+    fn insert_and_replace() {
+        let vmt = Vmt::from_bytes(TOP.as_bytes()).unwrap();
+        let material_path = path("materials/maps/test/concrete01.vmt");
+        let resolved =
+            resolve_chain(vmt, Some(&material_path), &TargetProfile::default(), read).unwrap();
+        let parameters = &resolved.shader.parameters;
+
+        assert_eq!(parameters["$surfaceprop".as_uncased()], "concrete");
+        assert_eq!(parameters["$envmaptint".as_uncased()], "[0.5 0.5 0.5]");
+        assert_eq!(parameters["$envmap".as_uncased()], "maps/test/c0_0_0");
+        assert!(!parameters.contains_key("$bumpmap".as_uncased()));
+
+        assert_eq!(
+            resolved.source_of("$basetexture"),
+            Some(&path("materials/concrete/concrete01.vmt"))
+        );
+        assert_eq!(
+            resolved.source_of("$surfaceprop"),
+            Some(&path("materials/concrete/concrete01.vmt"))
+        );
+        assert_eq!(
+            resolved.source_of("$EnvMapTint"),
+            Some(&path("materials/concrete/concrete01_envmap.vmt"))
+        );
+        assert_eq!(resolved.source_of("$envmap"), Some(&material_path));
+        assert_eq!(resolved.chain.len(), 3);
+    }
+
+    #[test]
+    fn cycle() {
+        const LOOP: &str = r#"
+"patch"
+{
+    "include" "materials/loop.vmt"
+}
+"#;
+
+        let vmt = Vmt::from_bytes(LOOP.as_bytes()).unwrap();
+        let result = resolve_chain(
+            vmt,
+            Some(&path("materials/loop.vmt")),
+            &TargetProfile::default(),
+            |_| Ok(LOOP.as_bytes().to_vec()),
+        );
+
+        assert_eq!(
+            result.unwrap_err(),
+            ShaderResolveError::PatchCycle {
+                chain: vec![
+                    "materials/loop.vmt".to_owned(),
+                    "materials/loop.vmt".to_owned()
+                ]
+            }
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::BTreeMap;
+
+use tracing::warn;
+
+use plumber_uncased::UncasedString;
+
+use crate::{Parameters, Shader};
+
+/// The hardware configuration materials are resolved for.
+///
+/// Selects which DX-level blocks (`">=dx90" { ... }`), shader fallback blocks
+/// (`"LightmappedGeneric_DX9" { ... }`) and conditional parameters (`"GPU>=2?$param"`) are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TargetProfile {
+    /// DX level as in `mat_dxlevel`, for example `95`.
+    pub dx_level: u32,
+    /// GPU level as in `gpu_level`, from `0` (low) to `3` (very high).
+    pub gpu_level: u32,
+    pub hdr: bool,
+}
+
+impl TargetProfile {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dx_level(&mut self, dx_level: u32) {
+        self.dx_level = dx_level;
+    }
+
+    pub fn gpu_level(&mut self, gpu_level: u32) {
+        self.gpu_level = gpu_level;
+    }
+
+    pub fn hdr(&mut self, hdr: bool) {
+        self.hdr = hdr;
+    }
+
+    /// Evaluates a material condition, such as `>=dx90`, `GPU<2`, `hdr` or `!srgb`.
+    /// Returns `None` if the condition is not recognized.
+    #[must_use]
+    pub fn matches(&self, condition: &str) -> Option<bool> {
+        let condition = condition.trim();
+
+        if let Some(condition) = condition.strip_prefix('!') {
+            return self.matches(condition).map(|matches| !matches);
+        }
+
+        let lowercase = condition.to_ascii_lowercase();
+
+        match lowercase.as_str() {
+            "hdr" => return Some(self.hdr),
+            "ldr" => return Some(!self.hdr),
+            "hdr_dx9" => return Some(self.hdr && self.dx_level >= 90),
+            "srgb" => return Some(self.dx_level >= 90),
+            "360" | "x360" | "$x360" | "ps3" | "$ps3" | "gameconsole" => return Some(false),
+            "$win32" | "win32" => return Some(true),
+            _ => {}
+        }
+
+        let (operator, rest) = split_operator(&lowercase);
+
+        if let Some(level) = rest.strip_prefix("gpu") {
+            let level = level.trim().parse().ok()?;
+            operator.compare(self.gpu_level, level)
+        } else if rest.starts_with("dx") {
+            operator.compare(self.dx_level, parse_dx_level(&rest)?)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the shader fallback block for the given level applies.
+    fn fallback_applies(&self, fallback: FallbackLevel) -> bool {
+        fallback.dx_level <= self.dx_level && (!fallback.hdr || self.hdr)
+    }
+}
+
+impl Default for TargetProfile {
+    fn default() -> Self {
+        Self {
+            dx_level: 95,
+            gpu_level: 3,
+            hdr: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+impl Operator {
+    fn compare(self, value: u32, reference: u32) -> Option<bool> {
+        Some(match self {
+            Self::Less => value < reference,
+            Self::LessOrEqual => value <= reference,
+            Self::Greater => value > reference,
+            Self::GreaterOrEqual => value >= reference,
+            Self::Equal => value == reference,
+        })
+    }
+}
+
+/// Splits the comparison operator from a condition.
+/// The operator may come before or after `gpu`, for example both `>=gpu2` and `gpu>=2` are valid.
+fn split_operator(condition: &str) -> (Operator, String) {
+    const OPERATORS: &[(&str, Operator)] = &[
+        (">=", Operator::GreaterOrEqual),
+        ("<=", Operator::LessOrEqual),
+        ("==", Operator::Equal),
+        (">", Operator::Greater),
+        ("<", Operator::Less),
+    ];
+
+    for &(symbol, operator) in OPERATORS {
+        if let Some(rest) = condition.strip_prefix(symbol) {
+            return (operator, rest.trim().to_owned());
+        }
+        if let Some((prefix, rest)) = condition.split_once(symbol) {
+            return (operator, format!("{}{}", prefix.trim(), rest.trim()));
+        }
+    }
+
+    (Operator::Equal, condition.to_owned())
+}
+
+/// Parses DX level names, such as `dx90`, `dx90_20b` or `dx8`.
+fn parse_dx_level(name: &str) -> Option<u32> {
+    let level = name.strip_prefix("dx")?;
+    let (level, ps20b) = match level.strip_suffix("_20b") {
+        Some(level) => (level, true),
+        None => (level, false),
+    };
+
+    let mut level: u32 = level.parse().ok()?;
+    if level < 10 {
+        level *= 10;
+    }
+    if ps20b {
+        level += 2;
+    }
+
+    Some(level)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct FallbackLevel {
+    dx_level: u32,
+    hdr: bool,
+}
+
+/// Parses shader fallback block names such as `LightmappedGeneric_DX9` or `LightmappedGeneric_HDR_DX9`.
+fn parse_fallback_block(shader: &str, name: &str) -> Option<FallbackLevel> {
+    if name.len() <= shader.len() || !name.is_char_boundary(shader.len()) {
+        return None;
+    }
+    let (prefix, suffix) = name.split_at(shader.len());
+    if !prefix.eq_ignore_ascii_case(shader) {
+        return None;
+    }
+
+    let suffix = suffix.strip_prefix('_')?.to_ascii_lowercase();
+    let (suffix, hdr) = match suffix.strip_prefix("hdr_") {
+        Some(suffix) => (suffix.to_owned(), true),
+        None => (suffix, false),
+    };
+
+    Some(FallbackLevel {
+        dx_level: parse_dx_level(&suffix)?,
+        hdr,
+    })
+}
+
+/// Folds the blocks matching the profile into the shader.
+/// Shader fallback blocks are applied before the condition blocks, and conditional parameters last,
+/// so that more specific values override less specific ones.
+pub(crate) fn apply_profile(
+    shader: &mut Shader,
+    blocks: Vec<(UncasedString, Parameters)>,
+    profile: &TargetProfile,
+) {
+    let fallback = blocks
+        .iter()
+        .filter_map(|(name, _)| parse_fallback_block(shader.shader.as_str(), name.as_str()))
+        .filter(|&level| profile.fallback_applies(level))
+        .max();
+
+    let (fallback_blocks, condition_blocks): (Vec<_>, Vec<_>) =
+        blocks.into_iter().partition(|(name, _)| {
+            parse_fallback_block(shader.shader.as_str(), name.as_str()).is_some()
+        });
+
+    for (name, block) in fallback_blocks {
+        if parse_fallback_block(shader.shader.as_str(), name.as_str()) == fallback {
+            merge_block(shader, block, profile);
+        }
+    }
+
+    for (name, block) in condition_blocks {
+        if block_applies(shader, &name, profile) {
+            merge_block(shader, block, profile);
+        }
+    }
+
+    apply_conditional_parameters(&mut shader.parameters, profile);
+}
+
+fn block_applies(shader: &Shader, name: &UncasedString, profile: &TargetProfile) -> bool {
+    match profile.matches(name.as_str()) {
+        Some(matches) => matches,
+        None => {
+            warn!(
+                "material with shader `{}`: unknown block `{}`",
+                shader.shader, name
+            );
+            false
+        }
+    }
+}
+
+fn merge_block(shader: &mut Shader, block: Parameters, profile: &TargetProfile) {
+    let Parameters {
+        mut parameters,
+        mut proxies,
+        blocks,
+    } = block;
+
+    shader.parameters.append(&mut parameters);
+    shader.proxies.append(&mut proxies);
+
+    for (name, block) in blocks {
+        if block_applies(shader, &name, profile) {
+            merge_block(shader, block, profile);
+        }
+    }
+}
+
+/// Replaces parameters such as `GPU>=2?$detailscale` with `$detailscale` if the condition matches,
+/// and removes them otherwise.
+pub(crate) fn apply_conditional_parameters(
+    parameters: &mut BTreeMap<UncasedString, String>,
+    profile: &TargetProfile,
+) {
+    let conditional = parameters
+        .keys()
+        .filter(|key| key.as_str().contains('?'))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for key in conditional {
+        let value = parameters.remove(&key).expect("key should exist");
+        let (condition, parameter) = key.as_str().split_once('?').expect("key contains `?`");
+
+        match profile.matches(condition) {
+            Some(true) => {
+                parameters.insert(parameter.trim().into(), value);
+            }
+            Some(false) => {}
+            None => warn!("unknown condition in parameter `{}`", key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use plumber_uncased::AsUncased;
+
+    use crate::Vmt;
+
+    const MATERIAL: &str = r#"
+"LightmappedGeneric"
+{
+    "$basetexture" "concrete/concrete01"
+    "$detailscale" "1"
+    "GPU>=2?$detailscale" "5"
+    "!srgb?$envmaptint" "[0.5 0.5 0.5]"
+    ">=dx90"
+    {
+        "$envmap" "env_cubemap"
+    }
+    "<dx90"
+    {
+        "$envmap" "env_cubemap_dx8"
+        "$basetexture" "concrete/concrete01_dx8"
+    }
+    "LightmappedGeneric_DX9"
+    {
+        "$bumpmap" "concrete/concrete01_normal"
+    }
+    "LightmappedGeneric_HDR_DX9"
+    {
+        "$bumpmap" "concrete/concrete01_normal_hdr"
+    }
+    "LightmappedGeneric_DX8"
+    {
+        "$bumpmap" "concrete/concrete01_normal_dx8"
+    }
+}
+"#;
+
+    fn resolve(profile: &TargetProfile) -> Shader {
+        let vmt = Vmt::from_bytes(MATERIAL.as_bytes()).unwrap();
+        vmt.into_shader_with_profile(profile).unwrap()
+    }
+
+    #[test]
+    fn default_profile() {
+        let shader = resolve(&TargetProfile::default());
+
+        assert_eq!(shader.parameters["$envmap".as_uncased()], "env_cubemap");
+        assert_eq!(
+            shader.parameters["$basetexture".as_uncased()],
+            "concrete/concrete01"
+        );
+        assert_eq!(
+            shader.parameters["$bumpmap".as_uncased()],
+            "concrete/concrete01_normal_hdr"
+        );
+        assert_eq!(shader.parameters["$detailscale".as_uncased()], "5");
+        assert!(!shader.parameters.contains_key("$envmaptint".as_uncased()));
+        assert!(!shader
+            .parameters
+            .keys()
+            .any(|key| key.as_str().contains('?')));
+    }
+
+    #[test]
+    fn low_profile() {
+        let mut profile = TargetProfile::new();
+        profile.dx_level(81);
+        profile.gpu_level(1);
+        profile.hdr(false);
+        let shader = resolve(&profile);
+
+        assert_eq!(shader.parameters["$envmap".as_uncased()], "env_cubemap_dx8");
+        assert_eq!(
+            shader.parameters["$basetexture".as_uncased()],
+            "concrete/concrete01_dx8"
+        );
+        assert_eq!(
+            shader.parameters["$bumpmap".as_uncased()],
+            "concrete/concrete01_normal_dx8"
+        );
+        assert_eq!(shader.parameters["$detailscale".as_uncased()], "1");
+        assert_eq!(
+            shader.parameters["$envmaptint".as_uncased()],
+            "[0.5 0.5 0.5]"
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let profile = TargetProfile::default();
+
+        assert_eq!(profile.matches(">=dx90_20b"), Some(true));
+        assert_eq!(profile.matches("<dx95"), Some(false));
+        assert_eq!(profile.matches("dx95"), Some(true));
+        assert_eq!(profile.matches("GPU<2"), Some(false));
+        assert_eq!(profile.matches(">=GPU3"), Some(true));
+        assert_eq!(profile.matches("!hdr"), Some(false));
+        assert_eq!(profile.matches("something"), None);
+    }
+}
@@ -0,0 +1,813 @@
+use std::{collections::BTreeMap, f32::consts::PI, fmt};
+
+use glam::{Vec2, Vec3};
+use serde::{
+    de::{IgnoredAny, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use tracing::warn;
+
+use plumber_fs::Path;
+use plumber_uncased::{AsUncased, UncasedString};
+
+use crate::{ParameterError, ParameterType, Shader, Transform};
+
+/// A material proxy block as written in the material.
+#[derive(Debug, Clone)]
+pub struct RawProxy {
+    pub name: UncasedString,
+    pub parameters: BTreeMap<UncasedString, String>,
+}
+
+impl RawProxy {
+    /// Extracts a proxy parameter.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the parameter is not valid.
+    pub fn try_extract_param<T: ParameterType>(
+        &self,
+        parameter: &'static str,
+    ) -> Result<Option<T>, ParameterError> {
+        let Some(value) = self.parameters.get(parameter.as_uncased()) else {
+            return Ok(None);
+        };
+
+        if let Some(res) = T::parse(value) {
+            Ok(Some(res))
+        } else {
+            Err(ParameterError {
+                parameter,
+                kind: T::TYPE_NAME,
+                value: value.clone(),
+            })
+        }
+    }
+
+    fn param_or<T: ParameterType>(
+        &self,
+        parameter: &'static str,
+        default: T,
+    ) -> Result<T, ParameterError> {
+        self.try_extract_param(parameter)
+            .map(|value| value.unwrap_or(default))
+    }
+
+    fn required_param<T: ParameterType>(
+        &self,
+        parameter: &'static str,
+    ) -> Result<T, ParameterError> {
+        self.try_extract_param(parameter)?
+            .ok_or_else(|| ParameterError {
+                parameter,
+                kind: T::TYPE_NAME,
+                value: String::new(),
+            })
+    }
+}
+
+/// Proxies in the order they are defined in the material.
+/// Unlike a map, keeps multiple proxies of the same type.
+pub(crate) struct RawProxies(pub Vec<RawProxy>);
+
+impl<'de> Deserialize<'de> for RawProxies {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ProxiesVisitor;
+
+        impl<'de> Visitor<'de> for ProxiesVisitor {
+            type Value = RawProxies;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("material proxies")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut proxies = Vec::new();
+
+                while let Some(name) = map.next_key::<UncasedString>()? {
+                    if let Ok(ProxyParameters(parameters)) = map.next_value() {
+                        proxies.push(RawProxy { name, parameters });
+                    } else {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                }
+
+                Ok(RawProxies(proxies))
+            }
+        }
+
+        deserializer.deserialize_map(ProxiesVisitor)
+    }
+}
+
+/// Serializes proxies as repeated keys.
+pub(crate) struct RawProxiesRef<'a>(pub &'a [RawProxy]);
+
+impl Serialize for RawProxiesRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for proxy in self.0 {
+            map.serialize_entry(&proxy.name, &proxy.parameters)?;
+        }
+        map.end()
+    }
+}
+
+struct ProxyParameters(BTreeMap<UncasedString, String>);
+
+impl<'de> Deserialize<'de> for ProxyParameters {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ProxyParametersVisitor;
+
+        impl<'de> Visitor<'de> for ProxyParametersVisitor {
+            type Value = ProxyParameters;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("proxy parameters")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut parameters = BTreeMap::new();
+
+                while let Some(key) = map.next_key::<UncasedString>()? {
+                    if let Ok(value) = map.next_value() {
+                        parameters.insert(key, value);
+                    } else {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                }
+
+                Ok(ProxyParameters(parameters))
+            }
+        }
+
+        deserializer.deserialize_map(ProxyParametersVisitor)
+    }
+}
+
+/// A reference to a material variable, optionally to a single component of it,
+/// for example `$color[1]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyVariable {
+    pub name: UncasedString,
+    pub component: Option<usize>,
+}
+
+impl ParameterType for ProxyVariable {
+    const TYPE_NAME: &'static str = "variable";
+
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+
+        if let Some((name, rest)) = s.split_once('[') {
+            let component = rest.strip_suffix(']')?.trim().parse().ok()?;
+            Some(Self {
+                name: name.trim().into(),
+                component: Some(component),
+            })
+        } else if s.is_empty() {
+            None
+        } else {
+            Some(Self {
+                name: s.into(),
+                component: None,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimatedTexture {
+    pub texture_var: ProxyVariable,
+    pub frame_num_var: ProxyVariable,
+    pub frame_rate: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureScroll {
+    pub texture_scroll_var: ProxyVariable,
+    pub rate: f32,
+    /// Scroll direction in degrees.
+    pub angle: f32,
+    pub scale: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sine {
+    pub result_var: ProxyVariable,
+    pub period: f32,
+    pub min: f32,
+    pub max: f32,
+    pub time_offset: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinearRamp {
+    pub result_var: ProxyVariable,
+    pub rate: f32,
+    pub initial_value: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UniformNoise {
+    pub result_var: ProxyVariable,
+    pub min: f32,
+    pub max: f32,
+}
+
+/// `Add`, `Subtract`, `Multiply` and `Divide` proxies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryOperation {
+    pub src_var1: ProxyVariable,
+    pub src_var2: ProxyVariable,
+    pub result_var: ProxyVariable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equals {
+    pub src_var1: ProxyVariable,
+    pub result_var: ProxyVariable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureTransform {
+    pub center_var: Option<ProxyVariable>,
+    pub scale_var: Option<ProxyVariable>,
+    pub rotate_var: Option<ProxyVariable>,
+    pub translate_var: Option<ProxyVariable>,
+    pub result_var: ProxyVariable,
+}
+
+#[derive(Debug, Clone)]
+pub enum Proxy {
+    AnimatedTexture(AnimatedTexture),
+    TextureScroll(TextureScroll),
+    Sine(Sine),
+    LinearRamp(LinearRamp),
+    UniformNoise(UniformNoise),
+    Add(BinaryOperation),
+    Subtract(BinaryOperation),
+    Multiply(BinaryOperation),
+    Divide(BinaryOperation),
+    Equals(Equals),
+    TextureTransform(TextureTransform),
+    /// Sets `$cheapwaterstartdistance` and `$cheapwaterenddistance` from the map's water LOD settings.
+    WaterLod,
+    /// A proxy that is not supported, usually a game-specific one.
+    Unknown(RawProxy),
+}
+
+impl Proxy {
+    /// Parses a typed proxy from a raw proxy block.
+    /// Unsupported proxies are returned as [`Proxy::Unknown`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if a parameter of a supported proxy is missing or invalid.
+    pub fn try_from_raw(raw: &RawProxy) -> Result<Self, ParameterError> {
+        let binary_operation = || -> Result<BinaryOperation, ParameterError> {
+            Ok(BinaryOperation {
+                src_var1: raw.required_param("srcvar1")?,
+                src_var2: raw.required_param("srcvar2")?,
+                result_var: raw.required_param("resultvar")?,
+            })
+        };
+
+        Ok(match raw.name.as_str().to_ascii_lowercase().as_str() {
+            "animatedtexture" => Self::AnimatedTexture(AnimatedTexture {
+                texture_var: raw.required_param("animatedtexturevar")?,
+                frame_num_var: raw.required_param("animatedtextureframenumvar")?,
+                frame_rate: raw.param_or("animatedtextureframerate", 15.0)?,
+            }),
+            "texturescroll" => Self::TextureScroll(TextureScroll {
+                texture_scroll_var: raw.required_param("texturescrollvar")?,
+                rate: raw.param_or("texturescrollrate", 1.0)?,
+                angle: raw.param_or("texturescrollangle", 0.0)?,
+                scale: raw.param_or("texturescale", 1.0)?,
+            }),
+            "sine" => Self::Sine(Sine {
+                result_var: raw.required_param("resultvar")?,
+                period: raw.param_or("sineperiod", 1.0)?,
+                min: raw.param_or("sinemin", -1.0)?,
+                max: raw.param_or("sinemax", 1.0)?,
+                time_offset: raw.param_or("sinetimeoffset", 0.0)?,
+            }),
+            "linearramp" => Self::LinearRamp(LinearRamp {
+                result_var: raw.required_param("resultvar")?,
+                rate: raw.param_or("rate", 1.0)?,
+                initial_value: raw.param_or("initialvalue", 0.0)?,
+            }),
+            "uniformnoise" => Self::UniformNoise(UniformNoise {
+                result_var: raw.required_param("resultvar")?,
+                min: raw.param_or("minval", 0.0)?,
+                max: raw.param_or("maxval", 1.0)?,
+            }),
+            "add" => Self::Add(binary_operation()?),
+            "subtract" => Self::Subtract(binary_operation()?),
+            "multiply" => Self::Multiply(binary_operation()?),
+            "divide" => Self::Divide(binary_operation()?),
+            "equals" => Self::Equals(Equals {
+                src_var1: raw.required_param("srcvar1")?,
+                result_var: raw.required_param("resultvar")?,
+            }),
+            "texturetransform" => Self::TextureTransform(TextureTransform {
+                center_var: raw.try_extract_param("centervar")?,
+                scale_var: raw.try_extract_param("scalevar")?,
+                rotate_var: raw.try_extract_param("rotatevar")?,
+                translate_var: raw.try_extract_param("translatevar")?,
+                result_var: raw.required_param("resultvar")?,
+            }),
+            "waterlod" => Self::WaterLod,
+            _ => Self::Unknown(raw.clone()),
+        })
+    }
+}
+
+impl Shader {
+    /// Parses the typed proxies of the shader, in the order they are defined.
+    /// Logs a warning and skips the proxy if it is invalid.
+    #[must_use]
+    pub fn parse_proxies(&self, material_name: Path) -> Vec<Proxy> {
+        self.proxies
+            .iter()
+            .filter_map(|raw| match Proxy::try_from_raw(raw) {
+                Ok(proxy) => Some(proxy),
+                Err(err) => {
+                    warn!(
+                        "material `{}`: proxy `{}`: {}",
+                        material_name, raw.name, err
+                    );
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// A value of a material variable during proxy evaluation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyValue {
+    Float(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Transform(Transform),
+}
+
+impl ProxyValue {
+    fn parse(s: &str) -> Option<Self> {
+        if let Some(v) = f32::parse(s) {
+            Some(Self::Float(v))
+        } else if let Some(v) = Vec2::parse(s) {
+            Some(Self::Vec2(v))
+        } else if let Some(v) = rgb::RGB::<f32>::parse(s) {
+            Some(Self::Vec3(Vec3::new(v.r, v.g, v.b)))
+        } else {
+            Transform::parse(s).map(Self::Transform)
+        }
+    }
+
+    /// Returns the value as a float, using the first component of vectors.
+    #[must_use]
+    pub fn as_float(&self) -> f32 {
+        self.component(0)
+    }
+
+    fn component(&self, index: usize) -> f32 {
+        match self {
+            Self::Float(v) => *v,
+            Self::Vec2(v) => v.to_array().get(index).copied().unwrap_or_default(),
+            Self::Vec3(v) => v.to_array().get(index).copied().unwrap_or_default(),
+            Self::Transform(_) => 0.0,
+        }
+    }
+
+    fn set_component(&mut self, index: usize, value: f32) {
+        match self {
+            Self::Float(v) => *v = value,
+            Self::Vec2(v) => {
+                if let Some(c) = v.as_mut().get_mut(index) {
+                    *c = value;
+                }
+            }
+            Self::Vec3(v) => {
+                if let Some(c) = v.as_mut().get_mut(index) {
+                    *c = value;
+                }
+            }
+            Self::Transform(_) => {}
+        }
+    }
+
+    fn as_vec2(&self) -> Option<Vec2> {
+        match self {
+            Self::Float(v) => Some(Vec2::splat(*v)),
+            Self::Vec2(v) => Some(*v),
+            Self::Vec3(v) => Some(v.truncate()),
+            Self::Transform(_) => None,
+        }
+    }
+
+    fn combine(self, other: Self, op: impl Fn(f32, f32) -> f32) -> Self {
+        match (self, other) {
+            (Self::Float(a), Self::Float(b)) => Self::Float(op(a, b)),
+            (Self::Vec2(a), b) => {
+                Self::Vec2(Vec2::new(op(a.x, b.component(0)), op(a.y, b.component(1))))
+            }
+            (a @ Self::Float(_), Self::Vec2(b)) => {
+                Self::Vec2(Vec2::new(op(a.as_float(), b.x), op(a.as_float(), b.y)))
+            }
+            (Self::Vec3(a), b) => Self::Vec3(Vec3::new(
+                op(a.x, b.component(0)),
+                op(a.y, b.component(1)),
+                op(a.z, b.component(2)),
+            )),
+            (a @ Self::Float(_), Self::Vec3(b)) => Self::Vec3(Vec3::new(
+                op(a.as_float(), b.x),
+                op(a.as_float(), b.y),
+                op(a.as_float(), b.z),
+            )),
+            (a, _) => a,
+        }
+    }
+}
+
+/// Evaluates material proxies at a given time.
+///
+/// Material variables start out with the values of the shader parameters.
+/// Proxies are evaluated in order, so later proxies see the results of earlier ones.
+#[derive(Debug, Clone)]
+pub struct ProxyEvaluator {
+    variables: BTreeMap<UncasedString, ProxyValue>,
+    frame_counts: BTreeMap<UncasedString, u32>,
+    water_lod: Option<(f32, f32)>,
+    noise_seed: u64,
+}
+
+impl ProxyEvaluator {
+    #[must_use]
+    pub fn new(shader: &Shader) -> Self {
+        Self {
+            variables: shader
+                .parameters
+                .iter()
+                .filter_map(|(key, value)| Some((key.clone(), ProxyValue::parse(value)?)))
+                .collect(),
+            frame_counts: BTreeMap::new(),
+            water_lod: None,
+            noise_seed: 0,
+        }
+    }
+
+    /// Sets the number of frames in the texture in the given texture variable.
+    /// Without it, `AnimatedTexture` frame numbers are not wrapped.
+    pub fn frame_count(&mut self, texture_var: &str, frame_count: u32) {
+        self.frame_counts.insert(texture_var.into(), frame_count);
+    }
+
+    /// Sets the cheap water start and end distances used by `WaterLOD`.
+    pub fn water_lod(&mut self, start_distance: f32, end_distance: f32) {
+        self.water_lod = Some((start_distance, end_distance));
+    }
+
+    /// Sets the seed for `UniformNoise`.
+    /// The same seed and time always produce the same noise.
+    pub fn noise_seed(&mut self, seed: u64) {
+        self.noise_seed = seed;
+    }
+
+    /// Evaluates the proxies at the given time in seconds.
+    /// Returns the final values of the variables written by the proxies.
+    #[must_use]
+    pub fn evaluate(&self, proxies: &[Proxy], time: f32) -> BTreeMap<UncasedString, ProxyValue> {
+        let mut state = EvaluationState {
+            variables: self.variables.clone(),
+            written: Vec::new(),
+        };
+
+        for (i, proxy) in proxies.iter().enumerate() {
+            match proxy {
+                Proxy::AnimatedTexture(proxy) => {
+                    let mut frame = (proxy.frame_rate * time).floor().max(0.0);
+                    if let Some(&count) = self.frame_counts.get(&proxy.texture_var.name) {
+                        #[allow(clippy::cast_precision_loss)]
+                        let count = count.max(1) as f32;
+                        frame %= count;
+                    }
+                    state.set_float(&proxy.frame_num_var, frame);
+                }
+                Proxy::TextureScroll(proxy) => {
+                    let angle = proxy.angle.to_radians();
+                    let offset = Vec2::new(angle.cos(), angle.sin()) * proxy.rate * time;
+                    let offset = offset - offset.floor();
+
+                    let var = &proxy.texture_scroll_var;
+                    let value = match state.get(var) {
+                        Some(ProxyValue::Vec2(_)) => ProxyValue::Vec2(offset),
+                        _ => ProxyValue::Transform(Transform {
+                            center: Vec2::ZERO,
+                            scale: Vec2::splat(proxy.scale),
+                            rotate: 0.0,
+                            translate: offset,
+                        }),
+                    };
+                    state.set(var, value);
+                }
+                Proxy::Sine(proxy) => {
+                    let value =
+                        (2.0 * PI * (time - proxy.time_offset) / proxy.period).sin() * 0.5 + 0.5;
+                    state.set_float(
+                        &proxy.result_var,
+                        proxy.min + (proxy.max - proxy.min) * value,
+                    );
+                }
+                Proxy::LinearRamp(proxy) => {
+                    state.set_float(&proxy.result_var, proxy.initial_value + proxy.rate * time);
+                }
+                Proxy::UniformNoise(proxy) => {
+                    let noise = noise(self.noise_seed, time, i);
+                    state.set_float(
+                        &proxy.result_var,
+                        proxy.min + (proxy.max - proxy.min) * noise,
+                    );
+                }
+                Proxy::Add(proxy) => state.binary_operation(proxy, |a, b| a + b),
+                Proxy::Subtract(proxy) => state.binary_operation(proxy, |a, b| a - b),
+                Proxy::Multiply(proxy) => state.binary_operation(proxy, |a, b| a * b),
+                Proxy::Divide(proxy) => {
+                    state.binary_operation(proxy, |a, b| if b == 0.0 { a } else { a / b });
+                }
+                Proxy::Equals(proxy) => {
+                    if let Some(value) = state.get(&proxy.src_var1) {
+                        state.set(&proxy.result_var, value);
+                    }
+                }
+                Proxy::TextureTransform(proxy) => {
+                    let default = Transform::default();
+                    let vec2_or = |var: &Option<ProxyVariable>, default: Vec2| {
+                        var.as_ref()
+                            .and_then(|var| state.get(var))
+                            .and_then(|value| value.as_vec2())
+                            .unwrap_or(default)
+                    };
+
+                    let transform = Transform {
+                        center: vec2_or(&proxy.center_var, default.center),
+                        scale: vec2_or(&proxy.scale_var, default.scale),
+                        rotate: proxy
+                            .rotate_var
+                            .as_ref()
+                            .and_then(|var| state.get(var))
+                            .map_or(default.rotate, |value| value.as_float()),
+                        translate: vec2_or(&proxy.translate_var, default.translate),
+                    };
+                    state.set(&proxy.result_var, ProxyValue::Transform(transform));
+                }
+                Proxy::WaterLod => {
+                    if let Some((start, end)) = self.water_lod {
+                        for (name, value) in [
+                            ("$cheapwaterstartdistance", start),
+                            ("$cheapwaterenddistance", end),
+                        ] {
+                            let var = ProxyVariable {
+                                name: name.into(),
+                                component: None,
+                            };
+                            state.set_float(&var, value);
+                        }
+                    }
+                }
+                Proxy::Unknown(_) => {}
+            }
+        }
+
+        let EvaluationState { variables, written } = state;
+        written
+            .into_iter()
+            .filter_map(|name| {
+                let value = *variables.get(&name)?;
+                Some((name, value))
+            })
+            .collect()
+    }
+}
+
+struct EvaluationState {
+    variables: BTreeMap<UncasedString, ProxyValue>,
+    written: Vec<UncasedString>,
+}
+
+impl EvaluationState {
+    fn get(&self, var: &ProxyVariable) -> Option<ProxyValue> {
+        let value = self.variables.get(&var.name)?;
+        Some(match var.component {
+            Some(index) => ProxyValue::Float(value.component(index)),
+            None => *value,
+        })
+    }
+
+    fn set(&mut self, var: &ProxyVariable, value: ProxyValue) {
+        match var.component {
+            Some(index) => {
+                let target = self
+                    .variables
+                    .entry(var.name.clone())
+                    .or_insert(ProxyValue::Vec3(Vec3::ZERO));
+                target.set_component(index, value.as_float());
+            }
+            None => {
+                self.variables.insert(var.name.clone(), value);
+            }
+        }
+
+        if !self.written.contains(&var.name) {
+            self.written.push(var.name.clone());
+        }
+    }
+
+    fn set_float(&mut self, var: &ProxyVariable, value: f32) {
+        self.set(var, ProxyValue::Float(value));
+    }
+
+    fn binary_operation(&mut self, proxy: &BinaryOperation, op: impl Fn(f32, f32) -> f32) {
+        if let (Some(a), Some(b)) = (self.get(&proxy.src_var1), self.get(&proxy.src_var2)) {
+            self.set(&proxy.result_var, a.combine(b, op));
+        }
+    }
+}
+
+/// Deterministic noise in `[0, 1)` based on the seed, time and proxy index.
+fn noise(seed: u64, time: f32, index: usize) -> f32 {
+    // splitmix64
+    let mut x = seed ^ u64::from(time.to_bits()) ^ ((index as u64) << 32);
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+
+    #[allow(clippy::cast_precision_loss)]
+    let noise = (x >> 40) as f32 / (1u64 << 24) as f32;
+    noise
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use plumber_fs::GamePath;
+
+    use crate::Vmt;
+
+    const MATERIAL: &str = r#"
+"UnlitGeneric"
+{
+    "$basetexture" "effects/water_anim"
+    "$frame" "0"
+    "$alpha" "1"
+    "$color" "[1 1 1]"
+    "$scale" "2"
+    "Proxies"
+    {
+        "AnimatedTexture"
+        {
+            "animatedTextureVar" "$basetexture"
+            "animatedTextureFrameNumVar" "$frame"
+            "animatedTextureFrameRate" "10"
+        }
+        "Sine"
+        {
+            "sineperiod" "2"
+            "sinemin" "0"
+            "sinemax" "1"
+            "resultVar" "$alpha"
+        }
+        "Sine"
+        {
+            "sineperiod" "4"
+            "sinemin" "0"
+            "sinemax" "1"
+            "resultVar" "$color[1]"
+        }
+        "Multiply"
+        {
+            "srcVar1" "$alpha"
+            "srcVar2" "$scale"
+            "resultVar" "$color[0]"
+        }
+        "TextureScroll"
+        {
+            "textureScrollVar" "$basetexturetransform"
+            "textureScrollRate" "0.25"
+            "textureScrollAngle" "90"
+        }
+        "SomeGameProxy"
+        {
+        }
+    }
+}
+"#;
+
+    fn evaluate(time: f32) -> BTreeMap<UncasedString, ProxyValue> {
+        let shader = Vmt::from_bytes(MATERIAL.as_bytes())
+            .unwrap()
+            .into_shader()
+            .unwrap();
+        let proxies = shader.parse_proxies(Path::Game(GamePath::try_from_str("test").unwrap()));
+        assert_eq!(proxies.len(), 6);
+        assert!(matches!(proxies[5], Proxy::Unknown(_)));
+
+        let mut evaluator = ProxyEvaluator::new(&shader);
+        evaluator.frame_count("$basetexture", 8);
+        evaluator.evaluate(&proxies, time)
+    }
+
+    #[test]
+    fn proxy_evaluation() {
+        let values = evaluate(1.5);
+
+        assert_eq!(
+            values[&UncasedString::from("$frame")],
+            ProxyValue::Float(7.0)
+        );
+
+        let alpha = values[&UncasedString::from("$alpha")].as_float();
+        assert!((alpha - 0.0).abs() < 1e-5);
+
+        let ProxyValue::Vec3(color) = values[&UncasedString::from("$color")] else {
+            panic!("color should be a vector");
+        };
+        assert!((color.x - 0.0).abs() < 1e-5);
+        assert!((color.y - (0.5 + 0.5 * (0.75 * PI).sin())).abs() < 1e-5);
+        assert!((color.z - 1.0).abs() < 1e-5);
+
+        let ProxyValue::Transform(transform) =
+            values[&UncasedString::from("$basetexturetransform")]
+        else {
+            panic!("texture scroll should produce a transform");
+        };
+        assert!((transform.translate.y - 0.375).abs() < 1e-5);
+    }
+
+    #[test]
+    fn frame_wrapping() {
+        let values = evaluate(2.0);
+        assert_eq!(
+            values[&UncasedString::from("$frame")],
+            ProxyValue::Float(4.0)
+        );
+    }
+
+    #[test]
+    fn proxies_roundtrip() {
+        let vmt = Vmt::from_bytes(MATERIAL.as_bytes()).unwrap();
+        let output = vmt.to_string().unwrap();
+        let shader = Vmt::from_bytes(output.as_bytes())
+            .unwrap()
+            .into_shader()
+            .unwrap();
+
+        let names = shader
+            .proxies
+            .iter()
+            .map(|proxy| proxy.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "AnimatedTexture",
+                "Sine",
+                "Sine",
+                "Multiply",
+                "TextureScroll",
+                "SomeGameProxy"
+            ]
+        );
+    }
+
+    #[test]
+    fn variable_parsing() {
+        assert_eq!(
+            ProxyVariable::parse(" $color[2] "),
+            Some(ProxyVariable {
+                name: "$color".into(),
+                component: Some(2)
+            })
+        );
+        assert_eq!(ProxyVariable::parse("$color[x]"), None);
+    }
+}
@@ -0,0 +1,718 @@
+//! Typed parameters of common Source shaders.
+//!
+//! Each schema is built from a resolved [`Shader`], so patch materials use the schema of their base shader.
+//! Parameters that are missing or invalid get the engine default value.
+
+use glam::{Vec2, Vec3};
+use rgb::RGB;
+use thiserror::Error;
+use tracing::warn;
+
+use plumber_fs::Path;
+use plumber_uncased::{AsUncased, UncasedString};
+
+use crate::{ParameterError, ParameterType, Shader, TexturePath, Transform};
+
+#[derive(Debug, Clone, Error, Hash, PartialEq, Eq)]
+pub enum SchemaWarning {
+    #[error("unknown parameter `{0}`")]
+    UnknownParameter(UncasedString),
+    #[error("{0}, using the default value")]
+    InvalidParameter(#[from] ParameterError),
+}
+
+/// Typed parameters of a shader.
+pub trait ShaderSchema: Sized {
+    /// Lowercase names of the shaders this schema is for, without DX-level suffixes.
+    const SHADERS: &'static [&'static str];
+    /// Parameters read by this schema.
+    const PARAMETERS: &'static [&'static str];
+
+    /// Builds the schema from a resolved shader.
+    /// Invalid parameters get the default value, and they and unknown parameters are added to `warnings`.
+    fn from_shader_with_warnings(shader: &Shader, warnings: &mut Vec<SchemaWarning>) -> Self;
+
+    /// Builds the schema from a resolved shader.
+    /// Logs a warning for every invalid or unknown parameter.
+    #[must_use]
+    fn from_shader(shader: &Shader, material_name: Path) -> Self {
+        let mut warnings = Vec::new();
+        let schema = Self::from_shader_with_warnings(shader, &mut warnings);
+        log_warnings(&warnings, material_name);
+        schema
+    }
+
+    /// Returns `true` if this schema is for the given shader.
+    #[must_use]
+    fn is_for(shader: &Shader) -> bool {
+        let name = base_shader_name(shader.shader.as_str());
+        Self::SHADERS.iter().any(|s| *s == name)
+    }
+}
+
+/// Returns the shader name in lowercase without the `SDK_` prefix and DX-level suffixes,
+/// for example `lightmappedgeneric` for `SDK_LightmappedGeneric_HDR_DX9`.
+#[must_use]
+pub fn base_shader_name(shader: &str) -> String {
+    let mut name = shader.to_ascii_lowercase();
+
+    if let Some(stripped) = name.strip_prefix("sdk_") {
+        name = stripped.to_owned();
+    }
+
+    // strip suffixes such as `_dx9`, `_dx81`, `_hdr_dx9` and `_dx9_hdr`
+    loop {
+        if let Some(stripped) = name.strip_suffix("_hdr") {
+            name.truncate(stripped.len());
+            continue;
+        }
+
+        let without_digits = name.trim_end_matches(|c: char| c.is_ascii_digit());
+        if without_digits.len() < name.len() {
+            if let Some(stripped) = without_digits.strip_suffix("_dx") {
+                name.truncate(stripped.len());
+                continue;
+            }
+        }
+
+        break;
+    }
+
+    name
+}
+
+/// Lowercase names of the shaders in Source engine games, without DX-level suffixes.
+pub const KNOWN_SHADERS: &[&str] = &[
+    "aftershock",
+    "cable",
+    "character",
+    "cloak",
+    "cloud",
+    "core",
+    "customcharacter",
+    "customhero",
+    "customweapon",
+    "decalbasetimeslightmapalphablendselfillum",
+    "decalmodulate",
+    "depthwrite",
+    "eye_refract",
+    "eyeball",
+    "eyerefract",
+    "eyes",
+    "flesh",
+    "infected",
+    "lightmapped_4wayblend",
+    "lightmappedgeneric",
+    "lightmappedreflective",
+    "lightmappedtwotexture",
+    "modulate",
+    "monitorscreen",
+    "movingcutout",
+    "particlesphere",
+    "portal",
+    "portalrefract",
+    "refract",
+    "screenspace_general",
+    "shadow",
+    "shadowbuild",
+    "shadowmodel",
+    "sky",
+    "splinecard",
+    "splinerope",
+    "sprite",
+    "spritecard",
+    "subrect",
+    "teeth",
+    "treeleaf",
+    "unlitgeneric",
+    "unlittwotexture",
+    "vertexlitgeneric",
+    "volumeclouds",
+    "vortwarp",
+    "water",
+    "weaponsheen",
+    "windowimposter",
+    "wireframe",
+    "worldtwotextureblend",
+    "worldvertextransition",
+];
+
+/// Returns `true` if the shader is a known Source engine shader.
+#[must_use]
+pub fn is_known_shader(shader: &str) -> bool {
+    let name = base_shader_name(shader);
+    KNOWN_SHADERS.iter().any(|s| *s == name)
+}
+
+fn log_warnings(warnings: &[SchemaWarning], material_name: Path) {
+    for warning in warnings {
+        warn!("material `{}`: {}", material_name, warning);
+    }
+}
+
+/// Parameters that are valid for every shader.
+const COMMON_PARAMETERS: &[&str] = &[
+    "$flags",
+    "$flags_defined",
+    "$flags2",
+    "$flags_defined2",
+    "$surfaceprop",
+    "$surfaceprop2",
+    "$fallbackmaterial",
+    "$no_draw",
+    "$nodecal",
+    "$decal",
+    "$decalscale",
+    "$decalsize",
+    "$model",
+    "$ignorez",
+    "$nofog",
+    "$znearer",
+    "$wireframe",
+    "$debug",
+    "$no_fullbright",
+    "$use_in_fillrate_mode",
+    "$allowalphatocoverage",
+    "$ignore_alpha_modulation",
+    "$noalphamod",
+    "$srgbtint",
+    "$nocull",
+    "$translucent",
+    "$additive",
+    "$alphatest",
+    "$alphatestreference",
+    "$vertexcolor",
+    "$vertexalpha",
+    "$color",
+    "$alpha",
+    "$reflectivity",
+    "$bottommaterial",
+    "$underwateroverlay",
+    "$envmapmode",
+    "$envmapsphere",
+    "$envmapcameraspace",
+    "$multipass",
+    "$softwareskin",
+    "$opaquetexture",
+    "$halflambert",
+    "$flat",
+    "$playerclip",
+    "$nopaint",
+];
+
+/// Editor and compiler parameters start with `%`, for example `%compilewater` or `%keywords`.
+const TOOL_PARAMETER_PREFIX: &str = "%";
+
+fn warn_unknown_parameters(
+    shader: &Shader,
+    parameters: &[&str],
+    warnings: &mut Vec<SchemaWarning>,
+) {
+    // proxies can use any parameter as a variable
+    let proxy_variables = shader
+        .proxies
+        .iter()
+        .flat_map(|proxy| proxy.parameters.values())
+        .filter_map(|value| {
+            let value = value.trim();
+            let name = value.split_once('[').map_or(value, |(name, _)| name);
+            name.starts_with('$').then(|| name.as_uncased())
+        })
+        .collect::<Vec<_>>();
+
+    for key in shader.parameters.keys() {
+        let known = key.as_str().starts_with(TOOL_PARAMETER_PREFIX)
+            || parameters
+                .iter()
+                .chain(COMMON_PARAMETERS)
+                .any(|p| key == p.as_uncased())
+            || proxy_variables.iter().any(|v| key == *v);
+
+        if !known {
+            warnings.push(SchemaWarning::UnknownParameter(key.clone()));
+        }
+    }
+}
+
+fn extract<T: ParameterType>(
+    shader: &Shader,
+    parameter: &'static str,
+    warnings: &mut Vec<SchemaWarning>,
+) -> Option<T> {
+    match shader.try_extract_param(parameter) {
+        Ok(value) => value,
+        Err(err) => {
+            warnings.push(err.into());
+            None
+        }
+    }
+}
+
+/// Declares a shader schema.
+/// Fields without a default value are optional.
+macro_rules! shader_schema {
+    (@type $ty:ty) => { Option<$ty> };
+    (@type $ty:ty, $default:expr) => { $ty };
+    (@default) => { None };
+    (@default $default:expr) => { $default };
+    (@extract $shader:ident, $warnings:ident, $param:literal, $ty:ty) => {
+        extract::<$ty>($shader, $param, $warnings)
+    };
+    (@extract $shader:ident, $warnings:ident, $param:literal, $ty:ty, $default:expr) => {
+        extract::<$ty>($shader, $param, $warnings).unwrap_or_else(|| $default)
+    };
+    (
+        $(#[$meta:meta])*
+        $name:ident for [$($shader_name:literal),+ $(,)?] {
+            $(
+                $(#[$field_meta:meta])*
+                $param:literal => $field:ident: $ty:ty $(= $default:expr)?,
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq)]
+        #[non_exhaustive]
+        pub struct $name {
+            $(
+                $(#[$field_meta])*
+                pub $field: shader_schema!(@type $ty $(, $default)?),
+            )*
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self {
+                    $($field: shader_schema!(@default $($default)?),)*
+                }
+            }
+        }
+
+        impl ShaderSchema for $name {
+            const SHADERS: &'static [&'static str] = &[$($shader_name),+];
+            const PARAMETERS: &'static [&'static str] = &[$($param),*];
+
+            fn from_shader_with_warnings(shader: &Shader, warnings: &mut Vec<SchemaWarning>) -> Self {
+                warn_unknown_parameters(shader, Self::PARAMETERS, warnings);
+
+                Self {
+                    $($field: shader_schema!(@extract shader, warnings, $param, $ty $(, $default)?),)*
+                }
+            }
+        }
+    };
+}
+
+const WHITE: RGB<f32> = RGB {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+};
+
+shader_schema! {
+    /// Brush surfaces.
+    LightmappedGeneric for ["lightmappedgeneric"] {
+        "$basetexture" => base_texture: TexturePath,
+        "$basetexturetransform" => base_texture_transform: Transform = Transform::default(),
+        "$frame" => frame: i32 = 0,
+        "$bumpmap" => bump_map: TexturePath,
+        "$bumpframe" => bump_frame: i32 = 0,
+        "$bumptransform" => bump_transform: Transform = Transform::default(),
+        "$ssbump" => ssbump: bool = false,
+        "$detail" => detail: TexturePath,
+        "$detailscale" => detail_scale: f32 = 4.0,
+        "$detailblendmode" => detail_blend_mode: i32 = 0,
+        "$detailblendfactor" => detail_blend_factor: f32 = 1.0,
+        "$detailtint" => detail_tint: RGB<f32> = WHITE,
+        "$detailtexturetransform" => detail_texture_transform: Transform = Transform::default(),
+        "$envmap" => env_map: TexturePath,
+        "$envmapframe" => env_map_frame: i32 = 0,
+        "$envmapmask" => env_map_mask: TexturePath,
+        "$envmapmasktransform" => env_map_mask_transform: Transform = Transform::default(),
+        "$envmaptint" => env_map_tint: RGB<f32> = WHITE,
+        "$envmapcontrast" => env_map_contrast: f32 = 0.0,
+        "$envmapsaturation" => env_map_saturation: f32 = 1.0,
+        "$fresnelreflection" => fresnel_reflection: f32 = 1.0,
+        "$basealphaenvmapmask" => base_alpha_env_map_mask: bool = false,
+        "$normalmapalphaenvmapmask" => normal_map_alpha_env_map_mask: bool = false,
+        "$selfillum" => self_illum: bool = false,
+        "$selfillumtint" => self_illum_tint: RGB<f32> = WHITE,
+        "$selfillummask" => self_illum_mask: TexturePath,
+        "$color" => color: RGB<f32> = WHITE,
+        "$alpha" => alpha: f32 = 1.0,
+        "$translucent" => translucent: bool = false,
+        "$alphatest" => alpha_test: bool = false,
+        "$alphatestreference" => alpha_test_reference: f32 = 0.5,
+        "$nocull" => no_cull: bool = false,
+        "$additive" => additive: bool = false,
+        "$vertexcolor" => vertex_color: bool = false,
+        "$vertexalpha" => vertex_alpha: bool = false,
+        "$seamless_scale" => seamless_scale: f32 = 0.0,
+    }
+}
+
+shader_schema! {
+    /// Lit models.
+    VertexLitGeneric for ["vertexlitgeneric"] {
+        "$basetexture" => base_texture: TexturePath,
+        "$basetexturetransform" => base_texture_transform: Transform = Transform::default(),
+        "$frame" => frame: i32 = 0,
+        "$bumpmap" => bump_map: TexturePath,
+        "$bumpframe" => bump_frame: i32 = 0,
+        "$bumptransform" => bump_transform: Transform = Transform::default(),
+        "$detail" => detail: TexturePath,
+        "$detailscale" => detail_scale: f32 = 4.0,
+        "$detailblendmode" => detail_blend_mode: i32 = 0,
+        "$detailblendfactor" => detail_blend_factor: f32 = 1.0,
+        "$detailtint" => detail_tint: RGB<f32> = WHITE,
+        "$envmap" => env_map: TexturePath,
+        "$envmapmask" => env_map_mask: TexturePath,
+        "$envmaptint" => env_map_tint: RGB<f32> = WHITE,
+        "$envmapcontrast" => env_map_contrast: f32 = 0.0,
+        "$envmapsaturation" => env_map_saturation: f32 = 1.0,
+        "$basealphaenvmapmask" => base_alpha_env_map_mask: bool = false,
+        "$normalmapalphaenvmapmask" => normal_map_alpha_env_map_mask: bool = false,
+        "$phong" => phong: bool = false,
+        "$phongexponent" => phong_exponent: f32 = 5.0,
+        "$phongexponenttexture" => phong_exponent_texture: TexturePath,
+        "$phongboost" => phong_boost: f32 = 1.0,
+        "$phongfresnelranges" => phong_fresnel_ranges: Vec3 = Vec3::new(0.0, 0.5, 1.0),
+        "$phongtint" => phong_tint: RGB<f32> = WHITE,
+        "$phongalbedotint" => phong_albedo_tint: bool = false,
+        "$basemapalphaphongmask" => base_map_alpha_phong_mask: bool = false,
+        "$lightwarptexture" => light_warp_texture: TexturePath,
+        "$rimlight" => rim_light: bool = false,
+        "$rimlightexponent" => rim_light_exponent: f32 = 4.0,
+        "$rimlightboost" => rim_light_boost: f32 = 1.0,
+        "$rimmask" => rim_mask: bool = false,
+        "$selfillum" => self_illum: bool = false,
+        "$selfillumtint" => self_illum_tint: RGB<f32> = WHITE,
+        "$selfillummask" => self_illum_mask: TexturePath,
+        "$selfillumfresnel" => self_illum_fresnel: bool = false,
+        "$selfillumfresnelminmaxexp" => self_illum_fresnel_min_max_exp: Vec3 = Vec3::new(0.0, 1.0, 1.0),
+        "$halflambert" => half_lambert: bool = false,
+        "$color" => color: RGB<f32> = WHITE,
+        "$color2" => color2: RGB<f32> = WHITE,
+        "$alpha" => alpha: f32 = 1.0,
+        "$translucent" => translucent: bool = false,
+        "$alphatest" => alpha_test: bool = false,
+        "$alphatestreference" => alpha_test_reference: f32 = 0.5,
+        "$nocull" => no_cull: bool = false,
+        "$additive" => additive: bool = false,
+    }
+}
+
+shader_schema! {
+    /// Surfaces that are not affected by lighting.
+    UnlitGeneric for ["unlitgeneric"] {
+        "$basetexture" => base_texture: TexturePath,
+        "$basetexturetransform" => base_texture_transform: Transform = Transform::default(),
+        "$frame" => frame: i32 = 0,
+        "$detail" => detail: TexturePath,
+        "$detailscale" => detail_scale: f32 = 4.0,
+        "$detailblendmode" => detail_blend_mode: i32 = 0,
+        "$detailblendfactor" => detail_blend_factor: f32 = 1.0,
+        "$detailtint" => detail_tint: RGB<f32> = WHITE,
+        "$envmap" => env_map: TexturePath,
+        "$envmapmask" => env_map_mask: TexturePath,
+        "$envmaptint" => env_map_tint: RGB<f32> = WHITE,
+        "$color" => color: RGB<f32> = WHITE,
+        "$alpha" => alpha: f32 = 1.0,
+        "$translucent" => translucent: bool = false,
+        "$alphatest" => alpha_test: bool = false,
+        "$alphatestreference" => alpha_test_reference: f32 = 0.5,
+        "$nocull" => no_cull: bool = false,
+        "$additive" => additive: bool = false,
+        "$vertexcolor" => vertex_color: bool = false,
+        "$vertexalpha" => vertex_alpha: bool = false,
+    }
+}
+
+shader_schema! {
+    /// Displacements blending between two textures.
+    WorldVertexTransition for ["worldvertextransition"] {
+        "$basetexture" => base_texture: TexturePath,
+        "$basetexturetransform" => base_texture_transform: Transform = Transform::default(),
+        "$frame" => frame: i32 = 0,
+        "$basetexture2" => base_texture2: TexturePath,
+        "$basetexturetransform2" => base_texture_transform2: Transform = Transform::default(),
+        "$frame2" => frame2: i32 = 0,
+        "$bumpmap" => bump_map: TexturePath,
+        "$bumpframe" => bump_frame: i32 = 0,
+        "$bumpmap2" => bump_map2: TexturePath,
+        "$bumpframe2" => bump_frame2: i32 = 0,
+        "$ssbump" => ssbump: bool = false,
+        "$blendmodulatetexture" => blend_modulate_texture: TexturePath,
+        "$blendmasktransform" => blend_mask_transform: Transform = Transform::default(),
+        "$detail" => detail: TexturePath,
+        "$detailscale" => detail_scale: f32 = 4.0,
+        "$detailblendmode" => detail_blend_mode: i32 = 0,
+        "$detailblendfactor" => detail_blend_factor: f32 = 1.0,
+        "$envmap" => env_map: TexturePath,
+        "$envmapmask" => env_map_mask: TexturePath,
+        "$envmaptint" => env_map_tint: RGB<f32> = WHITE,
+        "$basealphaenvmapmask" => base_alpha_env_map_mask: bool = false,
+        "$normalmapalphaenvmapmask" => normal_map_alpha_env_map_mask: bool = false,
+        "$seamless_scale" => seamless_scale: f32 = 0.0,
+        "$color" => color: RGB<f32> = WHITE,
+        "$alphatest" => alpha_test: bool = false,
+        "$alphatestreference" => alpha_test_reference: f32 = 0.5,
+    }
+}
+
+shader_schema! {
+    Water for ["water"] {
+        "$normalmap" => normal_map: TexturePath,
+        "$bumpframe" => bump_frame: i32 = 0,
+        "$bumptransform" => bump_transform: Transform = Transform::default(),
+        "$dudvmap" => dudv_map: TexturePath,
+        "$bottommaterial" => bottom_material: String,
+        "$underwateroverlay" => underwater_overlay: String,
+        "$reflecttexture" => reflect_texture: TexturePath,
+        "$refracttexture" => refract_texture: TexturePath,
+        "$reflecttint" => reflect_tint: RGB<f32> = WHITE,
+        "$refracttint" => refract_tint: RGB<f32> = WHITE,
+        "$reflectamount" => reflect_amount: f32 = 0.8,
+        "$refractamount" => refract_amount: f32 = 0.0,
+        "$reflectentities" => reflect_entities: bool = false,
+        "$envmap" => env_map: TexturePath,
+        "$fogenable" => fog_enable: bool = false,
+        "$fogcolor" => fog_color: RGB<f32> = RGB::new(0.0, 0.0, 0.0),
+        "$fogstart" => fog_start: f32 = 0.0,
+        "$fogend" => fog_end: f32 = 0.0,
+        "$abovewater" => above_water: bool = false,
+        "$forcecheap" => force_cheap: bool = false,
+        "$forceexpensive" => force_expensive: bool = false,
+        "$cheapwaterstartdistance" => cheap_water_start_distance: f32 = 500.0,
+        "$cheapwaterenddistance" => cheap_water_end_distance: f32 = 1000.0,
+        "$scale" => scale: Vec2 = Vec2::ONE,
+        "$flowmap" => flow_map: TexturePath,
+    }
+}
+
+shader_schema! {
+    /// Refracting surfaces, such as glass.
+    Refract for ["refract"] {
+        "$normalmap" => normal_map: TexturePath,
+        "$normalmap2" => normal_map2: TexturePath,
+        "$bumpframe" => bump_frame: i32 = 0,
+        "$bumptransform" => bump_transform: Transform = Transform::default(),
+        "$bumptransform2" => bump_transform2: Transform = Transform::default(),
+        "$dudvmap" => dudv_map: TexturePath,
+        "$refracttint" => refract_tint: RGB<f32> = WHITE,
+        "$refracttinttexture" => refract_tint_texture: TexturePath,
+        "$refractamount" => refract_amount: f32 = 0.5,
+        "$bluramount" => blur_amount: i32 = 1,
+        "$envmap" => env_map: TexturePath,
+        "$envmaptint" => env_map_tint: RGB<f32> = WHITE,
+        "$envmapcontrast" => env_map_contrast: f32 = 0.0,
+        "$envmapsaturation" => env_map_saturation: f32 = 1.0,
+        "$masked" => masked: bool = false,
+        "$vertexcolormodulate" => vertex_color_modulate: bool = false,
+        "$fadeoutonsilhouette" => fade_out_on_silhouette: bool = false,
+        "$localrefract" => local_refract: bool = false,
+    }
+}
+
+shader_schema! {
+    Sprite for ["sprite", "spritecard"] {
+        "$basetexture" => base_texture: TexturePath,
+        "$frame" => frame: i32 = 0,
+        "$spriteorientation" => sprite_orientation: String = "parallel_upright".to_owned(),
+        "$spriteorigin" => sprite_origin: Vec2 = Vec2::new(0.5, 0.5),
+        "$spriterendermode" => sprite_render_mode: i32 = 0,
+        "$color" => color: RGB<f32> = WHITE,
+        "$alpha" => alpha: f32 = 1.0,
+        "$translucent" => translucent: bool = false,
+        "$additive" => additive: bool = false,
+        "$nocull" => no_cull: bool = false,
+        "$vertexcolor" => vertex_color: bool = false,
+        "$vertexalpha" => vertex_alpha: bool = false,
+    }
+}
+
+shader_schema! {
+    Cable for ["cable", "splinerope"] {
+        "$basetexture" => base_texture: TexturePath,
+        "$bumpmap" => bump_map: TexturePath,
+        "$minlight" => min_light: f32 = 0.1,
+        "$maxlight" => max_light: f32 = 0.3,
+        "$color" => color: RGB<f32> = WHITE,
+        "$alpha" => alpha: f32 = 1.0,
+        "$translucent" => translucent: bool = false,
+        "$nocull" => no_cull: bool = false,
+    }
+}
+
+shader_schema! {
+    Eyes for ["eyes"] {
+        "$basetexture" => base_texture: TexturePath,
+        "$frame" => frame: i32 = 0,
+        "$iris" => iris: TexturePath,
+        "$irisframe" => iris_frame: i32 = 0,
+        "$glint" => glint: TexturePath,
+        "$dilation" => dilation: f32 = 0.0,
+        "$halflambert" => half_lambert: bool = false,
+    }
+}
+
+shader_schema! {
+    EyeRefract for ["eyerefract"] {
+        "$iris" => iris: TexturePath,
+        "$irisframe" => iris_frame: i32 = 0,
+        "$corneatexture" => cornea_texture: TexturePath,
+        "$corneabumpstrength" => cornea_bump_strength: f32 = 1.0,
+        "$parallaxstrength" => parallax_strength: f32 = 0.25,
+        "$dilation" => dilation: f32 = 0.5,
+        "$glossiness" => glossiness: f32 = 1.0,
+        "$eyeballradius" => eyeball_radius: f32 = 0.5,
+        "$ambientoccltexture" => ambient_occl_texture: TexturePath,
+        "$ambientocclcolor" => ambient_occl_color: RGB<f32> = RGB::new(0.33, 0.33, 0.33),
+        "$envmap" => env_map: TexturePath,
+        "$lightwarptexture" => light_warp_texture: TexturePath,
+        "$raytracesphere" => ray_trace_sphere: bool = false,
+        "$spheretexkillcombo" => sphere_tex_kill_combo: bool = false,
+        "$halflambert" => half_lambert: bool = false,
+    }
+}
+
+/// Typed parameters of any supported shader.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedShader {
+    LightmappedGeneric(LightmappedGeneric),
+    VertexLitGeneric(VertexLitGeneric),
+    UnlitGeneric(UnlitGeneric),
+    WorldVertexTransition(WorldVertexTransition),
+    Water(Water),
+    Refract(Refract),
+    Sprite(Sprite),
+    Cable(Cable),
+    Eyes(Eyes),
+    EyeRefract(EyeRefract),
+    /// The shader doesn't have a schema.
+    Unknown,
+}
+
+impl TypedShader {
+    /// Builds the schema matching the shader name from a resolved shader.
+    /// Invalid parameters get the default value, and they and unknown parameters are added to `warnings`.
+    #[must_use]
+    pub fn from_shader_with_warnings(shader: &Shader, warnings: &mut Vec<SchemaWarning>) -> Self {
+        macro_rules! try_schemas {
+            ($($schema:ident),*) => {
+                $(
+                    if $schema::is_for(shader) {
+                        return Self::$schema($schema::from_shader_with_warnings(shader, warnings));
+                    }
+                )*
+            };
+        }
+
+        try_schemas!(
+            LightmappedGeneric,
+            VertexLitGeneric,
+            UnlitGeneric,
+            WorldVertexTransition,
+            Water,
+            Refract,
+            Sprite,
+            Cable,
+            Eyes,
+            EyeRefract
+        );
+
+        Self::Unknown
+    }
+
+    /// Builds the schema matching the shader name from a resolved shader.
+    /// Logs a warning for every invalid or unknown parameter.
+    #[must_use]
+    pub fn from_shader(shader: &Shader, material_name: Path) -> Self {
+        let mut warnings = Vec::new();
+        let schema = Self::from_shader_with_warnings(shader, &mut warnings);
+        log_warnings(&warnings, material_name);
+        schema
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Vmt;
+
+    fn shader(input: &str) -> Shader {
+        Vmt::from_bytes(input.as_bytes())
+            .unwrap()
+            .into_shader()
+            .unwrap()
+    }
+
+    #[test]
+    fn lightmapped_generic() {
+        let shader = shader(
+            r#"
+"LightmappedGeneric_DX9"
+{
+    "$basetexture" "concrete/concrete01"
+    "$detailscale" "2.5"
+    "$envmaptint" "[0.2 0.2 0.2]"
+    "$ssbump" "yes"
+    "$mycustomvar" "1"
+    "$unusedvar" "1"
+    "%compilewater" "1"
+    "Proxies"
+    {
+        "Sine"
+        {
+            "resultVar" "$mycustomvar"
+        }
+    }
+}
+"#,
+        );
+
+        let mut warnings = Vec::new();
+        let TypedShader::LightmappedGeneric(schema) =
+            TypedShader::from_shader_with_warnings(&shader, &mut warnings)
+        else {
+            panic!("shader should be LightmappedGeneric");
+        };
+
+        assert_eq!(
+            schema.base_texture,
+            Some(TexturePath("concrete/concrete01".into()))
+        );
+        assert!((schema.detail_scale - 2.5).abs() < f32::EPSILON);
+        assert!((schema.env_map_tint.g - 0.2).abs() < f32::EPSILON);
+        assert!(!schema.ssbump);
+        assert_eq!(schema.bump_map, None);
+        assert!((schema.alpha_test_reference - 0.5).abs() < f32::EPSILON);
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(
+            warnings[0],
+            SchemaWarning::UnknownParameter("$unusedvar".into())
+        );
+        assert!(matches!(&warnings[1], SchemaWarning::InvalidParameter(_)));
+    }
+
+    #[test]
+    fn shader_names() {
+        assert_eq!(
+            base_shader_name("SDK_VertexLitGeneric_HDR_DX9"),
+            "vertexlitgeneric"
+        );
+        assert_eq!(base_shader_name("Water_DX81"), "water");
+        assert_eq!(base_shader_name("Water_DX9_HDR"), "water");
+        assert_eq!(base_shader_name("Refract_DX80"), "refract");
+        assert!(is_known_shader("Water_DX81"));
+        assert!(!is_known_shader("UnknownShader"));
+
+        let shader = shader("\"UnknownShader\" { \"$basetexture\" \"a\" }");
+        assert_eq!(
+            TypedShader::from_shader_with_warnings(&shader, &mut Vec::new()),
+            TypedShader::Unknown
+        );
+    }
+}
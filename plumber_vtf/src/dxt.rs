@@ -0,0 +1,324 @@
+//! Decoding of the 4x4 block compressed formats.
+
+use crate::ImageFormat;
+
+/// Decodes block compressed data into 8-bit RGBA.
+/// `data` must have the correct size for the dimensions.
+pub(crate) fn decode(format: ImageFormat, data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let width = width.max(1);
+    let height = height.max(1);
+    let row_blocks = width.div_ceil(4);
+    let block_size = if matches!(
+        format,
+        ImageFormat::Dxt1 | ImageFormat::Dxt1OneBitAlpha | ImageFormat::Ati1N
+    ) {
+        8
+    } else {
+        16
+    };
+
+    let mut output = vec![0; width * height * 4];
+
+    for (index, block) in data.chunks_exact(block_size).enumerate() {
+        let pixels = match format {
+            ImageFormat::Dxt1 | ImageFormat::Dxt1OneBitAlpha => decode_color_block(block, true),
+            ImageFormat::Dxt3 => decode_dxt3(block),
+            ImageFormat::Dxt5 => decode_dxt5(block),
+            ImageFormat::Ati1N => decode_ati1n(block),
+            ImageFormat::Ati2N => decode_ati2n(block),
+            _ => unreachable!("format must be block compressed"),
+        };
+
+        let block_x = (index % row_blocks) * 4;
+        let block_y = (index / row_blocks) * 4;
+
+        for (i, pixel) in pixels.iter().enumerate() {
+            let x = block_x + i % 4;
+            let y = block_y + i / 4;
+
+            if x < width && y < height {
+                let offset = (y * width + x) * 4;
+                output[offset..offset + 4].copy_from_slice(pixel);
+            }
+        }
+    }
+
+    output
+}
+
+fn rgb565(value: u16) -> [u8; 3] {
+    let r = (value >> 11) & 0x1f;
+    let g = (value >> 5) & 0x3f;
+    let b = value & 0x1f;
+
+    [
+        ((r << 3) | (r >> 2)) as u8,
+        ((g << 2) | (g >> 4)) as u8,
+        ((b << 3) | (b >> 2)) as u8,
+    ]
+}
+
+fn mix(a: [u8; 3], b: [u8; 3], weight_a: u16, weight_b: u16) -> [u8; 4] {
+    let total = weight_a + weight_b;
+    let channel = |i: usize| {
+        ((u16::from(a[i]) * weight_a + u16::from(b[i]) * weight_b + total / 2) / total) as u8
+    };
+    [channel(0), channel(1), channel(2), 255]
+}
+
+/// Decodes the color part of a DXT block.
+/// In DXT1, the color block can also encode one-bit alpha.
+fn decode_color_block(block: &[u8], allow_alpha: bool) -> [[u8; 4]; 16] {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let palette = color_palette(color0, color1, allow_alpha);
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let mut pixels = [[0; 4]; 16];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = palette[((indices >> (i * 2)) & 0b11) as usize];
+    }
+    pixels
+}
+
+fn color_palette(color0: u16, color1: u16, allow_alpha: bool) -> [[u8; 4]; 4] {
+    let c0 = rgb565(color0);
+    let c1 = rgb565(color1);
+
+    if color0 > color1 || !allow_alpha {
+        [
+            [c0[0], c0[1], c0[2], 255],
+            [c1[0], c1[1], c1[2], 255],
+            mix(c0, c1, 2, 1),
+            mix(c0, c1, 1, 2),
+        ]
+    } else {
+        [
+            [c0[0], c0[1], c0[2], 255],
+            [c1[0], c1[1], c1[2], 255],
+            mix(c0, c1, 1, 1),
+            [0, 0, 0, 0],
+        ]
+    }
+}
+
+fn decode_dxt3(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut pixels = decode_color_block(&block[8..], false);
+
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let alpha = (block[i / 2] >> ((i % 2) * 4)) & 0x0f;
+        pixel[3] = alpha | (alpha << 4);
+    }
+    pixels
+}
+
+fn decode_dxt5(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut pixels = decode_color_block(&block[8..], false);
+    let alpha = decode_alpha_block(&block[..8]);
+
+    for (pixel, alpha) in pixels.iter_mut().zip(alpha) {
+        pixel[3] = alpha;
+    }
+    pixels
+}
+
+fn decode_ati1n(block: &[u8]) -> [[u8; 4]; 16] {
+    decode_alpha_block(block).map(|value| [value, value, value, 255])
+}
+
+/// Decodes a two-channel normal map block.
+/// The Z component is reconstructed into the blue channel, like `VTFLib` does.
+fn decode_ati2n(block: &[u8]) -> [[u8; 4]; 16] {
+    let red = decode_alpha_block(&block[..8]);
+    let green = decode_alpha_block(&block[8..]);
+
+    let mut pixels = [[0; 4]; 16];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let x = f32::from(red[i]) / 255.0 * 2.0 - 1.0;
+        let y = f32::from(green[i]) / 255.0 * 2.0 - 1.0;
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+
+        *pixel = [
+            red[i],
+            green[i],
+            ((z * 0.5 + 0.5) * 255.0).round() as u8,
+            255,
+        ];
+    }
+    pixels
+}
+
+/// Decodes a DXT5 alpha block, also used by the ATI formats.
+fn decode_alpha_block(block: &[u8]) -> [u8; 16] {
+    let palette = alpha_palette(block[0], block[1]);
+
+    let mut bits = 0_u64;
+    for (i, &byte) in block[2..8].iter().enumerate() {
+        bits |= u64::from(byte) << (i * 8);
+    }
+
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[((bits >> (i * 3)) & 0b111) as usize];
+    }
+    values
+}
+
+fn alpha_palette(alpha0: u8, alpha1: u8) -> [u8; 8] {
+    let a0 = u16::from(alpha0);
+    let a1 = u16::from(alpha1);
+
+    let mut palette = [0_u8; 8];
+    palette[0] = alpha0;
+    palette[1] = alpha1;
+
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u16) * a0 + i as u16 * a1 + 3) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u16) * a0 + i as u16 * a1 + 2) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    palette
+}
+
+/// Encodes a DXT5 alpha block, using the eight value mode.
+fn encode_alpha_block(output: &mut Vec<u8>, values: [u8; 16]) {
+    let max = values.iter().copied().max().unwrap_or(0);
+    let min = values.iter().copied().min().unwrap_or(0);
+    let palette = alpha_palette(max, min);
+
+    let mut bits = 0_u64;
+    for (i, &value) in values.iter().enumerate() {
+        let index = (0..8)
+            .min_by_key(|&index| palette[index].abs_diff(value))
+            .expect("palette should not be empty");
+        bits |= (index as u64) << (i * 3);
+    }
+
+    output.push(max);
+    output.push(min);
+    output.extend_from_slice(&bits.to_le_bytes()[..6]);
+}
+
+/// Encodes 8-bit RGBA pixels into block compressed data.
+/// `pixels` must contain `width * height * 4` bytes.
+pub(crate) fn encode(format: ImageFormat, pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let width = width.max(1);
+    let height = height.max(1);
+    let mut output = Vec::with_capacity(format.image_size(width as u32, height as u32));
+
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            // pixels outside the image repeat the edge pixels
+            let mut block = [[0; 4]; 16];
+            for (i, pixel) in block.iter_mut().enumerate() {
+                let x = (block_x + i % 4).min(width - 1);
+                let y = (block_y + i / 4).min(height - 1);
+                let offset = (y * width + x) * 4;
+                pixel.copy_from_slice(&pixels[offset..offset + 4]);
+            }
+
+            match format {
+                ImageFormat::Dxt1 => encode_color_block(&mut output, &block, false),
+                ImageFormat::Dxt1OneBitAlpha => encode_color_block(&mut output, &block, true),
+                ImageFormat::Dxt3 => {
+                    for pair in block.chunks_exact(2) {
+                        output.push((pair[0][3] >> 4) | (pair[1][3] & 0xf0));
+                    }
+                    encode_color_block(&mut output, &block, false);
+                }
+                ImageFormat::Dxt5 => {
+                    encode_alpha_block(&mut output, block.map(|pixel| pixel[3]));
+                    encode_color_block(&mut output, &block, false);
+                }
+                ImageFormat::Ati1N => encode_alpha_block(&mut output, block.map(|pixel| pixel[0])),
+                ImageFormat::Ati2N => {
+                    encode_alpha_block(&mut output, block.map(|pixel| pixel[0]));
+                    encode_alpha_block(&mut output, block.map(|pixel| pixel[1]));
+                }
+                _ => unreachable!("format must be block compressed"),
+            }
+        }
+    }
+
+    output
+}
+
+fn to_rgb565(color: [u8; 3]) -> u16 {
+    let r = (u16::from(color[0]) * 31 + 127) / 255;
+    let g = (u16::from(color[1]) * 63 + 127) / 255;
+    let b = (u16::from(color[2]) * 31 + 127) / 255;
+
+    (r << 11) | (g << 5) | b
+}
+
+fn color_distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+    (0..3)
+        .map(|i| {
+            let difference = i32::from(a[i]) - i32::from(b[i]);
+            (difference * difference) as u32
+        })
+        .sum()
+}
+
+/// Encodes the color part of a DXT block, using the two most distant colors as endpoints.
+/// With `one_bit_alpha`, pixels with alpha below 128 are encoded as transparent.
+fn encode_color_block(output: &mut Vec<u8>, block: &[[u8; 4]; 16], one_bit_alpha: bool) {
+    let transparent = |pixel: &[u8; 4]| one_bit_alpha && pixel[3] < 128;
+    let has_transparency = block.iter().any(transparent);
+
+    let opaque: Vec<_> = block.iter().filter(|pixel| !transparent(pixel)).collect();
+
+    // the pair of pixels furthest apart spans the color line
+    let mut endpoints = ([0; 4], [0; 4]);
+    let mut furthest = 0;
+    for (i, &&a) in opaque.iter().enumerate() {
+        for &&b in &opaque[i..] {
+            let distance = color_distance(a, b);
+            if distance >= furthest {
+                furthest = distance;
+                endpoints = (a, b);
+            }
+        }
+    }
+    let (start, end) = endpoints;
+
+    let mut color0 = to_rgb565([start[0], start[1], start[2]]);
+    let mut color1 = to_rgb565([end[0], end[1], end[2]]);
+
+    // the order of the endpoints selects between the four color and the transparent mode
+    if has_transparency == (color0 > color1) {
+        std::mem::swap(&mut color0, &mut color1);
+    }
+
+    let palette = color_palette(color0, color1, one_bit_alpha);
+
+    let mut indices = 0_u32;
+    for (i, pixel) in block.iter().enumerate() {
+        let index = if transparent(pixel) {
+            3
+        } else {
+            // the fourth color is transparent black in the transparent mode
+            let candidates = if color0 > color1 || !one_bit_alpha {
+                0..4
+            } else {
+                0..3
+            };
+            candidates
+                .min_by_key(|&index| color_distance(*pixel, palette[index]))
+                .expect("palette should not be empty")
+        };
+        indices |= (index as u32) << (i * 2);
+    }
+
+    output.extend_from_slice(&color0.to_le_bytes());
+    output.extend_from_slice(&color1.to_le_bytes());
+    output.extend_from_slice(&indices.to_le_bytes());
+}
@@ -0,0 +1,347 @@
+//! VTF writing.
+
+use crate::{
+    Error, ImageFormat, Resource, Result, TextureFlags, BASE_HEADER_SIZE, MAJOR_VERSION,
+    MAX_MINOR_VERSION, RESOURCES_OFFSET, RESOURCE_ENTRY_SIZE, SIGNATURE,
+};
+
+/// Largest width and height of the low resolution thumbnail.
+const LOW_RES_MAX_SIZE: u32 = 16;
+const LOW_RES_FORMAT: ImageFormat = ImageFormat::Dxt1;
+
+/// Image data to encode, in RGBA channel order.
+#[derive(Debug, Clone, Copy)]
+pub enum SourceImage<'a> {
+    /// 8-bit channels, in the sRGB color space.
+    Rgba8(&'a [u8]),
+    /// 32-bit float channels, in a linear color space.
+    Rgba32F(&'a [f32]),
+}
+
+impl SourceImage<'_> {
+    fn len(self) -> usize {
+        match self {
+            Self::Rgba8(data) => data.len(),
+            Self::Rgba32F(data) => data.len(),
+        }
+    }
+
+    fn to_rgba32f(self) -> Vec<f32> {
+        match self {
+            Self::Rgba8(data) => data.iter().map(|&c| f32::from(c) / 255.0).collect(),
+            Self::Rgba32F(data) => data.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct EncodeSettings {
+    /// Minor version of the written file, 0 to 5.
+    pub minor_version: u32,
+    pub format: ImageFormat,
+    pub flags: TextureFlags,
+    /// Generate the full mipmap chain, instead of only the full size image.
+    pub mipmaps: bool,
+    /// Include a low resolution DXT1 thumbnail.
+    pub low_res_image: bool,
+    pub bumpmap_scale: f32,
+}
+
+impl EncodeSettings {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn minor_version(&mut self, minor_version: u32) {
+        self.minor_version = minor_version;
+    }
+
+    pub fn format(&mut self, format: ImageFormat) {
+        self.format = format;
+    }
+
+    pub fn flags(&mut self, flags: TextureFlags) {
+        self.flags = flags;
+    }
+
+    pub fn mipmaps(&mut self, mipmaps: bool) {
+        self.mipmaps = mipmaps;
+    }
+
+    pub fn low_res_image(&mut self, low_res_image: bool) {
+        self.low_res_image = low_res_image;
+    }
+
+    pub fn bumpmap_scale(&mut self, bumpmap_scale: f32) {
+        self.bumpmap_scale = bumpmap_scale;
+    }
+}
+
+impl Default for EncodeSettings {
+    fn default() -> Self {
+        Self {
+            minor_version: 2,
+            format: ImageFormat::Dxt5,
+            flags: TextureFlags::empty(),
+            mipmaps: true,
+            low_res_image: true,
+            bumpmap_scale: 1.0,
+        }
+    }
+}
+
+/// Encodes an image into a VTF file.
+///
+/// The mipmaps are box filtered, in linear space if `TextureFlags::SRGB` is set
+/// and the image is 8-bit, float images are already linear.
+/// `TextureFlags::NO_MIP` is set if mipmaps are disabled.
+/// The reflectivity is the average linear color of the image.
+///
+/// # Errors
+///
+/// Returns `Err` if the version is not supported, the dimensions are invalid,
+/// the data doesn't match the dimensions or the format can't be encoded.
+pub fn encode(
+    image: SourceImage,
+    width: u32,
+    height: u32,
+    settings: &EncodeSettings,
+) -> Result<Vec<u8>> {
+    if settings.minor_version > MAX_MINOR_VERSION {
+        return Err(Error::UnsupportedVersion {
+            major: MAJOR_VERSION,
+            minor: settings.minor_version,
+        });
+    }
+
+    if width == 0 || height == 0 || width > u32::from(u16::MAX) || height > u32::from(u16::MAX) {
+        return Err(Error::InvalidImage(
+            "dimensions must be between 1 and 65535",
+        ));
+    }
+
+    if image.len() != width as usize * height as usize * 4 {
+        return Err(Error::InvalidImage(
+            "pixel data doesn't match the dimensions",
+        ));
+    }
+
+    let low_res_mipmap = settings
+        .low_res_image
+        .then(|| low_res_mipmap(width, height));
+    let mipmap_count = if settings.mipmaps {
+        width.max(height).ilog2() as usize + 1
+    } else {
+        1
+    };
+    let level_count = mipmap_count.max(low_res_mipmap.map_or(0, |mipmap| mipmap + 1));
+    let srgb =
+        settings.flags.contains(TextureFlags::SRGB) && matches!(image, SourceImage::Rgba8(_));
+    let levels = generate_levels(image, width, height, level_count, srgb);
+    let flags = if settings.mipmaps {
+        settings.flags
+    } else {
+        settings.flags | TextureFlags::NO_MIP
+    };
+
+    let reflectivity = match image {
+        SourceImage::Rgba8(_) => average_color(&levels[0], srgb_to_linear),
+        SourceImage::Rgba32F(_) => average_color(&levels[0], |c| c),
+    };
+
+    let low_res_dimensions = low_res_mipmap.map(|mipmap| dimensions(width, height, mipmap));
+    let low_res_data = low_res_mipmap
+        .map(|mipmap| {
+            let (w, h) = dimensions(width, height, mipmap);
+            LOW_RES_FORMAT.encode_rgba32f(&levels[mipmap], w, h)
+        })
+        .transpose()?;
+
+    let mut high_res_data = Vec::new();
+    // mipmaps are stored from the smallest to the largest
+    for mipmap in (0..mipmap_count).rev() {
+        let (w, h) = dimensions(width, height, mipmap);
+        high_res_data.extend(settings.format.encode_rgba32f(&levels[mipmap], w, h)?);
+    }
+
+    let header_size = match settings.minor_version {
+        0 | 1 => BASE_HEADER_SIZE + 1,
+        2 => RESOURCES_OFFSET,
+        _ => RESOURCES_OFFSET + (1 + usize::from(low_res_data.is_some())) * RESOURCE_ENTRY_SIZE,
+    };
+
+    let mut bytes = Vec::with_capacity(header_size + high_res_data.len());
+
+    bytes.extend_from_slice(SIGNATURE);
+    bytes.extend_from_slice(&MAJOR_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&settings.minor_version.to_le_bytes());
+    bytes.extend_from_slice(&(header_size as u32).to_le_bytes());
+    bytes.extend_from_slice(&(width as u16).to_le_bytes());
+    bytes.extend_from_slice(&(height as u16).to_le_bytes());
+    bytes.extend_from_slice(&flags.bits().to_le_bytes());
+    bytes.extend_from_slice(&1_u16.to_le_bytes()); // frames
+    bytes.extend_from_slice(&0_u16.to_le_bytes()); // first frame
+    bytes.extend_from_slice(&[0; 4]);
+    for channel in reflectivity {
+        bytes.extend_from_slice(&channel.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&settings.bumpmap_scale.to_le_bytes());
+    bytes.extend_from_slice(&settings.format.id().to_le_bytes());
+    bytes.push(mipmap_count as u8);
+
+    if let Some((w, h)) = low_res_dimensions {
+        bytes.extend_from_slice(&LOW_RES_FORMAT.id().to_le_bytes());
+        bytes.push(w as u8);
+        bytes.push(h as u8);
+    } else {
+        bytes.extend_from_slice(&(-1_i32).to_le_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+    }
+
+    if settings.minor_version >= 2 {
+        bytes.extend_from_slice(&1_u16.to_le_bytes()); // depth
+    }
+
+    if settings.minor_version >= 3 {
+        let low_res_size = low_res_data.as_ref().map(Vec::len);
+        write_image_resources(&mut bytes, header_size, low_res_size);
+    }
+
+    bytes.resize(header_size, 0);
+
+    if let Some(low_res_data) = low_res_data {
+        bytes.extend(low_res_data);
+    }
+    bytes.extend(high_res_data);
+
+    Ok(bytes)
+}
+
+/// Writes the resource entries of the images, which follow the header in order.
+fn write_image_resources(bytes: &mut Vec<u8>, header_size: usize, low_res_size: Option<usize>) {
+    let resource_count = 1 + u32::from(low_res_size.is_some());
+
+    bytes.resize(68, 0);
+    bytes.extend_from_slice(&resource_count.to_le_bytes());
+    bytes.resize(RESOURCES_OFFSET, 0);
+
+    let mut offset = header_size;
+    if let Some(low_res_size) = low_res_size {
+        bytes.extend_from_slice(&Resource::LOW_RES_IMAGE);
+        bytes.push(0);
+        bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += low_res_size;
+    }
+    bytes.extend_from_slice(&Resource::HIGH_RES_IMAGE);
+    bytes.push(0);
+    bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+}
+
+fn dimensions(width: u32, height: u32, mipmap: usize) -> (u32, u32) {
+    ((width >> mipmap).max(1), (height >> mipmap).max(1))
+}
+
+/// The low resolution image is the largest mipmap that fits in 16x16.
+fn low_res_mipmap(width: u32, height: u32) -> usize {
+    let mut mipmap = 0;
+    while width >> mipmap > LOW_RES_MAX_SIZE || height >> mipmap > LOW_RES_MAX_SIZE {
+        mipmap += 1;
+    }
+    mipmap
+}
+
+/// Returns the full size image followed by `level_count - 1` mipmaps.
+fn generate_levels(
+    image: SourceImage,
+    width: u32,
+    height: u32,
+    level_count: usize,
+    linear: bool,
+) -> Vec<Vec<f32>> {
+    let mut levels = vec![image.to_rgba32f()];
+
+    for mipmap in 1..level_count {
+        let (w, h) = dimensions(width, height, mipmap - 1);
+        let next = downsample(&levels[mipmap - 1], w, h, linear);
+        levels.push(next);
+    }
+
+    levels
+}
+
+/// Halves the dimensions of an image with a box filter.
+fn downsample(pixels: &[f32], width: u32, height: u32, linear: bool) -> Vec<f32> {
+    let (width, height) = (width as usize, height as usize);
+    let new_width = (width / 2).max(1);
+    let new_height = (height / 2).max(1);
+
+    let to_filter_space = |c: f32, channel: usize| {
+        if linear && channel < 3 {
+            srgb_to_linear(c)
+        } else {
+            c
+        }
+    };
+
+    let mut output = Vec::with_capacity(new_width * new_height * 4);
+
+    for y in 0..new_height {
+        for x in 0..new_width {
+            let columns = x * 2..(x * 2 + 2).min(width);
+            let rows = y * 2..(y * 2 + 2).min(height);
+            let count = (columns.len() * rows.len()) as f32;
+
+            for channel in 0..4 {
+                let sum: f32 = rows
+                    .clone()
+                    .flat_map(|row| {
+                        columns
+                            .clone()
+                            .map(move |column| (row * width + column) * 4)
+                    })
+                    .map(|offset| to_filter_space(pixels[offset + channel], channel))
+                    .sum();
+                let average = sum / count;
+
+                output.push(if linear && channel < 3 {
+                    linear_to_srgb(average)
+                } else {
+                    average
+                });
+            }
+        }
+    }
+
+    output
+}
+
+fn average_color(pixels: &[f32], to_linear: impl Fn(f32) -> f32) -> [f32; 3] {
+    let mut sum = [0.0_f64; 3];
+    for pixel in pixels.chunks_exact(4) {
+        for (sum, &c) in sum.iter_mut().zip(pixel) {
+            *sum += f64::from(to_linear(c));
+        }
+    }
+
+    let count = (pixels.len() / 4).max(1) as f64;
+    sum.map(|sum| (sum / count) as f32)
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
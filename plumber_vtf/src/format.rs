@@ -0,0 +1,581 @@
+use std::fmt::{self, Display};
+
+use half::f16;
+
+use crate::{dxt, Error, Result};
+
+/// Image data formats used by VTF files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Rgba8888,
+    Abgr8888,
+    Rgb888,
+    Bgr888,
+    Rgb565,
+    I8,
+    Ia88,
+    P8,
+    A8,
+    Rgb888Bluescreen,
+    Bgr888Bluescreen,
+    Argb8888,
+    Bgra8888,
+    Dxt1,
+    Dxt3,
+    Dxt5,
+    Bgrx8888,
+    Bgr565,
+    Bgrx5551,
+    Bgra4444,
+    Dxt1OneBitAlpha,
+    Bgra5551,
+    Uv88,
+    Uvwq8888,
+    Rgba16161616F,
+    Rgba16161616,
+    Uvlx8888,
+    R32F,
+    Rgb323232F,
+    Rgba32323232F,
+    NvDst16,
+    NvDst24,
+    NvIntz,
+    NvRawz,
+    AtiDst16,
+    AtiDst24,
+    NvNull,
+    Ati2N,
+    Ati1N,
+}
+
+impl ImageFormat {
+    const FORMATS: [Self; 39] = [
+        Self::Rgba8888,
+        Self::Abgr8888,
+        Self::Rgb888,
+        Self::Bgr888,
+        Self::Rgb565,
+        Self::I8,
+        Self::Ia88,
+        Self::P8,
+        Self::A8,
+        Self::Rgb888Bluescreen,
+        Self::Bgr888Bluescreen,
+        Self::Argb8888,
+        Self::Bgra8888,
+        Self::Dxt1,
+        Self::Dxt3,
+        Self::Dxt5,
+        Self::Bgrx8888,
+        Self::Bgr565,
+        Self::Bgrx5551,
+        Self::Bgra4444,
+        Self::Dxt1OneBitAlpha,
+        Self::Bgra5551,
+        Self::Uv88,
+        Self::Uvwq8888,
+        Self::Rgba16161616F,
+        Self::Rgba16161616,
+        Self::Uvlx8888,
+        Self::R32F,
+        Self::Rgb323232F,
+        Self::Rgba32323232F,
+        Self::NvDst16,
+        Self::NvDst24,
+        Self::NvIntz,
+        Self::NvRawz,
+        Self::AtiDst16,
+        Self::AtiDst24,
+        Self::NvNull,
+        Self::Ati2N,
+        Self::Ati1N,
+    ];
+
+    /// Returns the format with the given id, as stored in VTF headers.
+    /// Returns `None` for unknown ids and for `-1`, which means no image.
+    #[must_use]
+    pub fn from_id(id: i32) -> Option<Self> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|index| Self::FORMATS.get(index))
+            .copied()
+    }
+
+    /// Returns the id of the format, as stored in VTF headers.
+    #[must_use]
+    pub fn id(self) -> i32 {
+        // the variants are declared in id order
+        self as i32
+    }
+
+    /// Returns `true` if the format is compressed in 4x4 blocks.
+    #[must_use]
+    pub fn is_compressed(self) -> bool {
+        self.block_size().is_some()
+    }
+
+    /// Size in bytes of a 4x4 block for block compressed formats.
+    fn block_size(self) -> Option<usize> {
+        match self {
+            Self::Dxt1 | Self::Dxt1OneBitAlpha | Self::Ati1N => Some(8),
+            Self::Dxt3 | Self::Dxt5 | Self::Ati2N => Some(16),
+            _ => None,
+        }
+    }
+
+    /// Size in bytes of a pixel for uncompressed formats.
+    fn pixel_size(self) -> usize {
+        match self {
+            Self::I8 | Self::P8 | Self::A8 => 1,
+            Self::Rgb565
+            | Self::Ia88
+            | Self::Bgr565
+            | Self::Bgrx5551
+            | Self::Bgra4444
+            | Self::Bgra5551
+            | Self::Uv88
+            | Self::NvDst16
+            | Self::AtiDst16 => 2,
+            Self::Rgb888 | Self::Bgr888 | Self::Rgb888Bluescreen | Self::Bgr888Bluescreen => 3,
+            Self::Rgba8888
+            | Self::Abgr8888
+            | Self::Argb8888
+            | Self::Bgra8888
+            | Self::Bgrx8888
+            | Self::Uvwq8888
+            | Self::Uvlx8888
+            | Self::R32F
+            | Self::NvDst24
+            | Self::NvIntz
+            | Self::NvRawz
+            | Self::AtiDst24
+            | Self::NvNull => 4,
+            Self::Rgba16161616F | Self::Rgba16161616 => 8,
+            Self::Rgb323232F => 12,
+            Self::Rgba32323232F => 16,
+            Self::Dxt1
+            | Self::Dxt3
+            | Self::Dxt5
+            | Self::Dxt1OneBitAlpha
+            | Self::Ati2N
+            | Self::Ati1N => 0,
+        }
+    }
+
+    /// Returns the size in bytes of an image with the given dimensions.
+    #[must_use]
+    pub fn image_size(self, width: u32, height: u32) -> usize {
+        let width = width.max(1) as usize;
+        let height = height.max(1) as usize;
+
+        match self.block_size() {
+            Some(block_size) => width.div_ceil(4) * height.div_ceil(4) * block_size,
+            None => width * height * self.pixel_size(),
+        }
+    }
+
+    /// Decodes image data in this format into 8-bit RGBA.
+    /// Float formats are clamped to the `0..=1` range.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the data is too short for the dimensions,
+    /// or if the format can't be decoded (`P8` and the depth formats).
+    pub fn decode_rgba8(self, data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+        let size = self.image_size(width, height);
+        let data = data
+            .get(..size)
+            .ok_or(Error::Corrupted("image data truncated"))?;
+
+        if self.is_compressed() {
+            return Ok(dxt::decode(self, data, width as usize, height as usize));
+        }
+
+        let mut output = Vec::with_capacity(width as usize * height as usize * 4);
+        let pixels = data.chunks_exact(self.pixel_size());
+
+        match self {
+            Self::Rgba8888 | Self::Uvwq8888 | Self::Uvlx8888 => output.extend_from_slice(data),
+            Self::Abgr8888 => extend(&mut output, pixels, |p| [p[3], p[2], p[1], p[0]]),
+            Self::Argb8888 => extend(&mut output, pixels, |p| [p[1], p[2], p[3], p[0]]),
+            Self::Bgra8888 => extend(&mut output, pixels, |p| [p[2], p[1], p[0], p[3]]),
+            Self::Bgrx8888 => extend(&mut output, pixels, |p| [p[2], p[1], p[0], 255]),
+            Self::Rgb888 => extend(&mut output, pixels, |p| [p[0], p[1], p[2], 255]),
+            Self::Bgr888 => extend(&mut output, pixels, |p| [p[2], p[1], p[0], 255]),
+            Self::Rgb888Bluescreen => extend(&mut output, pixels, |p| bluescreen(p[0], p[1], p[2])),
+            Self::Bgr888Bluescreen => extend(&mut output, pixels, |p| bluescreen(p[2], p[1], p[0])),
+            Self::I8 => extend(&mut output, pixels, |p| [p[0], p[0], p[0], 255]),
+            Self::Ia88 => extend(&mut output, pixels, |p| [p[0], p[0], p[0], p[1]]),
+            Self::A8 => extend(&mut output, pixels, |p| [0, 0, 0, p[0]]),
+            Self::Uv88 => extend(&mut output, pixels, |p| [p[0], p[1], 0, 255]),
+            Self::Rgb565 => extend(&mut output, pixels, |p| {
+                let v = u16::from_le_bytes([p[0], p[1]]);
+                [expand(v, 0, 5), expand(v, 5, 6), expand(v, 11, 5), 255]
+            }),
+            Self::Bgr565 => extend(&mut output, pixels, |p| {
+                let v = u16::from_le_bytes([p[0], p[1]]);
+                [expand(v, 11, 5), expand(v, 5, 6), expand(v, 0, 5), 255]
+            }),
+            Self::Bgrx5551 => extend(&mut output, pixels, |p| {
+                let v = u16::from_le_bytes([p[0], p[1]]);
+                [expand(v, 10, 5), expand(v, 5, 5), expand(v, 0, 5), 255]
+            }),
+            Self::Bgra5551 => extend(&mut output, pixels, |p| {
+                let v = u16::from_le_bytes([p[0], p[1]]);
+                [
+                    expand(v, 10, 5),
+                    expand(v, 5, 5),
+                    expand(v, 0, 5),
+                    expand(v, 15, 1),
+                ]
+            }),
+            Self::Bgra4444 => extend(&mut output, pixels, |p| {
+                let v = u16::from_le_bytes([p[0], p[1]]);
+                [
+                    expand(v, 8, 4),
+                    expand(v, 4, 4),
+                    expand(v, 0, 4),
+                    expand(v, 12, 4),
+                ]
+            }),
+            Self::Rgba16161616 => extend(&mut output, pixels, |p| [p[1], p[3], p[5], p[7]]),
+            Self::Rgba16161616F => extend(&mut output, pixels, |p| {
+                let channel = |i: usize| f16::from_le_bytes([p[i], p[i + 1]]).to_f32();
+                [
+                    unorm(channel(0)),
+                    unorm(channel(2)),
+                    unorm(channel(4)),
+                    unorm(channel(6)),
+                ]
+            }),
+            Self::R32F => extend(&mut output, pixels, |p| {
+                let r = unorm(read_f32(p, 0));
+                [r, r, r, 255]
+            }),
+            Self::Rgb323232F => extend(&mut output, pixels, |p| {
+                [
+                    unorm(read_f32(p, 0)),
+                    unorm(read_f32(p, 4)),
+                    unorm(read_f32(p, 8)),
+                    255,
+                ]
+            }),
+            Self::Rgba32323232F => extend(&mut output, pixels, |p| {
+                [
+                    unorm(read_f32(p, 0)),
+                    unorm(read_f32(p, 4)),
+                    unorm(read_f32(p, 8)),
+                    unorm(read_f32(p, 12)),
+                ]
+            }),
+            Self::P8
+            | Self::NvDst16
+            | Self::NvDst24
+            | Self::NvIntz
+            | Self::NvRawz
+            | Self::AtiDst16
+            | Self::AtiDst24
+            | Self::NvNull => return Err(Error::UnsupportedFormat(self)),
+            Self::Dxt1
+            | Self::Dxt3
+            | Self::Dxt5
+            | Self::Dxt1OneBitAlpha
+            | Self::Ati2N
+            | Self::Ati1N => unreachable!("compressed formats are handled above"),
+        }
+
+        Ok(output)
+    }
+
+    /// Returns `true` if the format has more than 8 bits of precision per channel.
+    #[must_use]
+    pub fn is_high_precision(self) -> bool {
+        matches!(
+            self,
+            Self::Rgba16161616F
+                | Self::Rgba16161616
+                | Self::R32F
+                | Self::Rgb323232F
+                | Self::Rgba32323232F
+        )
+    }
+
+    /// Decodes image data in this format into 32-bit float RGBA.
+    /// High precision formats keep their precision and range,
+    /// other formats are decoded to the `0..=1` range.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the data is too short for the dimensions,
+    /// or if the format can't be decoded (`P8` and the depth formats).
+    pub fn decode_rgba32f(self, data: &[u8], width: u32, height: u32) -> Result<Vec<f32>> {
+        let size = self.image_size(width, height);
+        let data = data
+            .get(..size)
+            .ok_or(Error::Corrupted("image data truncated"))?;
+
+        let mut output = Vec::with_capacity(width as usize * height as usize * 4);
+        let pixels = data.chunks_exact(self.pixel_size().max(1));
+
+        match self {
+            Self::Rgba16161616F => extend_f32(&mut output, pixels, |p| {
+                let channel = |i: usize| f16::from_le_bytes([p[i], p[i + 1]]).to_f32();
+                [channel(0), channel(2), channel(4), channel(6)]
+            }),
+            Self::Rgba16161616 => extend_f32(&mut output, pixels, |p| {
+                let channel = |i: usize| f32::from(u16::from_le_bytes([p[i], p[i + 1]])) / 65535.0;
+                [channel(0), channel(2), channel(4), channel(6)]
+            }),
+            Self::R32F => extend_f32(&mut output, pixels, |p| {
+                let r = read_f32(p, 0);
+                [r, r, r, 1.0]
+            }),
+            Self::Rgb323232F => extend_f32(&mut output, pixels, |p| {
+                [read_f32(p, 0), read_f32(p, 4), read_f32(p, 8), 1.0]
+            }),
+            Self::Rgba32323232F => extend_f32(&mut output, pixels, |p| {
+                [
+                    read_f32(p, 0),
+                    read_f32(p, 4),
+                    read_f32(p, 8),
+                    read_f32(p, 12),
+                ]
+            }),
+            _ => {
+                let data = self.decode_rgba8(data, width, height)?;
+                output.extend(data.into_iter().map(|c| f32::from(c) / 255.0));
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Decodes compressed HDR image data into 32-bit float RGBA.
+    ///
+    /// Compressed HDR textures (`$hdrcompressedtexture`) are stored in an 8-bit format,
+    /// with the alpha channel scaling the color channels.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the data is too short for the dimensions,
+    /// or if the format can't be decoded (`P8` and the depth formats).
+    pub fn decode_compressed_hdr(self, data: &[u8], width: u32, height: u32) -> Result<Vec<f32>> {
+        let data = self.decode_rgba8(data, width, height)?;
+
+        Ok(data
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let scale = f32::from(p[3]) * 16.0 / 262_144.0;
+                [
+                    f32::from(p[0]) * scale,
+                    f32::from(p[1]) * scale,
+                    f32::from(p[2]) * scale,
+                    1.0,
+                ]
+            })
+            .collect())
+    }
+    /// Encodes 32-bit float RGBA pixels into image data in this format.
+    /// Channels of formats with a fixed range are clamped to the `0..=1` range.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if `data` doesn't contain `width * height` pixels,
+    /// or if the format can't be encoded (`P8` and the depth formats).
+    pub fn encode_rgba32f(self, data: &[f32], width: u32, height: u32) -> Result<Vec<u8>> {
+        let width = width.max(1);
+        let height = height.max(1);
+        if data.len() != width as usize * height as usize * 4 {
+            return Err(Error::InvalidImage(
+                "pixel data doesn't match the dimensions",
+            ));
+        }
+
+        let pixels = data.chunks_exact(4);
+
+        if self.is_compressed() {
+            let data: Vec<_> = data.iter().map(|&c| unorm(c)).collect();
+            return Ok(dxt::encode(self, &data, width as usize, height as usize));
+        }
+
+        let mut output = Vec::with_capacity(self.image_size(width, height));
+
+        match self {
+            Self::Rgba8888 | Self::Uvwq8888 | Self::Uvlx8888 => {
+                output.extend(data.iter().map(|&c| unorm(c)));
+            }
+            Self::Abgr8888 => extend_encoded(&mut output, pixels, |p| [p[3], p[2], p[1], p[0]]),
+            Self::Argb8888 => extend_encoded(&mut output, pixels, |p| [p[3], p[0], p[1], p[2]]),
+            Self::Bgra8888 => extend_encoded(&mut output, pixels, |p| [p[2], p[1], p[0], p[3]]),
+            Self::Bgrx8888 => extend_encoded(&mut output, pixels, |p| [p[2], p[1], p[0], 1.0]),
+            Self::Rgb888 => extend_encoded(&mut output, pixels, |p| [p[0], p[1], p[2]]),
+            Self::Bgr888 => extend_encoded(&mut output, pixels, |p| [p[2], p[1], p[0]]),
+            Self::Rgb888Bluescreen => extend_encoded(&mut output, pixels, |p| {
+                let [r, g, b] = to_bluescreen(p);
+                [r, g, b]
+            }),
+            Self::Bgr888Bluescreen => extend_encoded(&mut output, pixels, |p| {
+                let [r, g, b] = to_bluescreen(p);
+                [b, g, r]
+            }),
+            Self::I8 => extend_encoded(&mut output, pixels, |p| [luminance(p)]),
+            Self::Ia88 => extend_encoded(&mut output, pixels, |p| [luminance(p), p[3]]),
+            Self::A8 => extend_encoded(&mut output, pixels, |p| [p[3]]),
+            Self::Uv88 => extend_encoded(&mut output, pixels, |p| [p[0], p[1]]),
+            Self::Rgb565 => extend_packed(&mut output, pixels, &[(0, 0, 5), (1, 5, 6), (2, 11, 5)]),
+            Self::Bgr565 => extend_packed(&mut output, pixels, &[(2, 0, 5), (1, 5, 6), (0, 11, 5)]),
+            Self::Bgrx5551 => {
+                extend_packed(&mut output, pixels, &[(2, 0, 5), (1, 5, 5), (0, 10, 5)]);
+            }
+            Self::Bgra5551 => extend_packed(
+                &mut output,
+                pixels,
+                &[(2, 0, 5), (1, 5, 5), (0, 10, 5), (3, 15, 1)],
+            ),
+            Self::Bgra4444 => extend_packed(
+                &mut output,
+                pixels,
+                &[(2, 0, 4), (1, 4, 4), (0, 8, 4), (3, 12, 4)],
+            ),
+            Self::Rgba16161616 => {
+                for &c in data {
+                    let value = (c.clamp(0.0, 1.0) * 65535.0).round() as u16;
+                    output.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            Self::Rgba16161616F => {
+                for &c in data {
+                    output.extend_from_slice(&f16::from_f32(c).to_le_bytes());
+                }
+            }
+            Self::R32F => {
+                for pixel in pixels {
+                    output.extend_from_slice(&pixel[0].to_le_bytes());
+                }
+            }
+            Self::Rgb323232F => {
+                for pixel in pixels {
+                    for c in &pixel[..3] {
+                        output.extend_from_slice(&c.to_le_bytes());
+                    }
+                }
+            }
+            Self::Rgba32323232F => {
+                for c in data {
+                    output.extend_from_slice(&c.to_le_bytes());
+                }
+            }
+            Self::P8
+            | Self::NvDst16
+            | Self::NvDst24
+            | Self::NvIntz
+            | Self::NvRawz
+            | Self::AtiDst16
+            | Self::AtiDst24
+            | Self::NvNull => return Err(Error::UnsupportedFormat(self)),
+            Self::Dxt1
+            | Self::Dxt3
+            | Self::Dxt5
+            | Self::Dxt1OneBitAlpha
+            | Self::Ati2N
+            | Self::Ati1N => unreachable!("compressed formats are handled above"),
+        }
+
+        Ok(output)
+    }
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+fn extend<'a>(
+    output: &mut Vec<u8>,
+    pixels: impl Iterator<Item = &'a [u8]>,
+    f: impl Fn(&[u8]) -> [u8; 4],
+) {
+    for pixel in pixels {
+        output.extend_from_slice(&f(pixel));
+    }
+}
+
+fn extend_f32<'a>(
+    output: &mut Vec<f32>,
+    pixels: impl Iterator<Item = &'a [u8]>,
+    f: impl Fn(&[u8]) -> [f32; 4],
+) {
+    for pixel in pixels {
+        output.extend_from_slice(&f(pixel));
+    }
+}
+
+/// Pure blue is transparent in the bluescreen formats.
+fn bluescreen(r: u8, g: u8, b: u8) -> [u8; 4] {
+    if r == 0 && g == 0 && b == 255 {
+        [0, 0, 0, 0]
+    } else {
+        [r, g, b, 255]
+    }
+}
+
+/// Expands a `bits` wide channel starting at `shift` to 8 bits.
+fn expand(value: u16, shift: u32, bits: u32) -> u8 {
+    let max = (1 << bits) - 1;
+    let channel = u32::from(value >> shift) & max;
+    ((channel * 255 + max / 2) / max) as u8
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes(
+        bytes[offset..offset + 4]
+            .try_into()
+            .expect("slice has correct length"),
+    )
+}
+
+/// Converts a float in the `0..=1` range to a byte.
+fn unorm(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn extend_encoded<'a, const N: usize>(
+    output: &mut Vec<u8>,
+    pixels: impl Iterator<Item = &'a [f32]>,
+    f: impl Fn(&[f32]) -> [f32; N],
+) {
+    for pixel in pixels {
+        output.extend(f(pixel).map(unorm));
+    }
+}
+
+/// Packs channels into 16-bit values, with each channel given as `(channel, shift, bits)`.
+fn extend_packed<'a>(
+    output: &mut Vec<u8>,
+    pixels: impl Iterator<Item = &'a [f32]>,
+    channels: &[(usize, u32, u32)],
+) {
+    for pixel in pixels {
+        let value = channels
+            .iter()
+            .fold(0_u16, |value, &(channel, shift, bits)| {
+                let max = f32::from((1_u16 << bits) - 1);
+                value | (((pixel[channel].clamp(0.0, 1.0) * max).round() as u16) << shift)
+            });
+        output.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Transparent pixels are stored as pure blue in the bluescreen formats.
+fn to_bluescreen(pixel: &[f32]) -> [f32; 3] {
+    if pixel[3] < 0.5 {
+        [0.0, 0.0, 1.0]
+    } else {
+        [pixel[0], pixel[1], pixel[2]]
+    }
+}
+
+fn luminance(pixel: &[f32]) -> f32 {
+    pixel[0] * 0.299 + pixel[1] * 0.587 + pixel[2] * 0.114
+}
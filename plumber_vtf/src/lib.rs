@@ -0,0 +1,560 @@
+#![warn(clippy::all, clippy::pedantic, clippy::multiple_crate_versions)]
+// These are intentional
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+
+//! VTF (Valve Texture Format) reading and writing.
+//!
+//! Supports VTF versions 7.0 to 7.5.
+
+mod dxt;
+mod encode;
+mod format;
+mod resources;
+
+use std::result;
+
+use bitflags::bitflags;
+use thiserror::Error;
+
+pub use encode::{encode, EncodeSettings, SourceImage};
+pub use format::ImageFormat;
+pub use resources::{LodControl, Sheet, SheetFrame, SheetSequence, UvRect};
+
+const SIGNATURE: &[u8; 4] = b"VTF\0";
+const MAJOR_VERSION: u32 = 7;
+const MAX_MINOR_VERSION: u32 = 5;
+
+/// Size of the header fields common to all versions.
+const BASE_HEADER_SIZE: usize = 63;
+/// Offset of the resource entries in 7.3+ headers.
+const RESOURCES_OFFSET: usize = 80;
+const RESOURCE_ENTRY_SIZE: usize = 8;
+/// Resource flag that marks resources that store a value instead of an offset.
+const RESOURCE_NO_DATA_CHUNK: u8 = 0x02;
+
+#[derive(Debug, Clone, Error, Hash, PartialEq, Eq)]
+pub enum Error {
+    #[error("not a vtf file: invalid signature `{0}`")]
+    InvalidSignature(String),
+    #[error("unsupported vtf version {major}.{minor}")]
+    UnsupportedVersion { major: u32, minor: u32 },
+    #[error("invalid image format {0}")]
+    InvalidFormat(i32),
+    #[error("unsupported image format {0}")]
+    UnsupportedFormat(ImageFormat),
+    #[error("vtf corrupted: {0}")]
+    Corrupted(&'static str),
+    #[error("invalid image: {0}")]
+    InvalidImage(&'static str),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct TextureFlags: u32 {
+        const POINT_SAMPLE = 0x0000_0001;
+        const TRILINEAR = 0x0000_0002;
+        const CLAMP_S = 0x0000_0004;
+        const CLAMP_T = 0x0000_0008;
+        const ANISOTROPIC = 0x0000_0010;
+        const HINT_DXT5 = 0x0000_0020;
+        const SRGB = 0x0000_0040;
+        const NORMAL = 0x0000_0080;
+        const NO_MIP = 0x0000_0100;
+        const NO_LOD = 0x0000_0200;
+        const ALL_MIPS = 0x0000_0400;
+        const PROCEDURAL = 0x0000_0800;
+        const ONE_BIT_ALPHA = 0x0000_1000;
+        const EIGHT_BIT_ALPHA = 0x0000_2000;
+        const ENVMAP = 0x0000_4000;
+        const RENDER_TARGET = 0x0000_8000;
+        const DEPTH_RENDER_TARGET = 0x0001_0000;
+        const NO_DEBUG_OVERRIDE = 0x0002_0000;
+        const SINGLE_COPY = 0x0004_0000;
+        const PRE_SRGB = 0x0008_0000;
+        const NO_DEPTH_BUFFER = 0x0080_0000;
+        const CLAMP_U = 0x0200_0000;
+        const VERTEX_TEXTURE = 0x0400_0000;
+        const SSBUMP = 0x0800_0000;
+        const BORDER = 0x2000_0000;
+    }
+}
+
+/// The fixed-size part of a VTF file header.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Header {
+    pub major_version: u32,
+    pub minor_version: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Depth of volume textures, 1 for other textures and for versions before 7.2.
+    pub depth: u32,
+    pub flags: TextureFlags,
+    pub frames: u32,
+    pub first_frame: u32,
+    pub reflectivity: [f32; 3],
+    pub bumpmap_scale: f32,
+    pub format: ImageFormat,
+    pub mipmap_count: u32,
+    pub low_res_format: Option<ImageFormat>,
+    pub low_res_width: u32,
+    pub low_res_height: u32,
+    /// Offset of the image data in versions before 7.3.
+    data_offset: usize,
+    resource_count: usize,
+}
+
+impl Header {
+    /// Parses only the header, without validating the image data.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the header is invalid or the version is not supported.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let signature = bytes.get(..4).ok_or(Error::Corrupted("header truncated"))?;
+        if signature != SIGNATURE {
+            return Err(Error::InvalidSignature(
+                String::from_utf8_lossy(signature).into_owned(),
+            ));
+        }
+
+        if bytes.len() < BASE_HEADER_SIZE {
+            return Err(Error::Corrupted("header truncated"));
+        }
+
+        let major_version = read_u32(bytes, 4);
+        let minor_version = read_u32(bytes, 8);
+        if major_version != MAJOR_VERSION || minor_version > MAX_MINOR_VERSION {
+            return Err(Error::UnsupportedVersion {
+                major: major_version,
+                minor: minor_version,
+            });
+        }
+
+        let format_id = read_u32(bytes, 52) as i32;
+        let format = ImageFormat::from_id(format_id).ok_or(Error::InvalidFormat(format_id))?;
+
+        let low_res_format_id = read_u32(bytes, 57) as i32;
+        let low_res_format = if low_res_format_id == -1 {
+            None
+        } else {
+            Some(
+                ImageFormat::from_id(low_res_format_id)
+                    .ok_or(Error::InvalidFormat(low_res_format_id))?,
+            )
+        };
+
+        let depth = if minor_version >= 2 {
+            u32::from(read_u16_checked(bytes, 63).ok_or(Error::Corrupted("header truncated"))?)
+        } else {
+            1
+        };
+
+        let resource_count = if minor_version >= 3 {
+            read_u32_checked(bytes, 68).ok_or(Error::Corrupted("header truncated"))? as usize
+        } else {
+            0
+        };
+
+        Ok(Self {
+            major_version,
+            minor_version,
+            width: u32::from(read_u16(bytes, 16)),
+            height: u32::from(read_u16(bytes, 18)),
+            depth: depth.max(1),
+            flags: TextureFlags::from_bits_retain(read_u32(bytes, 20)),
+            frames: u32::from(read_u16(bytes, 24)).max(1),
+            first_frame: u32::from(read_u16(bytes, 26)),
+            reflectivity: [
+                read_f32(bytes, 32),
+                read_f32(bytes, 36),
+                read_f32(bytes, 40),
+            ],
+            bumpmap_scale: read_f32(bytes, 48),
+            format,
+            mipmap_count: u32::from(bytes[56]).max(1),
+            low_res_format,
+            low_res_width: u32::from(bytes[61]),
+            low_res_height: u32::from(bytes[62]),
+            data_offset: read_u32(bytes, 12) as usize,
+            resource_count,
+        })
+    }
+
+    /// Returns the number of faces, 6 or 7 for cubemaps and 1 for other textures.
+    /// Cubemaps before version 7.5 may have a seventh face containing a spheremap.
+    #[must_use]
+    pub fn face_count(&self) -> u32 {
+        if !self.flags.contains(TextureFlags::ENVMAP) {
+            1
+        } else if self.minor_version < 5 && self.first_frame != 0xffff {
+            7
+        } else {
+            6
+        }
+    }
+
+    /// Returns the width, height and depth of a mipmap level, where 0 is the largest.
+    #[must_use]
+    pub fn mipmap_dimensions(&self, mipmap: u32) -> (u32, u32, u32) {
+        let scale = |size: u32| size.checked_shr(mipmap).unwrap_or(0).max(1);
+        (scale(self.width), scale(self.height), scale(self.depth))
+    }
+
+    /// Size in bytes of all frames, faces and slices of a mipmap level.
+    fn mipmap_size(&self, mipmap: u32) -> usize {
+        let (width, height, depth) = self.mipmap_dimensions(mipmap);
+        self.format.image_size(width, height)
+            * depth as usize
+            * self.frames as usize
+            * self.face_count() as usize
+    }
+
+    fn high_res_size(&self) -> usize {
+        (0..self.mipmap_count)
+            .map(|mipmap| self.mipmap_size(mipmap))
+            .sum()
+    }
+
+    fn low_res_size(&self) -> usize {
+        match self.low_res_format {
+            Some(format) if self.low_res_width > 0 && self.low_res_height > 0 => {
+                format.image_size(self.low_res_width, self.low_res_height)
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceData<'a> {
+    /// A value stored directly in the resource entry.
+    Value(u32),
+    /// Offset of image data in the file.
+    Offset(u32),
+    /// A data chunk, without the length prefix.
+    Chunk(&'a [u8]),
+}
+
+/// A resource entry of a VTF 7.3+ file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Resource<'a> {
+    pub tag: [u8; 3],
+    pub flags: u8,
+    pub data: ResourceData<'a>,
+}
+
+impl Resource<'_> {
+    pub const LOW_RES_IMAGE: [u8; 3] = [0x01, 0, 0];
+    pub const HIGH_RES_IMAGE: [u8; 3] = [0x30, 0, 0];
+    pub const ANIMATED_PARTICLE_SHEET: [u8; 3] = [0x10, 0, 0];
+    pub const CRC: [u8; 3] = *b"CRC";
+    pub const LOD_CONTROL: [u8; 3] = *b"LOD";
+    pub const EXTENDED_FLAGS: [u8; 3] = *b"TSO";
+    pub const KEY_VALUES: [u8; 3] = *b"KVD";
+}
+
+/// A parsed VTF file, borrowing the file contents.
+#[derive(Debug, Clone)]
+pub struct Vtf<'a> {
+    header: Header,
+    resources: Vec<Resource<'a>>,
+    low_res_data: Option<&'a [u8]>,
+    high_res_data: &'a [u8],
+}
+
+impl<'a> Vtf<'a> {
+    /// # Errors
+    ///
+    /// Returns `Err` if the file is invalid, truncated or the version is not supported.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        let header = Header::from_bytes(bytes)?;
+        let resources = parse_resources(&header, bytes)?;
+
+        let (low_res_offset, high_res_offset) = if header.minor_version >= 3 {
+            let image_offset = |tag| {
+                resources
+                    .iter()
+                    .find_map(|resource: &Resource| match resource.data {
+                        ResourceData::Offset(offset) if resource.tag == tag => {
+                            Some(offset as usize)
+                        }
+                        _ => None,
+                    })
+            };
+
+            (
+                image_offset(Resource::LOW_RES_IMAGE),
+                image_offset(Resource::HIGH_RES_IMAGE)
+                    .ok_or(Error::Corrupted("high resolution image resource missing"))?,
+            )
+        } else {
+            (
+                Some(header.data_offset),
+                header.data_offset + header.low_res_size(),
+            )
+        };
+
+        let low_res_data = match low_res_offset {
+            Some(offset) if header.low_res_size() > 0 => Some(
+                bytes
+                    .get(offset..offset + header.low_res_size())
+                    .ok_or(Error::Corrupted("low resolution image truncated"))?,
+            ),
+            _ => None,
+        };
+
+        let high_res_data = bytes
+            .get(high_res_offset..high_res_offset + header.high_res_size())
+            .ok_or(Error::Corrupted("image data truncated"))?;
+
+        Ok(Self {
+            header,
+            resources,
+            low_res_data,
+            high_res_data,
+        })
+    }
+
+    #[must_use]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.header.width
+    }
+
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.header.height
+    }
+
+    #[must_use]
+    pub fn format(&self) -> ImageFormat {
+        self.header.format
+    }
+
+    /// Returns the resource entries. Empty for versions before 7.3.
+    #[must_use]
+    pub fn resources(&self) -> &[Resource<'a>] {
+        &self.resources
+    }
+
+    /// Returns the first resource entry with the given tag.
+    #[must_use]
+    pub fn resource(&self, tag: [u8; 3]) -> Option<&Resource<'a>> {
+        self.resources.iter().find(|resource| resource.tag == tag)
+    }
+
+    fn resource_value(&self, tag: [u8; 3]) -> Option<u32> {
+        match self.resource(tag)?.data {
+            ResourceData::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn resource_chunk(&self, tag: [u8; 3]) -> Option<&'a [u8]> {
+        match self.resource(tag)?.data {
+            ResourceData::Chunk(chunk) => Some(chunk),
+            _ => None,
+        }
+    }
+
+    /// Returns the sprite sheet of an animated particle texture, if the file has one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the sheet is corrupted.
+    pub fn sheet(&self) -> Result<Option<Sheet>> {
+        self.resource_chunk(Resource::ANIMATED_PARTICLE_SHEET)
+            .map(Sheet::from_bytes)
+            .transpose()
+    }
+
+    #[must_use]
+    pub fn lod_control(&self) -> Option<LodControl> {
+        self.resource_value(Resource::LOD_CONTROL)
+            .map(LodControl::from_value)
+    }
+
+    /// Returns the CRC of the source image, if the file has one.
+    #[must_use]
+    pub fn crc(&self) -> Option<u32> {
+        self.resource_value(Resource::CRC)
+    }
+
+    /// Returns the extended texture settings flags, if the file has them.
+    #[must_use]
+    pub fn extended_flags(&self) -> Option<u32> {
+        self.resource_value(Resource::EXTENDED_FLAGS)
+    }
+
+    /// Returns the embedded key values text, if the file has one.
+    #[must_use]
+    pub fn key_values(&self) -> Option<&'a [u8]> {
+        self.resource_chunk(Resource::KEY_VALUES)
+            .map(|chunk| chunk.split(|&b| b == 0).next().unwrap_or(chunk))
+    }
+
+    /// Returns the raw data of the low resolution thumbnail, if the file has one.
+    #[must_use]
+    pub fn low_res_data(&self) -> Option<&'a [u8]> {
+        self.low_res_data
+    }
+
+    /// Returns the raw image data of a mipmap level, frame, face and depth slice,
+    /// or `None` if any of them is out of range.
+    /// Mipmap level 0 is the largest.
+    #[must_use]
+    pub fn data(&self, mipmap: u32, frame: u32, face: u32, slice: u32) -> Option<&'a [u8]> {
+        let header = &self.header;
+        let (width, height, depth) = header.mipmap_dimensions(mipmap);
+
+        if mipmap >= header.mipmap_count
+            || frame >= header.frames
+            || face >= header.face_count()
+            || slice >= depth
+        {
+            return None;
+        }
+
+        // mipmaps are stored from the smallest to the largest
+        let mipmap_offset: usize = (mipmap + 1..header.mipmap_count)
+            .map(|smaller| header.mipmap_size(smaller))
+            .sum();
+
+        let image_size = header.format.image_size(width, height);
+        let image_index = ((frame * header.face_count() + face) * depth + slice) as usize;
+        let offset = mipmap_offset + image_index * image_size;
+
+        self.high_res_data.get(offset..offset + image_size)
+    }
+
+    /// Decodes a mipmap level, frame, face and depth slice into 8-bit RGBA.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if any of the indices is out of range or the format can't be decoded.
+    pub fn decode_rgba8(&self, mipmap: u32, frame: u32, face: u32, slice: u32) -> Result<Vec<u8>> {
+        let data = self
+            .data(mipmap, frame, face, slice)
+            .ok_or(Error::Corrupted("image index out of range"))?;
+        let (width, height, _) = self.header.mipmap_dimensions(mipmap);
+
+        self.header.format.decode_rgba8(data, width, height)
+    }
+
+    /// Decodes a mipmap level, frame, face and depth slice into 32-bit float RGBA.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if any of the indices is out of range or the format can't be decoded.
+    pub fn decode_rgba32f(
+        &self,
+        mipmap: u32,
+        frame: u32,
+        face: u32,
+        slice: u32,
+    ) -> Result<Vec<f32>> {
+        let data = self
+            .data(mipmap, frame, face, slice)
+            .ok_or(Error::Corrupted("image index out of range"))?;
+        let (width, height, _) = self.header.mipmap_dimensions(mipmap);
+
+        self.header.format.decode_rgba32f(data, width, height)
+    }
+
+    /// Decodes a mipmap level, frame, face and depth slice of a compressed HDR texture
+    /// into 32-bit float RGBA.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if any of the indices is out of range or the format can't be decoded.
+    pub fn decode_compressed_hdr(
+        &self,
+        mipmap: u32,
+        frame: u32,
+        face: u32,
+        slice: u32,
+    ) -> Result<Vec<f32>> {
+        let data = self
+            .data(mipmap, frame, face, slice)
+            .ok_or(Error::Corrupted("image index out of range"))?;
+        let (width, height, _) = self.header.mipmap_dimensions(mipmap);
+
+        self.header
+            .format
+            .decode_compressed_hdr(data, width, height)
+    }
+}
+
+fn parse_resources<'a>(header: &Header, bytes: &'a [u8]) -> Result<Vec<Resource<'a>>> {
+    (0..header.resource_count)
+        .map(|index| {
+            let offset = RESOURCES_OFFSET + index * RESOURCE_ENTRY_SIZE;
+            let entry = bytes
+                .get(offset..offset + RESOURCE_ENTRY_SIZE)
+                .ok_or(Error::Corrupted("resource entry truncated"))?;
+
+            let tag = [entry[0], entry[1], entry[2]];
+            let flags = entry[3];
+            let value = read_u32(entry, 4);
+
+            let data = if flags & RESOURCE_NO_DATA_CHUNK != 0 {
+                ResourceData::Value(value)
+            } else if tag == Resource::LOW_RES_IMAGE || tag == Resource::HIGH_RES_IMAGE {
+                ResourceData::Offset(value)
+            } else {
+                let start = value as usize;
+                let length = read_u32_checked(bytes, start)
+                    .ok_or(Error::Corrupted("resource data truncated"))?
+                    as usize;
+                ResourceData::Chunk(
+                    bytes
+                        .get(start + 4..start + 4 + length)
+                        .ok_or(Error::Corrupted("resource data truncated"))?,
+                )
+            };
+
+            Ok(Resource { tag, flags, data })
+        })
+        .collect()
+}
+
+fn read_u16_checked(bytes: &[u8], offset: usize) -> Option<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+/// Reads a u16 from an offset that is known to be in bounds.
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    read_u16_checked(bytes, offset).expect("offset should be in bounds")
+}
+
+fn read_u32_checked(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Reads a u32 from an offset that is known to be in bounds.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    read_u32_checked(bytes, offset).expect("offset should be in bounds")
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_bits(read_u32(bytes, offset))
+}
+
+#[cfg(test)]
+mod tests;
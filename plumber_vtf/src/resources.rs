@@ -0,0 +1,187 @@
+//! Parsing of the data stored in VTF 7.3+ resources.
+
+use crate::{read_u32_checked, Error, Result};
+
+/// Number of images per frame in version 1 sheets.
+const SHEET_IMAGES_PER_FRAME: usize = 4;
+
+/// A texture coordinate rectangle, with `v` increasing downwards from the top of the texture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl UvRect {
+    /// Returns the rectangle with `v` increasing upwards from the bottom of the texture instead.
+    #[must_use]
+    pub fn flip_v(self) -> Self {
+        Self {
+            min: [self.min[0], 1.0 - self.max[1]],
+            max: [self.max[0], 1.0 - self.min[1]],
+        }
+    }
+}
+
+/// A frame of a sprite sheet sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheetFrame {
+    /// Display time of the frame in seconds.
+    pub duration: f32,
+    /// Rectangle of each image of the frame.
+    /// Version 0 sheets have one image per frame, version 1 sheets have four.
+    pub images: Vec<UvRect>,
+}
+
+impl SheetFrame {
+    /// Returns the rectangle of the first image of the frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the frame has no images.
+    #[must_use]
+    pub fn rect(&self) -> UvRect {
+        self.images[0]
+    }
+}
+
+/// An animation sequence of a sprite sheet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheetSequence {
+    /// The sequence number, referenced by particle systems.
+    pub number: u32,
+    /// If `true`, the sequence stops at the last frame instead of looping.
+    pub clamp: bool,
+    /// Total display time of the sequence in seconds.
+    pub total_time: f32,
+    pub frames: Vec<SheetFrame>,
+}
+
+impl SheetSequence {
+    /// Returns the frame displayed at the given time since the start of the sequence.
+    /// Returns `None` if the sequence has no frames.
+    #[must_use]
+    pub fn frame_at(&self, time: f32) -> Option<&SheetFrame> {
+        let last = self.frames.last()?;
+
+        let time = if self.clamp || self.total_time <= 0.0 {
+            time
+        } else {
+            time.rem_euclid(self.total_time)
+        };
+
+        let mut end = 0.0;
+        for frame in &self.frames {
+            end += frame.duration;
+            if time < end {
+                return Some(frame);
+            }
+        }
+
+        Some(last)
+    }
+}
+
+/// Sprite sheet of an animated particle texture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sheet {
+    pub version: u32,
+    pub sequences: Vec<SheetSequence>,
+}
+
+impl Sheet {
+    /// # Errors
+    ///
+    /// Returns `Err` if the sheet data is truncated or the version is unknown.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, offset: 0 };
+
+        let version = reader.read_u32()?;
+        let images_per_frame = match version {
+            0 => 1,
+            1 => SHEET_IMAGES_PER_FRAME,
+            _ => return Err(Error::Corrupted("unknown sheet version")),
+        };
+
+        let sequence_count = reader.read_u32()?;
+        let sequences = (0..sequence_count)
+            .map(|_| {
+                let number = reader.read_u32()?;
+                let clamp = reader.read_u32()? != 0;
+                let frame_count = reader.read_u32()?;
+                let total_time = reader.read_f32()?;
+
+                let frames = (0..frame_count)
+                    .map(|_| {
+                        let duration = reader.read_f32()?;
+                        let images = (0..images_per_frame)
+                            .map(|_| {
+                                Ok(UvRect {
+                                    min: [reader.read_f32()?, reader.read_f32()?],
+                                    max: [reader.read_f32()?, reader.read_f32()?],
+                                })
+                            })
+                            .collect::<Result<_>>()?;
+
+                        Ok(SheetFrame { duration, images })
+                    })
+                    .collect::<Result<_>>()?;
+
+                Ok(SheetSequence {
+                    number,
+                    clamp,
+                    total_time,
+                    frames,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { version, sequences })
+    }
+
+    /// Returns the sequence with the given sequence number.
+    #[must_use]
+    pub fn sequence(&self, number: u32) -> Option<&SheetSequence> {
+        self.sequences
+            .iter()
+            .find(|sequence| sequence.number == number)
+    }
+}
+
+/// Resolution clamping of a texture, used to limit the highest loaded mipmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LodControl {
+    /// Log2 of the largest allowed width.
+    pub resolution_clamp_u: u8,
+    /// Log2 of the largest allowed height.
+    pub resolution_clamp_v: u8,
+}
+
+impl LodControl {
+    #[must_use]
+    pub fn from_value(value: u32) -> Self {
+        let [resolution_clamp_u, resolution_clamp_v, ..] = value.to_le_bytes();
+        Self {
+            resolution_clamp_u,
+            resolution_clamp_v,
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn read_u32(&mut self) -> Result<u32> {
+        let value = read_u32_checked(self.bytes, self.offset)
+            .ok_or(Error::Corrupted("sheet data truncated"))?;
+        self.offset += 4;
+        Ok(value)
+    }
+
+    fn read_f32(&mut self) -> Result<f32> {
+        self.read_u32().map(f32::from_bits)
+    }
+}
@@ -0,0 +1,380 @@
+use super::*;
+
+/// Builds a VTF header with the given version, format and dimensions, and no low resolution image.
+fn header(minor_version: u32, format: ImageFormat, size: u16, mipmap_count: u8) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(SIGNATURE);
+    bytes.extend_from_slice(&7_u32.to_le_bytes());
+    bytes.extend_from_slice(&minor_version.to_le_bytes());
+    bytes.extend_from_slice(&80_u32.to_le_bytes()); // header size
+    bytes.extend_from_slice(&size.to_le_bytes()); // width
+    bytes.extend_from_slice(&size.to_le_bytes()); // height
+    bytes.extend_from_slice(&0_u32.to_le_bytes()); // flags
+    bytes.extend_from_slice(&1_u16.to_le_bytes()); // frames
+    bytes.extend_from_slice(&0_u16.to_le_bytes()); // first frame
+    bytes.extend_from_slice(&[0; 4]);
+    for reflectivity in [0.5_f32, 0.25, 0.125] {
+        bytes.extend_from_slice(&reflectivity.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&1.0_f32.to_le_bytes()); // bumpmap scale
+    bytes.extend_from_slice(&format.id().to_le_bytes());
+    bytes.push(mipmap_count);
+    bytes.extend_from_slice(&(-1_i32).to_le_bytes()); // low res format
+    bytes.extend_from_slice(&[0, 0]); // low res dimensions
+    bytes.extend_from_slice(&1_u16.to_le_bytes()); // depth
+    bytes.resize(80, 0);
+    bytes
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn uncompressed_mipmaps() {
+    let mut bytes = header(2, ImageFormat::Bgra8888, 4, 3);
+    // 1x1, 2x2 and 4x4 mipmaps, from the smallest to the largest
+    bytes.extend_from_slice(&[1, 2, 3, 4]);
+    bytes.extend_from_slice(&[5, 6, 7, 8].repeat(4));
+    bytes.extend_from_slice(&[9, 10, 11, 12].repeat(16));
+
+    let vtf = Vtf::from_bytes(&bytes).unwrap();
+    assert_eq!(vtf.width(), 4);
+    assert_eq!(vtf.header().mipmap_count, 3);
+    assert_eq!(vtf.header().reflectivity, [0.5, 0.25, 0.125]);
+    assert!(vtf.resources().is_empty());
+
+    assert_eq!(
+        vtf.decode_rgba8(0, 0, 0, 0).unwrap(),
+        [11, 10, 9, 12].repeat(16)
+    );
+    assert_eq!(
+        vtf.decode_rgba8(1, 0, 0, 0).unwrap(),
+        [7, 6, 5, 8].repeat(4)
+    );
+    assert_eq!(vtf.decode_rgba8(2, 0, 0, 0).unwrap(), [3, 2, 1, 4]);
+    assert!(vtf.data(3, 0, 0, 0).is_none());
+    assert!(vtf.data(0, 1, 0, 0).is_none());
+}
+
+#[test]
+fn resources_and_dxt1() {
+    let mut bytes = header(4, ImageFormat::Dxt1, 4, 1);
+    bytes[12..16].copy_from_slice(&96_u32.to_le_bytes());
+    bytes[68..72].copy_from_slice(&2_u32.to_le_bytes());
+
+    bytes.extend_from_slice(&Resource::CRC);
+    bytes.push(RESOURCE_NO_DATA_CHUNK);
+    bytes.extend_from_slice(&0xdead_beef_u32.to_le_bytes());
+    bytes.extend_from_slice(&Resource::HIGH_RES_IMAGE);
+    bytes.push(0);
+    bytes.extend_from_slice(&96_u32.to_le_bytes());
+
+    // pure red and pure blue endpoints, first row red, second row blue,
+    // third row halfway between and last row transparent black
+    let red = 0xf800_u16;
+    let blue = 0x001f_u16;
+    bytes.extend_from_slice(&blue.to_le_bytes());
+    bytes.extend_from_slice(&red.to_le_bytes());
+    bytes.extend_from_slice(&[0b01_01_01_01, 0b00_00_00_00, 0b10_10_10_10, 0b11_11_11_11]);
+
+    let vtf = Vtf::from_bytes(&bytes).unwrap();
+    assert_eq!(
+        vtf.resource(Resource::CRC).unwrap().data,
+        ResourceData::Value(0xdead_beef)
+    );
+
+    let pixels = vtf.decode_rgba8(0, 0, 0, 0).unwrap();
+    assert_eq!(pixels[..4], [255, 0, 0, 255]);
+    assert_eq!(pixels[16..20], [0, 0, 255, 255]);
+    assert_eq!(pixels[32..36], [128, 0, 128, 255]);
+    assert_eq!(pixels[48..52], [0, 0, 0, 0]);
+}
+
+#[test]
+fn dxt5_alpha() {
+    let mut block = vec![255, 0];
+    // indices 0 (255) and 1 (0) alternating
+    block.extend_from_slice(&[
+        0b0000_1000,
+        0b1000_0010,
+        0b0010_0000,
+        0b0000_1000,
+        0b1000_0010,
+        0b0010_0000,
+    ]);
+    block.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+
+    let pixels = ImageFormat::Dxt5.decode_rgba8(&block, 4, 4).unwrap();
+    let alpha: Vec<_> = pixels.chunks_exact(4).map(|pixel| pixel[3]).collect();
+    assert_eq!(alpha, [255, 0].repeat(8));
+    assert_eq!(pixels[..3], [255, 255, 255]);
+}
+
+#[test]
+fn packed_formats() {
+    assert_eq!(
+        ImageFormat::Bgr565
+            .decode_rgba8(&0xf800_u16.to_le_bytes(), 1, 1)
+            .unwrap(),
+        [255, 0, 0, 255]
+    );
+    assert_eq!(
+        ImageFormat::Bgra4444
+            .decode_rgba8(&0xf00f_u16.to_le_bytes(), 1, 1)
+            .unwrap(),
+        [0, 0, 255, 255]
+    );
+    assert_eq!(
+        ImageFormat::Rgb888Bluescreen
+            .decode_rgba8(&[0, 0, 255], 1, 1)
+            .unwrap(),
+        [0, 0, 0, 0]
+    );
+    assert_eq!(
+        ImageFormat::P8.decode_rgba8(&[0], 1, 1),
+        Err(Error::UnsupportedFormat(ImageFormat::P8))
+    );
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn hdr_formats() {
+    let mut data = Vec::new();
+    for value in [2.5_f32, 0.5, -1.0, 1.0] {
+        data.extend_from_slice(&half::f16::from_f32(value).to_le_bytes());
+    }
+    assert_eq!(
+        ImageFormat::Rgba16161616F
+            .decode_rgba32f(&data, 1, 1)
+            .unwrap(),
+        [2.5, 0.5, -1.0, 1.0]
+    );
+    assert_eq!(
+        ImageFormat::Rgba16161616F
+            .decode_rgba8(&data, 1, 1)
+            .unwrap(),
+        [255, 128, 0, 255]
+    );
+
+    let hdr = ImageFormat::Bgra8888
+        .decode_compressed_hdr(&[0, 64, 128, 255], 1, 1)
+        .unwrap();
+    assert_eq!(hdr[3], 1.0);
+    assert!((hdr[0] - 128.0 * 255.0 * 16.0 / 262_144.0).abs() < 1e-6);
+    assert_eq!(hdr[2], 0.0);
+}
+
+#[test]
+fn invalid_files() {
+    assert!(matches!(
+        Vtf::from_bytes(b"VTX\0"),
+        Err(Error::InvalidSignature(_))
+    ));
+
+    let mut bytes = header(6, ImageFormat::Rgba8888, 1, 1);
+    assert_eq!(
+        Vtf::from_bytes(&bytes).unwrap_err(),
+        Error::UnsupportedVersion { major: 7, minor: 6 }
+    );
+
+    bytes[8..12].copy_from_slice(&2_u32.to_le_bytes());
+    assert_eq!(
+        Vtf::from_bytes(&bytes).unwrap_err(),
+        Error::Corrupted("image data truncated")
+    );
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn encode_uncompressed() {
+    // left half red, right half transparent blue
+    let pixels: Vec<u8> = (0..8 * 4)
+        .flat_map(|i| {
+            if i % 8 < 4 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 255, 0]
+            }
+        })
+        .collect();
+
+    let mut settings = EncodeSettings::new();
+    settings.format(ImageFormat::Bgra8888);
+    settings.flags(TextureFlags::CLAMP_S | TextureFlags::CLAMP_T);
+
+    for minor_version in [0, 2, 5] {
+        settings.minor_version(minor_version);
+        let bytes = encode(SourceImage::Rgba8(&pixels), 8, 4, &settings).unwrap();
+        let vtf = Vtf::from_bytes(&bytes).unwrap();
+
+        let header = vtf.header();
+        assert_eq!(header.minor_version, minor_version);
+        assert_eq!((header.width, header.height), (8, 4));
+        assert_eq!(header.mipmap_count, 4);
+        assert_eq!(header.flags, TextureFlags::CLAMP_S | TextureFlags::CLAMP_T);
+        assert_eq!(header.low_res_format, Some(ImageFormat::Dxt1));
+        assert_eq!((header.low_res_width, header.low_res_height), (8, 4));
+        assert_eq!(header.reflectivity, [0.5, 0.0, 0.5]);
+        assert!(vtf.low_res_data().is_some());
+
+        assert_eq!(vtf.decode_rgba8(0, 0, 0, 0).unwrap(), pixels);
+        assert_eq!(vtf.decode_rgba8(3, 0, 0, 0).unwrap(), [128, 0, 128, 128]);
+    }
+
+    settings.minor_version(6);
+    assert_eq!(
+        encode(SourceImage::Rgba8(&pixels), 8, 4, &settings),
+        Err(Error::UnsupportedVersion { major: 7, minor: 6 })
+    );
+    settings.minor_version(2);
+    assert_eq!(
+        encode(SourceImage::Rgba8(&pixels), 4, 4, &settings),
+        Err(Error::InvalidImage(
+            "pixel data doesn't match the dimensions"
+        ))
+    );
+}
+
+#[test]
+fn encode_compressed() {
+    // a gradient between two colors with alternating alpha encodes exactly
+    let pixels: Vec<u8> = (0..16_u8)
+        .flat_map(|i| {
+            let red = if i % 4 < 2 { 255 } else { 0 };
+            [red, 0, 255 - red, if i % 2 == 0 { 255 } else { 0 }]
+        })
+        .collect();
+
+    let mut settings = EncodeSettings::new();
+    settings.mipmaps(false);
+    settings.low_res_image(false);
+
+    for format in [ImageFormat::Dxt5, ImageFormat::Dxt1] {
+        settings.format(format);
+        let bytes = encode(SourceImage::Rgba8(&pixels), 4, 4, &settings).unwrap();
+        let vtf = Vtf::from_bytes(&bytes).unwrap();
+        assert_eq!(vtf.header().mipmap_count, 1);
+        assert_eq!(vtf.header().flags, TextureFlags::NO_MIP);
+        assert_eq!(vtf.header().low_res_format, None);
+
+        let decoded = vtf.decode_rgba8(0, 0, 0, 0).unwrap();
+        for (decoded, original) in decoded.chunks_exact(4).zip(pixels.chunks_exact(4)) {
+            assert_eq!(decoded[..3], original[..3]);
+            if format == ImageFormat::Dxt5 {
+                assert_eq!(decoded[3], original[3]);
+            }
+        }
+    }
+
+    let bytes = ImageFormat::Dxt1OneBitAlpha
+        .encode_rgba32f(&[1.0, 1.0, 1.0, 0.0].repeat(4), 2, 2)
+        .unwrap();
+    assert_eq!(
+        ImageFormat::Dxt1OneBitAlpha
+            .decode_rgba8(&bytes, 2, 2)
+            .unwrap(),
+        [0; 16]
+    );
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn encode_hdr() {
+    let pixels = [4.0, 0.5, 0.25, 1.0];
+
+    let mut settings = EncodeSettings::new();
+    settings.format(ImageFormat::Rgba16161616F);
+    let bytes = encode(SourceImage::Rgba32F(&pixels), 1, 1, &settings).unwrap();
+    let vtf = Vtf::from_bytes(&bytes).unwrap();
+
+    assert_eq!(vtf.header().reflectivity, [4.0, 0.5, 0.25]);
+    assert_eq!(vtf.decode_rgba32f(0, 0, 0, 0).unwrap(), pixels);
+
+    // float images are linear, so the srgb flag doesn't change how mipmaps are filtered
+    let pixels = [1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+    settings.flags(TextureFlags::SRGB);
+    let bytes = encode(SourceImage::Rgba32F(&pixels), 2, 1, &settings).unwrap();
+    let vtf = Vtf::from_bytes(&bytes).unwrap();
+
+    assert_eq!(
+        vtf.decode_rgba32f(1, 0, 0, 0).unwrap(),
+        [0.5, 0.5, 0.5, 1.0]
+    );
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn resource_data() {
+    let mut sheet = Vec::new();
+    for value in [0_u32, 1, 3, 1, 2] {
+        // version, sequence count, sequence number, clamp, frame count
+        sheet.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [
+        1.5_f32, // total time
+        0.5, 0.0, 0.0, 0.5, 1.0, // duration and rect of the first frame
+        1.0, 0.5, 0.0, 1.0, 1.0, // duration and rect of the second frame
+    ] {
+        sheet.extend_from_slice(&value.to_le_bytes());
+    }
+    let key_values = b"\"settings\" { \"key\" \"value\" }\0";
+
+    let resources_end = 80 + 5 * 8;
+    let sheet_offset = resources_end;
+    let key_values_offset = sheet_offset + 4 + sheet.len();
+    let image_offset = key_values_offset + 4 + key_values.len();
+
+    let mut bytes = header(5, ImageFormat::I8, 1, 1);
+    bytes[12..16].copy_from_slice(&(resources_end as u32).to_le_bytes());
+    bytes[68..72].copy_from_slice(&5_u32.to_le_bytes());
+
+    let mut entry = |tag: [u8; 3], flags: u8, value: usize| {
+        bytes.extend_from_slice(&tag);
+        bytes.push(flags);
+        bytes.extend_from_slice(&(value as u32).to_le_bytes());
+    };
+    entry(Resource::ANIMATED_PARTICLE_SHEET, 0, sheet_offset);
+    entry(Resource::LOD_CONTROL, RESOURCE_NO_DATA_CHUNK, 0x0908);
+    entry(Resource::EXTENDED_FLAGS, RESOURCE_NO_DATA_CHUNK, 0x20);
+    entry(Resource::KEY_VALUES, 0, key_values_offset);
+    entry(Resource::HIGH_RES_IMAGE, 0, image_offset);
+
+    for chunk in [&sheet[..], key_values] {
+        bytes.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(chunk);
+    }
+    bytes.push(0x80);
+
+    let vtf = Vtf::from_bytes(&bytes).unwrap();
+    assert_eq!(vtf.decode_rgba8(0, 0, 0, 0).unwrap(), [128, 128, 128, 255]);
+    assert_eq!(vtf.crc(), None);
+    assert_eq!(vtf.extended_flags(), Some(0x20));
+    assert_eq!(
+        vtf.lod_control(),
+        Some(LodControl {
+            resolution_clamp_u: 8,
+            resolution_clamp_v: 9
+        })
+    );
+    assert_eq!(vtf.key_values(), Some(&key_values[..key_values.len() - 1]));
+
+    let sheet = vtf.sheet().unwrap().unwrap();
+    let sequence = sheet.sequence(3).unwrap();
+    assert!(sequence.clamp);
+    assert_eq!(sequence.frames.len(), 2);
+    assert_eq!(
+        sequence.frames[1].rect(),
+        UvRect {
+            min: [0.5, 0.0],
+            max: [1.0, 1.0]
+        }
+    );
+    assert_eq!(sequence.frame_at(0.25), Some(&sequence.frames[0]));
+    assert_eq!(sequence.frame_at(0.75), Some(&sequence.frames[1]));
+    assert_eq!(sequence.frame_at(10.0), Some(&sequence.frames[1]));
+
+    let truncated: Vec<_> = [0_u32, 1, 0].iter().flat_map(|v| v.to_le_bytes()).collect();
+    assert_eq!(
+        Sheet::from_bytes(&truncated),
+        Err(Error::Corrupted("sheet data truncated"))
+    );
+}